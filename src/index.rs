@@ -0,0 +1,290 @@
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::notes;
+
+/// Directory under the vault root for server state. Hidden, so it is never walked.
+pub const STATE_DIR: &str = ".stumbling";
+
+const INDEX_FILE: &str = "index.json";
+
+/// Cached frontmatter of every note, validated against each file's mtime and size.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct NoteIndex {
+    entries: BTreeMap<String, IndexEntry>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexEntry {
+    /// Modification time in nanoseconds since the Unix epoch
+    mtime: u64,
+    size: u64,
+    metadata: Option<serde_json::Value>,
+}
+
+/// What a `refresh` had to do.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RefreshStats {
+    pub reparsed: usize,
+    pub reused: usize,
+    pub removed: usize,
+}
+
+fn index_path(root: &Path) -> PathBuf {
+    root.join(STATE_DIR).join(INDEX_FILE)
+}
+
+/// Modification time and size of a file, used to detect stale entries.
+fn file_stamp(path: &Path) -> Option<(u64, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((mtime.as_nanos() as u64, meta.len()))
+}
+
+impl NoteIndex {
+    /// Load the persisted index, or start empty if it is missing or unreadable.
+    pub fn load(root: &Path) -> Self {
+        fs::read_to_string(index_path(root))
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    /// Bring the index up to date with the vault, reparsing only new or changed notes.
+    /// Notes in the trash directory (relative to root) are left out.
+    pub fn refresh(
+        &mut self,
+        root: &Path,
+        trash_dir: &str,
+        options: &notes::NoteOptions,
+    ) -> RefreshStats {
+        let files = notes::collect_markdown_files(root, trash_dir);
+
+        let refreshed: Vec<(String, IndexEntry, bool)> = files
+            .par_iter()
+            .filter_map(|path| {
+                let rel = notes::relative_path(root, path);
+                let (mtime, size) = file_stamp(path)?;
+
+                if let Some(entry) = self.entries.get(&rel) {
+                    if entry.mtime == mtime && entry.size == size {
+                        return Some((rel, entry.clone(), false));
+                    }
+                }
+
+                let entry = IndexEntry {
+                    mtime,
+                    size,
+                    metadata: notes::read_metadata(path, options),
+                };
+                Some((rel, entry, true))
+            })
+            .collect();
+
+        let mut stats = RefreshStats::default();
+        let mut entries = BTreeMap::new();
+        for (rel, entry, reparsed) in refreshed {
+            if reparsed {
+                stats.reparsed += 1;
+            } else {
+                stats.reused += 1;
+            }
+            entries.insert(rel, entry);
+        }
+        stats.removed = self
+            .entries
+            .keys()
+            .filter(|k| !entries.contains_key(*k))
+            .count();
+
+        if stats.reparsed > 0 || stats.removed > 0 {
+            self.dirty = true;
+        }
+        self.entries = entries;
+        stats
+    }
+
+    /// (relative path, frontmatter) pairs for every note with frontmatter.
+    pub fn metadata(&self) -> Vec<(String, serde_json::Value)> {
+        self.entries
+            .iter()
+            .filter_map(|(path, entry)| Some((path.clone(), entry.metadata.clone()?)))
+            .collect()
+    }
+
+    /// (relative path, frontmatter) pairs for every note, with or without frontmatter.
+    pub fn notes(&self) -> Vec<(String, Option<serde_json::Value>)> {
+        self.entries
+            .iter()
+            .map(|(path, entry)| (path.clone(), entry.metadata.clone()))
+            .collect()
+    }
+
+    /// Write the index to disk if it changed since it was loaded or last saved.
+    pub fn save(&mut self, root: &Path, options: &notes::NoteOptions) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let json = serde_json::to_string(self).context("Failed to serialize index")?;
+        notes::write_file(&index_path(root), &json, options)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn setup_vault() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("a.md"), "---\ntitle: A\n---\n\nBody").unwrap();
+        fs::write(dir.path().join("b.md"), "---\ntitle: B\n---\n\nBody").unwrap();
+        fs::write(dir.path().join("plain.md"), "No frontmatter").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_cold_build() {
+        let vault = setup_vault();
+        let mut index = NoteIndex::load(vault.path());
+
+        let stats = index.refresh(
+            vault.path(),
+            notes::DEFAULT_TRASH_DIR,
+            &notes::NoteOptions::default(),
+        );
+
+        assert_eq!(stats.reparsed, 3);
+        assert_eq!(stats.reused, 0);
+        let metadata = index.metadata();
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata[0].0, "a.md");
+        assert_eq!(metadata[0].1["title"], "A");
+    }
+
+    #[test]
+    fn test_warm_load_skips_unchanged() {
+        let vault = setup_vault();
+        let mut index = NoteIndex::load(vault.path());
+        index.refresh(
+            vault.path(),
+            notes::DEFAULT_TRASH_DIR,
+            &notes::NoteOptions::default(),
+        );
+        index
+            .save(vault.path(), &notes::NoteOptions::default())
+            .unwrap();
+
+        assert!(vault.path().join(STATE_DIR).join(INDEX_FILE).exists());
+
+        let mut warm = NoteIndex::load(vault.path());
+        let stats = warm.refresh(
+            vault.path(),
+            notes::DEFAULT_TRASH_DIR,
+            &notes::NoteOptions::default(),
+        );
+
+        assert_eq!(stats.reparsed, 0);
+        assert_eq!(stats.reused, 3);
+        assert_eq!(warm.metadata().len(), 2);
+    }
+
+    #[test]
+    fn test_modified_file_invalidated() {
+        let vault = setup_vault();
+        let mut index = NoteIndex::load(vault.path());
+        index.refresh(
+            vault.path(),
+            notes::DEFAULT_TRASH_DIR,
+            &notes::NoteOptions::default(),
+        );
+        index
+            .save(vault.path(), &notes::NoteOptions::default())
+            .unwrap();
+
+        fs::write(
+            vault.path().join("a.md"),
+            "---\ntitle: A changed\n---\n\nBody",
+        )
+        .unwrap();
+
+        let mut warm = NoteIndex::load(vault.path());
+        let stats = warm.refresh(
+            vault.path(),
+            notes::DEFAULT_TRASH_DIR,
+            &notes::NoteOptions::default(),
+        );
+
+        assert_eq!(stats.reparsed, 1);
+        assert_eq!(stats.reused, 2);
+        assert_eq!(warm.metadata()[0].1["title"], "A changed");
+    }
+
+    #[test]
+    fn test_deleted_file_removed() {
+        let vault = setup_vault();
+        let mut index = NoteIndex::load(vault.path());
+        index.refresh(
+            vault.path(),
+            notes::DEFAULT_TRASH_DIR,
+            &notes::NoteOptions::default(),
+        );
+
+        fs::remove_file(vault.path().join("b.md")).unwrap();
+        let stats = index.refresh(
+            vault.path(),
+            notes::DEFAULT_TRASH_DIR,
+            &notes::NoteOptions::default(),
+        );
+
+        assert_eq!(stats.removed, 1);
+        assert_eq!(index.metadata().len(), 1);
+    }
+
+    #[test]
+    fn test_state_dir_not_indexed() {
+        let vault = setup_vault();
+        fs::create_dir_all(vault.path().join(STATE_DIR)).unwrap();
+        fs::write(
+            vault.path().join(STATE_DIR).join("stray.md"),
+            "---\ntitle: X\n---\n",
+        )
+        .unwrap();
+
+        let mut index = NoteIndex::default();
+        index.refresh(
+            vault.path(),
+            notes::DEFAULT_TRASH_DIR,
+            &notes::NoteOptions::default(),
+        );
+
+        assert!(index
+            .metadata()
+            .iter()
+            .all(|(p, _)| !p.starts_with(STATE_DIR)));
+    }
+
+    #[test]
+    fn test_save_is_noop_when_clean() {
+        let vault = setup_vault();
+        let mut index = NoteIndex::default();
+
+        index
+            .save(vault.path(), &notes::NoteOptions::default())
+            .unwrap();
+
+        assert!(!vault.path().join(STATE_DIR).exists());
+    }
+}
@@ -2,18 +2,85 @@ use anyhow::{Context, Result};
 use rmcp::{
     handler::server::{tool::ToolRouter, wrapper::Parameters},
     model::*,
-    service::Peer,
-    tool, tool_handler, tool_router, ErrorData as McpError, RoleServer, ServerHandler, ServiceExt,
+    service::{NotificationContext, Peer, RequestContext, RunningService},
+    tool, tool_router,
+    transport::streamable_http_server::{
+        session::local::LocalSessionManager, StreamableHttpServerConfig, StreamableHttpService,
+    },
+    ErrorData as McpError, RoleServer, ServerHandler, ServiceExt,
 };
 use serde::{Deserialize, Serialize};
-use std::{env, path::PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
 
+mod index;
 mod notes;
+mod oplog;
+mod watch;
+
+/// Unwrap a `Result` in a tool handler like `?`, returning the error as a tool error with
+/// `context` instead of failing the request.
+macro_rules! tool_try {
+    ($result:expr, $context:expr) => {
+        match $result {
+            Ok(value) => value,
+            Err(e) => return Ok(tool_error($context, e)),
+        }
+    };
+}
 
 #[derive(Clone)]
 pub struct StumblingServer {
+    /// Default vault root (STUMBLING_ROOT)
     root: PathBuf,
+    /// Named vaults: each root when STUMBLING_ROOT lists several, named by directory, then
+    /// STUMBLING_VAULTS. Tools select one with `vault` or a `name:` prefix on a path.
+    vaults: Vec<(String, PathBuf)>,
+    /// Search every vault when search_notes isn't given one (STUMBLING_ROOT lists several)
+    search_all_vaults: bool,
     parse_frontmatter: bool,
+    max_file_bytes: Option<u64>,
+    /// Note size limit, write retries and frontmatter formats, passed to every notes call
+    note_options: notes::NoteOptions,
+    /// Frontmatter cache persisted under .stumbling/, enabled by STUMBLING_INDEX_PERSIST
+    index: Option<Arc<Mutex<index::NoteIndex>>>,
+    /// Where delete_note moves notes, relative to the vault root (STUMBLING_TRASH_DIR)
+    trash_dir: String,
+    /// Trash entries older than this are pruned at startup (STUMBLING_TRASH_RETENTION_DAYS)
+    trash_retention: Option<Duration>,
+    /// Where archive_note files notes by year, relative to the vault root (STUMBLING_ARCHIVE_DIR)
+    archive_dir: String,
+    /// Path of each day's note for add_to_daily, as a chrono format (STUMBLING_DAILY_FORMAT)
+    daily_format: String,
+    /// Content of a new daily note, with {{date}} replaced (STUMBLING_DAILY_TEMPLATE)
+    daily_template: String,
+    /// Hide and reject mutating tools (STUMBLING_READONLY)
+    read_only: bool,
+    /// Log deletes, moves and overwrites for undo_last (STUMBLING_UNDO)
+    undo: bool,
+    /// Notify each client session when notes change on disk (STUMBLING_WATCH)
+    watch: bool,
+    /// Results returned when a call omits `limit` (STUMBLING_DEFAULT_LIMIT)
+    default_limit: usize,
+    /// Requested limits are clamped to this (STUMBLING_MAX_LIMIT)
+    max_limit: usize,
+    /// How search results are serialized (STUMBLING_OUTPUT_FORMAT)
+    output_format: OutputFormat,
+    /// JSON Schema that validate_frontmatter checks notes against (STUMBLING_SCHEMA)
+    schema: Option<Arc<jsonschema::Validator>>,
+    /// Words the keywords tool ignores instead of the built-in list (STUMBLING_STOPWORDS)
+    stopwords: Option<Arc<BTreeSet<String>>>,
+    /// Note counts reported by ping, per vault root, with when they were taken
+    note_counts: Arc<Mutex<BTreeMap<PathBuf, (Instant, usize)>>>,
+    /// Messages from startup, such as trash pruning, logged to the first client to connect
+    startup_log: Arc<Mutex<Vec<String>>>,
     #[allow(dead_code)]
     tool_router: ToolRouter<Self>,
 }
@@ -22,238 +89,5426 @@ pub struct StumblingServer {
 pub struct ReadNoteParams {
     /// Relative path to the note from STUMBLING_ROOT (e.g., "daily/2024-01-01.md")
     path: String,
+    /// Override the server's STUMBLING_PARSE_FRONTMATTER setting for this call
+    #[serde(default)]
+    parse: Option<bool>,
+    /// If true, return only the body as plain markdown with frontmatter removed
+    #[serde(default)]
+    strip_frontmatter_only: bool,
+    /// If true, return `{frontmatter, format, body}` with the frontmatter block's original
+    /// text instead of parsed JSON, keeping comments and quoting
+    #[serde(default)]
+    raw_frontmatter: bool,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
-pub struct SearchNotesParams {
-    /// Search query (supports regex)
-    query: String,
-    /// Maximum number of results to return (default: 20)
-    #[serde(default = "default_limit")]
-    limit: usize,
+pub struct ChangedSinceParams {
+    /// Unix timestamp in seconds, which may be fractional, such as the newest `mtime` from
+    /// the last call
+    since: f64,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
 }
 
-fn default_limit() -> usize {
-    20
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct ListTrashParams {
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
-pub struct WriteNoteParams {
-    /// Relative path to the note from STUMBLING_ROOT (e.g., "daily/2024-01-01.md")
+pub struct RandomNoteParams {
+    /// If true, also return the note's content
+    #[serde(default)]
+    include_content: bool,
+    /// Seed for a reproducible pick. Default: a different note each call.
+    #[serde(default)]
+    seed: Option<u64>,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct ReadNoteSpansParams {
+    /// Relative path to the note from STUMBLING_ROOT
     path: String,
-    /// Body content to write to the note
-    content: String,
-    /// Optional frontmatter metadata as JSON object. Example: {"title": "My Note", "tags": ["rust", "mcp"], "date": "2026-01-01"}. NG: Do NOT pass as string like "{\"title\": ...}"
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
     #[serde(default)]
-    metadata: Option<serde_json::Value>,
+    vault: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
-pub struct DeleteNoteParams {
+pub struct GetFrontmatterParams {
     /// Relative path to the note from STUMBLING_ROOT
     path: String,
-    /// If true, permanently delete. If false (default), move to .trash directory.
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
     #[serde(default)]
-    permanent: bool,
+    vault: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
-pub struct SearchMetadataParams {
-    /// Field to search in frontmatter (e.g., "title", "tags", "author.name")
-    field: String,
-    /// Value pattern to match (supports regex)
-    pattern: String,
-    /// Maximum number of results to return (default: 20)
-    #[serde(default = "default_limit")]
-    limit: usize,
+pub struct RenderHtmlParams {
+    /// Relative path to the note from STUMBLING_ROOT
+    path: String,
+    /// Render the frontmatter as a table before the body
+    #[serde(default)]
+    include_frontmatter: bool,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
 }
 
-#[tool_router]
-impl StumblingServer {
-    pub fn new() -> Result<Self> {
-        let root =
-            env::var("STUMBLING_ROOT").context("STUMBLING_ROOT environment variable not set")?;
-        let root = PathBuf::from(root);
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct ReadNoteByTitleParams {
+    /// Title to look up (case-insensitive). Matches the frontmatter `title` field, or the
+    /// file name without `.md` if no title matches.
+    title: String,
+    /// Override the server's STUMBLING_PARSE_FRONTMATTER setting for this call
+    #[serde(default)]
+    parse: Option<bool>,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
 
-        if !root.exists() {
-            anyhow::bail!("STUMBLING_ROOT does not exist: {}", root.display());
-        }
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct GetTreeParams {
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
 
-        let parse_frontmatter = env::var("STUMBLING_PARSE_FRONTMATTER")
-            .map(|v| v == "true" || v == "1")
-            .unwrap_or(false);
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct OutlineParams {
+    /// Relative path to the note from STUMBLING_ROOT
+    path: String,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
 
-        Ok(Self {
-            root,
-            parse_frontmatter,
-            tool_router: Self::tool_router(),
-        })
-    }
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct ReadSectionParams {
+    /// Relative path to the note from STUMBLING_ROOT
+    path: String,
+    /// Heading text to read under (case-insensitive, without the leading "#").
+    /// Use "Parent > Child" to pick a heading nested under another.
+    heading: String,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
 
-    /// Read a markdown note from the vault.
-    /// Returns the note content, optionally with frontmatter parsed separately.
-    #[tool(name = "read_note")]
-    async fn read_note(
-        &self,
-        params: Parameters<ReadNoteParams>,
-    ) -> Result<CallToolResult, McpError> {
-        let Parameters(params) = params;
-        let path = self.root.join(&params.path);
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct NoteLinksParams {
+    /// Relative path to the note from STUMBLING_ROOT
+    path: String,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
 
-        match notes::read_note(&path, self.parse_frontmatter) {
-            Ok(content) => Ok(CallToolResult::success(vec![Content::text(content)])),
-            Err(e) => Ok(CallToolResult::error(vec![Content::text(format!(
-                "Failed to read note: {}",
-                e
-            ))])),
-        }
-    }
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct NoteHashParams {
+    /// Relative path to the note from STUMBLING_ROOT
+    path: String,
+    /// If true, also store a SHA-256 of the body in the note's `checksum` frontmatter field
+    #[serde(default)]
+    store: bool,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
 
-    /// Search for notes containing the given query.
-    /// Uses parallel processing for fast search across all markdown files.
-    #[tool(name = "search_notes")]
-    async fn search_notes(
-        &self,
-        params: Parameters<SearchNotesParams>,
-    ) -> Result<CallToolResult, McpError> {
-        let Parameters(params) = params;
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct ReadManyParams {
+    /// Relative paths to the notes from STUMBLING_ROOT
+    paths: Vec<String>,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
 
-        match notes::search_notes(&self.root, &params.query, params.limit) {
-            Ok(results) => match serde_json::to_string_pretty(&results) {
-                Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
-                Err(e) => Ok(CallToolResult::error(vec![Content::text(format!(
-                    "Failed to serialize search results: {}",
-                    e
-                ))])),
-            },
-            Err(e) => Ok(CallToolResult::error(vec![Content::text(format!(
-                "Search failed: {}",
-                e
-            ))])),
-        }
-    }
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct SearchNotesParams {
+    /// Search query: a regex, or words and "quoted phrases" with mode "terms"
+    query: String,
+    /// Maximum number of results to return (default: STUMBLING_DEFAULT_LIMIT, 20)
+    #[serde(default)]
+    limit: Option<usize>,
+    /// Report line numbers relative to the body start (after frontmatter) instead of the
+    /// top of the file. Matches inside frontmatter are skipped.
+    #[serde(default)]
+    body_relative_lines: bool,
+    /// Return an excerpt of about this many characters around each match, cut on word
+    /// boundaries, instead of the full line (default: 0, full line)
+    #[serde(default)]
+    snippet_len: usize,
+    /// Drop matching lines that also match this regex (e.g., query "TODO", exclude "TODONE")
+    #[serde(default)]
+    exclude: Option<String>,
+    /// Search notes that aren't valid UTF-8 (e.g., Latin-1) with undecodable bytes replaced,
+    /// listing them in `warnings`, instead of skipping the rest of the note
+    #[serde(default)]
+    lossy_utf8: bool,
+    /// Result order: "path" (default), "modified" (newest notes first; stats every matching
+    /// note), or "relevance" (notes with the most matches first). Orders other than "path"
+    /// search every note before applying the limit.
+    #[serde(default)]
+    sort: notes::SearchSort,
+    /// Also search hidden files and directories (like .config), except .trash, .git and .stumbling
+    #[serde(default)]
+    include_hidden: bool,
+    /// Maximum number of matches returned from any one note (default: unlimited)
+    #[serde(default)]
+    max_per_file: Option<usize>,
+    /// Also match the query against note paths. A path match is returned with line_number 0
+    /// and the path as its line.
+    #[serde(default)]
+    search_paths: bool,
+    /// Also return `stats`: notes scanned, bytes read, and elapsed milliseconds
+    #[serde(default)]
+    include_stats: bool,
+    /// "regex" (default) treats the query as a regex. "terms" splits it into words and
+    /// "quoted phrases" that must all appear on a line, matched literally ignoring case.
+    #[serde(default)]
+    mode: notes::SearchMode,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
 
-    /// Search notes by frontmatter metadata field.
-    /// Supports nested fields with dot notation (e.g., "author.name").
-    #[tool(name = "search_metadata")]
-    async fn search_metadata(
-        &self,
-        params: Parameters<SearchMetadataParams>,
-    ) -> Result<CallToolResult, McpError> {
-        let Parameters(params) = params;
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct WriteNoteParams {
+    /// Relative path to the note from STUMBLING_ROOT (e.g., "daily/2024-01-01.md")
+    path: String,
+    /// Body content to write to the note
+    content: String,
+    /// Optional frontmatter metadata as JSON object. Example: {"title": "My Note", "tags": ["rust", "mcp"], "date": "2026-01-01"}. NG: Do NOT pass as string like "{\"title\": ...}"
+    #[serde(default)]
+    metadata: Option<serde_json::Value>,
+    /// If true, merge `metadata` into the existing note's frontmatter instead of replacing it:
+    /// nested objects are merged, other values (including arrays) are replaced, and fields
+    /// not given are kept. Without `metadata`, the existing frontmatter is kept as is.
+    #[serde(default)]
+    merge_metadata: bool,
+    /// "overwrite" (default) replaces an existing note, "create_new" fails if it exists,
+    /// "append" adds content to the end. Metadata is ignored when appending to an existing note.
+    #[serde(default)]
+    mode: notes::WriteMode,
+    /// If true and the note already exists, include a unified diff of the change in the result
+    #[serde(default)]
+    return_diff: bool,
+    /// Hash from `note_hash`; the write is refused if the note has changed since
+    #[serde(default)]
+    expected_hash: Option<String>,
+    /// If true, return a unified diff of what would change instead of writing.
+    /// A new note shows its whole content as additions.
+    #[serde(default)]
+    preview: bool,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
 
-        match notes::search_metadata(&self.root, &params.field, &params.pattern, params.limit) {
-            Ok(results) => match serde_json::to_string_pretty(&results) {
-                Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
-                Err(e) => Ok(CallToolResult::error(vec![Content::text(format!(
-                    "Failed to serialize metadata search results: {}",
-                    e
-                ))])),
-            },
-            Err(e) => Ok(CallToolResult::error(vec![Content::text(format!(
-                "Metadata search failed: {}",
-                e
-            ))])),
-        }
-    }
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct PatchNoteParams {
+    /// Relative path to the note from STUMBLING_ROOT
+    path: String,
+    /// First line to replace (1-indexed, inclusive)
+    start_line: usize,
+    /// Last line to replace (1-indexed, inclusive). Use line count + 1 to append at the end.
+    end_line: usize,
+    /// Text replacing the given line range. Empty string deletes the lines.
+    replacement: String,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
 
-    /// Create or overwrite a markdown note.
-    /// Creates parent directories if they don't exist.
-    /// If metadata is provided, formats as YAML frontmatter.
-    #[tool(name = "write_note")]
-    async fn write_note(
-        &self,
-        params: Parameters<WriteNoteParams>,
-        peer: Peer<RoleServer>,
-    ) -> Result<CallToolResult, McpError> {
-        let Parameters(params) = params;
-        let path = self.root.join(&params.path);
-        let is_overwrite = path.exists();
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct EditNoteParams {
+    /// Relative path to the note from STUMBLING_ROOT
+    path: String,
+    /// Exact text to find (not a regex)
+    find: String,
+    /// Text to put in its place
+    replace: String,
+    /// Which occurrence to replace: "first" (default), "last", or "all"
+    #[serde(default)]
+    occurrence: notes::Occurrence,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
 
-        // Format content with frontmatter if metadata is provided
-        let content = match params.metadata {
-            Some(meta) => notes::format_with_frontmatter(&meta, &params.content),
-            None => params.content.clone(),
-        };
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct LinkNotesParams {
+    /// Relative path of the note to add the link to
+    from: String,
+    /// Relative path of the note to link to
+    to: String,
+    /// Heading of the section the link is listed under, added if missing (default: "Related")
+    #[serde(default = "default_link_heading")]
+    heading: String,
+    /// If true, also link `to` back to `from`
+    #[serde(default)]
+    bidirectional: bool,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
 
-        match notes::write_note(&path, &content) {
-            Ok(()) => {
-                let action = if is_overwrite { "Overwrote" } else { "Created" };
-                let msg = format!("{} {}", action, params.path);
+fn default_link_heading() -> String {
+    "Related".to_string()
+}
 
-                let _ = peer
-                    .notify_logging_message(LoggingMessageNotificationParam {
-                        level: LoggingLevel::Info,
-                        logger: Some("stumbling-rs".into()),
-                        data: msg.clone().into(),
-                    })
-                    .await;
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct SplitNoteParams {
+    /// Relative path to the note from STUMBLING_ROOT
+    path: String,
+    /// Heading level to split at (default: 2, i.e. "##")
+    #[serde(default = "default_split_level")]
+    level: u8,
+    /// If true, replace the split sections in the original with wikilinks to the new notes
+    #[serde(default)]
+    replace_with_toc: bool,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
 
-                Ok(CallToolResult::success(vec![Content::text(msg)]))
-            }
-            Err(e) => Ok(CallToolResult::error(vec![Content::text(format!(
-                "Failed to write note: {}",
-                e
-            ))])),
-        }
-    }
+fn default_split_level() -> u8 {
+    2
+}
 
-    /// Delete a markdown note.
-    /// By default, moves to .trash directory. Set permanent=true to permanently delete.
-    #[tool(name = "delete_note")]
-    async fn delete_note(
-        &self,
-        params: Parameters<DeleteNoteParams>,
-        peer: Peer<RoleServer>,
-    ) -> Result<CallToolResult, McpError> {
-        let Parameters(params) = params;
-        let path = self.root.join(&params.path);
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct MergeNotesParams {
+    /// Relative paths of the notes to combine, in order
+    sources: Vec<String>,
+    /// Relative path of the merged note. May be one of the sources.
+    destination: String,
+    /// If true, move the sources (other than the destination) to the trash afterwards
+    #[serde(default)]
+    trash_sources: bool,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
 
-        match notes::delete_note(&self.root, &path, params.permanent) {
-            Ok(msg) => {
-                let _ = peer
-                    .notify_logging_message(LoggingMessageNotificationParam {
-                        level: LoggingLevel::Info,
-                        logger: Some("stumbling-rs".into()),
-                        data: msg.clone().into(),
-                    })
-                    .await;
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct ArchiveNoteParams {
+    /// Relative path to the note from STUMBLING_ROOT
+    path: String,
+    /// If true, set an `archived: <YYYY-MM-DD>` field in the note's frontmatter
+    #[serde(default)]
+    stamp: bool,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
 
-                Ok(CallToolResult::success(vec![Content::text(msg)]))
-            }
-            Err(e) => Ok(CallToolResult::error(vec![Content::text(format!(
-                "Failed to delete note: {}",
-                e
-            ))])),
-        }
-    }
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct NormalizeFrontmatterParams {
+    /// Relative path to the note from STUMBLING_ROOT
+    path: String,
+    /// If true, only report whether the note would change
+    #[serde(default)]
+    dry_run: bool,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
 }
 
-#[tool_handler]
-impl ServerHandler for StumblingServer {
-    fn get_info(&self) -> ServerInfo {
-        ServerInfo {
-            protocol_version: ProtocolVersion::V_2024_11_05,
-            capabilities: ServerCapabilities::builder()
-                .enable_tools()
-                .enable_logging()
-                .build(),
-            server_info: Implementation::from_build_env(),
-            instructions: Some(
-                "MCP server for reading and searching markdown notes in a local vault.".to_string(),
-            ),
-        }
-    }
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct NoteNeighborsParams {
+    /// Relative path to a dated note (e.g., "daily/2024-01-01.md"); it needn't exist
+    path: String,
+    /// chrono format of the dates in file names, without ".md" (default: "%Y-%m-%d")
+    #[serde(default)]
+    date_format: Option<String>,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct AddToDailyParams {
+    /// Text of the bullet, prefixed with the current time
+    text: String,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct DeleteNoteParams {
+    /// Relative path to the note from STUMBLING_ROOT
+    path: String,
+    /// If true, permanently delete. If false (default), move to the trash directory.
+    #[serde(default)]
+    permanent: bool,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct RestoreNoteParams {
+    /// The trashed note's `trash_path` from list_trash (e.g., ".trash/1700000000_note.md")
+    trash_path: String,
+    /// Relative path to restore to (default: where the note was deleted from)
+    #[serde(default)]
+    to: Option<String>,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct DeleteDirParams {
+    /// Relative path to the directory from STUMBLING_ROOT
+    path: String,
+    /// If true, permanently delete. If false (default), move to the trash directory.
+    #[serde(default)]
+    permanent: bool,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct UndoLastParams {
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct SearchMetadataParams {
+    /// Field to search in frontmatter (e.g., "title", "tags", "author.name")
+    field: String,
+    /// Value pattern to match (supports regex)
+    pattern: String,
+    /// Maximum number of results to return (default: STUMBLING_DEFAULT_LIMIT, 20)
+    #[serde(default)]
+    limit: Option<usize>,
+    /// Optional frontmatter field to sort results by (e.g., "created"). Default: sort by path.
+    #[serde(default)]
+    sort_by: Option<String>,
+    /// Sort in descending order
+    #[serde(default)]
+    sort_desc: bool,
+    /// Also search hidden files and directories (like .config), except .trash, .git and .stumbling
+    #[serde(default)]
+    include_hidden: bool,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct ReplaceInNotesParams {
+    /// Regex pattern to find
+    pattern: String,
+    /// Replacement text. Supports capture groups like "$1".
+    replacement: String,
+    /// Optional glob on the relative path to restrict which notes are edited (e.g., "daily/**")
+    #[serde(default)]
+    path_glob: Option<String>,
+    /// If true, report what would change without writing
+    #[serde(default)]
+    dry_run: bool,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct RenameTagParams {
+    /// Existing tag to rename
+    old: String,
+    /// New tag name
+    new: String,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct TagNotesParams {
+    /// Tag to add or remove
+    tag: String,
+    /// Glob on the relative path selecting the notes (e.g., "projects/**")
+    #[serde(default)]
+    path_glob: Option<String>,
+    /// Relative paths of notes, in addition to any matching path_glob
+    #[serde(default)]
+    paths: Vec<String>,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct RenameNoteParams {
+    /// Relative path to the note from STUMBLING_ROOT
+    path: String,
+    /// New relative path for the note (e.g., "projects/new-name.md")
+    new_path: String,
+    /// If true, report the links that would change without renaming anything
+    #[serde(default)]
+    dry_run: bool,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct NoteMove {
+    /// Relative path to the note from STUMBLING_ROOT
+    from: String,
+    /// New relative path for the note
+    to: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct MoveNotesParams {
+    /// Notes to move, as {from, to} pairs
+    moves: Vec<NoteMove>,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct FindDuplicatesParams {
+    /// Compare bodies after trimming and collapsing whitespace
+    #[serde(default)]
+    fuzzy: bool,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct FindOrphansParams {
+    /// Optional glob on the relative path of notes not to report (e.g., "daily/**")
+    #[serde(default)]
+    exclude_glob: Option<String>,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct SuggestTagsParams {
+    /// Start of the tag to complete, matched case-insensitively
+    prefix: String,
+    /// Maximum number of tags to return (default: STUMBLING_DEFAULT_LIMIT, 20)
+    #[serde(default)]
+    limit: Option<usize>,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct KeywordsParams {
+    /// Relative path to the note from STUMBLING_ROOT
+    path: String,
+    /// Maximum number of terms to return (default: STUMBLING_DEFAULT_LIMIT, 20)
+    #[serde(default)]
+    limit: Option<usize>,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct NotesByTagsParams {
+    /// Tags to look for, compared case-insensitively with any leading "#" ignored
+    tags: Vec<String>,
+    /// "all" (default) for notes with every tag, "any" for notes with at least one
+    #[serde(default)]
+    mode: notes::TagMatch,
+    /// Leave out notes with any of these tags
+    #[serde(default)]
+    exclude: Vec<String>,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct TagCooccurrenceParams {
+    /// Maximum number of tag pairs to return (default: STUMBLING_DEFAULT_LIMIT, 20)
+    #[serde(default)]
+    limit: Option<usize>,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct DistinctValuesParams {
+    /// Frontmatter field to aggregate (supports dot notation, e.g. "author.name")
+    field: String,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct ExportVaultParams {
+    /// Include each note's body, not just its metadata (default: true)
+    #[serde(default = "default_true")]
+    include_body: bool,
+    /// Relative path of a new file to write the bundle to instead of returning it.
+    /// Must not exist yet or be a note (`.md` or `.md.gz`).
+    #[serde(default)]
+    output: Option<String>,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct ExportBundleParams {
+    /// Relative path to the note to start from
+    path: String,
+    /// How many links away from the note to follow (default: 1, the notes it links to)
+    #[serde(default = "default_bundle_depth")]
+    depth: usize,
+    /// Also list the paths of linked images and other attachments (without their content)
+    #[serde(default)]
+    include_attachments: bool,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+fn default_bundle_depth() -> usize {
+    1
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct ImportVaultParams {
+    /// Notes to write, in the format returned by export_vault
+    notes: Vec<notes::BundleNote>,
+    /// Replace notes that already exist instead of skipping them
+    #[serde(default)]
+    overwrite: bool,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct FindMissingMetadataParams {
+    /// Frontmatter fields every note should have (supports dot notation, e.g. "author.name")
+    required: Vec<String>,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct SearchByDateParams {
+    /// Frontmatter date field (e.g., "created", "updated")
+    field: String,
+    /// Inclusive start date, YYYY-MM-DD or RFC3339. Omit for no lower bound.
+    #[serde(default)]
+    from: Option<String>,
+    /// Inclusive end date, YYYY-MM-DD or RFC3339. Omit for no upper bound.
+    #[serde(default)]
+    to: Option<String>,
+    /// Maximum number of results to return (default: STUMBLING_DEFAULT_LIMIT, 20)
+    #[serde(default)]
+    limit: Option<usize>,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct ValidateFrontmatterParams {
+    /// Fields every note's frontmatter must have (dot notation for nested fields)
+    #[serde(default)]
+    required_fields: Vec<String>,
+    /// Expected type per field, e.g. {"tags": "array", "title": "string"}.
+    /// Types: string, number, bool, array, object
+    #[serde(default)]
+    field_types: BTreeMap<String, notes::FieldType>,
+    /// Flag notes that have no frontmatter at all (always done when fields are required)
+    #[serde(default)]
+    require_frontmatter: bool,
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct ListVaultsParams {}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct PingParams {
+    /// Named vault from STUMBLING_VAULTS to use (default: STUMBLING_ROOT)
+    #[serde(default)]
+    vault: Option<String>,
+}
+
+/// Serialization of search results, set by STUMBLING_OUTPUT_FORMAT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /// Indented JSON (default)
+    Pretty,
+    /// JSON on a single line
+    Compact,
+    /// One JSON object per line
+    Jsonl,
+}
+
+impl OutputFormat {
+    fn parse(value: &str) -> Result<Self> {
+        match value {
+            "pretty" => Ok(Self::Pretty),
+            "compact" => Ok(Self::Compact),
+            "jsonl" => Ok(Self::Jsonl),
+            other => anyhow::bail!(
+                "Invalid STUMBLING_OUTPUT_FORMAT: {} (expected pretty, compact, or jsonl)",
+                other
+            ),
+        }
+    }
+
+    /// Serialize results in this format. For `jsonl`, a list becomes one line per item;
+    /// an envelope with a `results` list becomes one line per result, followed by the rest
+    /// of the envelope on its own line if it has anything else.
+    fn render(self, value: &impl Serialize) -> serde_json::Result<String> {
+        match self {
+            Self::Pretty => serde_json::to_string_pretty(value),
+            Self::Compact => serde_json::to_string(value),
+            Self::Jsonl => {
+                let (items, rest) = match serde_json::to_value(value)? {
+                    serde_json::Value::Array(items) => (items, None),
+                    serde_json::Value::Object(mut envelope) => {
+                        match envelope.shift_remove("results") {
+                            Some(serde_json::Value::Array(items)) => {
+                                (items, (!envelope.is_empty()).then_some(envelope.into()))
+                            }
+                            Some(other) => {
+                                envelope.insert("results".to_string(), other);
+                                (vec![envelope.into()], None)
+                            }
+                            None => (vec![envelope.into()], None),
+                        }
+                    }
+                    other => (vec![other], None),
+                };
+
+                let lines = items
+                    .iter()
+                    .chain(rest.as_ref())
+                    .map(serde_json::to_string)
+                    .collect::<serde_json::Result<Vec<_>>>()?;
+                Ok(lines.join("\n"))
+            }
+        }
+    }
+}
+
+/// `notes::add_tag` or `notes::remove_tag`, as applied by `tag_notes`.
+type TagEdit = fn(&Path, &[PathBuf], &str, &notes::NoteOptions) -> Result<Vec<String>>;
+
+/// How long ping reuses a note count before walking the vault again.
+const NOTE_COUNT_TTL: Duration = Duration::from_secs(30);
+
+/// Results returned when a tool call doesn't pass `limit` and STUMBLING_DEFAULT_LIMIT isn't set.
+const DEFAULT_LIMIT: usize = 20;
+
+/// Ceiling on any requested `limit` when STUMBLING_MAX_LIMIT isn't set.
+const DEFAULT_MAX_LIMIT: usize = 1000;
+
+/// Parse STUMBLING_VAULTS, e.g. "work=/path/a,personal=/path/b".
+fn parse_vaults(spec: &str) -> Result<BTreeMap<String, PathBuf>> {
+    let mut vaults = BTreeMap::new();
+
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (name, root) = entry.split_once('=').with_context(|| {
+            format!(
+                "Invalid STUMBLING_VAULTS entry (expected name=path): {}",
+                entry
+            )
+        })?;
+        let (name, root) = (name.trim(), PathBuf::from(root.trim()));
+
+        if name.is_empty() {
+            anyhow::bail!("Invalid STUMBLING_VAULTS entry (empty name): {}", entry);
+        }
+        if !root.exists() {
+            anyhow::bail!("Vault {} does not exist: {}", name, root.display());
+        }
+        if vaults.insert(name.to_string(), root).is_some() {
+            anyhow::bail!("Duplicate vault name in STUMBLING_VAULTS: {}", name);
+        }
+    }
+
+    Ok(vaults)
+}
+
+/// Parse a colon-separated STUMBLING_ROOT, naming each root after its directory.
+fn parse_roots(spec: &str) -> Result<Vec<(String, PathBuf)>> {
+    let mut roots: Vec<(String, PathBuf)> = Vec::new();
+
+    for root in env::split_paths(spec).filter(|r| !r.as_os_str().is_empty()) {
+        if !root.exists() {
+            anyhow::bail!("STUMBLING_ROOT does not exist: {}", root.display());
+        }
+        let name = root
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .with_context(|| format!("STUMBLING_ROOT has no directory name: {}", root.display()))?;
+        if roots.iter().any(|(existing, _)| *existing == name) {
+            anyhow::bail!("Duplicate vault name in STUMBLING_ROOT: {}", name);
+        }
+        roots.push((name, root));
+    }
+
+    Ok(roots)
+}
+
+#[tool_router]
+impl StumblingServer {
+    pub fn new() -> Result<Self> {
+        let named = match env::var("STUMBLING_VAULTS") {
+            Ok(spec) => parse_vaults(&spec)?,
+            Err(_) => BTreeMap::new(),
+        };
+
+        let roots = match env::var("STUMBLING_ROOT") {
+            Ok(spec) => parse_roots(&spec)?,
+            Err(_) => Vec::new(),
+        };
+
+        // Without STUMBLING_ROOT, the first named vault is the default
+        let root = match roots.first() {
+            Some((_, root)) => root.clone(),
+            None => named
+                .values()
+                .next()
+                .cloned()
+                .context("STUMBLING_ROOT environment variable not set")?,
+        };
+
+        if !root.exists() {
+            anyhow::bail!("STUMBLING_ROOT does not exist: {}", root.display());
+        }
+
+        // With several roots, each is also a named vault
+        let search_all_vaults = roots.len() > 1;
+        let mut vaults = if search_all_vaults { roots } else { Vec::new() };
+        for (name, path) in named {
+            if vaults.iter().any(|(existing, _)| *existing == name) {
+                anyhow::bail!(
+                    "Vault {} is in both STUMBLING_ROOT and STUMBLING_VAULTS",
+                    name
+                );
+            }
+            vaults.push((name, path));
+        }
+
+        let parse_frontmatter = env::var("STUMBLING_PARSE_FRONTMATTER")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false);
+
+        // 0 disables the limit
+        let max_file_bytes = match env::var("STUMBLING_MAX_FILE_BYTES") {
+            Ok(v) => match v
+                .parse::<u64>()
+                .with_context(|| format!("Invalid STUMBLING_MAX_FILE_BYTES: {}", v))?
+            {
+                0 => None,
+                max => Some(max),
+            },
+            Err(_) => Some(notes::DEFAULT_MAX_FILE_BYTES),
+        };
+
+        let read_only = env::var("STUMBLING_READONLY")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false);
+        let watch = env::var("STUMBLING_WATCH")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false);
+
+        let output_format = match env::var("STUMBLING_OUTPUT_FORMAT") {
+            Ok(v) => OutputFormat::parse(&v)?,
+            Err(_) => OutputFormat::Pretty,
+        };
+
+        let mut note_options = notes::NoteOptions::default();
+        if let Ok(v) = env::var("STUMBLING_FRONTMATTER_FORMAT") {
+            note_options.frontmatter_format = notes::FrontmatterFormat::parse(&v)?;
+        }
+        if let Ok(v) = env::var("STUMBLING_FRONTMATTER_DETECT") {
+            let formats = notes::FrontmatterFormat::parse_list(&v)?;
+            // Otherwise frontmatter written to new notes would read back as body
+            if !formats.contains(&note_options.frontmatter_format) {
+                anyhow::bail!(
+                    "STUMBLING_FRONTMATTER_DETECT must include the STUMBLING_FRONTMATTER_FORMAT: {}",
+                    note_options.frontmatter_format.name()
+                );
+            }
+            note_options.frontmatter_detect = formats;
+        }
+
+        if let Ok(v) = env::var("STUMBLING_FS_RETRIES") {
+            note_options.fs_retries = v
+                .parse()
+                .with_context(|| format!("Invalid STUMBLING_FS_RETRIES: {}", v))?;
+        }
+
+        // 0 leaves note size unlimited
+        if let Ok(v) = env::var("STUMBLING_MAX_NOTE_BYTES") {
+            let max: u64 = v
+                .parse()
+                .with_context(|| format!("Invalid STUMBLING_MAX_NOTE_BYTES: {}", v))?;
+            note_options.max_note_bytes = (max > 0).then_some(max);
+        }
+
+        let schema = match env::var("STUMBLING_SCHEMA") {
+            Ok(path) => Some(Arc::new(notes::load_schema(Path::new(&path))?)),
+            Err(_) => None,
+        };
+
+        let stopwords = match env::var("STUMBLING_STOPWORDS") {
+            Ok(path) => Some(Arc::new(notes::load_stopwords(Path::new(&path))?)),
+            Err(_) => None,
+        };
+
+        let trash_dir = env::var("STUMBLING_TRASH_DIR")
+            .unwrap_or_else(|_| notes::DEFAULT_TRASH_DIR.to_string());
+        let archive_dir = env::var("STUMBLING_ARCHIVE_DIR")
+            .unwrap_or_else(|_| notes::DEFAULT_ARCHIVE_DIR.to_string());
+
+        // Checked now, since formatting a date with a bad format panics
+        let daily_format = env::var("STUMBLING_DAILY_FORMAT")
+            .unwrap_or_else(|_| notes::DEFAULT_DAILY_FORMAT.to_string());
+        notes::validate_daily_format(&daily_format)?;
+        let daily_template = match env::var("STUMBLING_DAILY_TEMPLATE") {
+            Ok(path) => std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read STUMBLING_DAILY_TEMPLATE: {}", path))?,
+            Err(_) => notes::DEFAULT_DAILY_TEMPLATE.to_string(),
+        };
+
+        // 0 keeps trash forever
+        let trash_retention = match env::var("STUMBLING_TRASH_RETENTION_DAYS") {
+            Ok(v) => match v
+                .parse::<u64>()
+                .with_context(|| format!("Invalid STUMBLING_TRASH_RETENTION_DAYS: {}", v))?
+            {
+                0 => None,
+                days => Some(Duration::from_secs(days.saturating_mul(24 * 60 * 60))),
+            },
+            Err(_) => None,
+        };
+
+        let max_limit = match env::var("STUMBLING_MAX_LIMIT") {
+            Ok(v) => v
+                .parse::<usize>()
+                .with_context(|| format!("Invalid STUMBLING_MAX_LIMIT: {}", v))?,
+            Err(_) => DEFAULT_MAX_LIMIT,
+        };
+        let default_limit = match env::var("STUMBLING_DEFAULT_LIMIT") {
+            Ok(v) => v
+                .parse::<usize>()
+                .with_context(|| format!("Invalid STUMBLING_DEFAULT_LIMIT: {}", v))?,
+            Err(_) => DEFAULT_LIMIT,
+        }
+        .min(max_limit);
+
+        let undo = env::var("STUMBLING_UNDO")
+            .map(|v| v != "false" && v != "0")
+            .unwrap_or(true);
+
+        let persist_index = env::var("STUMBLING_INDEX_PERSIST")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false);
+        let index = if persist_index {
+            // Reuse the index from the last run, reparsing only notes changed since
+            let mut index = index::NoteIndex::load(&root);
+            index.refresh(&root, &trash_dir, &note_options);
+            Some(Arc::new(Mutex::new(index)))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            vaults,
+            search_all_vaults,
+            parse_frontmatter,
+            max_file_bytes,
+            note_options,
+            index,
+            trash_dir,
+            trash_retention,
+            archive_dir,
+            daily_format,
+            daily_template,
+            read_only,
+            undo,
+            watch,
+            default_limit,
+            max_limit,
+            output_format,
+            schema,
+            stopwords,
+            note_counts: Arc::default(),
+            startup_log: Arc::default(),
+            ..Self::with_root(root)
+        })
+    }
+
+    /// Remove trash older than STUMBLING_TRASH_RETENTION_DAYS from every vault, unless the
+    /// server is read-only. Returns how many trash entries were removed.
+    fn prune_trash(&self) -> Result<usize> {
+        let Some(retention) = self.trash_retention.filter(|_| !self.read_only) else {
+            return Ok(0);
+        };
+
+        let roots: BTreeSet<&PathBuf> = std::iter::once(&self.root)
+            .chain(self.vaults.iter().map(|(_, root)| root))
+            .collect();
+        let mut pruned = 0;
+        for root in roots {
+            pruned += notes::prune_trash(root, &self.trash_dir, retention, &self.note_options)?;
+        }
+        Ok(pruned)
+    }
+
+    /// Write the frontmatter index to disk, if enabled and changed. Nothing is written to a
+    /// read-only server's vault, and a failed write only costs a reparse on the next start.
+    fn flush_index(&self) {
+        if let (Some(index), false) = (&self.index, self.read_only) {
+            let mut index = index
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let _ = index.save(&self.root, &self.note_options);
+        }
+    }
+
+    /// The frontmatter index, if enabled and covering this root.
+    fn index_for(&self, root: &Path) -> Option<&Mutex<index::NoteIndex>> {
+        self.index.as_deref().filter(|_| root == self.root)
+    }
+
+    /// Options for reading frontmatter across a vault with the server's settings.
+    fn metadata_options(&self) -> notes::MetadataSearchOptions {
+        notes::MetadataSearchOptions {
+            trash_dir: Some(self.trash_dir.clone()),
+            note: self.note_options.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// (path, frontmatter) pairs for the notes with frontmatter in every vault in scope,
+    /// read from the index where it covers the vault and hidden notes aren't wanted.
+    fn scope_metadata(
+        &self,
+        scope: &[notes::Vault],
+        options: &notes::MetadataSearchOptions,
+    ) -> Result<Vec<(String, serde_json::Value)>> {
+        let mut metadata = Vec::new();
+        for &(name, root) in scope {
+            let notes = match self.index_for(root).filter(|_| !options.include_hidden) {
+                Some(index) => self.refreshed_index(index).metadata(),
+                None => notes::note_metadata(root, options)?,
+            };
+            metadata.extend(
+                notes
+                    .into_iter()
+                    .map(|(path, meta)| (notes::vault_path(name, path), meta)),
+            );
+        }
+        Ok(metadata)
+    }
+
+    /// Frontmatter search over the vaults in scope, served from the index when enabled.
+    /// The index only covers the default root, so other vaults are always searched directly.
+    fn search_metadata_indexed(
+        &self,
+        scope: &[notes::Vault],
+        params: &SearchMetadataParams,
+        cancel: CancellationToken,
+    ) -> Result<Vec<notes::MetadataSearchResult>> {
+        let limit = self.limit(params.limit);
+        let options = notes::MetadataSearchOptions {
+            include_hidden: params.include_hidden,
+            cancel: Some(cancel),
+            ..self.metadata_options()
+        };
+        // The index doesn't cover hidden notes
+        if let [(None, root)] = scope {
+            if self.index_for(root).is_none() || params.include_hidden {
+                return notes::search_metadata(
+                    root,
+                    &params.field,
+                    &params.pattern,
+                    limit,
+                    params.sort_by.as_deref(),
+                    params.sort_desc,
+                    &options,
+                );
+            }
+        }
+
+        notes::search_metadata_in(
+            &self.scope_metadata(scope, &options)?,
+            &params.field,
+            &params.pattern,
+            limit,
+            params.sort_by.as_deref(),
+            params.sort_desc,
+        )
+    }
+
+    /// `notes::distinct_values` over the vaults in scope, served from the frontmatter index
+    /// where it covers a vault.
+    fn distinct_values_indexed(
+        &self,
+        scope: &[notes::Vault],
+        field: &str,
+    ) -> Result<Vec<notes::ValueCount>> {
+        if let [(None, root)] = scope {
+            if self.index_for(root).is_none() {
+                return Ok(notes::distinct_values(
+                    root,
+                    field,
+                    &self.trash_dir,
+                    &self.note_options,
+                ));
+            }
+        }
+        let metadata = self.scope_metadata(scope, &self.metadata_options())?;
+        Ok(notes::distinct_values_in(&metadata, field))
+    }
+
+    /// `notes::suggest_tags` over the vaults in scope, served from the frontmatter index
+    /// where it covers a vault.
+    fn suggest_tags_indexed(
+        &self,
+        scope: &[notes::Vault],
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<notes::ValueCount>> {
+        if let [(None, root)] = scope {
+            if self.index_for(root).is_none() {
+                return Ok(notes::suggest_tags(
+                    root,
+                    prefix,
+                    limit,
+                    &self.trash_dir,
+                    &self.note_options,
+                ));
+            }
+        }
+        let metadata = self.scope_metadata(scope, &self.metadata_options())?;
+        let tags = notes::distinct_values_in(&metadata, "tags");
+        Ok(notes::tags_with_prefix(tags, prefix, limit))
+    }
+
+    /// `notes::find_by_title`, served from the frontmatter index when it covers this root.
+    fn find_by_title_indexed(&self, root: &Path, title: &str) -> Result<Vec<String>> {
+        match self.index_for(root) {
+            Some(index) => {
+                let notes = self.refreshed_index(index).notes();
+                Ok(notes::find_by_title_in(&notes, title))
+            }
+            None => Ok(notes::find_by_title(
+                root,
+                title,
+                &self.trash_dir,
+                &self.note_options,
+            )),
+        }
+    }
+
+    /// Lock the index and bring it up to date with the default vault. Changes only mark it
+    /// dirty; flush_index writes it out at shutdown.
+    fn refreshed_index<'a>(
+        &self,
+        index: &'a Mutex<index::NoteIndex>,
+    ) -> std::sync::MutexGuard<'a, index::NoteIndex> {
+        let mut index = index
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        index.refresh(&self.root, &self.trash_dir, &self.note_options);
+        index
+    }
+
+    /// Root of the named vault, or the default root when no vault is given.
+    fn vault_root(&self, vault: Option<&str>) -> Result<&Path> {
+        match vault {
+            None => Ok(&self.root),
+            Some(name) => self
+                .vaults
+                .iter()
+                .find(|(vault_name, _)| vault_name == name)
+                .map(|(_, root)| root.as_path())
+                .ok_or_else(|| {
+                    notes::NoteError::NotFound(format!(
+                        "Unknown vault: {} (configured: {})",
+                        name,
+                        self.vaults
+                            .iter()
+                            .map(|(name, _)| name.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ))
+                    .into()
+                }),
+        }
+    }
+
+    /// The root a path is in, and the path relative to it. Without a vault, a `name:`
+    /// prefix naming a vault selects it, as in paths returned by search_notes.
+    fn route_path<'a, 'p>(
+        &'a self,
+        vault: Option<&str>,
+        path: &'p str,
+    ) -> Result<(&'a Path, &'p str)> {
+        if vault.is_none() {
+            if let Some((root, path)) = self.split_vault_prefix(path) {
+                return Ok((root, path));
+            }
+        }
+        Ok((self.vault_root(vault)?, path))
+    }
+
+    /// Like route_path for several paths, which must all be in the same vault. Paths
+    /// without a `name:` prefix are in the vault the others name, if any.
+    fn route_paths<'a, S: AsRef<str>>(
+        &'a self,
+        vault: Option<&str>,
+        paths: &[S],
+    ) -> Result<(&'a Path, Vec<String>)> {
+        let mut prefixed = None;
+        let mut relative = Vec::with_capacity(paths.len());
+        for path in paths {
+            let path = path.as_ref();
+            match self.split_vault_prefix(path).filter(|_| vault.is_none()) {
+                Some((root, rest)) => {
+                    if prefixed.is_some_and(|prefixed| prefixed != root) {
+                        anyhow::bail!("Notes must be in the same vault: {}", path);
+                    }
+                    prefixed = Some(root);
+                    relative.push(rest.to_string());
+                }
+                None => relative.push(path.to_string()),
+            }
+        }
+        match prefixed {
+            Some(root) => Ok((root, relative)),
+            None => Ok((self.vault_root(vault)?, relative)),
+        }
+    }
+
+    /// Resolve a note path within its vault (see route_path), rejecting paths that escape it.
+    fn note_path(&self, vault: Option<&str>, path: &str) -> Result<PathBuf> {
+        self.resolve_note(vault, path).map(|(_, path)| path)
+    }
+
+    /// Like note_path, but also returns the root the note belongs to.
+    fn resolve_note<'a>(&'a self, vault: Option<&str>, path: &str) -> Result<(&'a Path, PathBuf)> {
+        let (root, path) = self.route_path(vault, path)?;
+        Ok((root, notes::resolve_note_path(root, path)?))
+    }
+
+    /// Like resolve_note for several notes, which must all be in the same vault.
+    fn resolve_notes<'a, S: AsRef<str>>(
+        &'a self,
+        vault: Option<&str>,
+        paths: &[S],
+    ) -> Result<(&'a Path, Vec<PathBuf>)> {
+        let (root, paths) = self.route_paths(vault, paths)?;
+        let paths = paths
+            .iter()
+            .map(|path| notes::resolve_note_path(root, path))
+            .collect::<Result<_>>()?;
+        Ok((root, paths))
+    }
+
+    /// The vaults a vault-wide tool covers: the named vault or the default root, or every
+    /// vault when STUMBLING_ROOT lists several and no vault is given. Paths in a vault
+    /// given a name here are reported as `name:path`.
+    fn vault_scope(&self, vault: Option<&str>) -> Result<Vec<notes::Vault<'_>>> {
+        if vault.is_none() && self.search_all_vaults {
+            return Ok(self
+                .vaults
+                .iter()
+                .map(|(name, root)| (Some(name.as_str()), root.as_path()))
+                .collect());
+        }
+        Ok(vec![(None, self.vault_root(vault)?)])
+    }
+
+    /// Run a vault-wide operation over each vault in scope (see vault_scope), prefixing the
+    /// path `path` picks out of each item with its vault's name. Items stay in vault order.
+    fn across_vaults<T>(
+        &self,
+        vault: Option<&str>,
+        mut run: impl FnMut(&Path) -> Result<Vec<T>>,
+        path: impl Fn(&mut T) -> &mut String,
+    ) -> Result<Vec<T>> {
+        let mut all = Vec::new();
+        for (name, root) in self.vault_scope(vault)? {
+            let mut items = run(root)?;
+            for item in &mut items {
+                let path = path(item);
+                *path = notes::vault_path(name, std::mem::take(path));
+            }
+            all.extend(items);
+        }
+        Ok(all)
+    }
+
+    /// Shared body of add_tag and remove_tag. `summary` starts the message, before the
+    /// number of notes changed.
+    async fn tag_notes(
+        &self,
+        params: TagNotesParams,
+        edit: TagEdit,
+        failed: &str,
+        summary: &str,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        if params.path_glob.is_none() && params.paths.is_empty() {
+            return Ok(error_result(
+                ErrorCode::InvalidInput,
+                format!("{}: pass path_glob or paths", failed),
+            ));
+        }
+
+        let tag = |root: &Path, paths: &[String]| {
+            let selected =
+                notes::select_notes(root, params.path_glob.as_deref(), paths, &self.trash_dir)?;
+            let snapshots = self.snapshot_all_for_undo(root, &selected)?;
+            let changed = edit(root, &selected, &params.tag, &self.note_options)?;
+            self.record_writes_for_undo(root, snapshots, &changed);
+            Ok(changed)
+        };
+
+        // A glob alone selects notes in every vault in scope
+        let changed = if params.paths.is_empty() {
+            self.across_vaults(params.vault.as_deref(), |root| tag(root, &[]), |path| path)
+        } else {
+            self.route_paths(params.vault.as_deref(), &params.paths)
+                .and_then(|(root, paths)| tag(root, &paths))
+        };
+
+        match changed {
+            Ok(changed) => {
+                let msg = format!("{} {} notes", summary, changed.len());
+                if !changed.is_empty() {
+                    let _ = peer
+                        .notify_logging_message(LoggingMessageNotificationParam {
+                            level: LoggingLevel::Info,
+                            logger: Some("stumbling-rs".into()),
+                            data: msg.clone().into(),
+                        })
+                        .await;
+                }
+
+                let output = serde_json::json!({
+                    "message": msg,
+                    "changed": changed,
+                });
+                match serde_json::to_string_pretty(&output) {
+                    Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                    Err(e) => Ok(tool_error("Failed to serialize tag results", e)),
+                }
+            }
+            Err(e) => Ok(tool_error(failed, e)),
+        }
+    }
+
+    /// The limit to use for a call: the requested one, or the default, clamped to the max.
+    fn limit(&self, requested: Option<usize>) -> usize {
+        requested.unwrap_or(self.default_limit).min(self.max_limit)
+    }
+
+    /// Save a note's current content before changing it, so undo_last can restore it.
+    fn snapshot_for_undo(&self, root: &Path, path: &Path) -> Result<Option<String>> {
+        if !self.undo {
+            return Ok(None);
+        }
+        oplog::OperationLog::new(root, &self.note_options).snapshot(path)
+    }
+
+    /// Log a completed operation for undo_last.
+    /// The operation itself already succeeded, so a failure to log it isn't reported.
+    fn record_for_undo(&self, root: &Path, operation: oplog::Operation) {
+        if self.undo {
+            let _ = oplog::OperationLog::new(root, &self.note_options).record(operation);
+        }
+    }
+
+    /// snapshot_for_undo for every note a tool may rewrite, keyed by relative path.
+    fn snapshot_all_for_undo(
+        &self,
+        root: &Path,
+        paths: &[PathBuf],
+    ) -> Result<BTreeMap<String, Option<String>>> {
+        let mut snapshots = BTreeMap::new();
+        if self.undo {
+            for path in paths {
+                let snapshot = self.snapshot_for_undo(root, path)?;
+                snapshots.insert(notes::relative_path(root, path), snapshot);
+            }
+        }
+        Ok(snapshots)
+    }
+
+    /// Log a Write for each changed note that snapshot_all_for_undo saved.
+    fn record_writes_for_undo<'a>(
+        &self,
+        root: &Path,
+        mut snapshots: BTreeMap<String, Option<String>>,
+        changed: impl IntoIterator<Item = &'a String>,
+    ) {
+        for path in changed {
+            if let Some(snapshot) = snapshots.remove(path) {
+                let path = path.clone();
+                self.record_for_undo(root, oplog::Operation::Write { path, snapshot });
+            }
+        }
+    }
+
+    /// Split `name:path` into the root of the vault named `name` and the rest of the path.
+    fn split_vault_prefix<'a>(&self, path: &'a str) -> Option<(&Path, &'a str)> {
+        let (name, rest) = path.split_once(':')?;
+        self.vaults
+            .iter()
+            .find(|(vault_name, _)| vault_name == name)
+            .map(|(_, root)| (root.as_path(), rest))
+    }
+
+    /// Number of notes under root, recounted at most once per NOTE_COUNT_TTL.
+    fn cached_note_count(&self, root: &Path) -> usize {
+        let mut counts = self.note_counts.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((taken, count)) = counts.get(root) {
+            if taken.elapsed() < NOTE_COUNT_TTL {
+                return *count;
+            }
+        }
+
+        let count = notes::collect_markdown_files(root, &self.trash_dir).len();
+        counts.insert(root.to_path_buf(), (Instant::now(), count));
+        count
+    }
+
+    /// Create a server for the given root with default settings.
+    fn with_root(root: PathBuf) -> Self {
+        Self {
+            root,
+            vaults: Vec::new(),
+            search_all_vaults: false,
+            parse_frontmatter: false,
+            max_file_bytes: Some(notes::DEFAULT_MAX_FILE_BYTES),
+            note_options: notes::NoteOptions::default(),
+            index: None,
+            trash_dir: notes::DEFAULT_TRASH_DIR.to_string(),
+            trash_retention: None,
+            archive_dir: notes::DEFAULT_ARCHIVE_DIR.to_string(),
+            daily_format: notes::DEFAULT_DAILY_FORMAT.to_string(),
+            daily_template: notes::DEFAULT_DAILY_TEMPLATE.to_string(),
+            read_only: false,
+            undo: true,
+            watch: false,
+            default_limit: DEFAULT_LIMIT,
+            max_limit: DEFAULT_MAX_LIMIT,
+            output_format: OutputFormat::Pretty,
+            schema: None,
+            stopwords: None,
+            note_counts: Arc::default(),
+            startup_log: Arc::default(),
+            tool_router: Self::tool_router(),
+        }
+    }
+
+    /// List the named vaults configured via STUMBLING_ROOT and STUMBLING_VAULTS.
+    /// Pass a name as the `vault` parameter of other tools, or prefix paths with `name:`,
+    /// to use that vault.
+    #[tool(name = "list_vaults")]
+    async fn list_vaults(
+        &self,
+        _params: Parameters<ListVaultsParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let vaults: BTreeMap<&str, &PathBuf> = self
+            .vaults
+            .iter()
+            .map(|(name, root)| (name.as_str(), root))
+            .collect();
+        let output = serde_json::json!({
+            "default": self.root,
+            "vaults": vaults,
+        });
+
+        match serde_json::to_string_pretty(&output) {
+            Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+            Err(e) => Ok(tool_error("Failed to serialize vaults", e)),
+        }
+    }
+
+    /// Check that the server is up and the vault root is reachable.
+    /// Returns `{status, version, root, root_exists, readable, writable, note_count}`;
+    /// the count is cached briefly.
+    #[tool(name = "ping")]
+    async fn ping(&self, params: Parameters<PingParams>) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let root = tool_try!(self.vault_root(params.vault.as_deref()), "Ping failed");
+
+        // Only stat the root; the vault is walked at most once per NOTE_COUNT_TTL
+        let metadata = std::fs::metadata(root).ok();
+        let root_exists = metadata.as_ref().is_some_and(|m| m.is_dir());
+        let readable = root_exists && std::fs::read_dir(root).is_ok();
+        let writable = root_exists && metadata.is_some_and(|m| !m.permissions().readonly());
+        let note_count = if readable {
+            self.cached_note_count(root)
+        } else {
+            0
+        };
+        let output = serde_json::json!({
+            "status": if readable { "ok" } else { "unavailable" },
+            "version": env!("CARGO_PKG_VERSION"),
+            "root": root,
+            "root_exists": root_exists,
+            "readable": readable,
+            "writable": writable,
+            "note_count": note_count,
+        });
+
+        match serde_json::to_string_pretty(&output) {
+            Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+            Err(e) => Ok(tool_error("Failed to serialize ping", e)),
+        }
+    }
+
+    /// Read a markdown note from the vault.
+    /// Returns the note content, optionally with frontmatter parsed separately.
+    #[tool(name = "read_note")]
+    async fn read_note(
+        &self,
+        params: Parameters<ReadNoteParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let path = tool_try!(
+            self.note_path(params.vault.as_deref(), &params.path),
+            "Failed to read note"
+        );
+
+        let parse = params.parse.unwrap_or(self.parse_frontmatter);
+
+        let result = if params.strip_frontmatter_only {
+            notes::read_body(&path, &self.note_options)
+        } else if params.raw_frontmatter {
+            notes::read_raw_frontmatter(&path, &self.note_options)
+                .and_then(|raw| Ok(serde_json::to_string_pretty(&raw)?))
+        } else {
+            notes::read_note(&path, parse, &self.note_options)
+        };
+
+        match result {
+            Ok(content) => Ok(CallToolResult::success(vec![Content::text(content)])),
+            Err(e) => Ok(tool_error("Failed to read note", e)),
+        }
+    }
+
+    /// Get only a note's parsed frontmatter as a JSON object, or null if it has none.
+    /// Cheaper than read_note when only fields like tags or status are needed.
+    #[tool(name = "get_frontmatter")]
+    async fn get_frontmatter(
+        &self,
+        params: Parameters<GetFrontmatterParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let path = tool_try!(
+            self.note_path(params.vault.as_deref(), &params.path),
+            "Failed to read frontmatter"
+        );
+
+        match notes::get_frontmatter(&path, &self.note_options) {
+            Ok(meta) => match serde_json::to_string_pretty(&meta) {
+                Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                Err(e) => Ok(tool_error("Failed to serialize frontmatter", e)),
+            },
+            Err(e) => Ok(tool_error("Failed to read frontmatter", e)),
+        }
+    }
+
+    /// Notes modified after a Unix timestamp, oldest change first, with their mtimes, for
+    /// clients syncing incrementally. Pass the newest `mtime` back as `since` to poll.
+    #[tool(name = "changed_since")]
+    async fn changed_since(
+        &self,
+        params: Parameters<ChangedSinceParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let changed = self
+            .across_vaults(
+                params.vault.as_deref(),
+                |root| Ok(notes::changed_since(root, params.since, &self.trash_dir)),
+                |note| &mut note.path,
+            )
+            .map(|mut changed| {
+                changed.sort_by(|a, b| {
+                    a.mtime
+                        .total_cmp(&b.mtime)
+                        .then_with(|| a.path.cmp(&b.path))
+                });
+                changed
+            });
+
+        match changed {
+            Ok(changed) => match self.output_format.render(&changed) {
+                Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                Err(e) => Ok(tool_error("Failed to serialize changed notes", e)),
+            },
+            Err(e) => Ok(tool_error("Failed to list changed notes", e)),
+        }
+    }
+
+    /// Pick a note at random, for resurfacing old notes. Hidden notes and the trash are
+    /// never picked.
+    #[tool(name = "random_note")]
+    async fn random_note(
+        &self,
+        params: Parameters<RandomNoteParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let scope = tool_try!(
+            self.vault_scope(params.vault.as_deref()),
+            "Failed to pick a note"
+        );
+
+        let Some(path) = notes::random_note(&scope, params.seed, &self.trash_dir) else {
+            return Ok(error_result(
+                ErrorCode::NotFound,
+                "No notes in vault".to_string(),
+            ));
+        };
+        let mut output = serde_json::json!({ "path": path });
+        if params.include_content {
+            let content = tool_try!(
+                self.note_path(params.vault.as_deref(), &path)
+                    .and_then(|p| notes::read_note(&p, false, &self.note_options)),
+                "Failed to read note"
+            );
+            output["content"] = content.into();
+        }
+
+        match serde_json::to_string_pretty(&output) {
+            Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+            Err(e) => Ok(tool_error("Failed to serialize note", e)),
+        }
+    }
+
+    /// Get the byte range of a note's frontmatter block and the byte and line offsets where
+    /// its body begins, for splicing edits without reparsing.
+    #[tool(name = "read_note_spans")]
+    async fn read_note_spans(
+        &self,
+        params: Parameters<ReadNoteSpansParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let path = tool_try!(
+            self.note_path(params.vault.as_deref(), &params.path),
+            "Failed to read note"
+        );
+
+        match notes::read_note_spans(&path, &self.note_options) {
+            Ok(spans) => match serde_json::to_string_pretty(&spans) {
+                Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                Err(e) => Ok(tool_error("Failed to serialize spans", e)),
+            },
+            Err(e) => Ok(tool_error("Failed to read note", e)),
+        }
+    }
+
+    /// Render a note to HTML, including GFM tables and strikethrough.
+    /// Raw HTML in the note is escaped.
+    #[tool(name = "render_html")]
+    async fn render_html(
+        &self,
+        params: Parameters<RenderHtmlParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let path = tool_try!(
+            self.note_path(params.vault.as_deref(), &params.path),
+            "Failed to render note"
+        );
+
+        match notes::render_html(&path, params.include_frontmatter, &self.note_options) {
+            Ok(html) => Ok(CallToolResult::success(vec![Content::text(html)])),
+            Err(e) => Ok(tool_error("Failed to render note", e)),
+        }
+    }
+
+    /// Read a note by its title instead of its path.
+    /// If several notes share the title, returns `{ambiguous, matches}` listing their paths.
+    #[tool(name = "read_note_by_title")]
+    async fn read_note_by_title(
+        &self,
+        params: Parameters<ReadNoteByTitleParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let matches = tool_try!(
+            self.across_vaults(
+                params.vault.as_deref(),
+                |root| self.find_by_title_indexed(root, &params.title),
+                |path| path,
+            ),
+            "Failed to read note"
+        );
+
+        match matches.as_slice() {
+            [] => Ok(error_result(
+                ErrorCode::NotFound,
+                format!("No note found with title: {}", params.title),
+            )),
+            [path] => {
+                let parse = params.parse.unwrap_or(self.parse_frontmatter);
+                let content = self
+                    .note_path(params.vault.as_deref(), path)
+                    .and_then(|path| notes::read_note(&path, parse, &self.note_options));
+                match content {
+                    Ok(content) => Ok(CallToolResult::success(vec![Content::text(content)])),
+                    Err(e) => Ok(tool_error("Failed to read note", e)),
+                }
+            }
+            _ => {
+                let output = serde_json::json!({
+                    "ambiguous": true,
+                    "matches": matches,
+                });
+                match serde_json::to_string_pretty(&output) {
+                    Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                    Err(e) => Ok(tool_error("Failed to serialize matches", e)),
+                }
+            }
+        }
+    }
+
+    /// Get the vault's folders and notes as a nested tree.
+    /// Returns {name, path, is_dir, children} nodes, directories first.
+    #[tool(name = "get_tree")]
+    async fn get_tree(
+        &self,
+        params: Parameters<GetTreeParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let scope = tool_try!(
+            self.vault_scope(params.vault.as_deref()),
+            "Failed to read tree"
+        );
+
+        let tree = match scope.as_slice() {
+            [(None, root)] => notes::get_tree(root, &self.trash_dir),
+            vaults => notes::get_vault_trees(
+                &vaults
+                    .iter()
+                    .map(|&(name, root)| (name.unwrap_or_default(), root))
+                    .collect::<Vec<_>>(),
+                &self.trash_dir,
+            ),
+        };
+        match serde_json::to_string_pretty(&tree) {
+            Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+            Err(e) => Ok(tool_error("Failed to serialize tree", e)),
+        }
+    }
+
+    /// Get the heading outline (table of contents) of a note.
+    /// Returns nested {level, text, line_number, children} entries.
+    #[tool(name = "outline")]
+    async fn outline(&self, params: Parameters<OutlineParams>) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let path = tool_try!(
+            self.note_path(params.vault.as_deref(), &params.path),
+            "Failed to read outline"
+        );
+
+        match notes::outline(&path) {
+            Ok(entries) => match serde_json::to_string_pretty(&entries) {
+                Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                Err(e) => Ok(tool_error("Failed to serialize outline", e)),
+            },
+            Err(e) => Ok(tool_error("Failed to read outline", e)),
+        }
+    }
+
+    /// Read only the section of a note under the given heading.
+    /// The section ends at the next heading of the same or higher level.
+    #[tool(name = "read_section")]
+    async fn read_section(
+        &self,
+        params: Parameters<ReadSectionParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let path = tool_try!(
+            self.note_path(params.vault.as_deref(), &params.path),
+            "Failed to read section"
+        );
+
+        match notes::read_section(&path, &params.heading) {
+            Ok(section) => match serde_json::to_string_pretty(&section) {
+                Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                Err(e) => Ok(tool_error("Failed to serialize section", e)),
+            },
+            Err(e) => Ok(tool_error("Failed to read section", e)),
+        }
+    }
+
+    /// List every outbound link in a note: wikilinks, markdown links, and bare URLs.
+    /// Internal links are resolved to note paths where possible.
+    #[tool(name = "note_links")]
+    async fn note_links(
+        &self,
+        params: Parameters<NoteLinksParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let (root, path) = tool_try!(
+            self.resolve_note(params.vault.as_deref(), &params.path),
+            "Failed to read links"
+        );
+
+        match notes::note_links(root, &path, &self.trash_dir) {
+            Ok(links) => match serde_json::to_string_pretty(&links) {
+                Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                Err(e) => Ok(tool_error("Failed to serialize links", e)),
+            },
+            Err(e) => Ok(tool_error("Failed to read links", e)),
+        }
+    }
+
+    /// Hex SHA-256 of a note's file bytes, for sync tools.
+    /// With `store`, a hash of the body alone is also written to the `checksum` field.
+    #[tool(name = "note_hash")]
+    async fn note_hash(
+        &self,
+        params: Parameters<NoteHashParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        if params.store && self.read_only {
+            return Ok(error_result(
+                ErrorCode::ReadOnly,
+                "Server is read-only: note_hash cannot store a checksum".to_string(),
+            ));
+        }
+        let path = tool_try!(
+            self.note_path(params.vault.as_deref(), &params.path),
+            "Failed to hash note"
+        );
+
+        let checksum = if params.store {
+            Some(tool_try!(
+                notes::store_checksum(&path, &self.note_options),
+                "Failed to store checksum"
+            ))
+        } else {
+            None
+        };
+
+        match notes::note_hash(&path) {
+            Ok(hash) => {
+                let output = serde_json::json!({ "hash": hash, "checksum": checksum });
+                match serde_json::to_string_pretty(&output) {
+                    Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                    Err(e) => Ok(tool_error("Failed to serialize hash", e)),
+                }
+            }
+            Err(e) => Ok(tool_error("Failed to hash note", e)),
+        }
+    }
+
+    /// Read several markdown notes in one call.
+    /// Returns a JSON array of {path, content} or {path, error} entries in request order.
+    #[tool(name = "read_many")]
+    async fn read_many(
+        &self,
+        params: Parameters<ReadManyParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let (root, paths) = tool_try!(
+            self.route_paths(params.vault.as_deref(), &params.paths),
+            "Failed to read notes"
+        );
+        let entries = notes::read_many(root, &paths, self.parse_frontmatter, &self.note_options);
+
+        match serde_json::to_string_pretty(&entries) {
+            Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+            Err(e) => Ok(tool_error("Failed to serialize notes", e)),
+        }
+    }
+
+    /// Search for notes containing the given query.
+    /// Uses parallel processing for fast search across all markdown files.
+    #[tool(name = "search_notes")]
+    async fn search_notes(
+        &self,
+        params: Parameters<SearchNotesParams>,
+        ct: CancellationToken,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let scope = tool_try!(self.vault_scope(params.vault.as_deref()), "Search failed");
+
+        let limit = self.limit(params.limit);
+        let options = notes::SearchOptions {
+            max_file_bytes: self.max_file_bytes,
+            body_relative_lines: params.body_relative_lines,
+            snippet_len: params.snippet_len,
+            exclude: params.exclude,
+            lossy_utf8: params.lossy_utf8,
+            sort: params.sort,
+            include_hidden: params.include_hidden,
+            max_per_file: params.max_per_file,
+            cancel: Some(ct),
+            search_paths: params.search_paths,
+            include_stats: params.include_stats,
+            mode: params.mode,
+            trash_dir: Some(self.trash_dir.clone()),
+        };
+
+        // Without a vault, several roots are searched together with `name:` prefixed paths
+        let report = notes::search_notes(&scope, &params.query, limit, &options);
+
+        match report {
+            Ok(report) => match self.output_format.render(&report) {
+                Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                Err(e) => Ok(tool_error("Failed to serialize search results", e)),
+            },
+            Err(e) => Ok(tool_error("Search failed", e)),
+        }
+    }
+
+    /// Count every distinct value of a frontmatter field across the vault.
+    /// Array fields count each element. Sorted by count, most common first.
+    #[tool(name = "distinct_values")]
+    async fn distinct_values(
+        &self,
+        params: Parameters<DistinctValuesParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let values = self
+            .vault_scope(params.vault.as_deref())
+            .and_then(|scope| self.distinct_values_indexed(&scope, &params.field));
+
+        match values {
+            Ok(values) => match self.output_format.render(&values) {
+                Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                Err(e) => Ok(tool_error("Failed to serialize values", e)),
+            },
+            Err(e) => Ok(tool_error("Failed to count values", e)),
+        }
+    }
+
+    /// Find notes with identical bodies, ignoring frontmatter.
+    /// Returns groups of duplicate paths; notes without a duplicate are omitted.
+    #[tool(name = "find_duplicates")]
+    async fn find_duplicates(
+        &self,
+        params: Parameters<FindDuplicatesParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let scope = tool_try!(
+            self.vault_scope(params.vault.as_deref()),
+            "Failed to find duplicates"
+        );
+
+        // Each vault is checked on its own, so duplicates never span vaults
+        let groups: Vec<Vec<String>> = scope
+            .iter()
+            .flat_map(|&(name, root)| {
+                notes::find_duplicates(root, params.fuzzy, &self.trash_dir, &self.note_options)
+                    .into_iter()
+                    .map(move |group| {
+                        group
+                            .into_iter()
+                            .map(|path| notes::vault_path(name, path))
+                            .collect()
+                    })
+            })
+            .collect();
+        match self.output_format.render(&groups) {
+            Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+            Err(e) => Ok(tool_error("Failed to serialize duplicates", e)),
+        }
+    }
+
+    /// Find notes that no other note links to, by wikilink or relative markdown link.
+    /// Notes matching exclude_glob aren't reported, though their links still count.
+    #[tool(name = "find_orphans")]
+    async fn find_orphans(
+        &self,
+        params: Parameters<FindOrphansParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let orphans = self.across_vaults(
+            params.vault.as_deref(),
+            |root| notes::find_orphans(root, params.exclude_glob.as_deref(), &self.trash_dir),
+            |path| path,
+        );
+
+        match orphans {
+            Ok(orphans) => match self.output_format.render(&orphans) {
+                Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                Err(e) => Ok(tool_error("Failed to serialize orphans", e)),
+            },
+            Err(e) => Ok(tool_error("Failed to find orphans", e)),
+        }
+    }
+
+    /// Complete a tag: existing tags starting with the prefix, most used first.
+    #[tool(name = "suggest_tags")]
+    async fn suggest_tags(
+        &self,
+        params: Parameters<SuggestTagsParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let tags = self.vault_scope(params.vault.as_deref()).and_then(|scope| {
+            self.suggest_tags_indexed(&scope, &params.prefix, self.limit(params.limit))
+        });
+
+        match tags {
+            Ok(tags) => match self.output_format.render(&tags) {
+                Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                Err(e) => Ok(tool_error("Failed to serialize tags", e)),
+            },
+            Err(e) => Ok(tool_error("Failed to suggest tags", e)),
+        }
+    }
+
+    /// The most frequent words in a note's prose with their counts, for suggesting tags.
+    /// Stopwords, numbers, code and link targets are left out.
+    #[tool(name = "keywords")]
+    async fn keywords(
+        &self,
+        params: Parameters<KeywordsParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let path = tool_try!(
+            self.note_path(params.vault.as_deref(), &params.path),
+            "Failed to extract keywords"
+        );
+
+        let limit = self.limit(params.limit);
+        match notes::keywords(&path, limit, self.stopwords.as_deref(), &self.note_options) {
+            Ok(terms) => match self.output_format.render(&terms) {
+                Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                Err(e) => Ok(tool_error("Failed to serialize keywords", e)),
+            },
+            Err(e) => Ok(tool_error("Failed to extract keywords", e)),
+        }
+    }
+
+    /// List notes by their frontmatter tags: those with all (or any) of the given tags,
+    /// minus those with an excluded tag. Sorted by path.
+    #[tool(name = "notes_by_tags")]
+    async fn notes_by_tags(
+        &self,
+        params: Parameters<NotesByTagsParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        if params.tags.is_empty() {
+            return Ok(error_result(
+                ErrorCode::InvalidInput,
+                "At least one tag is required".to_string(),
+            ));
+        }
+        let paths = tool_try!(
+            self.across_vaults(
+                params.vault.as_deref(),
+                |root| {
+                    Ok(notes::notes_by_tags(
+                        root,
+                        &params.tags,
+                        params.mode,
+                        &params.exclude,
+                        &self.trash_dir,
+                        &self.note_options,
+                    ))
+                },
+                |path| path,
+            ),
+            "Failed to list notes by tags"
+        );
+        match self.output_format.render(&paths) {
+            Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+            Err(e) => Ok(tool_error("Failed to serialize notes", e)),
+        }
+    }
+
+    /// Pairs of frontmatter tags used together on notes, with how many notes share both,
+    /// most shared first. Useful for drawing a tag graph.
+    #[tool(name = "tag_cooccurrence")]
+    async fn tag_cooccurrence(
+        &self,
+        params: Parameters<TagCooccurrenceParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let scope = tool_try!(
+            self.vault_scope(params.vault.as_deref()),
+            "Failed to count tag pairs"
+        );
+
+        let roots: Vec<&Path> = scope.iter().map(|&(_, root)| root).collect();
+        let pairs = notes::tag_cooccurrence(
+            &roots,
+            self.limit(params.limit),
+            &self.trash_dir,
+            &self.note_options,
+        );
+        match self.output_format.render(&pairs) {
+            Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+            Err(e) => Ok(tool_error("Failed to serialize tag pairs", e)),
+        }
+    }
+
+    /// Export every note as a JSON array of `{path, metadata, body}`.
+    /// With `output`, the bundle is streamed to that file instead of returned.
+    #[tool(name = "export_vault")]
+    async fn export_vault(
+        &self,
+        params: Parameters<ExportVaultParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        if params.output.is_some() && self.read_only {
+            return Ok(error_result(
+                ErrorCode::ReadOnly,
+                "Server is read-only: export_vault cannot write a file".to_string(),
+            ));
+        }
+        let scope = tool_try!(
+            self.vault_scope(params.vault.as_deref()),
+            "Failed to export vault"
+        );
+
+        let Some(output) = params.output else {
+            let mut bundle = Vec::new();
+            return match notes::export_vault(
+                &scope,
+                params.include_body,
+                &mut bundle,
+                &self.trash_dir,
+                &self.note_options,
+            ) {
+                Ok(_) => Ok(CallToolResult::success(vec![Content::text(
+                    String::from_utf8_lossy(&bundle),
+                )])),
+                Err(e) => Ok(tool_error("Failed to export vault", e)),
+            };
+        };
+
+        let result = self
+            .note_path(params.vault.as_deref(), &output)
+            .and_then(|path| {
+                notes::export_vault_to_file(
+                    &scope,
+                    params.include_body,
+                    &path,
+                    &self.trash_dir,
+                    &self.note_options,
+                )
+            });
+
+        match result {
+            Ok(count) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Exported {} notes to {}",
+                count, output
+            ))])),
+            Err(e) => Ok(tool_error("Failed to export vault", e)),
+        }
+    }
+
+    /// Export a note and the notes it links to, transitively up to `depth` links away.
+    /// Returns `{notes, attachments}`, where notes maps each path to its content.
+    #[tool(name = "export_bundle")]
+    async fn export_bundle(
+        &self,
+        params: Parameters<ExportBundleParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let bundle = self
+            .resolve_note(params.vault.as_deref(), &params.path)
+            .and_then(|(root, path)| {
+                notes::export_bundle(
+                    root,
+                    &path,
+                    params.depth,
+                    params.include_attachments,
+                    &self.trash_dir,
+                )
+            });
+
+        match bundle {
+            Ok(bundle) => match serde_json::to_string_pretty(&bundle) {
+                Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                Err(e) => Ok(tool_error("Failed to serialize bundle", e)),
+            },
+            Err(e) => Ok(tool_error("Failed to export bundle", e)),
+        }
+    }
+
+    /// Write notes from an export_vault bundle, e.g. to migrate between vaults.
+    /// Returns `{created, overwritten, skipped, failed}`.
+    #[tool(name = "import_vault")]
+    async fn import_vault(
+        &self,
+        params: Parameters<ImportVaultParams>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        // Notes are routed by a `name:` prefix, as export_vault gives paths across vaults.
+        // Every path is checked before anything is written.
+        let mut batches: BTreeMap<&Path, (Vec<String>, Vec<notes::BundleNote>)> = BTreeMap::new();
+        for mut note in params.notes {
+            let (root, path) = tool_try!(
+                self.route_path(params.vault.as_deref(), &note.path),
+                "Failed to import notes"
+            );
+            let path = path.to_string();
+            tool_try!(
+                notes::resolve_note_path(root, &path),
+                "Failed to import notes"
+            );
+            let (originals, batch) = batches.entry(root).or_default();
+            originals.push(std::mem::replace(&mut note.path, path));
+            batch.push(note);
+        }
+
+        let mut report = notes::ImportReport::default();
+        for (root, (originals, batch)) in &batches {
+            let imported = tool_try!(
+                notes::import_vault(root, batch, params.overwrite, &self.note_options),
+                "Failed to import notes"
+            );
+            report.created += imported.created;
+            report.overwritten += imported.overwritten;
+            report.skipped += imported.skipped;
+            // Failures are reported by the path they were given as
+            report
+                .failed
+                .extend(imported.failed.into_iter().map(|mut failed| {
+                    if let Some(i) = batch.iter().position(|note| note.path == failed.path) {
+                        failed.path = originals[i].clone();
+                    }
+                    failed
+                }));
+        }
+
+        let _ = peer
+            .notify_logging_message(LoggingMessageNotificationParam {
+                level: LoggingLevel::Info,
+                logger: Some("stumbling-rs".into()),
+                data: format!(
+                    "Imported {} notes ({} overwritten, {} skipped, {} failed)",
+                    report.created + report.overwritten,
+                    report.overwritten,
+                    report.skipped,
+                    report.failed.len()
+                )
+                .into(),
+            })
+            .await;
+
+        match serde_json::to_string_pretty(&report) {
+            Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+            Err(e) => Ok(tool_error("Failed to serialize import report", e)),
+        }
+    }
+
+    /// List notes missing any of the required frontmatter fields, with the fields absent.
+    /// Notes without frontmatter are missing every field.
+    #[tool(name = "find_missing_metadata")]
+    async fn find_missing_metadata(
+        &self,
+        params: Parameters<FindMissingMetadataParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let missing = tool_try!(
+            self.across_vaults(
+                params.vault.as_deref(),
+                |root| Ok(notes::find_missing_metadata(
+                    root,
+                    &params.required,
+                    &self.trash_dir,
+                    &self.note_options
+                )),
+                |note| &mut note.path,
+            ),
+            "Failed to check metadata"
+        );
+        match self.output_format.render(&missing) {
+            Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+            Err(e) => Ok(tool_error("Failed to serialize results", e)),
+        }
+    }
+
+    /// Search notes by frontmatter metadata field.
+    /// Supports nested fields with dot notation (e.g., "author.name").
+    #[tool(name = "search_metadata")]
+    async fn search_metadata(
+        &self,
+        params: Parameters<SearchMetadataParams>,
+        ct: CancellationToken,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let scope = tool_try!(
+            self.vault_scope(params.vault.as_deref()),
+            "Metadata search failed"
+        );
+
+        match self.search_metadata_indexed(&scope, &params, ct) {
+            Ok(results) => match self.output_format.render(&results) {
+                Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                Err(e) => Ok(tool_error("Failed to serialize metadata search results", e)),
+            },
+            Err(e) => Ok(tool_error("Metadata search failed", e)),
+        }
+    }
+
+    /// Search notes by a frontmatter date field within an inclusive range.
+    /// Accepts YYYY-MM-DD and RFC3339 dates; unparseable values are skipped.
+    #[tool(name = "search_by_date")]
+    async fn search_by_date(
+        &self,
+        params: Parameters<SearchByDateParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let limit = self.limit(params.limit);
+        // Each vault's results are in path order, so the first `limit` overall are kept
+        let results = self
+            .across_vaults(
+                params.vault.as_deref(),
+                |root| {
+                    notes::search_by_date(
+                        root,
+                        &params.field,
+                        params.from.as_deref(),
+                        params.to.as_deref(),
+                        limit,
+                        &self.trash_dir,
+                        &self.note_options,
+                    )
+                },
+                |result| &mut result.path,
+            )
+            .map(|mut results| {
+                results.truncate(limit);
+                results
+            });
+
+        match results {
+            Ok(results) => match self.output_format.render(&results) {
+                Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                Err(e) => Ok(tool_error("Failed to serialize date search results", e)),
+            },
+            Err(e) => Ok(tool_error("Date search failed", e)),
+        }
+    }
+
+    /// Validate frontmatter across the vault.
+    /// Reports notes missing required fields, with fields of the wrong type, or failing
+    /// the STUMBLING_SCHEMA JSON Schema.
+    #[tool(name = "validate_frontmatter")]
+    async fn validate_frontmatter(
+        &self,
+        params: Parameters<ValidateFrontmatterParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let results = self.across_vaults(
+            params.vault.as_deref(),
+            |root| {
+                notes::validate_frontmatter(
+                    root,
+                    &params.required_fields,
+                    &params.field_types,
+                    self.schema.as_deref(),
+                    params.require_frontmatter,
+                    &self.trash_dir,
+                    &self.note_options,
+                )
+            },
+            |result| &mut result.path,
+        );
+
+        match results {
+            Ok(results) => match serde_json::to_string_pretty(&results) {
+                Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                Err(e) => Ok(tool_error("Failed to serialize validation results", e)),
+            },
+            Err(e) => Ok(tool_error("Frontmatter validation failed", e)),
+        }
+    }
+
+    /// Create, overwrite, or append to a markdown note, depending on `mode`.
+    /// Creates parent directories if they don't exist.
+    /// If metadata is provided, it's written as frontmatter in the note's existing format,
+    /// or STUMBLING_FRONTMATTER_FORMAT (YAML by default) for a note without any.
+    #[tool(name = "write_note")]
+    async fn write_note(
+        &self,
+        params: Parameters<WriteNoteParams>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let (root, path) = tool_try!(
+            self.resolve_note(params.vault.as_deref(), &params.path),
+            "Failed to write note"
+        );
+        let exists = path.exists();
+        let appending = params.mode == notes::WriteMode::Append;
+
+        if let Some(expected) = &params.expected_hash {
+            if !exists {
+                return Ok(error_result(
+                    ErrorCode::Conflict,
+                    format!(
+                        "Expected hash {} but {} does not exist",
+                        expected, params.path
+                    ),
+                ));
+            }
+            match notes::note_hash(&path) {
+                Ok(hash) if hash == *expected => {}
+                Ok(hash) => {
+                    return Ok(error_result(
+                        ErrorCode::Conflict,
+                        format!(
+                            "{} has changed (expected hash {}, found {})",
+                            params.path, expected, hash
+                        ),
+                    ));
+                }
+                Err(e) => return Ok(tool_error("Failed to write note", e)),
+            }
+        }
+
+        let metadata = if params.merge_metadata && exists && !appending {
+            match notes::get_frontmatter(&path, &self.note_options) {
+                Ok(Some(existing)) => Some(notes::deep_merge(
+                    existing,
+                    params.metadata.unwrap_or_else(|| serde_json::json!({})),
+                )),
+                Ok(None) => params.metadata,
+                Err(e) => return Ok(tool_error("Failed to merge metadata", e)),
+            }
+        } else {
+            params.metadata
+        };
+
+        // Format content with frontmatter if metadata is provided
+        let content = match metadata {
+            Some(meta) if !(appending && exists) => notes::format_with_frontmatter_for(
+                &path,
+                &meta,
+                &params.content,
+                &self.note_options,
+            ),
+            _ => params.content.clone(),
+        };
+
+        if params.preview {
+            let before = std::fs::read_to_string(&path).unwrap_or_default();
+            return match notes::preview_write(&path, &content, params.mode) {
+                Ok(after) => {
+                    let diff = notes::unified_diff(&params.path, &before, &after);
+                    let output = if diff.is_empty() {
+                        format!("No changes to {}", params.path)
+                    } else {
+                        diff
+                    };
+                    Ok(CallToolResult::success(vec![Content::text(output)]))
+                }
+                Err(e) => Ok(tool_error("Failed to preview write", e)),
+            };
+        }
+
+        // Old content for the diff; new notes have nothing to diff against
+        let before = if params.return_diff && exists {
+            std::fs::read_to_string(&path).ok()
+        } else {
+            None
+        };
+
+        let snapshot = tool_try!(self.snapshot_for_undo(root, &path), "Failed to write note");
+
+        let result = match params.mode {
+            notes::WriteMode::Overwrite => notes::write_note(&path, &content, &self.note_options),
+            notes::WriteMode::CreateNew => notes::create_note(&path, &content, &self.note_options),
+            notes::WriteMode::Append => notes::append_note(&path, &content, &self.note_options),
+        };
+
+        match result {
+            Ok(()) => {
+                self.record_for_undo(
+                    root,
+                    oplog::Operation::Write {
+                        path: notes::relative_path(root, &path),
+                        snapshot,
+                    },
+                );
+                let action = match (appending, exists) {
+                    (true, true) => "Appended to",
+                    (false, true) => "Overwrote",
+                    (_, false) => "Created",
+                };
+                let msg = format!("{} {}", action, params.path);
+
+                let _ = peer
+                    .notify_logging_message(LoggingMessageNotificationParam {
+                        level: LoggingLevel::Info,
+                        logger: Some("stumbling-rs".into()),
+                        data: msg.clone().into(),
+                    })
+                    .await;
+
+                let mut content = vec![Content::text(msg)];
+                if let Some(before) = before {
+                    let after = std::fs::read_to_string(&path).unwrap_or_default();
+                    let diff = notes::unified_diff(&params.path, &before, &after);
+                    if !diff.is_empty() {
+                        content.push(Content::text(diff));
+                    }
+                }
+                Ok(CallToolResult::success(content))
+            }
+            Err(e) => Ok(tool_error("Failed to write note", e)),
+        }
+    }
+
+    /// Replace a line range of a markdown note, leaving the rest untouched.
+    /// Line numbers are 1-indexed and inclusive.
+    #[tool(name = "patch_note")]
+    async fn patch_note(
+        &self,
+        params: Parameters<PatchNoteParams>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let (root, path) = tool_try!(
+            self.resolve_note(params.vault.as_deref(), &params.path),
+            "Failed to patch note"
+        );
+        let snapshot = tool_try!(self.snapshot_for_undo(root, &path), "Failed to patch note");
+
+        match notes::patch_note(
+            &path,
+            params.start_line,
+            params.end_line,
+            &params.replacement,
+            &self.note_options,
+        ) {
+            Ok(()) => {
+                self.record_for_undo(
+                    root,
+                    oplog::Operation::Write {
+                        path: notes::relative_path(root, &path),
+                        snapshot,
+                    },
+                );
+                let msg = format!(
+                    "Patched {} (lines {}-{})",
+                    params.path, params.start_line, params.end_line
+                );
+
+                let _ = peer
+                    .notify_logging_message(LoggingMessageNotificationParam {
+                        level: LoggingLevel::Info,
+                        logger: Some("stumbling-rs".into()),
+                        data: msg.clone().into(),
+                    })
+                    .await;
+
+                Ok(CallToolResult::success(vec![Content::text(msg)]))
+            }
+            Err(e) => Ok(tool_error("Failed to patch note", e)),
+        }
+    }
+
+    /// Replace exact text in a note, without needing line numbers.
+    /// Errors if the text isn't found. Returns the number of replacements.
+    #[tool(name = "edit_note")]
+    async fn edit_note(
+        &self,
+        params: Parameters<EditNoteParams>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let (root, path) = tool_try!(
+            self.resolve_note(params.vault.as_deref(), &params.path),
+            "Failed to edit note"
+        );
+        let snapshot = tool_try!(self.snapshot_for_undo(root, &path), "Failed to edit note");
+
+        match notes::replace_first(
+            &path,
+            &params.find,
+            &params.replace,
+            params.occurrence,
+            &self.note_options,
+        ) {
+            Ok(count) => {
+                self.record_for_undo(
+                    root,
+                    oplog::Operation::Write {
+                        path: notes::relative_path(root, &path),
+                        snapshot,
+                    },
+                );
+                let msg = format!("Made {} replacements in {}", count, params.path);
+
+                let _ = peer
+                    .notify_logging_message(LoggingMessageNotificationParam {
+                        level: LoggingLevel::Info,
+                        logger: Some("stumbling-rs".into()),
+                        data: msg.clone().into(),
+                    })
+                    .await;
+
+                Ok(CallToolResult::success(vec![Content::text(msg)]))
+            }
+            Err(e) => Ok(tool_error("Failed to edit note", e)),
+        }
+    }
+
+    /// Cross-link notes: list a wikilink to `to` under a "Related" (or other) heading in
+    /// `from`, creating the section if needed. Notes that already link are left alone.
+    #[tool(name = "link_notes")]
+    async fn link_notes(
+        &self,
+        params: Parameters<LinkNotesParams>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let (root, mut paths) = tool_try!(
+            self.resolve_notes(params.vault.as_deref(), &[&params.from, &params.to]),
+            "Failed to link notes"
+        );
+        let (to, from) = (paths.pop().unwrap(), paths.pop().unwrap());
+        let mut snapshots = Vec::new();
+        for path in [&from, &to] {
+            match self.snapshot_for_undo(root, path) {
+                Ok(snapshot) => snapshots.push((notes::relative_path(root, path), snapshot)),
+                Err(e) => return Ok(tool_error("Failed to link notes", e)),
+            }
+        }
+
+        match notes::link_notes(
+            root,
+            &from,
+            &to,
+            &params.heading,
+            params.bidirectional,
+            &self.trash_dir,
+            &self.note_options,
+        ) {
+            Ok(changed) => {
+                for (path, snapshot) in snapshots {
+                    if changed.contains(&path) {
+                        self.record_for_undo(root, oplog::Operation::Write { path, snapshot });
+                    }
+                }
+
+                let msg = format!("Linked {} to {}", params.from, params.to);
+                if !changed.is_empty() {
+                    let _ = peer
+                        .notify_logging_message(LoggingMessageNotificationParam {
+                            level: LoggingLevel::Info,
+                            logger: Some("stumbling-rs".into()),
+                            data: msg.clone().into(),
+                        })
+                        .await;
+                }
+
+                let output = serde_json::json!({
+                    "message": msg,
+                    "changed": changed,
+                });
+                match serde_json::to_string_pretty(&output) {
+                    Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                    Err(e) => Ok(tool_error("Failed to serialize link results", e)),
+                }
+            }
+            Err(e) => Ok(tool_error("Failed to link notes", e)),
+        }
+    }
+
+    /// Regex find-and-replace across the vault.
+    /// Returns per-file substitution counts. Use dry_run to preview without writing.
+    #[tool(name = "replace_in_notes")]
+    async fn replace_in_notes(
+        &self,
+        params: Parameters<ReplaceInNotesParams>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let replaced = self.across_vaults(
+            params.vault.as_deref(),
+            |root| {
+                let replace = |dry_run| {
+                    notes::replace_in_notes(
+                        root,
+                        &params.pattern,
+                        &params.replacement,
+                        params.path_glob.as_deref(),
+                        dry_run,
+                        &self.trash_dir,
+                        &self.note_options,
+                    )
+                };
+                if params.dry_run || !self.undo {
+                    return replace(params.dry_run);
+                }
+
+                // A dry run first finds the notes to snapshot before any is rewritten
+                let planned: Vec<PathBuf> = replace(true)?
+                    .iter()
+                    .map(|result| root.join(&result.path))
+                    .collect();
+                let snapshots = self.snapshot_all_for_undo(root, &planned)?;
+                let results = replace(false)?;
+                self.record_writes_for_undo(root, snapshots, results.iter().map(|r| &r.path));
+                Ok(results)
+            },
+            |result| &mut result.path,
+        );
+
+        match replaced {
+            Ok(results) => {
+                if !params.dry_run && !results.is_empty() {
+                    let total: usize = results.iter().map(|r| r.replacements).sum();
+                    let msg = format!("Replaced {} occurrences in {} notes", total, results.len());
+
+                    let _ = peer
+                        .notify_logging_message(LoggingMessageNotificationParam {
+                            level: LoggingLevel::Info,
+                            logger: Some("stumbling-rs".into()),
+                            data: msg.into(),
+                        })
+                        .await;
+                }
+
+                match serde_json::to_string_pretty(&results) {
+                    Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                    Err(e) => Ok(tool_error("Failed to serialize replace results", e)),
+                }
+            }
+            Err(e) => Ok(tool_error("Replace failed", e)),
+        }
+    }
+
+    /// Rename a tag in the frontmatter `tags` field of every note.
+    /// Body text (e.g., "#tag" mentions) is not modified.
+    #[tool(name = "rename_tag")]
+    async fn rename_tag(
+        &self,
+        params: Parameters<RenameTagParams>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let renamed = self.across_vaults(
+            params.vault.as_deref(),
+            |root| {
+                let tagged: Vec<PathBuf> = if self.undo {
+                    let old = std::slice::from_ref(&params.old);
+                    notes::notes_by_tags(
+                        root,
+                        old,
+                        notes::TagMatch::Any,
+                        &[],
+                        &self.trash_dir,
+                        &self.note_options,
+                    )
+                    .iter()
+                    .map(|path| root.join(path))
+                    .collect()
+                } else {
+                    Vec::new()
+                };
+                let snapshots = self.snapshot_all_for_undo(root, &tagged)?;
+                let changed = notes::rename_tag(
+                    root,
+                    &params.old,
+                    &params.new,
+                    &self.trash_dir,
+                    &self.note_options,
+                )?;
+                self.record_writes_for_undo(root, snapshots, &changed);
+                Ok(changed)
+            },
+            |path| path,
+        );
+
+        match renamed {
+            Ok(changed) => {
+                let msg = format!(
+                    "Renamed tag {} to {} in {} notes",
+                    params.old,
+                    params.new,
+                    changed.len()
+                );
+
+                if !changed.is_empty() {
+                    let _ = peer
+                        .notify_logging_message(LoggingMessageNotificationParam {
+                            level: LoggingLevel::Info,
+                            logger: Some("stumbling-rs".into()),
+                            data: msg.clone().into(),
+                        })
+                        .await;
+                }
+
+                let output = serde_json::json!({
+                    "message": msg,
+                    "changed": changed,
+                });
+                match serde_json::to_string_pretty(&output) {
+                    Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                    Err(e) => Ok(tool_error("Failed to serialize rename results", e)),
+                }
+            }
+            Err(e) => Ok(tool_error("Failed to rename tag", e)),
+        }
+    }
+
+    /// Add a tag to the frontmatter `tags` of every note matching path_glob or listed in
+    /// paths, creating the list if needed. Notes that already have it are left alone.
+    #[tool(name = "add_tag")]
+    async fn add_tag(
+        &self,
+        params: Parameters<TagNotesParams>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let summary = format!("Added tag {} to", params.tag);
+        self.tag_notes(params, notes::add_tag, "Failed to add tag", &summary, peer)
+            .await
+    }
+
+    /// Remove a tag from the frontmatter `tags` of every note matching path_glob or listed
+    /// in paths.
+    #[tool(name = "remove_tag")]
+    async fn remove_tag(
+        &self,
+        params: Parameters<TagNotesParams>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let summary = format!("Removed tag {} from", params.tag);
+        self.tag_notes(
+            params,
+            notes::remove_tag,
+            "Failed to remove tag",
+            &summary,
+            peer,
+        )
+        .await
+    }
+
+    /// Rename or move a note and rewrite the wikilinks and relative markdown links pointing
+    /// at it, keeping aliases and headings. Returns the number of links updated per note.
+    /// Use dry_run to preview without writing.
+    #[tool(name = "rename_note")]
+    async fn rename_note(
+        &self,
+        params: Parameters<RenameNoteParams>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let (root, mut paths) = tool_try!(
+            self.resolve_notes(params.vault.as_deref(), &[&params.path, &params.new_path]),
+            "Failed to rename note"
+        );
+        let (to, from) = (paths.pop().unwrap(), paths.pop().unwrap());
+
+        // A dry run first finds the notes whose links change, to snapshot before the rename.
+        // The moved note is snapshotted where it is now but restored at its new path.
+        let mut snapshots = BTreeMap::new();
+        if self.undo && !params.dry_run {
+            let planned = tool_try!(
+                notes::rename_note(root, &from, &to, true, &self.trash_dir, &self.note_options),
+                "Failed to rename note"
+            );
+            let paths: Vec<PathBuf> = planned
+                .files
+                .iter()
+                .map(|file| {
+                    if file.path == planned.to {
+                        from.clone()
+                    } else {
+                        root.join(&file.path)
+                    }
+                })
+                .collect();
+            snapshots = tool_try!(
+                self.snapshot_all_for_undo(root, &paths),
+                "Failed to rename note"
+            );
+            if let Some(snapshot) = snapshots.remove(&planned.from) {
+                snapshots.insert(planned.to, snapshot);
+            }
+        }
+
+        match notes::rename_note(
+            root,
+            &from,
+            &to,
+            params.dry_run,
+            &self.trash_dir,
+            &self.note_options,
+        ) {
+            Ok(report) => {
+                if !params.dry_run {
+                    self.record_for_undo(
+                        root,
+                        oplog::Operation::Move {
+                            from: report.from.clone(),
+                            to: report.to.clone(),
+                        },
+                    );
+                    self.record_writes_for_undo(
+                        root,
+                        snapshots,
+                        report.files.iter().map(|file| &file.path),
+                    );
+                    let msg = format!(
+                        "Renamed {} to {}, updating {} links in {} notes",
+                        report.from,
+                        report.to,
+                        report.updated_links,
+                        report.files.len()
+                    );
+
+                    let _ = peer
+                        .notify_logging_message(LoggingMessageNotificationParam {
+                            level: LoggingLevel::Info,
+                            logger: Some("stumbling-rs".into()),
+                            data: msg.into(),
+                        })
+                        .await;
+                }
+
+                match serde_json::to_string_pretty(&report) {
+                    Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                    Err(e) => Ok(tool_error("Failed to serialize rename results", e)),
+                }
+            }
+            Err(e) => Ok(tool_error("Failed to rename note", e)),
+        }
+    }
+
+    /// Move many notes in one call. All pairs are validated before any note moves, and
+    /// if a move fails the earlier ones are moved back. Links are not rewritten.
+    #[tool(name = "move_notes")]
+    async fn move_notes(
+        &self,
+        params: Parameters<MoveNotesParams>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let paths: Vec<&str> = params
+            .moves
+            .iter()
+            .flat_map(|pair| [pair.from.as_str(), pair.to.as_str()])
+            .collect();
+        let (root, paths) = tool_try!(
+            self.route_paths(params.vault.as_deref(), &paths),
+            "Failed to move notes"
+        );
+        let moves: Vec<(String, String)> = paths
+            .chunks(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect();
+
+        match notes::move_notes(root, &moves, &self.note_options) {
+            Ok(moved) => {
+                for pair in &moved {
+                    self.record_for_undo(
+                        root,
+                        oplog::Operation::Move {
+                            from: pair.from.clone(),
+                            to: pair.to.clone(),
+                        },
+                    );
+                }
+                let msg = format!("Moved {} notes", moved.len());
+                let _ = peer
+                    .notify_logging_message(LoggingMessageNotificationParam {
+                        level: LoggingLevel::Info,
+                        logger: Some("stumbling-rs".into()),
+                        data: msg.into(),
+                    })
+                    .await;
+
+                match serde_json::to_string_pretty(&moved) {
+                    Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                    Err(e) => Ok(tool_error("Failed to serialize move results", e)),
+                }
+            }
+            Err(e) => Ok(tool_error("Failed to move notes", e)),
+        }
+    }
+
+    /// Split a note into one new note per heading of the given level, named after the
+    /// heading. Each new note links back to the original.
+    #[tool(name = "split_note")]
+    async fn split_note(
+        &self,
+        params: Parameters<SplitNoteParams>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let (root, path) = tool_try!(
+            self.resolve_note(params.vault.as_deref(), &params.path),
+            "Failed to split note"
+        );
+
+        let snapshot = if params.replace_with_toc {
+            tool_try!(self.snapshot_for_undo(root, &path), "Failed to split note")
+        } else {
+            None
+        };
+
+        match notes::split_at_headings(
+            &path,
+            params.level,
+            params.replace_with_toc,
+            &self.note_options,
+        ) {
+            Ok(created) => {
+                let created: Vec<String> = created
+                    .iter()
+                    .map(|p| notes::relative_path(root, p))
+                    .collect();
+                for path in &created {
+                    self.record_for_undo(
+                        root,
+                        oplog::Operation::Write {
+                            path: path.clone(),
+                            snapshot: None,
+                        },
+                    );
+                }
+                if params.replace_with_toc {
+                    self.record_for_undo(
+                        root,
+                        oplog::Operation::Write {
+                            path: notes::relative_path(root, &path),
+                            snapshot,
+                        },
+                    );
+                }
+                let msg = format!("Split {} into {} notes", params.path, created.len());
+                let _ = peer
+                    .notify_logging_message(LoggingMessageNotificationParam {
+                        level: LoggingLevel::Info,
+                        logger: Some("stumbling-rs".into()),
+                        data: msg.into(),
+                    })
+                    .await;
+
+                match serde_json::to_string_pretty(&created) {
+                    Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                    Err(e) => Ok(tool_error("Failed to serialize split results", e)),
+                }
+            }
+            Err(e) => Ok(tool_error("Failed to split note", e)),
+        }
+    }
+
+    /// Combine several notes, in order, into one. Bodies go under headings named after each
+    /// source; frontmatter is merged with later keys winning and arrays unioned.
+    #[tool(name = "merge_notes")]
+    async fn merge_notes(
+        &self,
+        params: Parameters<MergeNotesParams>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let paths: Vec<&str> = params
+            .sources
+            .iter()
+            .chain([&params.destination])
+            .map(String::as_str)
+            .collect();
+        let (root, mut sources) = tool_try!(
+            self.resolve_notes(params.vault.as_deref(), &paths),
+            "Failed to merge notes"
+        );
+        let dest = sources.pop().unwrap();
+
+        let snapshot = tool_try!(self.snapshot_for_undo(root, &dest), "Failed to merge notes");
+
+        let trash_dir = params.trash_sources.then_some(self.trash_dir.as_str());
+        match notes::merge_notes(root, &sources, &dest, trash_dir, &self.note_options) {
+            Ok(trashed) => {
+                self.record_for_undo(
+                    root,
+                    oplog::Operation::Write {
+                        path: notes::relative_path(root, &dest),
+                        snapshot,
+                    },
+                );
+                for moved in trashed {
+                    self.record_for_undo(
+                        root,
+                        oplog::Operation::Move {
+                            from: moved.from,
+                            to: moved.to,
+                        },
+                    );
+                }
+                let msg = format!("Merged {} notes into {}", sources.len(), params.destination);
+                let _ = peer
+                    .notify_logging_message(LoggingMessageNotificationParam {
+                        level: LoggingLevel::Info,
+                        logger: Some("stumbling-rs".into()),
+                        data: msg.clone().into(),
+                    })
+                    .await;
+
+                Ok(CallToolResult::success(vec![Content::text(msg)]))
+            }
+            Err(e) => Ok(tool_error("Failed to merge notes", e)),
+        }
+    }
+
+    /// Move a note into the archive under a folder for its year (`archive/YYYY/`).
+    /// The year is taken from the note's `created` frontmatter field, or today's date.
+    /// A numeric suffix is added if the name is already taken in the archive.
+    #[tool(name = "archive_note")]
+    async fn archive_note(
+        &self,
+        params: Parameters<ArchiveNoteParams>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let (root, path) = tool_try!(
+            self.resolve_note(params.vault.as_deref(), &params.path),
+            "Failed to archive note"
+        );
+
+        match notes::archive_note(
+            root,
+            &path,
+            &self.archive_dir,
+            params.stamp,
+            &self.note_options,
+        ) {
+            Ok(archived) => {
+                self.record_for_undo(
+                    root,
+                    oplog::Operation::Move {
+                        from: notes::relative_path(root, &path),
+                        to: archived.clone(),
+                    },
+                );
+                let msg = format!("Archived {} to {}", params.path, archived);
+                let _ = peer
+                    .notify_logging_message(LoggingMessageNotificationParam {
+                        level: LoggingLevel::Info,
+                        logger: Some("stumbling-rs".into()),
+                        data: msg.clone().into(),
+                    })
+                    .await;
+
+                Ok(CallToolResult::success(vec![Content::text(msg)]))
+            }
+            Err(e) => Ok(tool_error("Failed to archive note", e)),
+        }
+    }
+
+    /// Rewrite a note's frontmatter in canonical form to avoid diff churn: scalar tags
+    /// become lists, lists are block lists, dates are YYYY-MM-DD. The body is untouched.
+    /// Returns whether the note changed. Use dry_run to check without writing.
+    #[tool(name = "normalize_frontmatter")]
+    async fn normalize_frontmatter(
+        &self,
+        params: Parameters<NormalizeFrontmatterParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let (root, path) = tool_try!(
+            self.resolve_note(params.vault.as_deref(), &params.path),
+            "Failed to normalize frontmatter"
+        );
+        let snapshot = if params.dry_run {
+            None
+        } else {
+            tool_try!(
+                self.snapshot_for_undo(root, &path),
+                "Failed to normalize frontmatter"
+            )
+        };
+
+        match notes::normalize_frontmatter(&path, params.dry_run, &self.note_options) {
+            Ok(changed) => {
+                if changed && !params.dry_run {
+                    self.record_for_undo(
+                        root,
+                        oplog::Operation::Write {
+                            path: notes::relative_path(root, &path),
+                            snapshot,
+                        },
+                    );
+                }
+                let output = serde_json::json!({
+                    "path": params.path,
+                    "changed": changed,
+                });
+                match serde_json::to_string_pretty(&output) {
+                    Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                    Err(e) => Ok(tool_error("Failed to serialize normalize result", e)),
+                }
+            }
+            Err(e) => Ok(tool_error("Failed to normalize frontmatter", e)),
+        }
+    }
+
+    /// The previous and next existing daily notes around a dated note, by the dates in
+    /// file names in the same folder, for flipping between days. Missing neighbors are null.
+    #[tool(name = "note_neighbors")]
+    async fn note_neighbors(
+        &self,
+        params: Parameters<NoteNeighborsParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let (root, path) = tool_try!(
+            self.resolve_note(params.vault.as_deref(), &params.path),
+            "Failed to find neighbors"
+        );
+
+        let date_format = params
+            .date_format
+            .as_deref()
+            .unwrap_or(notes::DEFAULT_NEIGHBOR_DATE_FORMAT);
+        match notes::note_neighbors(root, &path, date_format) {
+            Ok(neighbors) => match serde_json::to_string_pretty(&neighbors) {
+                Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+                Err(e) => Ok(tool_error("Failed to serialize neighbors", e)),
+            },
+            Err(e) => Ok(tool_error("Failed to find neighbors", e)),
+        }
+    }
+
+    /// Append a timestamped bullet (`- HH:MM text`) to today's daily note, creating the
+    /// note from the daily template if it doesn't exist yet.
+    #[tool(name = "add_to_daily")]
+    async fn add_to_daily(
+        &self,
+        params: Parameters<AddToDailyParams>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let root = tool_try!(
+            self.vault_root(params.vault.as_deref()),
+            "Failed to add to daily note"
+        );
+        let now = chrono::Local::now().naive_local();
+        let snapshot = tool_try!(
+            notes::resolve_note_path(root, &now.format(&self.daily_format).to_string())
+                .and_then(|path| self.snapshot_for_undo(root, &path)),
+            "Failed to add to daily note"
+        );
+
+        match notes::add_to_daily(
+            root,
+            &self.daily_format,
+            &self.daily_template,
+            &params.text,
+            now,
+            &self.note_options,
+        ) {
+            Ok(path) => {
+                self.record_for_undo(
+                    root,
+                    oplog::Operation::Write {
+                        path: path.clone(),
+                        snapshot,
+                    },
+                );
+                let msg = format!("Added to {}", path);
+                let _ = peer
+                    .notify_logging_message(LoggingMessageNotificationParam {
+                        level: LoggingLevel::Info,
+                        logger: Some("stumbling-rs".into()),
+                        data: msg.clone().into(),
+                    })
+                    .await;
+
+                Ok(CallToolResult::success(vec![Content::text(msg)]))
+            }
+            Err(e) => Ok(tool_error("Failed to add to daily note", e)),
+        }
+    }
+
+    /// Delete a markdown note.
+    /// By default, moves to the trash directory (STUMBLING_TRASH_DIR, default .trash).
+    /// Set permanent=true to permanently delete.
+    #[tool(name = "delete_note")]
+    async fn delete_note(
+        &self,
+        params: Parameters<DeleteNoteParams>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let (root, path) = tool_try!(
+            self.resolve_note(params.vault.as_deref(), &params.path),
+            "Failed to delete note"
+        );
+
+        let result = if params.permanent {
+            let snapshot = tool_try!(self.snapshot_for_undo(root, &path), "Failed to delete note");
+            notes::delete_note(root, &path, true, &self.trash_dir, &self.note_options).inspect(
+                |_| {
+                    if let Some(snapshot) = snapshot {
+                        self.record_for_undo(
+                            root,
+                            oplog::Operation::Delete {
+                                path: notes::relative_path(root, &path),
+                                snapshot,
+                            },
+                        );
+                    }
+                },
+            )
+        } else {
+            notes::move_note_to_trash(root, &path, &self.trash_dir, &self.note_options).map(
+                |trashed| {
+                    let trashed = notes::relative_path(root, &trashed);
+                    self.record_for_undo(
+                        root,
+                        oplog::Operation::Move {
+                            from: notes::relative_path(root, &path),
+                            to: trashed.clone(),
+                        },
+                    );
+                    format!("Moved to trash: {}", trashed)
+                },
+            )
+        };
+
+        match result {
+            Ok(msg) => {
+                let _ = peer
+                    .notify_logging_message(LoggingMessageNotificationParam {
+                        level: LoggingLevel::Info,
+                        logger: Some("stumbling-rs".into()),
+                        data: msg.clone().into(),
+                    })
+                    .await;
+
+                Ok(CallToolResult::success(vec![Content::text(msg)]))
+            }
+            Err(e) => Ok(tool_error("Failed to delete note", e)),
+        }
+    }
+
+    /// List trashed notes and directories, newest first, with when they were deleted
+    /// and their original path.
+    #[tool(name = "list_trash")]
+    async fn list_trash(
+        &self,
+        params: Parameters<ListTrashParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let scope = tool_try!(
+            self.vault_scope(params.vault.as_deref()),
+            "Failed to list trash"
+        );
+
+        let mut entries = Vec::new();
+        for (name, root) in scope {
+            let trashed = tool_try!(
+                notes::list_trash(root, &self.trash_dir),
+                "Failed to list trash"
+            );
+            entries.extend(trashed.into_iter().map(|mut entry| {
+                entry.trash_path = notes::vault_path(name, entry.trash_path);
+                entry.original = notes::vault_path(name, entry.original);
+                entry
+            }));
+        }
+        // Newest first across vaults too
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.timestamp));
+
+        match serde_json::to_string_pretty(&entries) {
+            Ok(output) => Ok(CallToolResult::success(vec![Content::text(output)])),
+            Err(e) => Ok(tool_error("Failed to serialize trash", e)),
+        }
+    }
+
+    /// Move a trashed note back to where it was deleted from, recreating its folder, or to
+    /// `to`. An existing note is never overwritten.
+    #[tool(name = "restore_note")]
+    async fn restore_note(
+        &self,
+        params: Parameters<RestoreNoteParams>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        // The trashed entry and `to` are in the same vault
+        let paths: Vec<&str> = std::iter::once(params.trash_path.as_str())
+            .chain(params.to.as_deref())
+            .collect();
+        let resolved =
+            self.route_paths(params.vault.as_deref(), &paths)
+                .and_then(|(root, paths)| {
+                    let to = paths
+                        .get(1)
+                        .map(|to| notes::resolve_note_path(root, to))
+                        .transpose()?;
+                    Ok((root, paths[0].clone(), to))
+                });
+        let (root, trash_path, to) = tool_try!(resolved, "Failed to restore note");
+
+        match notes::restore_note(
+            root,
+            &self.trash_dir,
+            &trash_path,
+            to.as_deref(),
+            &self.note_options,
+        ) {
+            Ok(restored) => {
+                let msg = format!("Restored {} to {}", trash_path, restored);
+                self.record_for_undo(
+                    root,
+                    oplog::Operation::Move {
+                        from: trash_path,
+                        to: restored,
+                    },
+                );
+                let _ = peer
+                    .notify_logging_message(LoggingMessageNotificationParam {
+                        level: LoggingLevel::Info,
+                        logger: Some("stumbling-rs".into()),
+                        data: msg.clone().into(),
+                    })
+                    .await;
+
+                Ok(CallToolResult::success(vec![Content::text(msg)]))
+            }
+            Err(e) => Ok(tool_error("Failed to restore note", e)),
+        }
+    }
+
+    /// Delete a directory and everything in it.
+    /// By default, moves it to a timestamped folder in the trash directory, keeping its
+    /// relative path. Set permanent=true to permanently delete.
+    #[tool(name = "delete_dir")]
+    async fn delete_dir(
+        &self,
+        params: Parameters<DeleteDirParams>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        let (root, path) = tool_try!(
+            self.resolve_note(params.vault.as_deref(), &params.path),
+            "Failed to delete directory"
+        );
+
+        // Permanent deletes of whole directories aren't snapshotted, so can't be undone
+        let result = if params.permanent {
+            notes::delete_dir(root, &path, true, &self.trash_dir, &self.note_options)
+        } else {
+            notes::move_dir_to_trash(root, &path, &self.trash_dir, &self.note_options).map(
+                |trashed| {
+                    let trashed = notes::relative_path(root, &trashed);
+                    self.record_for_undo(
+                        root,
+                        oplog::Operation::Move {
+                            from: notes::relative_path(root, &path),
+                            to: trashed.clone(),
+                        },
+                    );
+                    format!("Moved to trash: {}", trashed)
+                },
+            )
+        };
+
+        match result {
+            Ok(msg) => {
+                let _ = peer
+                    .notify_logging_message(LoggingMessageNotificationParam {
+                        level: LoggingLevel::Info,
+                        logger: Some("stumbling-rs".into()),
+                        data: msg.clone().into(),
+                    })
+                    .await;
+
+                Ok(CallToolResult::success(vec![Content::text(msg)]))
+            }
+            Err(e) => Ok(tool_error("Failed to delete directory", e)),
+        }
+    }
+
+    /// Undo the most recent delete, move, archive, write or patch.
+    /// Trashed and archived notes are moved back, and overwritten or permanently deleted
+    /// notes are restored from a snapshot. Call repeatedly to step further back.
+    #[tool(name = "undo_last")]
+    async fn undo_last(
+        &self,
+        params: Parameters<UndoLastParams>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Parameters(params) = params;
+        if !self.undo {
+            return Ok(error_result(
+                ErrorCode::InvalidInput,
+                "Undo is disabled (STUMBLING_UNDO=false)".to_string(),
+            ));
+        }
+        let root = tool_try!(self.vault_root(params.vault.as_deref()), "Failed to undo");
+
+        match oplog::OperationLog::new(root, &self.note_options).undo_last() {
+            Ok(Some(msg)) => {
+                let _ = peer
+                    .notify_logging_message(LoggingMessageNotificationParam {
+                        level: LoggingLevel::Info,
+                        logger: Some("stumbling-rs".into()),
+                        data: msg.clone().into(),
+                    })
+                    .await;
+
+                Ok(CallToolResult::success(vec![Content::text(msg)]))
+            }
+            Ok(None) => Ok(error_result(
+                ErrorCode::NotFound,
+                "Nothing to undo".to_string(),
+            )),
+            Err(e) => Ok(tool_error("Failed to undo", e)),
+        }
+    }
+}
+
+/// Machine-readable category of a tool error, so clients can branch on `code`
+/// instead of matching message text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ErrorCode {
+    NotFound,
+    InvalidPath,
+    InvalidRegex,
+    InvalidInput,
+    Conflict,
+    Io,
+    ReadOnly,
+    Internal,
+}
+
+impl ErrorCode {
+    /// Classify an error by the first recognizable cause in its chain.
+    fn of(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+                return match e.kind() {
+                    std::io::ErrorKind::NotFound => Self::NotFound,
+                    std::io::ErrorKind::AlreadyExists => Self::Conflict,
+                    _ => Self::Io,
+                };
+            }
+            if cause.is::<grep::regex::Error>() || cause.is::<regex::Error>() {
+                return Self::InvalidRegex;
+            }
+            if let Some(e) = cause.downcast_ref::<notes::NoteError>() {
+                return match e {
+                    notes::NoteError::NotFound(_) => Self::NotFound,
+                    notes::NoteError::Conflict(_) => Self::Conflict,
+                    notes::NoteError::InvalidPath(_) => Self::InvalidPath,
+                };
+            }
+            if cause.is::<serde_json::Error>() {
+                return Self::Internal;
+            }
+        }
+
+        Self::InvalidInput
+    }
+}
+
+/// Error result for a failed tool call: `{code, message}` JSON, with the message
+/// prefixed by what was being attempted.
+fn tool_error(context: &str, error: impl Into<anyhow::Error>) -> CallToolResult {
+    let error = error.into();
+    error_result(ErrorCode::of(&error), format!("{}: {}", context, error))
+}
+
+fn error_result(code: ErrorCode, message: String) -> CallToolResult {
+    let body = serde_json::json!({ "code": code, "message": message });
+    CallToolResult::error(vec![Content::text(body.to_string())])
+}
+
+/// Tools that change the vault, hidden and rejected when STUMBLING_READONLY is set.
+const MUTATING_TOOLS: &[&str] = &[
+    "write_note",
+    "patch_note",
+    "edit_note",
+    "link_notes",
+    "replace_in_notes",
+    "rename_tag",
+    "normalize_frontmatter",
+    "add_tag",
+    "remove_tag",
+    "rename_note",
+    "move_notes",
+    "split_note",
+    "merge_notes",
+    "archive_note",
+    "add_to_daily",
+    "delete_note",
+    "delete_dir",
+    "restore_note",
+    "import_vault",
+    "undo_last",
+];
+
+impl ServerHandler for StumblingServer {
+    fn get_info(&self) -> ServerInfo {
+        let mut instructions =
+            "MCP server for reading and searching markdown notes in a local vault.".to_string();
+        if self.read_only {
+            instructions
+                .push_str(" The server is read-only: tools that modify notes are disabled.");
+        }
+
+        ServerInfo {
+            protocol_version: ProtocolVersion::V_2024_11_05,
+            capabilities: ServerCapabilities::builder()
+                .enable_tools()
+                .enable_resources()
+                .enable_logging()
+                .build(),
+            server_info: Implementation::from_build_env(),
+            instructions: Some(instructions),
+        }
+    }
+
+    /// Log startup messages once a client is ready for them, and start watching the vault
+    /// for this session if STUMBLING_WATCH is set. Over http, sessions share the startup
+    /// messages, so only the first session gets them, but each session has its own watcher.
+    async fn on_initialized(&self, context: NotificationContext<RoleServer>) {
+        let messages = std::mem::take(
+            &mut *self
+                .startup_log
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+        );
+        for msg in messages {
+            let _ = context
+                .peer
+                .notify_logging_message(LoggingMessageNotificationParam {
+                    level: LoggingLevel::Info,
+                    logger: Some("stumbling-rs".into()),
+                    data: msg.into(),
+                })
+                .await;
+        }
+
+        if self.watch {
+            let watched = watch::spawn_watcher(
+                self.root.clone(),
+                self.trash_dir.clone(),
+                context.peer.clone(),
+            );
+            if let Err(e) = watched {
+                let _ = context
+                    .peer
+                    .notify_logging_message(LoggingMessageNotificationParam {
+                        level: LoggingLevel::Warning,
+                        logger: Some("stumbling-rs".into()),
+                        data: format!("{:#}", e).into(),
+                    })
+                    .await;
+            }
+        }
+    }
+
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        if self.read_only && MUTATING_TOOLS.contains(&request.name.as_ref()) {
+            return Ok(error_result(
+                ErrorCode::ReadOnly,
+                format!("Server is read-only: {} is disabled", request.name),
+            ));
+        }
+
+        let context = rmcp::handler::server::tool::ToolCallContext::new(self, request, context);
+        self.tool_router.call(context).await
+    }
+
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParams>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, McpError> {
+        let tools = self
+            .tool_router
+            .list_all()
+            .into_iter()
+            .filter(|tool| !self.read_only || !MUTATING_TOOLS.contains(&tool.name.as_ref()))
+            .collect();
+
+        Ok(ListToolsResult {
+            tools,
+            next_cursor: None,
+            meta: None,
+        })
+    }
+
+    /// Every note in the default vault, as `stumbling:///<path>` resources.
+    async fn list_resources(
+        &self,
+        _request: Option<PaginatedRequestParams>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourcesResult, McpError> {
+        let mut files = notes::collect_markdown_files(&self.root, &self.trash_dir);
+        files.sort();
+
+        let resources = files
+            .iter()
+            .map(|path| {
+                let relative = notes::relative_path(&self.root, path);
+                let mut resource = RawResource::new(note_uri(None, &relative), relative);
+                resource.mime_type = Some(NOTE_MIME_TYPE.to_string());
+                resource.size = std::fs::metadata(path)
+                    .ok()
+                    .and_then(|m| u32::try_from(m.len()).ok());
+                resource.no_annotation()
+            })
+            .collect();
+
+        Ok(ListResourcesResult {
+            resources,
+            next_cursor: None,
+            meta: None,
+        })
+    }
+
+    /// Templates for reading any note by path, in the default or a named vault.
+    async fn list_resource_templates(
+        &self,
+        _request: Option<PaginatedRequestParams>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourceTemplatesResult, McpError> {
+        let template = |uri_template: &str, name: &str, description: &str| {
+            RawResourceTemplate {
+                uri_template: uri_template.to_string(),
+                name: name.to_string(),
+                title: None,
+                description: Some(description.to_string()),
+                mime_type: Some(NOTE_MIME_TYPE.to_string()),
+                icons: None,
+            }
+            .no_annotation()
+        };
+
+        Ok(ListResourceTemplatesResult {
+            resource_templates: vec![
+                template(
+                    "stumbling:///{path}",
+                    "note",
+                    "A note by its path relative to STUMBLING_ROOT",
+                ),
+                template(
+                    "stumbling://{vault}/{path}",
+                    "vault_note",
+                    "A note in a named vault from STUMBLING_VAULTS",
+                ),
+            ],
+            next_cursor: None,
+            meta: None,
+        })
+    }
+
+    /// Raw markdown of the note a `stumbling://` URI points at.
+    async fn read_resource(
+        &self,
+        request: ReadResourceRequestParams,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ReadResourceResult, McpError> {
+        let (vault, relative) = parse_note_uri(&request.uri).ok_or_else(|| {
+            McpError::invalid_params(
+                format!("Not a stumbling:// note URI: {}", request.uri),
+                None,
+            )
+        })?;
+
+        let text = self
+            .note_path(vault.as_deref(), &relative)
+            .and_then(|path| notes::read_note(&path, false, &self.note_options))
+            .map_err(|e| {
+                McpError::resource_not_found(format!("Failed to read note: {}", e), None)
+            })?;
+
+        Ok(ReadResourceResult {
+            contents: vec![ResourceContents::TextResourceContents {
+                uri: request.uri,
+                mime_type: Some(NOTE_MIME_TYPE.to_string()),
+                text,
+                meta: None,
+            }],
+        })
+    }
+}
+
+const NOTE_MIME_TYPE: &str = "text/markdown";
+
+/// Resource URI of a note: `stumbling:///<path>` for the default vault,
+/// `stumbling://<vault>/<path>` for a named one. The path is percent-encoded.
+fn note_uri(vault: Option<&str>, relative: &str) -> String {
+    let mut uri = format!("stumbling://{}/", vault.unwrap_or_default());
+    for byte in relative.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+/// Split a `stumbling://` URI into its vault (None for the default) and decoded note path.
+fn parse_note_uri(uri: &str) -> Option<(Option<String>, String)> {
+    let rest = uri.strip_prefix("stumbling://")?;
+    let (vault, path) = rest.split_once('/')?;
+
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut iter = path.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    let path = String::from_utf8(bytes).ok()?;
+    if path.is_empty() {
+        return None;
+    }
+
+    let vault = (!vault.is_empty()).then(|| vault.to_string());
+    Some((vault, path))
+}
+
+/// Size rayon's global pool, used by every search, from STUMBLING_THREADS.
+/// Unset or 0 keeps rayon's default of one thread per core.
+fn configure_threads() -> Result<()> {
+    let threads = match env::var("STUMBLING_THREADS") {
+        Ok(v) => v
+            .parse::<usize>()
+            .with_context(|| format!("Invalid STUMBLING_THREADS: {}", v))?,
+        Err(_) => 0,
+    };
+
+    if threads > 0 {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .context("Failed to configure thread pool")?;
+    }
+    Ok(())
+}
+
+/// Serve MCP over streamable HTTP (with SSE responses) on the given listener.
+/// Each client session gets its own clone of the server.
+/// Active sessions are terminated once `shutdown` resolves.
+async fn serve_http(
+    server: StumblingServer,
+    listener: tokio::net::TcpListener,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
+    let config = StreamableHttpServerConfig::default();
+    let sessions = config.cancellation_token.clone();
+    let service = StreamableHttpService::new(
+        move || Ok(server.clone()),
+        LocalSessionManager::default().into(),
+        config,
+    );
+    let router = axum::Router::new().nest_service("/mcp", service);
+
+    axum::serve(listener, router)
+        .with_graceful_shutdown(async move {
+            shutdown.await;
+            sessions.cancel();
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Wait for a running service to finish, either because the client went away
+/// (e.g., stdin closed) or because `shutdown` resolved.
+async fn run_until_shutdown(
+    service: RunningService<RoleServer, StumblingServer>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let token = service.cancellation_token();
+    let waiting = service.waiting();
+    tokio::pin!(waiting);
+
+    tokio::select! {
+        result = &mut waiting => {
+            result?;
+        }
+        _ = shutdown => {
+            token.cancel();
+            waiting.await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolve on SIGINT, or SIGTERM on Unix.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    configure_threads()?;
+
     let server = StumblingServer::new()?;
+    let handle = server.clone();
+    let pruned = server.prune_trash()?;
+    if pruned > 0 {
+        server
+            .startup_log
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(format!("Pruned {} old trash entries", pruned));
+    }
+
+    let transport = env::var("STUMBLING_TRANSPORT").unwrap_or_else(|_| "stdio".to_string());
+    match transport.as_str() {
+        "stdio" => {
+            let transport = rmcp::transport::io::stdio();
+            let service = server.serve(transport).await?;
+            run_until_shutdown(service, shutdown_signal()).await?;
+        }
+        "http" | "sse" => {
+            let bind = env::var("STUMBLING_BIND").unwrap_or_else(|_| "127.0.0.1:8000".to_string());
+            let listener = tokio::net::TcpListener::bind(&bind)
+                .await
+                .with_context(|| format!("Failed to bind STUMBLING_BIND: {}", bind))?;
 
-    let transport = rmcp::transport::io::stdio();
-    let service = server.serve(transport).await?;
+            serve_http(server, listener, shutdown_signal()).await?;
+        }
+        other => anyhow::bail!(
+            "Unknown STUMBLING_TRANSPORT: {} (expected stdio, http, or sse)",
+            other
+        ),
+    }
 
-    service.waiting().await?;
+    handle.flush_index();
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmcp::transport::StreamableHttpClientTransport;
+    use tempfile::TempDir;
+
+    fn result_text(result: &CallToolResult) -> &str {
+        &result.content[0].as_text().unwrap().text
+    }
+
+    /// Serve `server` over an in-memory pipe, returning a connected client and the running
+    /// server, which must be kept alive for the duration of the test.
+    async fn connect(
+        server: StumblingServer,
+    ) -> (
+        RunningService<rmcp::RoleClient, ()>,
+        RunningService<RoleServer, StumblingServer>,
+    ) {
+        let (server_io, client_io) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move { server.serve(server_io).await.unwrap() });
+        let client = ().serve(client_io).await.unwrap();
+        (client, server.await.unwrap())
+    }
+
+    fn call(name: &str, arguments: serde_json::Value) -> CallToolRequestParams {
+        CallToolRequestParams {
+            meta: None,
+            name: name.to_string().into(),
+            arguments: arguments.as_object().cloned(),
+            task: None,
+        }
+    }
+
+    fn setup_server() -> (TempDir, StumblingServer) {
+        let vault = TempDir::new().unwrap();
+        std::fs::write(
+            vault.path().join("test.md"),
+            "---\ntitle: Test Note\n---\n\n# Hello World",
+        )
+        .unwrap();
+
+        let server = StumblingServer::with_root(vault.path().to_path_buf());
+        (vault, server)
+    }
+
+    #[tokio::test]
+    async fn test_read_note_parse_override() {
+        let (_vault, server) = setup_server();
+
+        let parsed = server
+            .read_note(Parameters(ReadNoteParams {
+                path: "test.md".to_string(),
+                parse: Some(true),
+                strip_frontmatter_only: false,
+                raw_frontmatter: false,
+                vault: None,
+            }))
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_str(result_text(&parsed)).unwrap();
+        assert_eq!(json["metadata"]["title"], "Test Note");
+
+        let raw = server
+            .read_note(Parameters(ReadNoteParams {
+                path: "test.md".to_string(),
+                parse: Some(false),
+                strip_frontmatter_only: false,
+                raw_frontmatter: false,
+                vault: None,
+            }))
+            .await
+            .unwrap();
+        assert!(result_text(&raw).starts_with("---\ntitle: Test Note"));
+    }
+
+    #[tokio::test]
+    async fn test_read_note_parse_falls_back_to_server_default() {
+        let (_vault, mut server) = setup_server();
+        server.parse_frontmatter = true;
+
+        let result = server
+            .read_note(Parameters(ReadNoteParams {
+                path: "test.md".to_string(),
+                parse: None,
+                strip_frontmatter_only: false,
+                raw_frontmatter: false,
+                vault: None,
+            }))
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_str(result_text(&result)).unwrap();
+        assert_eq!(json["body"], "\n# Hello World");
+    }
+
+    #[tokio::test]
+    async fn test_read_note_strip_frontmatter_only() {
+        let (_vault, server) = setup_server();
+
+        let result = server
+            .read_note(Parameters(ReadNoteParams {
+                path: "test.md".to_string(),
+                parse: Some(true),
+                strip_frontmatter_only: true,
+                raw_frontmatter: false,
+                vault: None,
+            }))
+            .await
+            .unwrap();
+
+        assert_eq!(result_text(&result), "\n# Hello World");
+    }
+
+    #[tokio::test]
+    async fn test_search_metadata_uses_index() {
+        let (vault, mut server) = setup_server();
+        server.index = Some(Arc::new(Mutex::new(index::NoteIndex::default())));
+
+        let params = SearchMetadataParams {
+            field: "title".to_string(),
+            pattern: "Test".to_string(),
+            limit: Some(10),
+            sort_by: None,
+            sort_desc: false,
+            include_hidden: false,
+            vault: None,
+        };
+        let result = server
+            .search_metadata(Parameters(params), CancellationToken::new())
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_str(result_text(&result)).unwrap();
+        assert_eq!(json[0]["path"], "test.md");
+
+        // The refreshed index is only persisted at shutdown
+        let index_file = vault.path().join(".stumbling/index.json");
+        assert!(!index_file.exists());
+        server.flush_index();
+        assert!(index_file.exists());
+    }
+
+    #[tokio::test]
+    async fn test_read_only_index_not_persisted() {
+        let (vault, mut server) = setup_server();
+        server.index = Some(Arc::new(Mutex::new(index::NoteIndex::default())));
+        server.read_only = true;
+
+        let found = server
+            .read_note_by_title(Parameters(ReadNoteByTitleParams {
+                title: "Test Note".to_string(),
+                parse: None,
+                vault: None,
+            }))
+            .await
+            .unwrap();
+        assert_eq!(found.is_error, Some(false), "{}", result_text(&found));
+        server.flush_index();
+        assert!(!vault.path().join(".stumbling").exists());
+    }
+
+    #[tokio::test]
+    async fn test_search_limits() {
+        let (vault, mut server) = setup_server();
+        for i in 0..5 {
+            let note = format!("---\ntitle: Hello {}\n---\n\nHello", i);
+            std::fs::write(vault.path().join(format!("{}.md", i)), note).unwrap();
+        }
+        // As set by STUMBLING_DEFAULT_LIMIT and STUMBLING_MAX_LIMIT
+        server.default_limit = 2;
+        server.max_limit = 3;
+
+        let search = |limit: Option<usize>| {
+            server.search_notes(
+                Parameters(SearchNotesParams {
+                    query: "Hello".to_string(),
+                    limit,
+                    body_relative_lines: false,
+                    snippet_len: 0,
+                    exclude: None,
+                    lossy_utf8: false,
+                    sort: notes::SearchSort::Path,
+                    include_hidden: false,
+                    max_per_file: None,
+                    search_paths: false,
+                    include_stats: false,
+                    mode: notes::SearchMode::Regex,
+                    vault: None,
+                }),
+                CancellationToken::new(),
+            )
+        };
+        let count = |result: CallToolResult| {
+            let json: serde_json::Value = serde_json::from_str(result_text(&result)).unwrap();
+            json["results"].as_array().unwrap().len()
+        };
+
+        assert_eq!(count(search(None).await.unwrap()), 2);
+        assert_eq!(count(search(Some(1_000_000)).await.unwrap()), 3);
+
+        let metadata = server
+            .search_metadata(
+                Parameters(SearchMetadataParams {
+                    field: "title".to_string(),
+                    pattern: "Hello".to_string(),
+                    limit: Some(1_000_000),
+                    sort_by: None,
+                    sort_desc: false,
+                    include_hidden: false,
+                    vault: None,
+                }),
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_str(result_text(&metadata)).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_ping_caches_note_count() {
+        let (vault, server) = setup_server();
+        let ping = || server.ping(Parameters(PingParams { vault: None }));
+
+        let result = ping().await.unwrap();
+        let json: serde_json::Value = serde_json::from_str(result_text(&result)).unwrap();
+        assert_eq!(json["status"], "ok");
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["root"], vault.path().to_str().unwrap());
+        assert_eq!(json["root_exists"], true);
+        assert_eq!(json["readable"], true);
+        assert_eq!(json["writable"], true);
+        assert_eq!(json["note_count"], 1);
+
+        // Within the TTL the cached count is reused
+        std::fs::write(vault.path().join("other.md"), "# Other").unwrap();
+        let result = ping().await.unwrap();
+        let json: serde_json::Value = serde_json::from_str(result_text(&result)).unwrap();
+        assert_eq!(json["note_count"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_ping_missing_root() {
+        let server = StumblingServer::with_root(PathBuf::from("/nonexistent/stumbling-vault"));
+
+        let result = server
+            .ping(Parameters(PingParams { vault: None }))
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_str(result_text(&result)).unwrap();
+        assert_eq!(json["status"], "unavailable");
+        assert_eq!(json["root_exists"], false);
+        assert_eq!(json["readable"], false);
+        assert_eq!(json["note_count"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_read_note_by_title() {
+        let (vault, server) = setup_server();
+        let by_title = |title: &str| {
+            server.read_note_by_title(Parameters(ReadNoteByTitleParams {
+                title: title.to_string(),
+                parse: None,
+                vault: None,
+            }))
+        };
+
+        let found = by_title("test note").await.unwrap();
+        assert!(result_text(&found).contains("# Hello World"));
+
+        let missing = by_title("Nope").await.unwrap();
+        assert_eq!(missing.is_error, Some(true));
+
+        std::fs::write(vault.path().join("copy.md"), "---\ntitle: Test Note\n---\n").unwrap();
+        let ambiguous = by_title("Test Note").await.unwrap();
+        let json: serde_json::Value = serde_json::from_str(result_text(&ambiguous)).unwrap();
+        assert_eq!(json["matches"], serde_json::json!(["copy.md", "test.md"]));
+    }
+
+    #[tokio::test]
+    async fn test_read_from_two_vaults() {
+        let (_vault, mut server) = setup_server();
+        let work = TempDir::new().unwrap();
+        std::fs::write(work.path().join("test.md"), "# Work Note").unwrap();
+        server
+            .vaults
+            .push(("work".to_string(), work.path().to_path_buf()));
+
+        let read = |vault: Option<&str>| ReadNoteParams {
+            path: "test.md".to_string(),
+            parse: None,
+            strip_frontmatter_only: false,
+            raw_frontmatter: false,
+            vault: vault.map(str::to_string),
+        };
+
+        let default = server.read_note(Parameters(read(None))).await.unwrap();
+        assert!(result_text(&default).contains("# Hello World"));
+
+        let work_note = server
+            .read_note(Parameters(read(Some("work"))))
+            .await
+            .unwrap();
+        assert_eq!(result_text(&work_note), "# Work Note");
+
+        let unknown = server
+            .read_note(Parameters(read(Some("nope"))))
+            .await
+            .unwrap();
+        assert_eq!(unknown.is_error, Some(true));
+        assert!(result_text(&unknown).contains("Unknown vault: nope"));
+    }
+
+    #[tokio::test]
+    async fn test_path_cannot_escape_vault() {
+        let (_vault, server) = setup_server();
+
+        let result = server
+            .read_note(Parameters(ReadNoteParams {
+                path: "../outside.md".to_string(),
+                parse: None,
+                strip_frontmatter_only: false,
+                raw_frontmatter: false,
+                vault: None,
+            }))
+            .await
+            .unwrap();
+
+        assert_eq!(result.is_error, Some(true));
+        let json: serde_json::Value = serde_json::from_str(result_text(&result)).unwrap();
+        assert_eq!(json["code"], "invalid_path");
+    }
+
+    #[tokio::test]
+    async fn test_errors_are_structured() {
+        let (_vault, server) = setup_server();
+
+        let result = server
+            .read_note(Parameters(ReadNoteParams {
+                path: "missing.md".to_string(),
+                parse: None,
+                strip_frontmatter_only: false,
+                raw_frontmatter: false,
+                vault: None,
+            }))
+            .await
+            .unwrap();
+
+        assert_eq!(result.is_error, Some(true));
+        let json: serde_json::Value = serde_json::from_str(result_text(&result)).unwrap();
+        assert_eq!(json["code"], "not_found");
+        assert!(json["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Failed to read note: "));
+    }
+
+    #[test]
+    fn test_error_code_classification() {
+        let io =
+            |kind| anyhow::Error::new(std::io::Error::from(kind)).context("Failed to read file");
+        assert_eq!(
+            ErrorCode::of(&io(std::io::ErrorKind::NotFound)),
+            ErrorCode::NotFound
+        );
+        assert_eq!(
+            ErrorCode::of(&io(std::io::ErrorKind::AlreadyExists)),
+            ErrorCode::Conflict
+        );
+        assert_eq!(
+            ErrorCode::of(&io(std::io::ErrorKind::PermissionDenied)),
+            ErrorCode::Io
+        );
+
+        let tmp = TempDir::new().unwrap();
+        let regex =
+            notes::search_notes(&[(None, tmp.path())], "(", 1, &Default::default()).unwrap_err();
+        assert_eq!(ErrorCode::of(&regex), ErrorCode::InvalidRegex);
+
+        let conflict =
+            notes::create_note(&tmp.path().join("a.md"), "", &notes::NoteOptions::default())
+                .and_then(|()| {
+                    notes::create_note(&tmp.path().join("a.md"), "", &notes::NoteOptions::default())
+                })
+                .unwrap_err();
+        assert_eq!(ErrorCode::of(&conflict), ErrorCode::Conflict);
+        let escaping = notes::resolve_note_path(tmp.path(), "../a.md").unwrap_err();
+        assert_eq!(ErrorCode::of(&escaping), ErrorCode::InvalidPath);
+        // The kind survives context added on the way up, and the message alone counts for nothing
+        let missing = notes::read_section(&tmp.path().join("a.md"), "Nope")
+            .context("Failed to read section")
+            .unwrap_err();
+        assert_eq!(ErrorCode::of(&missing), ErrorCode::NotFound);
+        let lookalike = anyhow::anyhow!("Note already exists: a.md");
+        assert_eq!(ErrorCode::of(&lookalike), ErrorCode::InvalidInput);
+        let input = anyhow::anyhow!("start_line (3) must not be greater than end_line (2)");
+        assert_eq!(ErrorCode::of(&input), ErrorCode::InvalidInput);
+    }
+
+    #[test]
+    fn test_output_formats() {
+        let results = serde_json::json!([{"path": "a.md"}, {"path": "b.md"}]);
+
+        assert_eq!(
+            OutputFormat::Pretty.render(&results).unwrap(),
+            serde_json::to_string_pretty(&results).unwrap()
+        );
+        assert_eq!(
+            OutputFormat::Compact.render(&results).unwrap(),
+            r#"[{"path":"a.md"},{"path":"b.md"}]"#
+        );
+        assert_eq!(
+            OutputFormat::Jsonl.render(&results).unwrap(),
+            "{\"path\":\"a.md\"}\n{\"path\":\"b.md\"}"
+        );
+        assert_eq!(
+            OutputFormat::Jsonl.render(&serde_json::json!([])).unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_jsonl_search_envelope() {
+        let report = serde_json::json!({
+            "results": [{"path": "a.md"}],
+            "skipped": [{"path": "big.md", "reason": "too big"}],
+        });
+        let lines: Vec<serde_json::Value> = OutputFormat::Jsonl
+            .render(&report)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["path"], "a.md");
+        assert_eq!(lines[1]["skipped"][0]["path"], "big.md");
+
+        let clean = serde_json::json!({"results": [{"path": "a.md"}]});
+        assert_eq!(
+            OutputFormat::Jsonl.render(&clean).unwrap(),
+            r#"{"path":"a.md"}"#
+        );
+    }
+
+    #[test]
+    fn test_output_format_parse() {
+        assert_eq!(OutputFormat::parse("jsonl").unwrap(), OutputFormat::Jsonl);
+        assert!(OutputFormat::parse("yaml").is_err());
+    }
+
+    #[test]
+    fn test_parse_vaults() {
+        let a = TempDir::new().unwrap();
+        let b = TempDir::new().unwrap();
+        let spec = format!(
+            "work={}, personal={}",
+            a.path().display(),
+            b.path().display()
+        );
+
+        let vaults = parse_vaults(&spec).unwrap();
+
+        assert_eq!(vaults.len(), 2);
+        assert_eq!(vaults["work"], a.path());
+        assert_eq!(vaults["personal"], b.path());
+    }
+
+    #[test]
+    fn test_parse_roots() {
+        let parent = TempDir::new().unwrap();
+        let (work, personal) = (parent.path().join("work"), parent.path().join("personal"));
+        std::fs::create_dir(&work).unwrap();
+        std::fs::create_dir(&personal).unwrap();
+
+        let roots = parse_roots(&format!("{}:{}", work.display(), personal.display())).unwrap();
+
+        assert_eq!(
+            roots,
+            vec![
+                ("work".to_string(), work.clone()),
+                ("personal".to_string(), personal)
+            ]
+        );
+        assert!(parse_roots(&format!("{0}:{0}", work.display())).is_err());
+        assert!(parse_roots("/definitely/not/here").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_multiple_roots() {
+        let (vault, mut server) = setup_server();
+        let personal = TempDir::new().unwrap();
+        std::fs::write(personal.path().join("diary.md"), "# Hello Diary").unwrap();
+        server.vaults = vec![
+            ("work".to_string(), vault.path().to_path_buf()),
+            ("personal".to_string(), personal.path().to_path_buf()),
+        ];
+        server.search_all_vaults = true;
+
+        let search = server
+            .search_notes(
+                Parameters(SearchNotesParams {
+                    query: "Hello".to_string(),
+                    limit: Some(20),
+                    body_relative_lines: false,
+                    snippet_len: 0,
+                    exclude: None,
+                    lossy_utf8: false,
+                    sort: notes::SearchSort::Path,
+                    include_hidden: false,
+                    max_per_file: None,
+                    search_paths: false,
+                    include_stats: false,
+                    mode: notes::SearchMode::Regex,
+                    vault: None,
+                }),
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_str(result_text(&search)).unwrap();
+        let paths: Vec<_> = json["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["path"].as_str().unwrap())
+            .collect();
+        assert_eq!(paths, ["work:test.md", "personal:diary.md"]);
+
+        let read = server
+            .read_note(Parameters(ReadNoteParams {
+                path: "personal:diary.md".to_string(),
+                parse: None,
+                strip_frontmatter_only: false,
+                raw_frontmatter: false,
+                vault: None,
+            }))
+            .await
+            .unwrap();
+        assert_eq!(result_text(&read), "# Hello Diary");
+    }
+
+    #[tokio::test]
+    async fn test_vault_wide_tools_cover_every_root() {
+        let (vault, mut server) = setup_server();
+        let personal = TempDir::new().unwrap();
+        std::fs::write(
+            personal.path().join("diary.md"),
+            "---\ntitle: Diary\ntags: [life]\n---\n\nHello\nHello\nHello\n",
+        )
+        .unwrap();
+        server.vaults = vec![
+            ("work".to_string(), vault.path().to_path_buf()),
+            ("personal".to_string(), personal.path().to_path_buf()),
+        ];
+        server.search_all_vaults = true;
+        let (client, _server) = connect(server).await;
+        let call_text = |name: &'static str, arguments: serde_json::Value| {
+            let client = &client;
+            async move {
+                let result = client.call_tool(call(name, arguments)).await.unwrap();
+                assert_eq!(result.is_error, Some(false), "{}", result_text(&result));
+                result_text(&result).to_string()
+            }
+        };
+
+        // The best match is in the second vault, so the first vault must not fill the limit
+        let search = call_text(
+            "search_notes",
+            serde_json::json!({"query": "Hello", "limit": 1, "sort": "relevance"}),
+        )
+        .await;
+        let json: serde_json::Value = serde_json::from_str(&search).unwrap();
+        assert_eq!(json["results"][0]["path"], "personal:diary.md");
+
+        let metadata = call_text(
+            "search_metadata",
+            serde_json::json!({"field": "title", "pattern": "."}),
+        )
+        .await;
+        assert!(metadata.contains("work:test.md"), "{}", metadata);
+        assert!(metadata.contains("personal:diary.md"), "{}", metadata);
+
+        let changed = call_text("changed_since", serde_json::json!({"since": 0.0})).await;
+        assert!(changed.contains("work:test.md"), "{}", changed);
+        assert!(changed.contains("personal:diary.md"), "{}", changed);
+
+        let tagged = call_text("notes_by_tags", serde_json::json!({"tags": ["life"]})).await;
+        assert!(tagged.contains("personal:diary.md"), "{}", tagged);
+        assert!(!tagged.contains("work:"), "{}", tagged);
+
+        let tree = call_text("get_tree", serde_json::json!({})).await;
+        assert!(tree.contains("personal:diary.md"), "{}", tree);
+        assert!(tree.contains("work:test.md"), "{}", tree);
+
+        let bundle = call_text("export_vault", serde_json::json!({})).await;
+        let json: serde_json::Value = serde_json::from_str(&bundle).unwrap();
+        let paths: Vec<_> = json
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|note| note["path"].as_str().unwrap())
+            .collect();
+        assert_eq!(paths, ["work:test.md", "personal:diary.md"]);
+
+        // A `vault` still narrows the search to that vault, with plain paths
+        let work_only = call_text(
+            "changed_since",
+            serde_json::json!({"since": 0.0, "vault": "work"}),
+        )
+        .await;
+        assert!(work_only.contains("\"test.md\""), "{}", work_only);
+        assert!(!work_only.contains("diary"), "{}", work_only);
+    }
+
+    #[tokio::test]
+    async fn test_vault_prefix_routes_every_tool() {
+        let (vault, mut server) = setup_server();
+        let personal = TempDir::new().unwrap();
+        std::fs::write(personal.path().join("a.md"), "# A\n").unwrap();
+        std::fs::write(personal.path().join("b.md"), "# B\n").unwrap();
+        std::fs::write(personal.path().join("c.md"), "# C\n").unwrap();
+        server.vaults = vec![
+            ("work".to_string(), vault.path().to_path_buf()),
+            ("personal".to_string(), personal.path().to_path_buf()),
+        ];
+        server.search_all_vaults = true;
+        let (client, _server) = connect(server).await;
+        let call_ok = |name: &'static str, arguments: serde_json::Value| {
+            let client = &client;
+            async move {
+                let result = client.call_tool(call(name, arguments)).await.unwrap();
+                assert_eq!(result.is_error, Some(false), "{}", result_text(&result));
+            }
+        };
+
+        // An unprefixed path goes in the vault the other paths name
+        call_ok(
+            "rename_note",
+            serde_json::json!({"path": "personal:a.md", "new_path": "renamed.md"}),
+        )
+        .await;
+        assert!(personal.path().join("renamed.md").exists());
+        call_ok(
+            "merge_notes",
+            serde_json::json!({"sources": ["personal:renamed.md", "personal:b.md"], "destination": "personal:merged.md"}),
+        )
+        .await;
+        assert!(personal.path().join("merged.md").exists());
+        call_ok("delete_note", serde_json::json!({"path": "personal:c.md"})).await;
+        assert!(!personal.path().join("c.md").exists());
+
+        let mixed = client
+            .call_tool(call(
+                "rename_note",
+                serde_json::json!({"path": "personal:merged.md", "new_path": "work:merged.md"}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(mixed.is_error, Some(true));
+        assert!(result_text(&mixed).contains("same vault"));
+
+        // Nothing was created under a literal `name:` file name
+        for dir in [vault.path(), personal.path()] {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let name = entry.unwrap().file_name();
+                assert!(!name.to_string_lossy().contains(':'), "{:?}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_vaults_invalid() {
+        let a = TempDir::new().unwrap();
+
+        assert!(parse_vaults("no-equals-sign").is_err());
+        assert!(parse_vaults("missing=/definitely/not/here").is_err());
+        let dup = format!("x={0},x={0}", a.path().display());
+        assert!(parse_vaults(&dup).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_list_and_read_resources() {
+        let (vault, server) = setup_server();
+        std::fs::create_dir(vault.path().join("daily")).unwrap();
+        std::fs::write(vault.path().join("daily/my day.md"), "# Today").unwrap();
+
+        let (client, _server) = connect(server).await;
+
+        let resources = client.list_all_resources().await.unwrap();
+        let uris: Vec<_> = resources.iter().map(|r| r.uri.as_str()).collect();
+        assert_eq!(
+            uris,
+            ["stumbling:///daily/my%20day.md", "stumbling:///test.md"]
+        );
+        assert_eq!(resources[1].mime_type.as_deref(), Some("text/markdown"));
+
+        let templates = client.list_all_resource_templates().await.unwrap();
+        assert!(templates
+            .iter()
+            .any(|t| t.uri_template == "stumbling:///{path}"));
+
+        let read = client
+            .read_resource(ReadResourceRequestParams {
+                meta: None,
+                uri: resources[0].uri.clone(),
+            })
+            .await
+            .unwrap();
+        match &read.contents[0] {
+            ResourceContents::TextResourceContents { text, .. } => assert_eq!(text, "# Today"),
+            other => panic!("unexpected contents: {:?}", other),
+        }
+
+        let missing = client
+            .read_resource(ReadResourceRequestParams {
+                meta: None,
+                uri: "stumbling:///missing.md".to_string(),
+            })
+            .await;
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_note_uri_roundtrip() {
+        let uri = note_uri(Some("work"), "projects/plan #1.md");
+
+        assert_eq!(uri, "stumbling://work/projects/plan%20%231.md");
+        assert_eq!(
+            parse_note_uri(&uri),
+            Some((Some("work".to_string()), "projects/plan #1.md".to_string()))
+        );
+        assert_eq!(
+            parse_note_uri("stumbling:///test.md"),
+            Some((None, "test.md".to_string()))
+        );
+        assert_eq!(parse_note_uri("file:///test.md"), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_only_rejects_writes() {
+        let (vault, mut server) = setup_server();
+        server.read_only = true;
+        assert!(server
+            .get_info()
+            .instructions
+            .unwrap()
+            .contains("read-only"));
+
+        let (client, _server) = connect(server).await;
+
+        let tools = client.list_all_tools().await.unwrap();
+        assert!(tools.iter().any(|t| t.name == "read_note"));
+        assert!(tools
+            .iter()
+            .all(|t| !MUTATING_TOOLS.contains(&t.name.as_ref())));
+
+        let result = client
+            .call_tool(CallToolRequestParams {
+                meta: None,
+                name: "write_note".into(),
+                arguments: serde_json::json!({"path": "new.md", "content": "x"})
+                    .as_object()
+                    .cloned(),
+                task: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(result_text(&result).contains("read-only"));
+        assert!(!vault.path().join("new.md").exists());
+
+        let read = client
+            .call_tool(CallToolRequestParams {
+                meta: None,
+                name: "read_note".into(),
+                arguments: serde_json::json!({"path": "test.md"}).as_object().cloned(),
+                task: None,
+            })
+            .await
+            .unwrap();
+        assert_ne!(read.is_error, Some(true));
+    }
+
+    #[tokio::test]
+    async fn test_write_note_modes() {
+        let (vault, server) = setup_server();
+
+        let (client, _server) = connect(server).await;
+
+        let write = |arguments: serde_json::Value| CallToolRequestParams {
+            meta: None,
+            name: "write_note".into(),
+            arguments: arguments.as_object().cloned(),
+            task: None,
+        };
+
+        let rejected = client
+            .call_tool(write(serde_json::json!({
+                "path": "test.md", "content": "clobbered", "mode": "create_new"
+            })))
+            .await
+            .unwrap();
+        assert_eq!(rejected.is_error, Some(true));
+        assert!(result_text(&rejected).contains("already exists"));
+
+        let created = client
+            .call_tool(write(serde_json::json!({
+                "path": "new.md", "content": "# New", "mode": "create_new"
+            })))
+            .await
+            .unwrap();
+        assert_eq!(result_text(&created), "Created new.md");
+
+        let appended = client
+            .call_tool(write(serde_json::json!({
+                "path": "test.md", "content": "\nMore", "mode": "append",
+                "metadata": {"title": "Ignored"}
+            })))
+            .await
+            .unwrap();
+        assert_eq!(result_text(&appended), "Appended to test.md");
+        assert_eq!(
+            std::fs::read_to_string(vault.path().join("test.md")).unwrap(),
+            "---\ntitle: Test Note\n---\n\n# Hello World\n\nMore"
+        );
+
+        let overwritten = client
+            .call_tool(write(
+                serde_json::json!({"path": "new.md", "content": "# Replaced"}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(result_text(&overwritten), "Overwrote new.md");
+    }
+
+    #[tokio::test]
+    async fn test_note_options_apply_to_writes() {
+        let (vault, mut server) = setup_server();
+        server.note_options.max_note_bytes = Some(64);
+        server.note_options.frontmatter_format = notes::FrontmatterFormat::Toml;
+        let (client, _server) = connect(server).await;
+
+        let too_big = client
+            .call_tool(call(
+                "write_note",
+                serde_json::json!({"path": "test.md", "content": "x".repeat(65)}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(too_big.is_error, Some(true));
+        assert!(result_text(&too_big).contains("STUMBLING_MAX_NOTE_BYTES"));
+        assert!(std::fs::read_to_string(vault.path().join("test.md"))
+            .unwrap()
+            .contains("Hello World"));
+
+        let written = client
+            .call_tool(call(
+                "write_note",
+                serde_json::json!({"path": "new.md", "content": "Body", "metadata": {"title": "New"}}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(written.is_error, Some(false), "{}", result_text(&written));
+        let content = std::fs::read_to_string(vault.path().join("new.md")).unwrap();
+        assert!(content.starts_with("+++\n"), "{}", content);
+    }
+
+    #[tokio::test]
+    async fn test_write_note_merge_metadata() {
+        let (vault, server) = setup_server();
+        std::fs::write(
+            vault.path().join("test.md"),
+            "---\ntitle: Test Note\ntags: [keep]\nauthor:\n  name: Gagagigo\n---\n\nOld",
+        )
+        .unwrap();
+
+        let (client, _server) = connect(server).await;
+
+        let result = client
+            .call_tool(CallToolRequestParams {
+                meta: None,
+                name: "write_note".into(),
+                arguments: serde_json::json!({
+                    "path": "test.md",
+                    "content": "New",
+                    "metadata": {"title": "Renamed", "author": {"url": "x"}},
+                    "merge_metadata": true
+                })
+                .as_object()
+                .cloned(),
+                task: None,
+            })
+            .await
+            .unwrap();
+        assert_ne!(result.is_error, Some(true));
+
+        let meta = notes::get_frontmatter(
+            &vault.path().join("test.md"),
+            &notes::NoteOptions::default(),
+        )
+        .unwrap()
+        .unwrap();
+        let keys: Vec<_> = meta.as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["title", "tags", "author"]);
+        assert_eq!(
+            meta,
+            serde_json::json!({
+                "title": "Renamed",
+                "tags": ["keep"],
+                "author": {"name": "Gagagigo", "url": "x"}
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_note_merge_metadata_keeps_toml() {
+        let (vault, server) = setup_server();
+        let path = vault.path().join("hugo.md");
+        std::fs::write(&path, "+++\ntitle = \"Hugo\"\n+++\n\nOld").unwrap();
+        let (client, _server) = connect(server).await;
+
+        let result = client
+            .call_tool(call(
+                "write_note",
+                serde_json::json!({
+                    "path": "hugo.md",
+                    "content": "New",
+                    "metadata": {"draft": true},
+                    "merge_metadata": true
+                }),
+            ))
+            .await
+            .unwrap();
+        assert_ne!(result.is_error, Some(true));
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "+++\ntitle = \"Hugo\"\ndraft = true\n+++\n\nNew"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_note_return_diff() {
+        let (_vault, server) = setup_server();
+
+        let (client, _server) = connect(server).await;
+
+        let write = |content: &str| CallToolRequestParams {
+            meta: None,
+            name: "write_note".into(),
+            arguments: serde_json::json!({
+                "path": "test.md", "content": content, "return_diff": true
+            })
+            .as_object()
+            .cloned(),
+            task: None,
+        };
+
+        let changed = client.call_tool(write("# Goodbye World")).await.unwrap();
+        assert_eq!(changed.content.len(), 2);
+        let diff = &changed.content[1].as_text().unwrap().text;
+        assert!(diff.contains("-# Hello World"));
+        assert!(diff.contains("+# Goodbye World"));
+
+        let identical = client.call_tool(write("# Goodbye World")).await.unwrap();
+        assert_eq!(identical.content.len(), 1);
+        assert_eq!(result_text(&identical), "Overwrote test.md");
+    }
+
+    #[tokio::test]
+    async fn test_write_note_preview() {
+        let (vault, server) = setup_server();
+
+        let (client, _server) = connect(server).await;
+
+        let preview = |path: &str, content: &str| CallToolRequestParams {
+            meta: None,
+            name: "write_note".into(),
+            arguments: serde_json::json!({"path": path, "content": content, "preview": true})
+                .as_object()
+                .cloned(),
+            task: None,
+        };
+
+        let changed = client
+            .call_tool(preview("test.md", "# Goodbye World"))
+            .await
+            .unwrap();
+        let diff = result_text(&changed);
+        assert!(diff.contains("-# Hello World"));
+        assert!(diff.contains("+# Goodbye World"));
+        assert_eq!(
+            std::fs::read_to_string(vault.path().join("test.md")).unwrap(),
+            "---\ntitle: Test Note\n---\n\n# Hello World"
+        );
+
+        let created = client
+            .call_tool(preview("new.md", "line one\nline two\n"))
+            .await
+            .unwrap();
+        let diff = result_text(&created);
+        assert!(diff.contains("+line one\n+line two"));
+        assert!(!vault.path().join("new.md").exists());
+    }
+
+    #[tokio::test]
+    async fn test_write_note_expected_hash() {
+        let (vault, server) = setup_server();
+
+        let (client, _server) = connect(server).await;
+
+        let hash = || notes::note_hash(&vault.path().join("test.md")).unwrap();
+
+        let stale = hash();
+        let matching = client
+            .call_tool(call(
+                "write_note",
+                serde_json::json!({"path": "test.md", "content": "first", "expected_hash": stale}),
+            ))
+            .await
+            .unwrap();
+        assert_ne!(matching.is_error, Some(true));
+
+        let conflict = client
+            .call_tool(call(
+                "write_note",
+                serde_json::json!({"path": "test.md", "content": "second", "expected_hash": stale}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(conflict.is_error, Some(true));
+        assert!(result_text(&conflict).contains("\"conflict\""));
+        assert_eq!(
+            std::fs::read_to_string(vault.path().join("test.md")).unwrap(),
+            "first"
+        );
+
+        let read = client
+            .call_tool(call("note_hash", serde_json::json!({"path": "test.md"})))
+            .await
+            .unwrap();
+        let fresh: serde_json::Value = serde_json::from_str(result_text(&read)).unwrap();
+        let retried = client
+            .call_tool(call(
+                "write_note",
+                serde_json::json!({"path": "test.md", "content": "second", "expected_hash": fresh["hash"]}),
+            ))
+            .await
+            .unwrap();
+        assert_ne!(retried.is_error, Some(true));
+
+        let missing = client
+            .call_tool(call(
+                "write_note",
+                serde_json::json!({"path": "missing.md", "content": "x", "expected_hash": stale}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(missing.is_error, Some(true));
+        assert!(!vault.path().join("missing.md").exists());
+    }
+
+    #[tokio::test]
+    async fn test_custom_trash_dir_skipped_by_walks() {
+        let (vault, mut server) = setup_server();
+        server.trash_dir = "bin".to_string();
+        let (client, _server) = connect(server).await;
+
+        let deleted = client
+            .call_tool(call("delete_note", serde_json::json!({"path": "test.md"})))
+            .await
+            .unwrap();
+        assert_eq!(deleted.is_error, Some(false), "{}", result_text(&deleted));
+        // No marker: walks must skip the directory because it is the configured trash
+        let _ = std::fs::remove_file(vault.path().join("bin/.ignore"));
+        assert!(std::fs::read_dir(vault.path().join("bin")).unwrap().count() > 0);
+
+        for (name, arguments) in [
+            ("search_notes", serde_json::json!({"query": "Hello"})),
+            ("get_tree", serde_json::json!({})),
+            ("changed_since", serde_json::json!({"since": 0.0})),
+            ("export_vault", serde_json::json!({})),
+        ] {
+            let result = client.call_tool(call(name, arguments)).await.unwrap();
+            assert_eq!(result.is_error, Some(false), "{}", result_text(&result));
+            assert!(
+                !result_text(&result).contains("test"),
+                "{}: {}",
+                name,
+                result_text(&result)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_undo_last() {
+        let (vault, server) = setup_server();
+        let note = vault.path().join("test.md");
+        let original = std::fs::read_to_string(&note).unwrap();
+
+        let (client, _server) = connect(server).await;
+
+        client
+            .call_tool(call(
+                "write_note",
+                serde_json::json!({"path": "test.md", "content": "overwritten"}),
+            ))
+            .await
+            .unwrap();
+        client
+            .call_tool(call("delete_note", serde_json::json!({"path": "test.md"})))
+            .await
+            .unwrap();
+        assert!(!note.exists());
+
+        let undone = client
+            .call_tool(call("undo_last", serde_json::json!({})))
+            .await
+            .unwrap();
+        assert_ne!(undone.is_error, Some(true));
+        assert_eq!(std::fs::read_to_string(&note).unwrap(), "overwritten");
+
+        client
+            .call_tool(call("undo_last", serde_json::json!({})))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&note).unwrap(), original);
+
+        let empty = client
+            .call_tool(call("undo_last", serde_json::json!({})))
+            .await
+            .unwrap();
+        assert_eq!(empty.is_error, Some(true));
+        assert!(result_text(&empty).contains("Nothing to undo"));
+    }
+
+    #[tokio::test]
+    async fn test_undo_rename_note() {
+        let (vault, server) = setup_server();
+        let linking = vault.path().join("linking.md");
+        std::fs::write(&linking, "See [[test]]\n").unwrap();
+        let (client, _server) = connect(server).await;
+
+        let renamed = client
+            .call_tool(call(
+                "rename_note",
+                serde_json::json!({"path": "test.md", "new_path": "moved.md"}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(renamed.is_error, Some(false), "{}", result_text(&renamed));
+        assert_eq!(
+            std::fs::read_to_string(&linking).unwrap(),
+            "See [[moved]]\n"
+        );
+
+        for _ in 0..2 {
+            let undone = client
+                .call_tool(call("undo_last", serde_json::json!({})))
+                .await
+                .unwrap();
+            assert_eq!(undone.is_error, Some(false), "{}", result_text(&undone));
+        }
+        assert_eq!(std::fs::read_to_string(&linking).unwrap(), "See [[test]]\n");
+        assert!(vault.path().join("test.md").exists());
+        assert!(!vault.path().join("moved.md").exists());
+    }
+
+    #[tokio::test]
+    async fn test_undo_add_tag() {
+        let (vault, server) = setup_server();
+        let note = vault.path().join("test.md");
+        let original = std::fs::read_to_string(&note).unwrap();
+        let (client, _server) = connect(server).await;
+
+        let tagged = client
+            .call_tool(call(
+                "add_tag",
+                serde_json::json!({"tag": "fresh", "path_glob": "*.md"}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(tagged.is_error, Some(false), "{}", result_text(&tagged));
+        assert_ne!(std::fs::read_to_string(&note).unwrap(), original);
+
+        let undone = client
+            .call_tool(call("undo_last", serde_json::json!({})))
+            .await
+            .unwrap();
+        assert_eq!(undone.is_error, Some(false), "{}", result_text(&undone));
+        assert_eq!(std::fs::read_to_string(&note).unwrap(), original);
+    }
+
+    #[tokio::test]
+    async fn test_undo_bulk_rewrites() {
+        let (vault, server) = setup_server();
+        let a = vault.path().join("a.md");
+        let b = vault.path().join("b.md");
+        std::fs::write(&a, "---\ntags: [old]\n---\n\nfoo\n").unwrap();
+        std::fs::write(&b, "foo foo\n").unwrap();
+        let (client, _server) = connect(server).await;
+        let call_ok = |name: &'static str, arguments: serde_json::Value| {
+            let client = &client;
+            async move {
+                let result = client.call_tool(call(name, arguments)).await.unwrap();
+                assert_eq!(result.is_error, Some(false), "{}", result_text(&result));
+            }
+        };
+        let undo = || call_ok("undo_last", serde_json::json!({}));
+
+        call_ok(
+            "replace_in_notes",
+            serde_json::json!({"pattern": "foo", "replacement": "bar"}),
+        )
+        .await;
+        call_ok(
+            "rename_tag",
+            serde_json::json!({"old": "old", "new": "new"}),
+        )
+        .await;
+        undo().await;
+        assert!(std::fs::read_to_string(&a).unwrap().contains("old"));
+        undo().await;
+        undo().await;
+        assert_eq!(
+            std::fs::read_to_string(&a).unwrap(),
+            "---\ntags: [old]\n---\n\nfoo\n"
+        );
+        assert_eq!(std::fs::read_to_string(&b).unwrap(), "foo foo\n");
+
+        call_ok(
+            "merge_notes",
+            serde_json::json!({"sources": ["a.md", "b.md"], "destination": "merged.md", "trash_sources": true}),
+        )
+        .await;
+        assert!(!a.exists() && !b.exists());
+        for _ in 0..3 {
+            undo().await;
+        }
+        assert!(a.exists() && b.exists());
+        assert!(!vault.path().join("merged.md").exists());
+
+        std::fs::write(&a, "intro\n\n## One\n\nfirst\n").unwrap();
+        call_ok(
+            "split_note",
+            serde_json::json!({"path": "a.md", "level": 2, "replace_with_toc": true}),
+        )
+        .await;
+        undo().await;
+        undo().await;
+        assert_eq!(
+            std::fs::read_to_string(&a).unwrap(),
+            "intro\n\n## One\n\nfirst\n"
+        );
+        assert!(!vault.path().join("one.md").exists());
+    }
+
+    #[tokio::test]
+    async fn test_tools_end_to_end() {
+        let (vault, server) = setup_server();
+        let (client, _server) = connect(server).await;
+        let error_code = |result: &CallToolResult| {
+            assert_eq!(result.is_error, Some(true));
+            let body: serde_json::Value = serde_json::from_str(result_text(result)).unwrap();
+            body["code"].as_str().unwrap().to_string()
+        };
+
+        let written = client
+            .call_tool(call(
+                "write_note",
+                serde_json::json!({
+                    "path": "projects/plan.md",
+                    "content": "# Plan\n\nShip the harness",
+                    "metadata": {"title": "Plan"}
+                }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(written.is_error, Some(false));
+        assert_eq!(result_text(&written), "Created projects/plan.md");
+
+        let read = client
+            .call_tool(call(
+                "read_note",
+                serde_json::json!({"path": "projects/plan.md", "parse": true}),
+            ))
+            .await
+            .unwrap();
+        let note: serde_json::Value = serde_json::from_str(result_text(&read)).unwrap();
+        assert_eq!(note["metadata"]["title"], "Plan");
+        assert!(note["body"].as_str().unwrap().contains("Ship the harness"));
+
+        let found = client
+            .call_tool(call(
+                "search_notes",
+                serde_json::json!({"query": "harness"}),
+            ))
+            .await
+            .unwrap();
+        let report: serde_json::Value = serde_json::from_str(result_text(&found)).unwrap();
+        assert_eq!(report["results"][0]["path"], "projects/plan.md");
+        assert_eq!(report["results"][0]["line_number"], 7);
+
+        // Errors come back as tool results with a code, not as protocol errors
+        let missing = client
+            .call_tool(call("read_note", serde_json::json!({"path": "missing.md"})))
+            .await
+            .unwrap();
+        assert_eq!(error_code(&missing), "not_found");
+        let escaped = client
+            .call_tool(call(
+                "write_note",
+                serde_json::json!({"path": "../outside.md", "content": "x"}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(error_code(&escaped), "invalid_path");
+        assert!(!vault.path().join("../outside.md").exists());
+        let invalid = client
+            .call_tool(call("search_notes", serde_json::json!({"query": "("})))
+            .await
+            .unwrap();
+        assert_eq!(error_code(&invalid), "invalid_regex");
+
+        // Missing required params are rejected before the tool runs
+        assert!(client
+            .call_tool(call("read_note", serde_json::json!({})))
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_prune_trash_every_vault_unless_read_only() {
+        let (vault, mut server) = setup_server();
+        let personal = TempDir::new().unwrap();
+        server.vaults = vec![
+            ("work".to_string(), vault.path().to_path_buf()),
+            ("personal".to_string(), personal.path().to_path_buf()),
+        ];
+        server.trash_retention = Some(Duration::from_secs(60));
+        for root in [vault.path(), personal.path()] {
+            let trash = root.join(notes::DEFAULT_TRASH_DIR);
+            std::fs::create_dir_all(&trash).unwrap();
+            std::fs::write(trash.join("1000_old.md"), "old").unwrap();
+        }
+
+        server.read_only = true;
+        assert_eq!(server.prune_trash().unwrap(), 0);
+        assert!(personal.path().join(".trash/1000_old.md").exists());
+
+        server.read_only = false;
+        assert_eq!(server.prune_trash().unwrap(), 2);
+        assert!(!vault.path().join(".trash/1000_old.md").exists());
+        assert!(!personal.path().join(".trash/1000_old.md").exists());
+    }
+
+    #[tokio::test]
+    async fn test_service_stops_on_transport_eof() {
+        let (_vault, server) = setup_server();
+        let (server_io, client_io) = tokio::io::duplex(4096);
+
+        let client = tokio::spawn(async move { ().serve(client_io).await.unwrap() });
+        let service = server.serve(server_io).await.unwrap();
+
+        // Closing the client side is what the server sees when stdin hits EOF
+        drop(client.await.unwrap());
+
+        let result = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            run_until_shutdown(service, std::future::pending()),
+        )
+        .await
+        .expect("service did not stop after EOF");
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_service_stops_on_shutdown_signal() {
+        let (_vault, server) = setup_server();
+        let (server_io, client_io) = tokio::io::duplex(4096);
+
+        let client = tokio::spawn(async move { ().serve(client_io).await.unwrap() });
+        let service = server.serve(server_io).await.unwrap();
+        let _client = client.await.unwrap();
+
+        let result = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            run_until_shutdown(service, async {}),
+        )
+        .await
+        .expect("service did not stop on shutdown");
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_http_transport_lists_tools() {
+        let vault = TempDir::new().unwrap();
+        let server = StumblingServer::with_root(vault.path().to_path_buf());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = tokio::spawn(serve_http(server, listener, std::future::pending()));
+
+        let transport = StreamableHttpClientTransport::from_uri(format!("http://{}/mcp", addr));
+        let client = ().serve(transport).await.unwrap();
+        let tools = client.list_all_tools().await.unwrap();
+
+        assert!(tools.iter().any(|t| t.name == "read_note"));
+        assert!(tools.iter().any(|t| t.name == "search_notes"));
+
+        client.cancel().await.unwrap();
+        handle.abort();
+    }
+
+    /// A client that forwards the server's logging notifications to a channel.
+    struct LogCollector(tokio::sync::mpsc::UnboundedSender<serde_json::Value>);
+
+    impl rmcp::ClientHandler for LogCollector {
+        async fn on_logging_message(
+            &self,
+            params: LoggingMessageNotificationParam,
+            _context: NotificationContext<rmcp::RoleClient>,
+        ) {
+            let _ = self.0.send(params.data);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http_sessions_are_watched() {
+        let vault = TempDir::new().unwrap();
+        let mut server = StumblingServer::with_root(vault.path().to_path_buf());
+        server.watch = true;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = tokio::spawn(serve_http(server, listener, std::future::pending()));
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let transport = StreamableHttpClientTransport::from_uri(format!("http://{}/mcp", addr));
+        let client = LogCollector(tx).serve(transport).await.unwrap();
+
+        // The watcher starts once the session is initialized, so keep writing until it
+        // reports a change
+        let changed = tokio::time::timeout(std::time::Duration::from_secs(10), async {
+            for i in 0.. {
+                std::fs::write(vault.path().join("new.md"), format!("# New {}", i)).unwrap();
+                let wait = std::time::Duration::from_secs(1);
+                while let Ok(Some(data)) = tokio::time::timeout(wait, rx.recv()).await {
+                    if data["message"] == "Vault changed on disk" {
+                        return data["changed"].clone();
+                    }
+                }
+            }
+            unreachable!()
+        })
+        .await
+        .expect("no change notification over http");
+        assert_eq!(changed, serde_json::json!(["new.md"]));
+
+        client.cancel().await.unwrap();
+        handle.abort();
+    }
+}
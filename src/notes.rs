@@ -1,20 +1,197 @@
 use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
 use ignore::WalkBuilder;
 use markdown::{mdast::Node, Constructs, ParseOptions};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use rayon::prelude::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
+    collections::{BTreeMap, BTreeSet},
     fs,
-    path::Path,
-    sync::Mutex,
-    time::{SystemTime, UNIX_EPOCH},
+    io::{BufRead, BufReader, Read, Write},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
+use tokio_util::sync::CancellationToken;
+
+use crate::index::STATE_DIR;
 
 #[derive(Debug, Serialize)]
 pub struct SearchResult {
     pub path: String,
+    /// 0 when the match is in the note's path rather than a line, with `search_paths`
     pub line_number: usize,
-    pub line: String,
+    /// The full matching line, when no snippet was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<String>,
+    /// Excerpt around the match, when `snippet_len` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+    /// Byte offset of the first match within the full line
+    pub match_start: usize,
+    /// Byte offset just past the end of the first match
+    pub match_end: usize,
+}
+
+/// Options for `search_notes`.
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    /// Skip files larger than this many bytes
+    pub max_file_bytes: Option<u64>,
+    /// Report line numbers relative to the body start, skipping frontmatter
+    pub body_relative_lines: bool,
+    /// Return an excerpt of about this many characters around the match instead of the
+    /// whole line. 0 returns the full line.
+    pub snippet_len: usize,
+    /// Drop matching lines that also match this regex
+    pub exclude: Option<String>,
+    /// Decode invalid UTF-8 with replacement characters instead of skipping the rest of
+    /// the file, listing such files in `warnings`
+    pub lossy_utf8: bool,
+    /// Order of the notes in the results
+    pub sort: SearchSort,
+    /// Search hidden files and directories too, except `.trash`, `.git` and `.stumbling`
+    pub include_hidden: bool,
+    /// Stop searching a file after this many matches in it
+    pub max_per_file: Option<usize>,
+    /// Stop searching early when cancelled, failing the search
+    pub cancel: Option<CancellationToken>,
+    /// Also match the query against each note's relative path
+    pub search_paths: bool,
+    /// Report how much was scanned and how long it took in `SearchReport::stats`
+    pub include_stats: bool,
+    /// Whether the query is a regex or a list of terms
+    pub mode: SearchMode,
+    /// Trash directory to skip, relative to each vault root (default `.trash`)
+    pub trash_dir: Option<String>,
+}
+
+impl SearchOptions {
+    fn trash_dir(&self) -> &str {
+        self.trash_dir.as_deref().unwrap_or(DEFAULT_TRASH_DIR)
+    }
+}
+
+/// Options for `search_metadata`.
+#[derive(Debug, Clone, Default)]
+pub struct MetadataSearchOptions {
+    /// Search hidden files and directories too, except `.trash`, `.git` and `.stumbling`
+    pub include_hidden: bool,
+    /// Stop reading notes early when cancelled, failing the search
+    pub cancel: Option<CancellationToken>,
+    /// Trash directory to skip, relative to the vault root (default `.trash`)
+    pub trash_dir: Option<String>,
+    /// How the notes' frontmatter is read
+    pub note: NoteOptions,
+}
+
+impl MetadataSearchOptions {
+    fn trash_dir(&self) -> &str {
+        self.trash_dir.as_deref().unwrap_or(DEFAULT_TRASH_DIR)
+    }
+}
+
+/// Failures callers tell apart by kind rather than by message. They are raised inside
+/// `anyhow::Error`, so find them with `downcast_ref` on the error's chain.
+#[derive(Debug)]
+pub enum NoteError {
+    /// A note, heading, directory or other target doesn't exist
+    NotFound(String),
+    /// Something is already where a note would be created or moved to
+    Conflict(String),
+    /// A path is absolute or escapes the vault root
+    InvalidPath(String),
+}
+
+impl std::fmt::Display for NoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(message) | Self::Conflict(message) | Self::InvalidPath(message) => {
+                f.write_str(message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for NoteError {}
+
+/// Fail with "Search cancelled" if `cancel` has been triggered.
+fn check_cancelled(cancel: Option<&CancellationToken>) -> Result<()> {
+    if cancel.is_some_and(|cancel| cancel.is_cancelled()) {
+        anyhow::bail!("Search cancelled");
+    }
+    Ok(())
+}
+
+/// Order of search results. Within a note, matches are always in line order.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchSort {
+    /// By path
+    #[default]
+    Path,
+    /// Most recently modified notes first. Needs a stat of every matching note.
+    Modified,
+    /// Notes with the most matches first
+    Relevance,
+}
+
+/// How `search_notes` interprets its query.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMode {
+    /// The query is a regex
+    #[default]
+    Regex,
+    /// Space-separated words and "quoted phrases" that must all appear on a line,
+    /// literally and ignoring case
+    Terms,
+}
+
+/// A note in an export bundle.
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct BundleNote {
+    /// Path relative to the vault root
+    pub path: String,
+    /// Frontmatter as a JSON object
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    /// Note content after the frontmatter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+/// Outcome of `import_vault`.
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub created: usize,
+    pub overwritten: usize,
+    /// Notes that already existed and were left alone
+    pub skipped: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed: Vec<SkippedFile>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MissingMetadata {
+    pub path: String,
+    /// Required fields the note lacks, in the order they were requested
+    pub missing: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValueCount {
+    pub value: serde_json::Value,
+    pub count: usize,
 }
 
 #[derive(Debug, Serialize)]
@@ -23,570 +200,9341 @@ pub struct MetadataSearchResult {
     pub value: serde_json::Value,
 }
 
-/// Parse frontmatter from markdown content using markdown-rs AST.
-/// Returns (yaml_string, body) if frontmatter is present.
-fn parse_frontmatter(content: &str) -> Option<(String, String)> {
-    let options = ParseOptions {
+#[derive(Debug, Serialize)]
+pub struct DateSearchResult {
+    pub path: String,
+    /// The field as written in the frontmatter
+    pub value: serde_json::Value,
+    /// The parsed date, as YYYY-MM-DD
+    pub date: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplaceResult {
+    pub path: String,
+    pub replacements: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReadManyEntry {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValidationResult {
+    pub path: String,
+    pub problems: Vec<String>,
+}
+
+/// How write_note treats an existing note.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteMode {
+    /// Replace the note if it exists
+    #[default]
+    Overwrite,
+    /// Fail if the note already exists
+    CreateNew,
+    /// Add to the end of the note, creating it if needed
+    Append,
+}
+
+/// Which occurrences of the text `replace_first` replaces.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Occurrence {
+    #[default]
+    First,
+    Last,
+    All,
+}
+
+/// Expected type of a frontmatter field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, schemars::JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    String,
+    Number,
+    Bool,
+    Array,
+    Object,
+}
+
+impl FieldType {
+    fn matches(self, value: &serde_json::Value) -> bool {
+        match self {
+            FieldType::String => value.is_string(),
+            FieldType::Number => value.is_number(),
+            FieldType::Bool => value.is_boolean(),
+            FieldType::Array => value.is_array(),
+            FieldType::Object => value.is_object(),
+        }
+    }
+
+    fn of(value: &serde_json::Value) -> &'static str {
+        match value {
+            serde_json::Value::Null => "null",
+            serde_json::Value::Bool(_) => "bool",
+            serde_json::Value::Number(_) => "number",
+            serde_json::Value::String(_) => "string",
+            serde_json::Value::Array(_) => "array",
+            serde_json::Value::Object(_) => "object",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct OutlineEntry {
+    pub level: u8,
+    pub text: String,
+    pub line_number: usize,
+    pub children: Vec<OutlineEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TreeNode {
+    pub name: String,
+    /// Path relative to the vault root; empty for the root itself
+    pub path: String,
+    pub is_dir: bool,
+    pub children: Vec<TreeNode>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Section {
+    pub heading: String,
+    pub line_number: usize,
+    pub content: String,
+    /// Line numbers of other headings with the same text, if the match was ambiguous
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub other_matches: Vec<usize>,
+}
+
+/// How a link was written in a note.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkKind {
+    /// `[[note]]` or `[[note|alias]]`
+    Wikilink,
+    /// `[text](url)` or `<url>`
+    Markdown,
+    /// A bare URL in the text
+    Url,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NoteLink {
+    pub kind: LinkKind,
+    pub target: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    pub line_number: usize,
+    /// True for links to notes in the vault, false for external URLs
+    pub internal: bool,
+    /// Relative path of the linked note, if it exists
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved: Option<String>,
+}
+
+/// Files larger than this are skipped by search unless STUMBLING_MAX_FILE_BYTES says otherwise.
+pub const DEFAULT_MAX_FILE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Archive directory under the vault root, unless overridden by STUMBLING_ARCHIVE_DIR.
+pub const DEFAULT_ARCHIVE_DIR: &str = "archive";
+
+/// Path of each day's note under the vault root, unless overridden by STUMBLING_DAILY_FORMAT.
+pub const DEFAULT_DAILY_FORMAT: &str = "daily/%Y-%m-%d.md";
+
+/// Content of a new daily note, unless STUMBLING_DAILY_TEMPLATE gives a file.
+/// `{{date}}` is replaced with the note's `YYYY-MM-DD` date.
+pub const DEFAULT_DAILY_TEMPLATE: &str = "# {{date}}\n";
+
+/// Trash directory under the vault root, unless overridden by STUMBLING_TRASH_DIR.
+pub const DEFAULT_TRASH_DIR: &str = ".trash";
+
+/// File in the trash directory mapping trashed notes' names to their original paths.
+pub const TRASH_MANIFEST: &str = "manifest.json";
+
+/// Per-vault ignore file with gitignore syntax, applied on top of the hidden-dir filter.
+pub const IGNORE_FILE: &str = ".stumblingignore";
+
+/// Hidden directories that stay excluded when a search includes hidden files.
+const ALWAYS_EXCLUDED_DIRS: [&str; 3] = [DEFAULT_TRASH_DIR, ".git", STATE_DIR];
+
+/// A vault covered by a vault-wide operation: the name its paths are prefixed with as
+/// `name:path` when several vaults are covered at once, and its root.
+pub type Vault<'a> = (Option<&'a str>, &'a Path);
+
+/// A path as reported for a vault: `name:path` for a named vault, unchanged otherwise.
+pub fn vault_path(name: Option<&str>, path: String) -> String {
+    match name {
+        Some(name) => format!("{}:{}", name, path),
+        None => path,
+    }
+}
+
+/// Collect all markdown files under root, skipping hidden files and directories and the
+/// trash directory (relative to root). Compressed `.md.gz` notes are left out: they are
+/// read-only archives, so metadata tools, the index and bulk rewrites don't see them, and
+/// only `collect_searchable_files` includes them.
+pub(crate) fn collect_markdown_files(root: &Path, trash_dir: &str) -> Vec<PathBuf> {
+    collect_markdown_files_with(root, false, trash_dir)
+}
+
+/// `collect_markdown_files`, optionally including hidden files and directories other
+/// than the trash directory, `.trash`, `.git` and `.stumbling`.
+pub(crate) fn collect_markdown_files_with(
+    root: &Path,
+    include_hidden: bool,
+    trash_dir: &str,
+) -> Vec<PathBuf> {
+    markdown_files(markdown_walk(root, include_hidden, trash_dir)).collect()
+}
+
+/// The walk behind `collect_markdown_files`, for callers that adjust it before building.
+/// The trash directory is skipped whether or not it is hidden.
+fn markdown_walk(root: &Path, include_hidden: bool, trash_dir: &str) -> WalkBuilder {
+    let trash = root.join(trash_dir);
+    let mut walk = WalkBuilder::new(root);
+    walk.hidden(!include_hidden) // Skip hidden files/dirs
+        // Gitignore-style rules for the vault, re-read on every walk
+        .add_custom_ignore_filename(IGNORE_FILE)
+        .filter_entry(move |e| {
+            if e.path() == trash {
+                return false;
+            }
+            // Skip .obsidian and other common ignored directories
+            let name = e.file_name().to_string_lossy();
+            if include_hidden {
+                !ALWAYS_EXCLUDED_DIRS.contains(&name.as_ref())
+            } else {
+                !name.starts_with('.')
+            }
+        });
+    walk
+}
+
+/// Markdown files plus gzip-compressed `.md.gz` notes, which are only read and
+/// searched, never written.
+fn collect_searchable_files(root: &Path, include_hidden: bool, trash_dir: &str) -> Vec<PathBuf> {
+    markdown_walk(root, include_hidden, trash_dir)
+        .build()
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            let path = e.path();
+            path.extension().is_some_and(|ext| ext == "md") || is_compressed_note(path)
+        })
+        .map(|e| e.into_path())
+        .collect()
+}
+
+/// True for a gzip-compressed note, named `*.md.gz`.
+fn is_compressed_note(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().ends_with(".md.gz"))
+}
+
+/// Open a note for reading, decompressing `.md.gz` notes on the fly.
+fn open_note(path: &Path) -> std::io::Result<Box<dyn Read + Send>> {
+    let file = fs::File::open(path)?;
+    if is_compressed_note(path) {
+        Ok(Box::new(flate2::read::GzDecoder::new(file)))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+/// Read a whole note as text, decompressing `.md.gz` notes.
+fn read_note_text(path: &Path) -> Result<String> {
+    let mut content = String::new();
+    open_note(path)
+        .and_then(|mut reader| reader.read_to_string(&mut content))
+        .with_context(|| format!("Failed to read file: {}", path.display()))?;
+    Ok(content)
+}
+
+/// The markdown files found by a walk, as they are found.
+fn markdown_files(walk: WalkBuilder) -> impl Iterator<Item = PathBuf> {
+    walk.build()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().extension().map(|ext| ext == "md").unwrap_or(false))
+        .map(|e| e.into_path())
+}
+
+/// A note picked uniformly at random from the vaults, by reservoir sampling as they are
+/// walked so paths aren't collected up front. Hidden notes and the trash are skipped.
+/// With a `seed`, the same vaults always give the same note. None if there are no notes.
+pub fn random_note(vaults: &[Vault], seed: Option<u64>, trash_dir: &str) -> Option<String> {
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    };
+
+    let mut chosen = None;
+    let mut seen = 0;
+    for &(name, root) in vaults {
+        let mut walk = markdown_walk(root, false, trash_dir);
+        if seed.is_some() {
+            // Directory order varies between filesystems
+            walk.sort_by_file_name(|a, b| a.cmp(b));
+        }
+        for path in markdown_files(walk) {
+            // Keep the n-th note with probability 1/n
+            if rng.random_range(0..=seen) == 0 {
+                chosen = Some((name, root, path));
+            }
+            seen += 1;
+        }
+    }
+    chosen.map(|(name, root, path)| vault_path(name, relative_path(root, &path)))
+}
+
+/// A note modified after the time given to `changed_since`.
+#[derive(Debug, Serialize)]
+pub struct ChangedNote {
+    pub path: String,
+    /// Modification time in seconds since the Unix epoch, with a fractional part
+    pub mtime: f64,
+}
+
+/// Notes modified after `since` (Unix seconds), oldest change first. Passing the newest
+/// `mtime` seen back as `since` returns only later changes. Deleted notes aren't reported.
+pub fn changed_since(root: &Path, since: f64, trash_dir: &str) -> Vec<ChangedNote> {
+    let mut changed: Vec<ChangedNote> = collect_markdown_files(root, trash_dir)
+        .par_iter()
+        .filter_map(|path| {
+            let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+            let mtime = modified.duration_since(UNIX_EPOCH).ok()?.as_secs_f64();
+            Some(ChangedNote {
+                path: relative_path(root, path),
+                mtime,
+            })
+        })
+        .filter(|note| note.mtime > since)
+        .collect();
+    changed.sort_by(|a, b| {
+        a.mtime
+            .total_cmp(&b.mtime)
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    changed
+}
+
+/// File name date format `note_neighbors` uses unless a call gives another.
+pub const DEFAULT_NEIGHBOR_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// The dated notes either side of a note, from `note_neighbors`.
+#[derive(Debug, Serialize)]
+pub struct Neighbors {
+    pub previous: Option<String>,
+    pub next: Option<String>,
+}
+
+/// The closest earlier and later notes in the same folder as `path` whose file names
+/// (without `.md`) are dates in `date_format`, e.g. `%Y-%m-%d`. `path` must be named
+/// that way too, but needn't exist, so the neighbors of a day without a note can be found.
+pub fn note_neighbors(root: &Path, path: &Path, date_format: &str) -> Result<Neighbors> {
+    use chrono::format::{Item, StrftimeItems};
+
+    if StrftimeItems::new(date_format).any(|item| matches!(item, Item::Error)) {
+        anyhow::bail!("Invalid date format: {}", date_format);
+    }
+    let date_of = |path: &Path| {
+        let name = path.file_name()?.to_str()?.strip_suffix(".md")?;
+        NaiveDate::parse_from_str(name, date_format).ok()
+    };
+    let Some(date) = date_of(path) else {
+        anyhow::bail!(
+            "Note name is not a date in the format {}: {}",
+            date_format,
+            path.display()
+        );
+    };
+
+    let dir = path.parent().unwrap_or(root);
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Neighbors {
+                previous: None,
+                next: None,
+            })
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read directory: {}", dir.display()))
+        }
+    };
+
+    let mut previous: Option<(NaiveDate, PathBuf)> = None;
+    let mut next: Option<(NaiveDate, PathBuf)> = None;
+    for entry in entries.filter_map(|e| e.ok()) {
+        let candidate = entry.path();
+        let Some(other) = date_of(&candidate).filter(|_| candidate.is_file()) else {
+            continue;
+        };
+        if other < date && previous.as_ref().is_none_or(|(d, _)| other > *d) {
+            previous = Some((other, candidate));
+        } else if other > date && next.as_ref().is_none_or(|(d, _)| other < *d) {
+            next = Some((other, candidate));
+        }
+    }
+
+    Ok(Neighbors {
+        previous: previous.map(|(_, p)| relative_path(root, &p)),
+        next: next.map(|(_, p)| relative_path(root, &p)),
+    })
+}
+
+/// The vault's notes as a nested tree, directories first and then alphabetical.
+/// Built from the same walk as searches, so directories without notes are left out.
+pub fn get_tree(root: &Path, trash_dir: &str) -> TreeNode {
+    let mut tree = TreeNode {
+        name: root
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        path: String::new(),
+        is_dir: true,
+        children: Vec::new(),
+    };
+
+    for file in collect_markdown_files(root, trash_dir) {
+        let relative = file.strip_prefix(root).unwrap_or(&file);
+        let parts: Vec<String> = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+
+        let mut node = &mut tree;
+        for (i, part) in parts.iter().enumerate() {
+            let is_dir = i + 1 < parts.len();
+            let index = match node
+                .children
+                .iter()
+                .position(|c| c.name == *part && c.is_dir == is_dir)
+            {
+                Some(index) => index,
+                None => {
+                    node.children.push(TreeNode {
+                        name: part.clone(),
+                        path: parts[..=i].join("/"),
+                        is_dir,
+                        children: Vec::new(),
+                    });
+                    node.children.len() - 1
+                }
+            };
+            node = &mut node.children[index];
+        }
+    }
+
+    fn sort(node: &mut TreeNode) {
+        node.children
+            .sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+        node.children.iter_mut().for_each(sort);
+    }
+    sort(&mut tree);
+    tree
+}
+
+/// `get_tree` for several named vaults at once: an unnamed root with each vault's tree
+/// as a child named after it, and every path prefixed with `name:`.
+pub fn get_vault_trees(vaults: &[(&str, &Path)], trash_dir: &str) -> TreeNode {
+    fn prefix(node: &mut TreeNode, name: &str) {
+        node.path = format!("{}:{}", name, node.path);
+        node.children
+            .iter_mut()
+            .for_each(|child| prefix(child, name));
+    }
+
+    let children = vaults
+        .iter()
+        .map(|&(name, root)| {
+            let mut tree = get_tree(root, trash_dir);
+            prefix(&mut tree, name);
+            tree.name = name.to_string();
+            tree
+        })
+        .collect();
+    TreeNode {
+        name: String::new(),
+        path: String::new(),
+        is_dir: true,
+        children,
+    }
+}
+
+/// Path of a note relative to root, as reported in results.
+pub(crate) fn relative_path(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Markdown parse options with frontmatter enabled, so it is never mistaken for body content.
+fn parse_options() -> ParseOptions {
+    ParseOptions {
         constructs: Constructs {
             frontmatter: true,
             ..Constructs::default()
         },
         ..ParseOptions::default()
-    };
+    }
+}
+
+/// Syntax of a note's frontmatter block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrontmatterFormat {
+    /// `---` delimited YAML (the default)
+    #[default]
+    Yaml,
+    /// `+++` delimited TOML, as used by Hugo
+    Toml,
+    /// A leading JSON object, as used by Hugo, or one fenced by `;;;` or ```` ```json ````
+    Json,
+}
 
-    let ast = markdown::to_mdast(content, &options).ok()?;
+impl FrontmatterFormat {
+    /// Parse a STUMBLING_FRONTMATTER_FORMAT value.
+    pub fn parse(value: &str) -> Result<Self> {
+        Self::from_name(value).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Invalid STUMBLING_FRONTMATTER_FORMAT: {} (expected yaml, toml, or json)",
+                value
+            )
+        })
+    }
+
+    /// Parse a comma-separated STUMBLING_FRONTMATTER_DETECT list, e.g. `yaml,toml`.
+    pub fn parse_list(value: &str) -> Result<Vec<Self>> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                Self::from_name(name).ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Invalid STUMBLING_FRONTMATTER_DETECT entry: {} (expected yaml, toml, or json)",
+                        name
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// The name used in STUMBLING_FRONTMATTER_FORMAT and STUMBLING_FRONTMATTER_DETECT.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Yaml => "yaml",
+            Self::Toml => "toml",
+            Self::Json => "json",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "yaml" => Some(Self::Yaml),
+            "toml" => Some(Self::Toml),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+const ALL_FRONTMATTER_FORMATS: &[FrontmatterFormat] = &[
+    FrontmatterFormat::Yaml,
+    FrontmatterFormat::Toml,
+    FrontmatterFormat::Json,
+];
+
+/// How notes are read and written, from the server's STUMBLING_* settings.
+#[derive(Debug, Clone)]
+pub struct NoteOptions {
+    /// Largest note `write_note` accepts, in bytes (STUMBLING_MAX_NOTE_BYTES). None is unlimited.
+    pub max_note_bytes: Option<u64>,
+    /// How many times renames and writes are retried (STUMBLING_FS_RETRIES)
+    pub fs_retries: u32,
+    /// Format for frontmatter written to notes that don't have any yet
+    /// (STUMBLING_FRONTMATTER_FORMAT)
+    pub frontmatter_format: FrontmatterFormat,
+    /// Formats recognized as frontmatter (STUMBLING_FRONTMATTER_DETECT)
+    pub frontmatter_detect: Vec<FrontmatterFormat>,
+}
+
+impl Default for NoteOptions {
+    fn default() -> Self {
+        Self {
+            max_note_bytes: None,
+            fs_retries: DEFAULT_FS_RETRIES,
+            frontmatter_format: FrontmatterFormat::default(),
+            frontmatter_detect: ALL_FRONTMATTER_FORMATS.to_vec(),
+        }
+    }
+}
+
+/// A frontmatter block split from the body of a note.
+struct Frontmatter {
+    format: FrontmatterFormat,
+    /// Opening and closing lines of JSON fenced by `;;;` or ```` ```json ````
+    fence: Option<(&'static str, &'static str)>,
+    /// The block's text, without delimiters
+    raw: String,
+    /// Byte offset just past the closing delimiter
+    end: usize,
+    body: String,
+}
+
+impl Frontmatter {
+    /// Parse the block into JSON according to its format.
+    fn metadata(&self) -> Result<serde_json::Value> {
+        match self.format {
+            FrontmatterFormat::Yaml => {
+                serde_yaml_ng::from_str(&self.raw).context("Invalid YAML frontmatter")
+            }
+            FrontmatterFormat::Toml => {
+                let table: toml::Table =
+                    toml::from_str(&self.raw).context("Invalid TOML frontmatter")?;
+                Ok(toml_to_json(toml::Value::Table(table)))
+            }
+            FrontmatterFormat::Json => {
+                serde_json::from_str(&self.raw).context("Invalid JSON frontmatter")
+            }
+        }
+    }
+}
+
+/// Convert TOML to JSON, writing datetimes as their TOML strings.
+fn toml_to_json(value: toml::Value) -> serde_json::Value {
+    match value {
+        toml::Value::String(s) => s.into(),
+        toml::Value::Integer(i) => i.into(),
+        toml::Value::Float(f) => f.into(),
+        toml::Value::Boolean(b) => b.into(),
+        toml::Value::Datetime(dt) => dt.to_string().into(),
+        toml::Value::Array(items) => items.into_iter().map(toml_to_json).collect(),
+        toml::Value::Table(table) => table
+            .into_iter()
+            .map(|(key, value)| (key, toml_to_json(value)))
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+    }
+}
+
+/// Drop nulls, which TOML can't represent.
+fn strip_nulls(value: &serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Array(items) => items
+            .iter()
+            .filter(|v| !v.is_null())
+            .map(strip_nulls)
+            .collect(),
+        serde_json::Value::Object(map) => map
+            .iter()
+            .filter(|(_, v)| !v.is_null())
+            .map(|(k, v)| (k.clone(), strip_nulls(v)))
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+        other => other.clone(),
+    }
+}
+
+/// Parse frontmatter from markdown content.
+/// A note can start with `---` YAML (found via the markdown-rs AST), `+++` TOML,
+/// a JSON object, or JSON fenced by `;;;` or ```` ```json ````. Only a block at the
+/// very start of the note counts, so whichever style comes first wins and any later
+/// block is treated as body.
+fn parse_frontmatter(content: &str, options: &NoteOptions) -> Option<Frontmatter> {
+    parse_frontmatter_detecting(content, &options.frontmatter_detect)
+}
+
+/// `parse_frontmatter` recognizing only the given formats; other blocks are body.
+fn parse_frontmatter_detecting(
+    content: &str,
+    formats: &[FrontmatterFormat],
+) -> Option<Frontmatter> {
+    let json = formats.contains(&FrontmatterFormat::Json);
+    if content.starts_with('{') {
+        return json.then(|| parse_json_frontmatter(content)).flatten();
+    }
+    let first_line = content.lines().next().unwrap_or_default().trim_end();
+    match first_line {
+        "+++" if formats.contains(&FrontmatterFormat::Toml) => {
+            return parse_fenced_frontmatter(content, ("+++", "+++"), FrontmatterFormat::Toml)
+        }
+        ";;;" if json => {
+            return parse_fenced_frontmatter(content, (";;;", ";;;"), FrontmatterFormat::Json)
+        }
+        "```json" if json => {
+            return parse_fenced_frontmatter(content, ("```json", "```"), FrontmatterFormat::Json)
+        }
+        _ => {}
+    }
+    if !formats.contains(&FrontmatterFormat::Yaml) {
+        return None;
+    }
+
+    let ast = markdown::to_mdast(content, &parse_options()).ok()?;
 
     if let Node::Root(root) = ast {
         for child in &root.children {
             if let Node::Yaml(yaml) = child {
                 // Get the end position of frontmatter to extract body
-                let body = if let Some(pos) = &yaml.position {
-                    let end_offset = pos.end.offset;
-                    content[end_offset..].trim_start().to_string()
-                } else {
-                    String::new()
-                };
-                return Some((yaml.value.clone(), body));
+                let end = yaml
+                    .position
+                    .as_ref()
+                    .map_or(content.len(), |pos| pos.end.offset);
+                return Some(Frontmatter {
+                    format: FrontmatterFormat::Yaml,
+                    fence: None,
+                    raw: yaml.value.clone(),
+                    end,
+                    body: body_after_delimiter(&content[end..]).to_string(),
+                });
             }
         }
     }
     None
 }
 
-/// Read a note from the given path.
-/// If `should_parse` is true, separates YAML frontmatter from body.
-pub fn read_note(path: &Path, should_parse: bool) -> Result<String> {
-    let content = fs::read_to_string(path)
-        .with_context(|| format!("Failed to read file: {}", path.display()))?;
+/// Parse a leading JSON object as frontmatter. It must be followed by a line break or EOF.
+fn parse_json_frontmatter(content: &str) -> Option<Frontmatter> {
+    let mut stream = serde_json::Deserializer::from_str(content).into_iter::<serde_json::Value>();
+    let Some(Ok(serde_json::Value::Object(_))) = stream.next() else {
+        return None;
+    };
 
-    if !should_parse {
-        return Ok(content);
+    let end_offset = stream.byte_offset();
+    let rest = &content[end_offset..];
+    let after_object = rest.trim_start_matches([' ', '\t']);
+    if !(after_object.is_empty() || after_object.starts_with(['\n', '\r'])) {
+        return None;
     }
 
-    // Parse frontmatter using markdown-rs AST
-    if let Some((yaml_str, body)) = parse_frontmatter(&content) {
-        if let Ok(meta) = serde_yaml_ng::from_str::<serde_json::Value>(&yaml_str) {
-            let output = serde_json::json!({
-                "metadata": meta,
-                "body": body
+    Some(Frontmatter {
+        format: FrontmatterFormat::Json,
+        fence: None,
+        raw: content[..end_offset].to_string(),
+        end: end_offset,
+        body: body_after_delimiter(rest).to_string(),
+    })
+}
+
+/// Parse a block from the first line, the opening line of `fence`, up to a line that is
+/// just its closing line. Unclosed blocks aren't frontmatter.
+fn parse_fenced_frontmatter(
+    content: &str,
+    fence: (&'static str, &'static str),
+    format: FrontmatterFormat,
+) -> Option<Frontmatter> {
+    let close = fence.1;
+    let mut lines = content.split_inclusive('\n');
+    let start = lines.next()?.len();
+
+    let mut offset = start;
+    for line in lines {
+        if line.trim_end() == close {
+            return Some(Frontmatter {
+                format,
+                // TOML is always fenced by `+++`, so only JSON needs to remember its fence
+                fence: (format == FrontmatterFormat::Json).then_some(fence),
+                raw: content[start..offset].to_string(),
+                end: offset + close.len(),
+                body: body_after_delimiter(&content[offset + close.len()..]).to_string(),
             });
-            return Ok(serde_json::to_string_pretty(&output)?);
         }
+        offset += line.len();
+    }
+    None
+}
+
+/// The body following the frontmatter's closing line: trailing spaces and the line break
+/// ending that line are dropped. Blank lines and indentation after it belong to the body,
+/// so `format_with_frontmatter` doesn't add a separator of its own.
+fn body_after_delimiter(rest: &str) -> &str {
+    let rest = rest.trim_start_matches([' ', '\t']);
+    rest.strip_prefix("\r\n")
+        .or_else(|| rest.strip_prefix('\n'))
+        .unwrap_or(rest)
+}
+
+/// Read a note's frontmatter as JSON, or None if it has no valid frontmatter.
+pub(crate) fn read_metadata(path: &Path, options: &NoteOptions) -> Option<serde_json::Value> {
+    read_frontmatter_only(path, options).ok().flatten()
+}
+
+/// Join a relative note path onto root, rejecting absolute paths and `..` components
+/// so callers can't reach outside the vault.
+pub fn resolve_note_path(root: &Path, relative: &str) -> Result<PathBuf> {
+    let relative = Path::new(relative);
+
+    let escapes = relative.components().any(|c| {
+        matches!(
+            c,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_)
+        )
+    });
+    if escapes {
+        anyhow::bail!(NoteError::InvalidPath(format!(
+            "Path must be relative to the vault root: {}",
+            relative.display()
+        )));
+    }
+
+    Ok(root.join(relative))
+}
+
+/// Parse a date from `YYYY-MM-DD` or RFC3339. RFC3339 values use their own date part.
+fn parse_date(s: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .ok()
+        .or_else(|| DateTime::parse_from_rfc3339(s).ok().map(|d| d.date_naive()))
+        .or_else(|| {
+            // Local times without an offset, as many editors write them
+            ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+                .iter()
+                .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+                .map(|d| d.date())
+        })
+}
+
+/// Read a note from the given path, decompressing `.md.gz` notes.
+/// If `should_parse` is true, separates YAML frontmatter from body.
+pub fn read_note(path: &Path, should_parse: bool, options: &NoteOptions) -> Result<String> {
+    let content = read_note_text(path)?;
+
+    if !should_parse {
+        return Ok(content);
+    }
+
+    // Parse frontmatter using markdown-rs AST
+    if let Some(frontmatter) = parse_frontmatter(&content, options) {
+        if let Ok(meta) = frontmatter.metadata() {
+            let output = serde_json::json!({
+                "metadata": meta,
+                "body": frontmatter.body
+            });
+            return Ok(serde_json::to_string_pretty(&output)?);
+        }
+    }
+
+    Ok(content)
+}
+
+/// Parse only a note's frontmatter, or None if it has none.
+/// Unlike `read_metadata`, frontmatter that doesn't parse is an error.
+pub fn get_frontmatter(path: &Path, options: &NoteOptions) -> Result<Option<serde_json::Value>> {
+    read_frontmatter_only(path, options)
+}
+
+/// Parse a note's frontmatter without reading its body: the file is read line by line
+/// only up to the closing delimiter. JSON object frontmatter has no closing line, so
+/// those notes are read whole. Frontmatter that doesn't parse is an error.
+pub fn read_frontmatter_only(
+    path: &Path,
+    options: &NoteOptions,
+) -> Result<Option<serde_json::Value>> {
+    let Some(head) = read_frontmatter_head(path)? else {
+        return Ok(None);
+    };
+
+    parse_frontmatter(&head, options)
+        .map(|frontmatter| {
+            frontmatter
+                .metadata()
+                .map_err(|e| anyhow::anyhow!("{:#} in {}", e, path.display()))
+        })
+        .transpose()
+}
+
+/// The start of a note up to and including its frontmatter's closing line, or None if
+/// the note doesn't start with a frontmatter block.
+fn read_frontmatter_head(path: &Path) -> Result<Option<String>> {
+    let file =
+        open_note(path).with_context(|| format!("Failed to read file: {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let read_line = |reader: &mut BufReader<_>, head: &mut String| {
+        reader
+            .read_line(head)
+            .with_context(|| format!("Failed to read file: {}", path.display()))
+    };
+
+    let mut head = String::new();
+    read_line(&mut reader, &mut head)?;
+    let close = match head.trim_end() {
+        "---" => "---",
+        "+++" => "+++",
+        ";;;" => ";;;",
+        "```json" => "```",
+        first if first.starts_with('{') => {
+            reader
+                .read_to_string(&mut head)
+                .with_context(|| format!("Failed to read file: {}", path.display()))?;
+            return Ok(Some(head));
+        }
+        _ => return Ok(None),
+    };
+
+    loop {
+        let start = head.len();
+        if read_line(&mut reader, &mut head)? == 0 {
+            // Unclosed blocks aren't frontmatter
+            return Ok(None);
+        }
+        if head[start..].trim_end() == close {
+            return Ok(Some(head));
+        }
+    }
+}
+
+/// A note's frontmatter block exactly as written, alongside its body.
+#[derive(Debug, Serialize)]
+pub struct RawFrontmatter {
+    /// The block's text without its delimiters, or None if the note has no frontmatter
+    pub frontmatter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<FrontmatterFormat>,
+    pub body: String,
+}
+
+/// Read a note's frontmatter as its original text instead of re-serialized JSON,
+/// keeping comments, quoting, and anchors intact.
+pub fn read_raw_frontmatter(path: &Path, options: &NoteOptions) -> Result<RawFrontmatter> {
+    let content = read_note_text(path)?;
+
+    Ok(match parse_frontmatter(&content, options) {
+        Some(frontmatter) => RawFrontmatter {
+            frontmatter: Some(frontmatter.raw),
+            format: Some(frontmatter.format),
+            body: frontmatter.body,
+        },
+        None => RawFrontmatter {
+            frontmatter: None,
+            format: None,
+            body: content,
+        },
+    })
+}
+
+/// Where a note's frontmatter and body are, for clients that splice edits into the text.
+#[derive(Debug, Serialize)]
+pub struct NoteSpans {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<FrontmatterFormat>,
+    /// Bytes of the frontmatter block, delimiters included, or None without frontmatter
+    pub frontmatter_byte_range: Option<std::ops::Range<usize>>,
+    /// Byte offset where the body begins
+    pub body_byte_offset: usize,
+    /// Number of lines before the body, so the body begins on line `body_line_offset + 1`
+    pub body_line_offset: usize,
+}
+
+/// Locate a note's frontmatter block and the start of its body.
+/// The offsets match what `read_note` strips, including the blank separator line.
+pub fn read_note_spans(path: &Path, options: &NoteOptions) -> Result<NoteSpans> {
+    let content = read_note_text(path)?;
+
+    Ok(match parse_frontmatter(&content, options) {
+        Some(frontmatter) => {
+            // The body is always the tail of the content
+            let body_byte_offset = content.len() - frontmatter.body.len();
+            NoteSpans {
+                format: Some(frontmatter.format),
+                frontmatter_byte_range: Some(0..frontmatter.end),
+                body_byte_offset,
+                body_line_offset: content[..body_byte_offset].matches('\n').count(),
+            }
+        }
+        None => NoteSpans {
+            format: None,
+            frontmatter_byte_range: None,
+            body_byte_offset: 0,
+            body_line_offset: 0,
+        },
+    })
+}
+
+/// Read only the body of a note, with any frontmatter block removed.
+/// Notes without frontmatter are returned unchanged.
+pub fn read_body(path: &Path, options: &NoteOptions) -> Result<String> {
+    let content = read_note_text(path)?;
+
+    Ok(match parse_frontmatter(&content, options) {
+        Some(frontmatter) => frontmatter.body,
+        None => content,
+    })
+}
+
+/// Render a note's body to HTML, with GFM tables, strikethrough, task lists, and autolinks.
+/// Raw HTML in the note is escaped. With `include_frontmatter`, the metadata is rendered
+/// first as a two-column table.
+pub fn render_html(
+    path: &Path,
+    include_frontmatter: bool,
+    options: &NoteOptions,
+) -> Result<String> {
+    let content = read_note_text(path)?;
+
+    let (meta, body) = match parse_frontmatter(&content, options) {
+        Some(frontmatter) => (frontmatter.metadata().ok(), frontmatter.body),
+        None => (None, content),
+    };
+
+    let mut html = String::new();
+    if include_frontmatter {
+        if let Some(serde_json::Value::Object(meta)) = meta {
+            html.push_str("<table class=\"frontmatter\">\n");
+            for (key, value) in &meta {
+                let value = match value {
+                    serde_json::Value::String(s) => s.clone(),
+                    serde_json::Value::Array(items) => items
+                        .iter()
+                        .map(|item| {
+                            item.as_str()
+                                .map_or_else(|| item.to_string(), str::to_string)
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    other => other.to_string(),
+                };
+                html.push_str(&format!(
+                    "<tr><th>{}</th><td>{}</td></tr>\n",
+                    escape_html(key),
+                    escape_html(&value)
+                ));
+            }
+            html.push_str("</table>\n");
+        }
+    }
+
+    let rendered = markdown::to_html_with_options(&body, &markdown::Options::gfm())
+        .map_err(|e| anyhow::anyhow!("Failed to render markdown: {}", e))?;
+    html.push_str(&rendered);
+    Ok(html)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Read several notes in parallel, relative to root.
+/// Failures are reported per entry instead of failing the whole batch.
+pub fn read_many(
+    root: &Path,
+    paths: &[String],
+    should_parse: bool,
+    options: &NoteOptions,
+) -> Vec<ReadManyEntry> {
+    paths
+        .par_iter()
+        .map(|rel| {
+            match resolve_note_path(root, rel).and_then(|p| read_note(&p, should_parse, options)) {
+                Ok(content) => ReadManyEntry {
+                    path: rel.clone(),
+                    content: Some(content),
+                    error: None,
+                },
+                Err(e) => ReadManyEntry {
+                    path: rel.clone(),
+                    content: None,
+                    error: Some(format!("{:#}", e)),
+                },
+            }
+        })
+        .collect()
+}
+
+/// Extract the heading hierarchy of a note.
+/// Each heading contains the headings of deeper levels that follow it.
+pub fn outline(path: &Path) -> Result<Vec<OutlineEntry>> {
+    let content = read_note_text(path)?;
+
+    let mut roots: Vec<OutlineEntry> = Vec::new();
+    // Stack of open headings, each one deeper than the one below it
+    let mut stack: Vec<OutlineEntry> = Vec::new();
+
+    for heading in parse_headings(&content)? {
+        let entry = OutlineEntry {
+            level: heading.level,
+            text: heading.text,
+            line_number: heading.line_number,
+            children: Vec::new(),
+        };
+
+        while stack.last().is_some_and(|open| open.level >= entry.level) {
+            close_outline_entry(&mut stack, &mut roots);
+        }
+        stack.push(entry);
+    }
+
+    while !stack.is_empty() {
+        close_outline_entry(&mut stack, &mut roots);
+    }
+
+    Ok(roots)
+}
+
+/// A top-level heading found in a note.
+struct Heading {
+    level: u8,
+    text: String,
+    line_number: usize,
+    offset: usize,
+}
+
+/// Collect the top-level headings of a note in document order.
+fn parse_headings(content: &str) -> Result<Vec<Heading>> {
+    let ast = markdown::to_mdast(content, &parse_options())
+        .map_err(|e| anyhow::anyhow!("Failed to parse markdown: {}", e))?;
+
+    let Node::Root(root) = ast else {
+        return Ok(Vec::new());
+    };
+
+    Ok(root
+        .children
+        .iter()
+        .filter_map(|child| {
+            let Node::Heading(heading) = child else {
+                return None;
+            };
+            let start = heading.position.as_ref().map(|p| &p.start);
+            Some(Heading {
+                level: heading.depth,
+                text: child.to_string(),
+                line_number: start.map(|p| p.line).unwrap_or(0),
+                offset: start.map(|p| p.offset).unwrap_or(0),
+            })
+        })
+        .collect())
+}
+
+/// Read the section under a heading, up to the next heading of the same or higher level.
+/// The heading is matched case-insensitively, and may be a path like `Parent > Child`
+/// to pick a heading nested (at any depth) under the given ancestors. If several match,
+/// the first is returned and the line numbers of the others are listed.
+pub fn read_section(path: &Path, heading: &str) -> Result<Section> {
+    let content = read_note_text(path)?;
+
+    let headings = parse_headings(&content)?;
+    let target: Vec<String> = heading
+        .split('>')
+        .map(|part| part.trim().to_lowercase())
+        .collect();
+    let Some((name, ancestors)) = target.split_last() else {
+        anyhow::bail!(NoteError::NotFound(format!(
+            "Heading not found: {}",
+            heading
+        )));
+    };
+
+    // Texts of the headings enclosing each heading, outermost first
+    let mut stack: Vec<&Heading> = Vec::new();
+    let mut matches = headings.iter().enumerate().filter(|(_, h)| {
+        while stack.last().is_some_and(|open| open.level >= h.level) {
+            stack.pop();
+        }
+        let is_match = h.text.trim().to_lowercase() == *name
+            && is_subsequence(
+                ancestors,
+                stack.iter().map(|a| a.text.trim().to_lowercase()),
+            );
+        stack.push(h);
+        is_match
+    });
+
+    let Some((index, found)) = matches.next() else {
+        anyhow::bail!(NoteError::NotFound(format!(
+            "Heading not found: {}",
+            heading
+        )));
+    };
+    let other_matches = matches.map(|(_, h)| h.line_number).collect();
+
+    let end = headings[index + 1..]
+        .iter()
+        .find(|h| h.level <= found.level)
+        .map(|h| h.offset)
+        .unwrap_or(content.len());
+
+    Ok(Section {
+        heading: found.text.clone(),
+        line_number: found.line_number,
+        content: content[found.offset..end].trim_end().to_string(),
+        other_matches,
+    })
+}
+
+/// True if every item of `wanted` appears in `items`, in order.
+fn is_subsequence(wanted: &[String], mut items: impl Iterator<Item = String>) -> bool {
+    wanted.iter().all(|w| items.any(|item| item == *w))
+}
+
+/// Split a note into one new note per heading of the given level, each named after
+/// its heading and ending with a wikilink back to the original. Text before the first
+/// such heading stays in the original. If `replace_with_toc` is true, the split
+/// sections in the original are replaced with a list of wikilinks to the new notes.
+/// Returns the paths of the new notes in document order.
+pub fn split_at_headings(
+    path: &Path,
+    level: u8,
+    replace_with_toc: bool,
+    options: &NoteOptions,
+) -> Result<Vec<PathBuf>> {
+    let content = read_note_text(path)?;
+
+    let headings = parse_headings(&content)?;
+    let splits: Vec<usize> = (0..headings.len())
+        .filter(|&i| headings[i].level == level)
+        .collect();
+    let Some(&first) = splits.first() else {
+        anyhow::bail!(
+            "No level {} headings to split at: {}",
+            level,
+            path.display()
+        );
+    };
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let original = path
+        .file_stem()
+        .context("Note has no file name")?
+        .to_string_lossy();
+
+    let mut taken = BTreeSet::new();
+    let mut sections = Vec::new();
+    for &index in &splits {
+        let heading = &headings[index];
+        let end = headings[index + 1..]
+            .iter()
+            .find(|h| h.level <= level)
+            .map(|h| h.offset)
+            .unwrap_or(content.len());
+
+        // Avoid clobbering existing notes and sections with the same title
+        let slug = slugify(&heading.text);
+        let mut name = slug.clone();
+        let mut n = 1;
+        while taken.contains(&name) || dir.join(format!("{}.md", name)).exists() {
+            n += 1;
+            name = format!("{}-{}", slug, n);
+        }
+        taken.insert(name.clone());
+
+        let body = content[heading.offset..end].trim_end();
+        sections.push((name, heading.offset, end, body));
+    }
+
+    let mut created = Vec::new();
+    for (name, _, _, body) in &sections {
+        let sub_note = dir.join(format!("{}.md", name));
+        write_note(
+            &sub_note,
+            &format!("{}\n\nSplit from [[{}]]\n", body, original),
+            options,
+        )?;
+        created.push(sub_note);
+    }
+
+    if replace_with_toc {
+        let mut toc = content[..headings[first].offset].to_string();
+        let mut last_end = headings[first].offset;
+        for (name, start, end, _) in &sections {
+            // Keep anything between split sections, such as higher-level headings
+            toc.push_str(&content[last_end..*start]);
+            toc.push_str(&format!("- [[{}]]\n", name));
+            last_end = *end;
+        }
+        toc.push_str(&content[last_end..]);
+        write_note(path, &toc, options)?;
+    }
+
+    Ok(created)
+}
+
+/// Link `from` to `to` with a wikilink listed under the `heading` section of `from`,
+/// adding the section as `## heading` at the end of the note if it has none. A note that
+/// already links to the other anywhere is left alone. If `bidirectional`, `to` gets a
+/// link back to `from` the same way. Returns the relative paths of the notes changed.
+pub fn link_notes(
+    root: &Path,
+    from: &Path,
+    to: &Path,
+    heading: &str,
+    bidirectional: bool,
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Result<Vec<String>> {
+    for path in [from, to] {
+        if !path.is_file() {
+            anyhow::bail!(NoteError::NotFound(format!(
+                "Note not found: {}",
+                path.display()
+            )));
+        }
+    }
+    if from == to {
+        anyhow::bail!("Cannot link a note to itself: {}", from.display());
+    }
+
+    let mut changed = Vec::new();
+    let mut pairs = vec![(from, to)];
+    if bidirectional {
+        pairs.push((to, from));
+    }
+    for (source, target) in pairs {
+        if add_link_under_heading(root, source, target, heading, trash_dir, options)? {
+            changed.push(relative_path(root, source));
+        }
+    }
+    Ok(changed)
+}
+
+/// Add a `- [[target]]` item to the end of the `heading` section of the note at `path`,
+/// unless it already links to `target`. Returns whether the note was changed.
+fn add_link_under_heading(
+    root: &Path,
+    path: &Path,
+    target: &Path,
+    heading: &str,
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Result<bool> {
+    let target = relative_path(root, target);
+    let already_linked = note_links(root, path, trash_dir)?
+        .iter()
+        .any(|link| link.resolved.as_deref() == Some(target.as_str()));
+    if already_linked {
+        return Ok(false);
+    }
+
+    let content = read_note_text(path)?;
+    let item = format!("- [[{}]]", target.strip_suffix(".md").unwrap_or(&target));
+
+    let headings = parse_headings(&content)?;
+    let wanted = heading.trim().to_lowercase();
+    let updated = match headings
+        .iter()
+        .position(|h| h.text.trim().to_lowercase() == wanted)
+    {
+        Some(index) => {
+            let found = &headings[index];
+            let end = headings[index + 1..]
+                .iter()
+                .find(|h| h.level <= found.level)
+                .map(|h| h.offset)
+                .unwrap_or(content.len());
+            let section = content[..end].trim_end();
+            // An empty section needs a blank line after its heading
+            let separator = if section[found.offset..].contains('\n') {
+                "\n"
+            } else {
+                "\n\n"
+            };
+            let rest = &content[end..];
+            if rest.is_empty() {
+                format!("{}{}{}\n", section, separator, item)
+            } else {
+                format!("{}{}{}\n\n{}", section, separator, item, rest)
+            }
+        }
+        None => {
+            let body = content.trim_end();
+            if body.is_empty() {
+                format!("## {}\n\n{}\n", heading, item)
+            } else {
+                format!("{}\n\n## {}\n\n{}\n", body, heading, item)
+            }
+        }
+    };
+
+    write_note(path, &updated, options)?;
+    Ok(true)
+}
+
+/// Combine notes, in order, into `dest`. Each body is placed under a `#` heading with the
+/// source's title (or file name). Frontmatter is merged with later keys overriding earlier
+/// ones, except arrays, which are unioned. `dest` may be one of the sources, but must not
+/// be an unrelated existing note. If `trash_dir` is given, the sources are moved there.
+/// Returns where each trashed source ended up.
+pub fn merge_notes(
+    root: &Path,
+    sources: &[PathBuf],
+    dest: &Path,
+    trash_dir: Option<&str>,
+    options: &NoteOptions,
+) -> Result<Vec<MovedNote>> {
+    if sources.is_empty() {
+        anyhow::bail!("No notes to merge");
+    }
+    if dest.exists() && !sources.iter().any(|s| s == dest) {
+        anyhow::bail!(NoteError::Conflict(format!(
+            "Destination already exists: {}",
+            relative_path(root, dest)
+        )));
+    }
+
+    let mut metadata = serde_json::Map::new();
+    let mut sections = Vec::new();
+    for source in sources {
+        let body = read_body(source, options)?;
+        let meta = read_metadata(source, options);
+
+        let title = meta
+            .as_ref()
+            .and_then(|m| m.get("title"))
+            .and_then(|t| t.as_str())
+            .map(str::to_string)
+            .or_else(|| source.file_stem().map(|s| s.to_string_lossy().to_string()))
+            .unwrap_or_default();
+        sections.push(format!("# {}\n\n{}", title, body.trim()));
+
+        if let Some(serde_json::Value::Object(meta)) = meta {
+            merge_metadata(&mut metadata, meta);
+        }
+    }
+
+    let body = sections.join("\n\n") + "\n";
+    let content = if metadata.is_empty() {
+        body
+    } else {
+        format_with_frontmatter_for(dest, &serde_json::Value::Object(metadata), &body, options)
+    };
+    write_note(dest, &content, options)?;
+
+    let mut trashed = Vec::new();
+    if let Some(trash_dir) = trash_dir {
+        for source in sources.iter().filter(|s| *s != dest) {
+            let trash_path = move_note_to_trash(root, source, trash_dir, options)?;
+            trashed.push(MovedNote {
+                from: relative_path(root, source),
+                to: relative_path(root, &trash_path),
+            });
+        }
+    }
+
+    Ok(trashed)
+}
+
+/// Merge `overlay` over `base`: nested objects are merged key by key, and any other
+/// value in `overlay`, arrays included, replaces the one in `base`.
+pub fn deep_merge(base: serde_json::Value, overlay: serde_json::Value) -> serde_json::Value {
+    match (base, overlay) {
+        (serde_json::Value::Object(mut base), serde_json::Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                // Merged in place, so existing keys keep their order
+                match base.get_mut(&key) {
+                    Some(existing) => *existing = deep_merge(existing.take(), value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+            serde_json::Value::Object(base)
+        }
+        (_, overlay) => overlay,
+    }
+}
+
+/// Merge `other` into `merged`: later values win, arrays are unioned in order.
+fn merge_metadata(
+    merged: &mut serde_json::Map<String, serde_json::Value>,
+    other: serde_json::Map<String, serde_json::Value>,
+) {
+    for (key, value) in other {
+        match (merged.get_mut(&key), value) {
+            (Some(serde_json::Value::Array(existing)), serde_json::Value::Array(items)) => {
+                for item in items {
+                    if !existing.contains(&item) {
+                        existing.push(item);
+                    }
+                }
+            }
+            (_, value) => {
+                merged.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Filename-safe slug of a heading: lowercase words joined by hyphens.
+fn slugify(text: &str) -> String {
+    let slug = text
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    if slug.is_empty() {
+        "section".to_string()
+    } else {
+        slug
+    }
+}
+
+/// Pop the innermost open heading and attach it to its parent (or the top level).
+fn close_outline_entry(stack: &mut Vec<OutlineEntry>, roots: &mut Vec<OutlineEntry>) {
+    if let Some(entry) = stack.pop() {
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(entry),
+            None => roots.push(entry),
+        }
+    }
+}
+
+/// Extract every outbound link of a note: wikilinks, markdown links, and bare URLs.
+/// Internal links are resolved to notes under root where possible. Links inside
+/// code are ignored. Results are in document order.
+pub fn note_links(root: &Path, path: &Path, trash_dir: &str) -> Result<Vec<NoteLink>> {
+    let content = read_note_text(path)?;
+    let ast = markdown::to_mdast(&content, &link_parse_options())
+        .map_err(|e| anyhow::anyhow!("Failed to parse markdown: {}", e))?;
+
+    let mut links = Vec::new();
+    collect_links(&ast, &content, &mut links);
+    resolve_links(root, path, &mut links, &mut None, trash_dir);
+    Ok(links)
+}
+
+/// Notes that no other note links to, sorted by path. Notes matching `exclude_glob`
+/// (e.g. "daily/**") aren't reported, but their links still count. A note linking to
+/// itself is still an orphan.
+pub fn find_orphans(
+    root: &Path,
+    exclude_glob: Option<&str>,
+    trash_dir: &str,
+) -> Result<Vec<String>> {
+    let exclude = exclude_glob
+        .map(|g| {
+            globset::Glob::new(g)
+                .map(|g| g.compile_matcher())
+                .with_context(|| format!("Invalid glob pattern: {}", g))
+        })
+        .transpose()?;
+
+    let files = collect_markdown_files(root, trash_dir);
+    let stems = &note_stems(root, trash_dir);
+    let linked: BTreeSet<String> = files
+        .par_iter()
+        .flat_map_iter(|path| {
+            let source = relative_path(root, path);
+            let note_dir = path.parent().unwrap_or(root);
+            let mut links = Vec::new();
+            if let Ok(content) = read_note_text(path) {
+                if let Ok(ast) = markdown::to_mdast(&content, &link_parse_options()) {
+                    collect_links(&ast, &content, &mut links);
+                }
+            }
+            links
+                .into_iter()
+                .filter(|link| link.internal)
+                .filter_map(move |link| match link.kind {
+                    LinkKind::Wikilink => resolve_wikilink(root, &link.target, stems),
+                    _ => resolve_relative_link(root, note_dir, &link.target),
+                })
+                .filter(move |target| *target != source)
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    let mut orphans: Vec<String> = files
+        .iter()
+        .map(|path| relative_path(root, path))
+        .filter(|path| !linked.contains(path))
+        .filter(|path| !exclude.as_ref().is_some_and(|glob| glob.is_match(path)))
+        .collect();
+    orphans.sort();
+    Ok(orphans)
+}
+
+fn link_parse_options() -> ParseOptions {
+    ParseOptions {
+        constructs: Constructs {
+            frontmatter: true,
+            ..Constructs::gfm()
+        },
+        ..ParseOptions::gfm()
+    }
+}
+
+/// Fill in `resolved` for the internal links of the note at `path`.
+/// The note name map is built on first use and kept in `stems` for later calls.
+fn resolve_links(
+    root: &Path,
+    path: &Path,
+    links: &mut [NoteLink],
+    stems: &mut Option<BTreeMap<String, Vec<String>>>,
+    trash_dir: &str,
+) {
+    let note_dir = path.parent().unwrap_or(root);
+    for link in links {
+        if !link.internal {
+            continue;
+        }
+        link.resolved = match link.kind {
+            LinkKind::Wikilink => {
+                let stems = stems.get_or_insert_with(|| note_stems(root, trash_dir));
+                resolve_wikilink(root, &link.target, stems)
+            }
+            _ => resolve_relative_link(root, note_dir, &link.target),
+        };
+    }
+}
+
+/// Walk the AST collecting links. Wikilinks aren't markdown, so they're matched in text nodes.
+fn collect_links(node: &Node, content: &str, links: &mut Vec<NoteLink>) {
+    let line_number = node.position().map(|p| p.start.line).unwrap_or(0);
+
+    match node {
+        Node::Link(link) => {
+            // Autolink literals have no brackets in the source
+            let source = node
+                .position()
+                .and_then(|p| content.get(p.start.offset..p.end.offset))
+                .unwrap_or_default();
+            let kind = if source.starts_with('[') || source.starts_with('<') {
+                LinkKind::Markdown
+            } else {
+                LinkKind::Url
+            };
+            let text = node.to_string();
+            links.push(NoteLink {
+                kind,
+                internal: !is_external_url(&link.url),
+                target: link.url.clone(),
+                text: (text != link.url).then_some(text),
+                line_number,
+                resolved: None,
+            });
+            return;
+        }
+        Node::Text(text) => {
+            let wikilink = wikilink_regex();
+            for caps in wikilink.captures_iter(&text.value) {
+                let line_number = line_number
+                    + text.value[..caps.get(0).map_or(0, |m| m.start())]
+                        .matches('\n')
+                        .count();
+                links.push(NoteLink {
+                    kind: LinkKind::Wikilink,
+                    target: caps[1].trim().to_string(),
+                    text: caps.get(2).map(|m| m.as_str().trim().to_string()),
+                    line_number,
+                    internal: true,
+                    resolved: None,
+                });
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(children) = node.children() {
+        for child in children {
+            collect_links(child, content, links);
+        }
+    }
+}
+
+/// Matches `[[target]]`, `[[target#heading]]`, and `[[target|alias]]`, capturing target and alias.
+fn wikilink_regex() -> &'static regex::Regex {
+    static WIKILINK: OnceLock<regex::Regex> = OnceLock::new();
+    WIKILINK.get_or_init(|| {
+        regex::Regex::new(r"\[\[([^\[\]|#]+)(?:#[^\[\]|]*)?(?:\|([^\[\]]*))?\]\]")
+            .expect("wikilink regex is valid")
+    })
+}
+
+fn is_external_url(url: &str) -> bool {
+    url.contains("://") || url.starts_with("mailto:")
+}
+
+/// Map of file stem to the notes with that stem, for resolving wikilinks by name.
+fn note_stems(root: &Path, trash_dir: &str) -> BTreeMap<String, Vec<String>> {
+    let mut stems: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for path in collect_markdown_files(root, trash_dir) {
+        if let Some(stem) = path.file_stem() {
+            stems
+                .entry(stem.to_string_lossy().to_lowercase())
+                .or_default()
+                .push(relative_path(root, &path));
+        }
+    }
+    stems
+}
+
+/// Resolve a wikilink as a vault-relative path first, then by unique note name.
+fn resolve_wikilink(
+    root: &Path,
+    target: &str,
+    stems: &BTreeMap<String, Vec<String>>,
+) -> Option<String> {
+    let with_ext = if target.ends_with(".md") {
+        target.to_string()
+    } else {
+        format!("{}.md", target)
+    };
+    if let Ok(path) = resolve_note_path(root, &with_ext) {
+        if path.is_file() {
+            return Some(relative_path(root, &path));
+        }
+    }
+
+    let name = Path::new(target)
+        .file_stem()?
+        .to_string_lossy()
+        .to_lowercase();
+    match stems.get(&name).map(Vec::as_slice) {
+        Some([only]) => Some(only.clone()),
+        _ => None,
+    }
+}
+
+/// Resolve a markdown link relative to the linking note's directory.
+fn resolve_relative_link(root: &Path, note_dir: &Path, target: &str) -> Option<String> {
+    let target = target.split(['#', '?']).next()?;
+    if target.is_empty() {
+        return None;
+    }
+
+    let path = note_dir.join(target);
+    let path = path.canonicalize().ok()?;
+    let root = root.canonicalize().ok()?;
+    if !path.starts_with(&root) || !path.is_file() {
+        return None;
+    }
+    Some(relative_path(&root, &path))
+}
+
+/// Search results along with the files that couldn't be searched.
+#[derive(Debug, Default, Serialize)]
+pub struct SearchReport {
+    pub results: Vec<SearchResult>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<SkippedFile>,
+    /// Notes searched despite a problem, like invalid UTF-8 decoded lossily
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<SearchWarning>,
+    /// Scan counts and timing, when `include_stats` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<SearchStats>,
+}
+
+/// How much work a search did, for diagnosing slow vaults.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct SearchStats {
+    /// Notes whose lines were read, which stops short of every note once the limit is met
+    pub files_scanned: usize,
+    /// Bytes of those notes read, counting line breaks
+    pub bytes_read: usize,
+    /// Wall time of the search, including walking the vault
+    pub elapsed_ms: u128,
+}
+
+/// A note that was searched, but maybe not exactly as written.
+#[derive(Debug, Serialize)]
+pub struct SearchWarning {
+    pub path: String,
+    pub message: String,
+}
+
+/// A note left out of a search, and why.
+#[derive(Debug, Serialize)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: String,
+}
+
+/// Search for notes matching the query using parallel processing, across the vaults as if
+/// they were one, with each note's path given as `vault_path` does.
+/// Files are streamed line by line. Results are sorted by path and line number, taking
+/// the vaults in the order given.
+/// Oversized, binary, and unreadable files are reported in `skipped`.
+/// With `lossy_utf8`, invalid UTF-8 is searched anyway and reported in `warnings`.
+pub fn search_notes(
+    vaults: &[Vault],
+    query: &str,
+    limit: usize,
+    options: &SearchOptions,
+) -> Result<SearchReport> {
+    let (report, _) = search_files(vaults, query, limit, options)?;
+    // Whatever was found before cancelling is incomplete, so it isn't returned
+    check_cancelled(options.cancel.as_ref())?;
+    Ok(report)
+}
+
+/// Notes searched in parallel before checking whether a path-ordered search has met its limit.
+const SEARCH_BATCH_FILES: usize = 64;
+
+/// Implementation of `search_notes` that also returns the number of lines scanned.
+/// A cancelled search returns what it found so far.
+fn search_files(
+    vaults: &[Vault],
+    query: &str,
+    limit: usize,
+    options: &SearchOptions,
+) -> Result<(SearchReport, usize)> {
+    let started = Instant::now();
+    // A line matches if every matcher does
+    let matchers = match options.mode {
+        SearchMode::Regex => vec![compile_matcher(query, "regex pattern")?],
+        SearchMode::Terms => {
+            let terms = parse_terms(query);
+            if terms.is_empty() {
+                anyhow::bail!("Query has no terms to search for");
+            }
+            terms
+                .iter()
+                .map(|term| compile_matcher(&format!("(?i){}", regex::escape(term)), "term"))
+                .collect::<Result<_>>()?
+        }
+    };
+    let exclude = options
+        .exclude
+        .as_deref()
+        .map(|pattern| compile_matcher(pattern, "exclude pattern"))
+        .transpose()?;
+
+    // In path order, no note needs more than `limit` matches, and notes after the batch
+    // that meets the limit needn't be searched at all. Other orders can't know which notes
+    // make the cut until every note is searched.
+    let (stop_at, batch_len) = if options.sort == SearchSort::Path {
+        (limit, SEARCH_BATCH_FILES)
+    } else {
+        (usize::MAX, usize::MAX)
+    };
+    let lines_scanned = AtomicUsize::new(0);
+    let files_scanned = AtomicUsize::new(0);
+    let bytes_read = AtomicUsize::new(0);
+    let is_cancelled = || options.cancel.as_ref().is_some_and(|c| c.is_cancelled());
+
+    // A result for `line` if it matches the query and not the exclude pattern
+    let match_line = |path: &str, line_number: usize, line: String| {
+        // The earliest of the matches is reported
+        let m = matchers
+            .iter()
+            .map(|matcher| grep::matcher::Matcher::find(matcher, line.as_bytes()).ok()?)
+            .collect::<Option<Vec<_>>>()?
+            .into_iter()
+            .min_by_key(|m| m.start())?;
+        if let Some(exclude) = &exclude {
+            if let Ok(true) = grep::matcher::Matcher::is_match(exclude, line.as_bytes()) {
+                return None;
+            }
+        }
+
+        let (line, snippet) = if options.snippet_len > 0 {
+            (
+                None,
+                Some(snippet(&line, m.start(), m.end(), options.snippet_len)),
+            )
+        } else {
+            (Some(line), None)
+        };
+        Some(SearchResult {
+            path: path.to_string(),
+            line_number,
+            line,
+            snippet,
+            match_start: m.start(),
+            match_end: m.end(),
+        })
+    };
+
+    // Collect all markdown files first, in a stable order, with the paths to report
+    let mut files: Vec<(String, PathBuf)> = Vec::new();
+    for &(name, root) in vaults {
+        let mut found = collect_searchable_files(root, options.include_hidden, options.trash_dir());
+        found.sort();
+        files.extend(
+            found
+                .into_iter()
+                .map(|path| (vault_path(name, relative_path(root, &path)), path)),
+        );
+    }
+
+    // Matches in one file, plus why it was skipped or decoded lossily
+    let search_file = |(relative_path, path): &(String, PathBuf)| {
+        let mut local = Vec::new();
+        if is_cancelled() {
+            return (local, None, None);
+        }
+        let relative_path = relative_path.clone();
+        let skip = |reason: String| SkippedFile {
+            path: relative_path.clone(),
+            reason,
+        };
+
+        // A path match is reported as line 0, ahead of the note's lines
+        if options.search_paths && stop_at > 0 {
+            if let Some(result) = match_line(&relative_path, 0, relative_path.clone()) {
+                local.push(result);
+            }
+        }
+
+        if let Some(max) = options.max_file_bytes {
+            match fs::metadata(path) {
+                Ok(meta) if meta.len() > max => {
+                    let reason = format!("larger than {} bytes", max);
+                    return (local, Some(skip(reason)), None);
+                }
+                Ok(_) => {}
+                Err(e) => return (local, Some(skip(e.to_string())), None),
+            }
+        }
+
+        let file = match open_note(path) {
+            Ok(file) => file,
+            Err(e) => return (local, Some(skip(e.to_string())), None),
+        };
+        let mut reader = BufReader::new(file);
+
+        // Same heuristic as grep: a NUL byte near the start means binary
+        match reader.fill_buf() {
+            Ok(head) if head.contains(&0) => {
+                return (local, Some(skip("binary content".to_string())), None);
+            }
+            Ok(_) => {}
+            Err(e) => return (local, Some(skip(e.to_string())), None),
+        }
+
+        let line_offset = if options.body_relative_lines {
+            body_line_offset(path)
+        } else {
+            0
+        };
+
+        // Stream lines instead of reading the whole file into memory
+        files_scanned.fetch_add(1, Ordering::Relaxed);
+        let mut skipped = None;
+        let mut lossy_line = None;
+        let lines = reader.split(b'\n').inspect(|line| {
+            if let Ok(bytes) = line {
+                bytes_read.fetch_add(bytes.len() + 1, Ordering::Relaxed);
+            }
+        });
+        for (line_num, line) in lines.enumerate().skip(line_offset) {
+            if local.len() >= stop_at || is_cancelled() {
+                break;
+            }
+            let line = match line.and_then(|bytes| decode_line(bytes, options.lossy_utf8)) {
+                Ok((line, lossy)) => {
+                    if lossy && lossy_line.is_none() {
+                        lossy_line = Some(line_num + 1);
+                    }
+                    line
+                }
+                Err(e) => {
+                    let reason = format!("stopped at line {}: {}", line_num + 1, e);
+                    skipped = Some(skip(reason));
+                    break;
+                }
+            };
+            lines_scanned.fetch_add(1, Ordering::Relaxed);
+
+            let Some(result) = match_line(&relative_path, line_num + 1 - line_offset, line) else {
+                continue;
+            };
+            local.push(result);
+            if options.max_per_file.is_some_and(|max| local.len() >= max) {
+                break;
+            }
+        }
+
+        let warning = lossy_line.map(|line| SearchWarning {
+            path: relative_path.clone(),
+            message: format!("invalid UTF-8 from line {}, decoded lossily", line),
+        });
+        (local, skipped, warning)
+    };
+
+    // Search batches of files in parallel using rayon, stopping between batches once the
+    // limit is met, so the results are the same however the workers are scheduled
+    let mut per_file = Vec::with_capacity(files.len());
+    let mut found = 0;
+    for batch in files.chunks(batch_len) {
+        if found >= stop_at || is_cancelled() {
+            break;
+        }
+        let searched: Vec<_> = batch.par_iter().map(search_file).collect();
+        found += searched
+            .iter()
+            .map(|(results, _, _)| results.len())
+            .sum::<usize>();
+        per_file.extend(searched);
+    }
+
+    let mut report = SearchReport::default();
+    let mut matched = Vec::new();
+    for ((_, path), (results, skipped, warning)) in files.iter().zip(per_file) {
+        if !results.is_empty() {
+            matched.push((path, results));
+        }
+        report.skipped.extend(skipped);
+        report.warnings.extend(warning);
+    }
+
+    // Files are already in path order, and the stable sorts below keep it for ties
+    match options.sort {
+        SearchSort::Path => {}
+        SearchSort::Modified => matched.sort_by_cached_key(|(path, _)| {
+            std::cmp::Reverse(fs::metadata(path).and_then(|m| m.modified()).ok())
+        }),
+        SearchSort::Relevance => {
+            matched.sort_by_key(|(_, results)| std::cmp::Reverse(results.len()))
+        }
+    }
+
+    // The last batch may overshoot the limit, so truncate after ordering
+    report.results = matched
+        .into_iter()
+        .flat_map(|(_, results)| results)
+        .take(limit)
+        .collect();
+
+    if options.include_stats {
+        report.stats = Some(SearchStats {
+            files_scanned: files_scanned.into_inner(),
+            bytes_read: bytes_read.into_inner(),
+            elapsed_ms: started.elapsed().as_millis(),
+        });
+    }
+
+    Ok((report, lines_scanned.into_inner()))
+}
+
+/// Split a `terms` query on whitespace, keeping "quoted phrases" together.
+/// An unclosed quote runs to the end of the query.
+fn parse_terms(query: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    let mut rest = query.trim_start();
+    while !rest.is_empty() {
+        let (term, after) = match rest.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => rest.split_once(char::is_whitespace).unwrap_or((rest, "")),
+        };
+        if !term.trim().is_empty() {
+            terms.push(term.trim().to_string());
+        }
+        rest = after.trim_start();
+    }
+    terms
+}
+
+/// Compile a regex passed to a tool. `label` names it in errors, e.g. "exclude pattern".
+fn compile_regex(pattern: &str, label: &str) -> Result<regex::Regex> {
+    regex::Regex::new(pattern).map_err(|e| regex_error(pattern, label, e))
+}
+
+/// `compile_regex` for the line matcher used by `search_notes`.
+fn compile_matcher(pattern: &str, label: &str) -> Result<grep::regex::RegexMatcher> {
+    grep::regex::RegexMatcher::new(pattern).map_err(|e| match regex::Regex::new(pattern) {
+        Err(regex_err) => regex_error(pattern, label, regex_err),
+        // Only grep rejects it, e.g. for matching a line break
+        Ok(_) => anyhow::Error::new(e).context(format!("Invalid {}: {}", label, pattern)),
+    })
+}
+
+/// Explain a bad regex: where the syntax error is, or that the pattern is too big.
+/// The regex crate's error stays in the chain so it's still classified as a regex error.
+fn regex_error(pattern: &str, label: &str, error: regex::Error) -> anyhow::Error {
+    let message = if let regex::Error::CompiledTooBig(limit) = error {
+        format!(
+            "Invalid {}: {:?} is too large, compiling to more than {} bytes. \
+             Use smaller repetition counts or a more specific pattern",
+            label, pattern, limit
+        )
+    } else {
+        let located = match regex_syntax::Parser::new().parse(pattern) {
+            Err(regex_syntax::Error::Parse(e)) => Some((e.kind().to_string(), e.span().start)),
+            Err(regex_syntax::Error::Translate(e)) => Some((e.kind().to_string(), e.span().start)),
+            _ => None,
+        };
+        match located {
+            Some((kind, start)) => {
+                let column = pattern[..start.offset].chars().count();
+                format!(
+                    "Invalid {}: {} at position {}\n  {}\n  {}^\n\
+                     To match characters like ( ) [ ] {{ }} . * + ? | \\ literally, \
+                     escape them with a backslash",
+                    label,
+                    kind,
+                    column + 1,
+                    pattern,
+                    " ".repeat(column)
+                )
+            }
+            None => format!("Invalid {}: {}", label, pattern),
+        }
+    };
+    anyhow::Error::new(error).context(message)
+}
+
+/// Decode a line split on `\n`, dropping a trailing `\r` like `BufRead::lines`.
+/// Invalid UTF-8 is an error unless `lossy`, when it's replaced and flagged with `true`.
+fn decode_line(mut bytes: Vec<u8>, lossy: bool) -> std::io::Result<(String, bool)> {
+    if bytes.last() == Some(&b'\r') {
+        bytes.pop();
+    }
+    match String::from_utf8(bytes) {
+        Ok(line) => Ok((line, false)),
+        Err(e) if lossy => Ok((String::from_utf8_lossy(e.as_bytes()).into_owned(), true)),
+        Err(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "stream did not contain valid UTF-8",
+        )),
+    }
+}
+
+/// Excerpt of about `len` characters of `line` centered on the match at `start..end`
+/// (byte offsets). Partial words at either end are dropped, and "…" marks cut text.
+fn snippet(line: &str, start: usize, end: usize, len: usize) -> String {
+    let chars: Vec<usize> = line.char_indices().map(|(i, _)| i).collect();
+    let total = chars.len();
+    if total <= len {
+        return line.to_string();
+    }
+
+    let byte = |pos: usize| chars.get(pos).copied().unwrap_or(line.len());
+    let is_space = |pos: usize| line[byte(pos)..].starts_with(char::is_whitespace);
+
+    // Window in char positions, centered on the match and kept inside the line
+    let match_start = chars.partition_point(|&i| i < start);
+    let match_end = chars.partition_point(|&i| i < end);
+    let center = (match_start + match_end) / 2;
+    let mut from = center.saturating_sub(len / 2).min(total - len);
+    let mut to = from + len;
+
+    // Move inward to word boundaries, but never into the match itself
+    if from > 0 && !is_space(from - 1) {
+        if let Some(space) = (from..match_start).find(|&p| is_space(p)) {
+            from = space + 1;
+        }
+    }
+    if to < total && !is_space(to) {
+        if let Some(space) = (match_end..to).rev().find(|&p| is_space(p)) {
+            to = space;
+        }
+    }
+
+    let mut excerpt = String::new();
+    if from > 0 {
+        excerpt.push('…');
+    }
+    excerpt.push_str(line[byte(from)..byte(to)].trim());
+    if to < total {
+        excerpt.push('…');
+    }
+    excerpt
+}
+
+/// Number of lines before a note's body starts: the frontmatter block, matching the body
+/// returned by `read_note`. Only reads as far as the end of the frontmatter.
+fn body_line_offset(path: &Path) -> usize {
+    let Ok(file) = open_note(path) else {
+        return 0;
+    };
+    let mut lines = BufReader::new(file).lines().map_while(Result::ok);
+    let Some(first) = lines.next() else {
+        return 0;
+    };
+
+    let close = match first.trim_end() {
+        "---" => Some("---"),
+        "+++" => Some("+++"),
+        ";;;" => Some(";;;"),
+        "```json" => Some("```"),
+        _ => None,
+    };
+
+    let mut count = 1;
+    if let Some(close) = close {
+        loop {
+            let Some(line) = lines.next() else {
+                // Unclosed frontmatter is body
+                return 0;
+            };
+            count += 1;
+            if line.trim_end() == close {
+                break;
+            }
+        }
+    } else if first.starts_with('{') {
+        let mut block = first;
+        while serde_json::from_str::<serde_json::Value>(&block).map_or(true, |v| !v.is_object()) {
+            let Some(line) = lines.next() else {
+                return 0;
+            };
+            count += 1;
+            block.push('\n');
+            block.push_str(&line);
+        }
+    } else {
+        return 0;
+    }
+
+    count
+}
+
+/// Get a nested field value from JSON using dot notation (e.g., "author.name").
+fn get_nested_field<'a>(
+    value: &'a serde_json::Value,
+    field: &str,
+) -> Option<&'a serde_json::Value> {
+    let mut current = value;
+    for part in field.split('.') {
+        current = current.get(part)?;
+    }
+    Some(current)
+}
+
+/// Check if a JSON value matches a regex pattern.
+fn value_matches_pattern(value: &serde_json::Value, regex: &regex::Regex) -> bool {
+    match value {
+        serde_json::Value::String(s) => regex.is_match(s),
+        serde_json::Value::Number(n) => regex.is_match(&n.to_string()),
+        serde_json::Value::Bool(b) => regex.is_match(&b.to_string()),
+        serde_json::Value::Array(arr) => arr.iter().any(|v| value_matches_pattern(v, regex)),
+        _ => false,
+    }
+}
+
+/// Search notes by frontmatter metadata field.
+/// Results are ordered by path, or by the `sort_by` frontmatter field when given
+/// (notes missing the sort field come last, ties are broken by path).
+pub fn search_metadata(
+    root: &Path,
+    field: &str,
+    pattern: &str,
+    limit: usize,
+    sort_by: Option<&str>,
+    sort_desc: bool,
+    options: &MetadataSearchOptions,
+) -> Result<Vec<MetadataSearchResult>> {
+    let notes = note_metadata(root, options)?;
+    search_metadata_in(&notes, field, pattern, limit, sort_by, sort_desc)
+}
+
+/// (relative path, frontmatter) pairs for the notes under root that have frontmatter,
+/// as `search_metadata` reads them.
+pub(crate) fn note_metadata(
+    root: &Path,
+    options: &MetadataSearchOptions,
+) -> Result<Vec<(String, serde_json::Value)>> {
+    // Collect all markdown files
+    let files = collect_markdown_files_with(root, options.include_hidden, options.trash_dir());
+    let cancel = options.cancel.as_ref();
+
+    // Parse frontmatter in parallel
+    let notes = files
+        .par_iter()
+        .filter_map(|path| {
+            check_cancelled(cancel).ok()?;
+            Some((
+                relative_path(root, path),
+                read_metadata(path, &options.note)?,
+            ))
+        })
+        .collect();
+    check_cancelled(cancel)?;
+    Ok(notes)
+}
+
+/// Every distinct value of a frontmatter field across the vault, with how many notes
+/// have it. Array fields count each element. Supports dot notation for nested fields.
+/// Sorted by count, most common first.
+pub fn distinct_values(
+    root: &Path,
+    field: &str,
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Vec<ValueCount> {
+    let files = collect_markdown_files(root, trash_dir);
+    let notes: Vec<(String, serde_json::Value)> = files
+        .par_iter()
+        .filter_map(|path| Some((relative_path(root, path), read_metadata(path, options)?)))
+        .collect();
+
+    distinct_values_in(&notes, field)
+}
+
+/// `distinct_values` over already-parsed (relative path, frontmatter) pairs.
+pub(crate) fn distinct_values_in(
+    notes: &[(String, serde_json::Value)],
+    field: &str,
+) -> Vec<ValueCount> {
+    // Keyed by the serialized value, since JSON values aren't Ord
+    let mut counts: BTreeMap<String, ValueCount> = BTreeMap::new();
+    for (_, meta) in notes {
+        let values = match get_nested_field(meta, field) {
+            Some(serde_json::Value::Array(items)) => items.iter().collect(),
+            Some(serde_json::Value::Null) | None => Vec::new(),
+            Some(value) => vec![value],
+        };
+        for value in values {
+            counts
+                .entry(value.to_string())
+                .or_insert_with(|| ValueCount {
+                    value: value.clone(),
+                    count: 0,
+                })
+                .count += 1;
+        }
+    }
+
+    let mut values: Vec<ValueCount> = counts.into_values().collect();
+    // Stable sort keeps ties in value order
+    values.sort_by_key(|v| std::cmp::Reverse(v.count));
+    values
+}
+
+/// A note and the notes it links to, from `export_bundle`.
+#[derive(Debug, Default, Serialize)]
+pub struct LinkBundle {
+    /// Content of each reachable note, by relative path
+    pub notes: BTreeMap<String, String>,
+    /// Images and other non-note files the notes link to, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attachments: Option<Vec<String>>,
+}
+
+/// Collect a note and the notes it links to, following links transitively up to `depth`
+/// hops (0 is just the note itself). Each note is included once, so cycles are harmless.
+/// With `include_attachments`, the paths of linked files that aren't notes are listed too.
+pub fn export_bundle(
+    root: &Path,
+    path: &Path,
+    depth: usize,
+    include_attachments: bool,
+    trash_dir: &str,
+) -> Result<LinkBundle> {
+    if !path.is_file() {
+        anyhow::bail!(NoteError::NotFound(format!(
+            "File does not exist: {}",
+            path.display()
+        )));
+    }
+
+    let mut bundle = LinkBundle::default();
+    let mut attachments = BTreeSet::new();
+    let mut stems = None;
+    let mut queue = std::collections::VecDeque::from([(relative_path(root, path), 0)]);
+
+    while let Some((relative, hops)) = queue.pop_front() {
+        if bundle.notes.contains_key(&relative) {
+            continue;
+        }
+        let path = root.join(&relative);
+        let content = read_note_text(&path)?;
+
+        if hops < depth || include_attachments {
+            let ast = markdown::to_mdast(&content, &link_parse_options())
+                .map_err(|e| anyhow::anyhow!("Failed to parse markdown: {}", e))?;
+            let mut links = Vec::new();
+            collect_links(&ast, &content, &mut links);
+            if include_attachments {
+                collect_images(&ast, &mut links);
+            }
+            resolve_links(root, &path, &mut links, &mut stems, trash_dir);
+
+            for link in links.into_iter().filter(|link| link.internal) {
+                match link.resolved {
+                    Some(target) if !target.ends_with(".md") => {
+                        attachments.insert(target);
+                    }
+                    Some(target) if hops < depth => queue.push_back((target, hops + 1)),
+                    Some(_) => {}
+                    // `![[image.png]]` embeds name a file in the vault, not a note
+                    None if link.kind == LinkKind::Wikilink
+                        && Path::new(&link.target).extension().is_some() =>
+                    {
+                        if let Ok(file) = resolve_note_path(root, &link.target) {
+                            if file.is_file() {
+                                attachments.insert(link.target);
+                            }
+                        }
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        bundle.notes.insert(relative, content);
+    }
+
+    if include_attachments {
+        bundle.attachments = Some(attachments.into_iter().collect());
+    }
+    Ok(bundle)
+}
+
+/// Add the images in the AST as markdown links, for finding attachments.
+fn collect_images(node: &Node, links: &mut Vec<NoteLink>) {
+    if let Node::Image(image) = node {
+        links.push(NoteLink {
+            kind: LinkKind::Markdown,
+            internal: !is_external_url(&image.url),
+            target: image.url.clone(),
+            text: None,
+            line_number: node.position().map(|p| p.start.line).unwrap_or(0),
+            resolved: None,
+        });
+    }
+    for child in node.children().into_iter().flatten() {
+        collect_images(child, links);
+    }
+}
+
+/// Write every note as a JSON array of `{path, metadata, body}` to `out`, one note at a
+/// time so large vaults aren't held in memory. Returns the number of notes written.
+pub fn export_vault(
+    vaults: &[Vault],
+    include_body: bool,
+    out: &mut impl Write,
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Result<usize> {
+    let mut files = Vec::new();
+    for &(name, root) in vaults {
+        let mut found = collect_markdown_files(root, trash_dir);
+        found.sort();
+        files.extend(found.into_iter().map(|path| (name, root, path)));
+    }
+
+    out.write_all(b"[")?;
+    for (i, (name, root, path)) in files.iter().enumerate() {
+        let content = read_note_text(path)?;
+        let (metadata, body) = match parse_frontmatter(&content, options) {
+            Some(frontmatter) => (frontmatter.metadata().ok(), frontmatter.body),
+            None => (None, content),
+        };
+        let note = BundleNote {
+            path: vault_path(*name, relative_path(root, path)),
+            metadata,
+            body: include_body.then_some(body),
+        };
+
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        serde_json::to_writer(&mut *out, &note)?;
+    }
+    out.write_all(b"]")?;
+    out.flush()?;
+
+    Ok(files.len())
+}
+
+/// `export_vault` streamed to a new file at `output`, through a temp file renamed into
+/// place once complete. Notes and existing files are never overwritten.
+pub fn export_vault_to_file(
+    vaults: &[Vault],
+    include_body: bool,
+    output: &Path,
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Result<usize> {
+    if output.extension().is_some_and(|ext| ext == "md") || is_compressed_note(output) {
+        anyhow::bail!("Refusing to export to a note: {}", output.display());
+    }
+    if output.exists() {
+        anyhow::bail!(NoteError::Conflict(format!(
+            "Export file already exists: {}",
+            output.display()
+        )));
+    }
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+
+    let temp_path = output.with_extension("tmp");
+    let exported = fs::File::create(&temp_path)
+        .with_context(|| format!("Failed to create file: {}", temp_path.display()))
+        .and_then(|file| {
+            export_vault(
+                vaults,
+                include_body,
+                &mut std::io::BufWriter::new(file),
+                trash_dir,
+                options,
+            )
+        })
+        .and_then(|count| {
+            rename(&temp_path, output, options)
+                .with_context(|| format!("Failed to rename temp file to: {}", output.display()))?;
+            Ok(count)
+        });
+    if exported.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    exported
+}
+
+/// Write the notes of an export bundle under root. Existing notes are replaced only with
+/// `overwrite`. Every path is checked before anything is written, so a bundle with a path
+/// escaping root writes nothing.
+pub fn import_vault(
+    root: &Path,
+    bundle: &[BundleNote],
+    overwrite: bool,
+    options: &NoteOptions,
+) -> Result<ImportReport> {
+    let targets = bundle
+        .iter()
+        .map(|note| resolve_note_path(root, &note.path))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut report = ImportReport::default();
+    for (note, path) in bundle.iter().zip(targets) {
+        let exists = path.exists();
+        if exists && !overwrite {
+            report.skipped += 1;
+            continue;
+        }
+
+        let body = note.body.as_deref().unwrap_or_default();
+        let content = match &note.metadata {
+            // The bundle's body is what followed the frontmatter, blank line included
+            Some(metadata) => format!(
+                "{}{}",
+                frontmatter_block_for(&path, metadata, options),
+                body
+            ),
+            None => body.to_string(),
+        };
+        match write_note(&path, &content, options) {
+            Ok(()) if exists => report.overwritten += 1,
+            Ok(()) => report.created += 1,
+            Err(e) => report.failed.push(SkippedFile {
+                path: note.path.clone(),
+                reason: format!("{:#}", e),
+            }),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Groups of notes with identical bodies (frontmatter ignored), each sorted by path.
+/// With `fuzzy`, bodies are compared after trimming and collapsing whitespace.
+/// Notes without a duplicate are left out.
+pub fn find_duplicates(
+    root: &Path,
+    fuzzy: bool,
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Vec<Vec<String>> {
+    let mut files = collect_markdown_files(root, trash_dir);
+    files.sort();
+
+    let hashes: Vec<(String, String)> = files
+        .par_iter()
+        .filter_map(|path| {
+            let body = read_body(path, options).ok()?;
+            let body = if fuzzy {
+                body.split_whitespace().collect::<Vec<_>>().join(" ")
+            } else {
+                body
+            };
+            let hash = format!("{:x}", Sha256::digest(body.as_bytes()));
+            Some((hash, relative_path(root, path)))
+        })
+        .collect();
+
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (hash, path) in hashes {
+        groups.entry(hash).or_default().push(path);
+    }
+
+    let mut duplicates: Vec<Vec<String>> = groups
+        .into_values()
+        .filter(|paths| paths.len() > 1)
+        .collect();
+    duplicates.sort();
+    duplicates
+}
+
+/// Existing tags starting with `prefix` (case-insensitive), most used first.
+pub fn suggest_tags(
+    root: &Path,
+    prefix: &str,
+    limit: usize,
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Vec<ValueCount> {
+    tags_with_prefix(
+        distinct_values(root, "tags", trash_dir, options),
+        prefix,
+        limit,
+    )
+}
+
+/// Two tags and how many notes have both, from `tag_cooccurrence`.
+#[derive(Debug, Serialize)]
+pub struct TagPair {
+    /// The tags, in sorted order
+    pub tags: [String; 2],
+    pub count: usize,
+}
+
+/// Pairs of frontmatter tags that appear on the same notes in any of the vaults under
+/// `roots`, most shared first. A tag listed twice on one note counts once.
+pub fn tag_cooccurrence(
+    roots: &[&Path],
+    limit: usize,
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Vec<TagPair> {
+    let tag_sets: Vec<BTreeSet<String>> = roots
+        .iter()
+        .flat_map(|root| collect_markdown_files(root, trash_dir))
+        .collect::<Vec<_>>()
+        .par_iter()
+        .filter_map(|path| {
+            let meta = read_metadata(path, options)?;
+            let tags: BTreeSet<String> = frontmatter_tags(&meta)
+                .into_iter()
+                .map(str::to_string)
+                .collect();
+            (tags.len() > 1).then_some(tags)
+        })
+        .collect();
+
+    let mut counts: BTreeMap<(&str, &str), usize> = BTreeMap::new();
+    for tags in &tag_sets {
+        for (i, a) in tags.iter().enumerate() {
+            for b in tags.iter().skip(i + 1) {
+                *counts.entry((a, b)).or_default() += 1;
+            }
+        }
+    }
+
+    let mut pairs: Vec<TagPair> = counts
+        .into_iter()
+        .map(|((a, b), count)| TagPair {
+            tags: [a.to_string(), b.to_string()],
+            count,
+        })
+        .collect();
+    // Stable sort keeps ties in tag order
+    pairs.sort_by_key(|pair| std::cmp::Reverse(pair.count));
+    pairs.truncate(limit);
+    pairs
+}
+
+/// The string tags in a note's frontmatter `tags` field, which may be a list or one tag.
+fn frontmatter_tags(meta: &serde_json::Value) -> Vec<&str> {
+    match meta.get("tags") {
+        Some(serde_json::Value::Array(items)) => {
+            items.iter().filter_map(|tag| tag.as_str()).collect()
+        }
+        Some(serde_json::Value::String(tag)) => vec![tag.as_str()],
+        _ => Vec::new(),
+    }
+}
+
+/// A tag as compared by `notes_by_tags`: lowercase, without a leading `#`.
+fn normalize_tag(tag: &str) -> String {
+    tag.strip_prefix('#').unwrap_or(tag).to_lowercase()
+}
+
+/// How `notes_by_tags` combines the requested tags.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum TagMatch {
+    /// Notes with every tag
+    #[default]
+    All,
+    /// Notes with at least one of the tags
+    Any,
+}
+
+/// Notes whose frontmatter tags satisfy `tags` combined by `mode`, leaving out notes with
+/// any `exclude` tag. Tags are compared case-insensitively, ignoring a leading `#`.
+/// Sorted by path.
+pub fn notes_by_tags(
+    root: &Path,
+    tags: &[String],
+    mode: TagMatch,
+    exclude: &[String],
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Vec<String> {
+    let wanted: BTreeSet<String> = tags.iter().map(|tag| normalize_tag(tag)).collect();
+    let excluded: BTreeSet<String> = exclude.iter().map(|tag| normalize_tag(tag)).collect();
+
+    let mut files = collect_markdown_files(root, trash_dir);
+    files.sort();
+    files
+        .par_iter()
+        .filter_map(|path| {
+            let meta = read_metadata(path, options)?;
+            let note_tags: BTreeSet<String> = frontmatter_tags(&meta)
+                .into_iter()
+                .map(normalize_tag)
+                .collect();
+            let matches = match mode {
+                TagMatch::All => wanted.is_subset(&note_tags),
+                TagMatch::Any => !wanted.is_disjoint(&note_tags),
+            };
+            (matches && excluded.is_disjoint(&note_tags)).then(|| relative_path(root, path))
+        })
+        .collect()
+}
+
+/// `suggest_tags` over already-counted `tags` values.
+pub(crate) fn tags_with_prefix(
+    tags: Vec<ValueCount>,
+    prefix: &str,
+    limit: usize,
+) -> Vec<ValueCount> {
+    let prefix = prefix.to_lowercase();
+    tags.into_iter()
+        .filter(|tag| {
+            tag.value
+                .as_str()
+                .is_some_and(|t| t.to_lowercase().starts_with(&prefix))
+        })
+        .take(limit)
+        .collect()
+}
+
+/// Notes missing any of the required frontmatter fields (dot notation for nested keys).
+/// Null fields count as missing, and notes without frontmatter lack every field.
+pub fn find_missing_metadata(
+    root: &Path,
+    required: &[String],
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Vec<MissingMetadata> {
+    let mut files = collect_markdown_files(root, trash_dir);
+    files.sort();
+
+    files
+        .par_iter()
+        .filter_map(|path| {
+            let meta = read_metadata(path, options).unwrap_or(serde_json::Value::Null);
+            let missing: Vec<String> = required
+                .iter()
+                .filter(|field| {
+                    matches!(
+                        get_nested_field(&meta, field),
+                        None | Some(serde_json::Value::Null)
+                    )
+                })
+                .cloned()
+                .collect();
+
+            (!missing.is_empty()).then(|| MissingMetadata {
+                path: relative_path(root, path),
+                missing,
+            })
+        })
+        .collect()
+}
+
+/// Notes whose frontmatter `title` matches, case-insensitively. If no title matches,
+/// notes whose file name (without `.md`) matches are returned instead. Sorted by path.
+pub fn find_by_title(
+    root: &Path,
+    title: &str,
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Vec<String> {
+    let files = collect_markdown_files(root, trash_dir);
+    let notes: Vec<(String, Option<serde_json::Value>)> = files
+        .par_iter()
+        .map(|path| (relative_path(root, path), read_metadata(path, options)))
+        .collect();
+
+    find_by_title_in(&notes, title)
+}
+
+/// `find_by_title` over already-parsed (relative path, frontmatter) pairs.
+pub(crate) fn find_by_title_in(
+    notes: &[(String, Option<serde_json::Value>)],
+    title: &str,
+) -> Vec<String> {
+    let target = title.trim().to_lowercase();
+
+    let mut found: Vec<String> = notes
+        .iter()
+        .filter(|(_, meta)| {
+            meta.as_ref()
+                .and_then(|m| m.get("title"))
+                .and_then(|t| t.as_str())
+                .is_some_and(|t| t.trim().to_lowercase() == target)
+        })
+        .map(|(path, _)| path.clone())
+        .collect();
+
+    if found.is_empty() {
+        found = notes
+            .iter()
+            .filter(|(path, _)| {
+                Path::new(path)
+                    .file_stem()
+                    .is_some_and(|stem| stem.to_string_lossy().to_lowercase() == target)
+            })
+            .map(|(path, _)| path.clone())
+            .collect();
+    }
+
+    found.sort();
+    found
+}
+
+/// `search_metadata` over already-parsed (relative path, frontmatter) pairs.
+pub(crate) fn search_metadata_in(
+    notes: &[(String, serde_json::Value)],
+    field: &str,
+    pattern: &str,
+    limit: usize,
+    sort_by: Option<&str>,
+    sort_desc: bool,
+) -> Result<Vec<MetadataSearchResult>> {
+    let regex = compile_regex(pattern, "regex pattern")?;
+
+    // Match in parallel, keeping the sort value alongside each match
+    let mut matches: Vec<(Option<serde_json::Value>, MetadataSearchResult)> = notes
+        .par_iter()
+        .filter_map(|(path, meta)| {
+            let value = get_nested_field(meta, field)?;
+            if !value_matches_pattern(value, &regex) {
+                return None;
+            }
+
+            let sort_value = sort_by.and_then(|key| get_nested_field(meta, key).cloned());
+            Some((
+                sort_value,
+                MetadataSearchResult {
+                    path: path.clone(),
+                    value: value.clone(),
+                },
+            ))
+        })
+        .collect();
+
+    matches.sort_by(|(a_key, a), (b_key, b)| {
+        let by_key = match (a_key, b_key) {
+            (Some(a_key), Some(b_key)) => {
+                let ordering = compare_values(a_key, b_key);
+                if sort_desc {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            }
+            // Missing sort values always sort last
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => std::cmp::Ordering::Equal,
+        };
+        by_key.then_with(|| a.path.cmp(&b.path))
+    });
+
+    Ok(matches
+        .into_iter()
+        .take(limit)
+        .map(|(_, result)| result)
+        .collect())
+}
+
+/// Order JSON values for sorting: numbers numerically, strings and bools naturally.
+/// Values of different types are ordered by type.
+fn compare_values(a: &serde_json::Value, b: &serde_json::Value) -> std::cmp::Ordering {
+    use serde_json::Value;
+
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Number(_) => 2,
+            Value::String(_) => 3,
+            Value::Array(_) => 4,
+            Value::Object(_) => 5,
+        }
+    }
+
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Number(a), Value::Number(b)) => {
+            let a = a.as_f64().unwrap_or(0.0);
+            let b = b.as_f64().unwrap_or(0.0);
+            a.total_cmp(&b)
+        }
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// Words that `keywords` leaves out unless STUMBLING_STOPWORDS replaces them.
+const STOPWORDS: &str = "\
+    a about above after again against all also am an and any are as at be because been \
+    before being below between both but by can could did do does doing don down during \
+    each even few for from further get got had has have having he her here hers \
+    herself him himself his how if in into is it its itself just let like ll may me \
+    might more most much must my myself no nor not now of off on once one only or \
+    other our ours ourselves out over own re same she should so some still such than \
+    that the their theirs them themselves then there these they this those through to \
+    too under until up us use used using ve very was way we well were what when where \
+    which while who whom why will with would yet you your yours yourself yourselves";
+
+fn default_stopwords() -> &'static BTreeSet<String> {
+    static DEFAULT_STOPWORDS: OnceLock<BTreeSet<String>> = OnceLock::new();
+    DEFAULT_STOPWORDS.get_or_init(|| STOPWORDS.split_whitespace().map(str::to_string).collect())
+}
+
+/// Load a stopword list for `keywords`: words separated by whitespace, one per line or
+/// several to a line. Words are lowercased.
+pub fn load_stopwords(path: &Path) -> Result<BTreeSet<String>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read stopwords: {}", path.display()))?;
+    Ok(content.split_whitespace().map(str::to_lowercase).collect())
+}
+
+/// A word and how often it occurs, from `keywords`.
+#[derive(Debug, Serialize)]
+pub struct TermCount {
+    pub term: String,
+    pub count: usize,
+}
+
+/// The most frequent words in a note's body, most frequent first (ties alphabetical).
+/// Only prose is counted: frontmatter, code, HTML and link targets are left out, along
+/// with numbers, single letters, and `stopwords` (the built-in English list if None).
+pub fn keywords(
+    path: &Path,
+    limit: usize,
+    stopwords: Option<&BTreeSet<String>>,
+    options: &NoteOptions,
+) -> Result<Vec<TermCount>> {
+    fn collect_text<'a>(node: &'a Node, text: &mut Vec<&'a str>) {
+        match node {
+            Node::Text(t) => text.push(&t.value),
+            _ => {
+                for child in node.children().into_iter().flatten() {
+                    collect_text(child, text);
+                }
+            }
+        }
+    }
+
+    let body = read_body(path, options)?;
+    let ast = markdown::to_mdast(&body, &ParseOptions::gfm())
+        .map_err(|e| anyhow::anyhow!("Failed to parse markdown: {}", e))?;
+    let mut text = Vec::new();
+    collect_text(&ast, &mut text);
+
+    let stopwords = stopwords.unwrap_or_else(|| default_stopwords());
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for word in text
+        .iter()
+        .flat_map(|t| t.split(|c: char| !c.is_alphanumeric()))
+        .map(str::to_lowercase)
+    {
+        if word.chars().count() < 2
+            || word.chars().all(|c| c.is_numeric())
+            || stopwords.contains(&word)
+        {
+            continue;
+        }
+        *counts.entry(word).or_default() += 1;
+    }
+
+    let mut terms: Vec<TermCount> = counts
+        .into_iter()
+        .map(|(term, count)| TermCount { term, count })
+        .collect();
+    // Stable sort keeps ties in alphabetical order
+    terms.sort_by_key(|term| std::cmp::Reverse(term.count));
+    terms.truncate(limit);
+    Ok(terms)
+}
+
+/// Load a JSON Schema file for `validate_frontmatter`.
+pub fn load_schema(path: &Path) -> Result<jsonschema::Validator> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read schema: {}", path.display()))?;
+    let schema: serde_json::Value = serde_json::from_str(&content)
+        .with_context(|| format!("Schema is not valid JSON: {}", path.display()))?;
+    jsonschema::validator_for(&schema)
+        .map_err(|e| anyhow::anyhow!("Invalid JSON schema {}: {}", path.display(), e))
+}
+
+/// Validate every note's frontmatter against required fields, expected field types, and
+/// optionally a JSON Schema. Only notes with problems are returned. Notes without
+/// frontmatter are flagged only when fields are required or `require_frontmatter` is set.
+/// Nested fields use dot notation.
+pub fn validate_frontmatter(
+    root: &Path,
+    required_fields: &[String],
+    field_types: &BTreeMap<String, FieldType>,
+    schema: Option<&jsonschema::Validator>,
+    require_frontmatter: bool,
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Result<Vec<ValidationResult>> {
+    let files = collect_markdown_files(root, trash_dir);
+
+    let mut results: Vec<ValidationResult> = files
+        .par_iter()
+        .filter_map(|path| {
+            let content = read_note_text(path).ok()?;
+            let mut problems = Vec::new();
+
+            match parse_frontmatter(&content, options) {
+                None => {
+                    if require_frontmatter || !required_fields.is_empty() {
+                        problems.push("missing frontmatter".to_string());
+                    }
+                }
+                Some(frontmatter) => match frontmatter.metadata() {
+                    Err(e) => problems.push(format!("{:#}", e)),
+                    Ok(meta) => {
+                        for field in required_fields {
+                            if get_nested_field(&meta, field).is_none() {
+                                problems.push(format!("missing required field: {}", field));
+                            }
+                        }
+                        for (field, expected) in field_types {
+                            if let Some(value) = get_nested_field(&meta, field) {
+                                if !expected.matches(value) {
+                                    problems.push(format!(
+                                        "field {} should be {:?} but is {}",
+                                        field,
+                                        expected,
+                                        FieldType::of(value)
+                                    ));
+                                }
+                            }
+                        }
+                        if let Some(schema) = schema {
+                            for error in schema.iter_errors(&meta) {
+                                let at = error.instance_path().to_string();
+                                problems.push(if at.is_empty() {
+                                    format!("schema: {}", error)
+                                } else {
+                                    format!("schema: {}: {}", at, error)
+                                });
+                            }
+                        }
+                    }
+                },
+            }
+
+            if problems.is_empty() {
+                return None;
+            }
+            Some(ValidationResult {
+                path: relative_path(root, path),
+                problems,
+            })
+        })
+        .collect();
+
+    results.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(results)
+}
+
+/// Search notes whose frontmatter date field falls within an inclusive range.
+/// Either bound may be omitted for an open-ended range. Values that can't be
+/// parsed as a date are skipped. Results are sorted by path.
+pub fn search_by_date(
+    root: &Path,
+    field: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+    limit: usize,
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Result<Vec<DateSearchResult>> {
+    let parse_bound = |bound: &str| {
+        parse_date(bound)
+            .with_context(|| format!("Invalid date: {} (expected YYYY-MM-DD or RFC3339)", bound))
+    };
+    let from = from.map(parse_bound).transpose()?;
+    let to = to.map(parse_bound).transpose()?;
+
+    let files = collect_markdown_files(root, trash_dir);
+
+    let mut results: Vec<DateSearchResult> = files
+        .par_iter()
+        .filter_map(|path| {
+            let meta = read_metadata(path, options)?;
+            let value = get_nested_field(&meta, field)?;
+            let date = parse_date(value.as_str()?)?;
+
+            let in_range = from.is_none_or(|from| date >= from) && to.is_none_or(|to| date <= to);
+            in_range.then(|| DateSearchResult {
+                path: relative_path(root, path),
+                value: value.clone(),
+                date: date.format("%Y-%m-%d").to_string(),
+            })
+        })
+        .collect();
+
+    results.sort_by(|a, b| a.path.cmp(&b.path));
+    results.truncate(limit);
+    Ok(results)
+}
+
+/// Format content with frontmatter in `options.frontmatter_format` (YAML unless
+/// STUMBLING_FRONTMATTER_FORMAT says otherwise).
+///
+/// Note: AI tools (e.g., Claude) sometimes serialize metadata as a JSON string
+/// `"{\"title\": ...}"` instead of passing a JSON object `{"title": ...}`.
+/// This function handles both cases by parsing string values as JSON.
+pub fn format_with_frontmatter(
+    metadata: &serde_json::Value,
+    body: &str,
+    options: &NoteOptions,
+) -> String {
+    format_with_frontmatter_as(options.frontmatter_format, metadata, body)
+}
+
+/// Format content with frontmatter in the given format, with a blank line between the
+/// frontmatter and the body. Metadata that isn't an object can't be TOML, so it is written
+/// as YAML instead.
+pub fn format_with_frontmatter_as(
+    format: FrontmatterFormat,
+    metadata: &serde_json::Value,
+    body: &str,
+) -> String {
+    format!("{}\n{}", frontmatter_block(format, metadata), body)
+}
+
+/// Format content with frontmatter in the format of the frontmatter the note at `path`
+/// already has, or the configured format if it has none (or doesn't exist yet).
+pub fn format_with_frontmatter_for(
+    path: &Path,
+    metadata: &serde_json::Value,
+    body: &str,
+    options: &NoteOptions,
+) -> String {
+    format!(
+        "{}\n{}",
+        frontmatter_block_for(path, metadata, options),
+        body
+    )
+}
+
+/// The frontmatter block alone, ending with the line break after its closing line.
+fn frontmatter_block(format: FrontmatterFormat, metadata: &serde_json::Value) -> String {
+    let meta = metadata_object(metadata);
+    match format {
+        FrontmatterFormat::Toml if meta.is_object() => {
+            let toml = toml::to_string(&strip_nulls(&meta)).unwrap_or_default();
+            format!("+++\n{}\n+++\n", toml.trim_end())
+        }
+        FrontmatterFormat::Json => {
+            let json = serde_json::to_string_pretty(&meta).unwrap_or_default();
+            format!("{}\n", json)
+        }
+        _ => {
+            let yaml = serde_yaml_ng::to_string(&meta).unwrap_or_default();
+            // serde_yaml_ng adds a trailing newline, so we trim it
+            let yaml = yaml.trim_end();
+            format!("---\n{}\n---\n", yaml)
+        }
+    }
+}
+
+/// The frontmatter block for the note at `path`, written like the frontmatter it already
+/// has, or in the configured format if it has none (or doesn't exist yet).
+fn frontmatter_block_for(
+    path: &Path,
+    metadata: &serde_json::Value,
+    options: &NoteOptions,
+) -> String {
+    let existing = read_frontmatter_head(path)
+        .ok()
+        .flatten()
+        .and_then(|head| parse_frontmatter(&head, options));
+    match existing {
+        Some(existing) => frontmatter_block_like(&existing, metadata),
+        None => frontmatter_block(options.frontmatter_format, metadata),
+    }
+}
+
+/// The frontmatter block written like `existing`: in its format and, for fenced JSON, with
+/// its fence. A body parsed from the same note already starts with whatever separated it
+/// from the old block, so callers splice it on without adding a separator.
+fn frontmatter_block_like(existing: &Frontmatter, metadata: &serde_json::Value) -> String {
+    match existing.fence {
+        Some((open, close)) => {
+            let json = serde_json::to_string_pretty(&metadata_object(metadata)).unwrap_or_default();
+            format!("{}\n{}\n{}\n", open, json, close)
+        }
+        None => frontmatter_block(existing.format, metadata),
+    }
+}
+
+/// Metadata to write, parsing it first if it was passed as a JSON string.
+fn metadata_object(metadata: &serde_json::Value) -> serde_json::Value {
+    match metadata {
+        serde_json::Value::String(s) => {
+            serde_json::from_str(s).unwrap_or_else(|_| metadata.clone())
+        }
+        _ => metadata.clone(),
+    }
+}
+
+/// Retries after a transient filesystem error when STUMBLING_FS_RETRIES isn't set.
+pub const DEFAULT_FS_RETRIES: u32 = 3;
+
+/// Delay before the first retry, doubled for each one after.
+const FS_RETRY_DELAY: Duration = Duration::from_millis(20);
+
+/// Errors that network filesystems and virus scanners cause briefly, and that
+/// may succeed if tried again. Anything else, like NotFound, fails immediately.
+fn is_transient(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    matches!(
+        error.kind(),
+        ErrorKind::PermissionDenied
+            | ErrorKind::ResourceBusy
+            | ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+    )
+}
+
+/// Run `op`, retrying up to `retries` times with exponential backoff on transient errors.
+fn with_retries<T>(retries: u32, mut op: impl FnMut() -> std::io::Result<T>) -> std::io::Result<T> {
+    let mut delay = FS_RETRY_DELAY;
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(e) if attempt < retries && is_transient(&e) => {
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// `fs::rename`, retried on transient errors.
+pub(crate) fn rename(from: &Path, to: &Path, options: &NoteOptions) -> std::io::Result<()> {
+    with_retries(options.fs_retries, || fs::rename(from, to))
+}
+
+/// Write content to a note file.
+/// Creates parent directories if they don't exist.
+/// Uses atomic write (write to temp, then rename) to prevent data corruption.
+/// Content over `options.max_note_bytes` is rejected before anything is written.
+pub fn write_note(path: &Path, content: &str, options: &NoteOptions) -> Result<()> {
+    check_writable(path)?;
+    check_note_size(path, content, options.max_note_bytes)?;
+    write_file(path, content, options)
+}
+
+/// Reject content longer than `max` bytes.
+fn check_note_size(path: &Path, content: &str, max: Option<u64>) -> Result<()> {
+    if let Some(max) = max {
+        if content.len() as u64 > max {
+            anyhow::bail!(
+                "Note is {} bytes, over the {} byte limit (STUMBLING_MAX_NOTE_BYTES): {}",
+                content.len(),
+                max,
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The atomic write behind `write_note`, without its checks on notes, for server state
+/// such as the index and the undo log.
+pub(crate) fn write_file(path: &Path, content: &str, options: &NoteOptions) -> Result<()> {
+    // Ensure parent directory exists
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+
+    // Atomic write: write to temp file, then rename
+    let temp_path = path.with_extension("tmp");
+    with_retries(options.fs_retries, || fs::write(&temp_path, content))
+        .with_context(|| format!("Failed to write temp file: {}", temp_path.display()))?;
+
+    // The temp file gets the default mode, so carry over the replaced note's permissions
+    if let Ok(meta) = fs::metadata(path) {
+        fs::set_permissions(&temp_path, meta.permissions())
+            .with_context(|| format!("Failed to set permissions on: {}", temp_path.display()))?;
+    }
+
+    rename(&temp_path, path, options)
+        .with_context(|| format!("Failed to rename temp file to: {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Compressed notes can be read and searched, but not written.
+fn check_writable(path: &Path) -> Result<()> {
+    if is_compressed_note(path) {
+        anyhow::bail!(
+            "Compressed notes are read-only, decompress it to edit: {}",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Write a new note, failing if one already exists at path.
+/// The content goes to a temp file that is then hard-linked into place, so a note created
+/// by someone else in the meantime is never replaced.
+pub fn create_note(path: &Path, content: &str, options: &NoteOptions) -> Result<()> {
+    check_writable(path)?;
+    check_note_size(path, content, options.max_note_bytes)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+
+    // Unique, so concurrent creates of the same note don't share a temp file
+    let temp_path = path.with_extension(format!("{:08x}.tmp", rand::random::<u32>()));
+    with_retries(options.fs_retries, || fs::write(&temp_path, content))
+        .with_context(|| format!("Failed to write temp file: {}", temp_path.display()))?;
+    let linked = with_retries(options.fs_retries, || fs::hard_link(&temp_path, path));
+    let _ = fs::remove_file(&temp_path);
+
+    match linked {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            anyhow::bail!(NoteError::Conflict(format!(
+                "Note already exists: {}",
+                path.display()
+            )))
+        }
+        Err(e) => Err(e).with_context(|| format!("Failed to create note: {}", path.display())),
+    }
+}
+
+/// Append content to the end of a note on a new line, creating the note if it doesn't exist.
+pub fn append_note(path: &Path, content: &str, options: &NoteOptions) -> Result<()> {
+    let combined = preview_write(path, content, WriteMode::Append)?;
+    write_note(path, &combined, options)
+}
+
+/// What a note would contain after writing `content` with `mode`, without writing it.
+pub fn preview_write(path: &Path, content: &str, mode: WriteMode) -> Result<String> {
+    check_writable(path)?;
+    let existing = match fs::read_to_string(path) {
+        Ok(existing) => Some(existing),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read file: {}", path.display()))
+        }
+    };
+
+    match (mode, existing) {
+        (WriteMode::CreateNew, Some(_)) => {
+            anyhow::bail!(NoteError::Conflict(format!(
+                "Note already exists: {}",
+                path.display()
+            )))
+        }
+        (WriteMode::Append, Some(mut combined)) => {
+            if !combined.is_empty() && !combined.ends_with('\n') {
+                combined.push('\n');
+            }
+            combined.push_str(content);
+            Ok(combined)
+        }
+        _ => Ok(content.to_string()),
+    }
+}
+
+/// Unified diff from `old` to `new`, labelled with the note's path. Empty when identical.
+pub fn unified_diff(path: &str, old: &str, new: &str) -> String {
+    if old == new {
+        return String::new();
+    }
+    similar::TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(&format!("a/{}", path), &format!("b/{}", path))
+        .to_string()
+}
+
+/// Replace a 1-indexed, inclusive line range of a note with new text.
+/// `end_line` may be one past the last line to append at the end of the file.
+/// The file's line endings (LF or CRLF) and trailing-newline state are preserved.
+pub fn patch_note(
+    path: &Path,
+    start_line: usize,
+    end_line: usize,
+    replacement: &str,
+    options: &NoteOptions,
+) -> Result<()> {
+    let content = read_note_text(path)?;
+
+    let mut lines: Vec<&str> = content.lines().collect();
+    let line_count = lines.len();
+
+    if start_line == 0 {
+        anyhow::bail!("Line numbers are 1-indexed, got start_line 0");
+    }
+    if start_line > end_line {
+        anyhow::bail!(
+            "start_line ({}) must not be greater than end_line ({})",
+            start_line,
+            end_line
+        );
+    }
+    if end_line > line_count + 1 {
+        anyhow::bail!(
+            "end_line ({}) is out of range: file has {} lines",
+            end_line,
+            line_count
+        );
+    }
+
+    // end_line == line_count + 1 only extends the range past the end, so clamp it
+    let end = end_line.min(line_count);
+    lines.splice(start_line - 1..end, replacement.lines());
+
+    let newline = if content.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let mut patched = lines.join(newline);
+    if content.ends_with('\n') && !patched.is_empty() {
+        patched.push_str(newline);
+    }
+
+    write_note(path, &patched, options)
+}
+
+/// Replace literal text in a note, without needing its line numbers.
+/// Returns the number of replacements; it's an error if `find` doesn't occur.
+pub fn replace_first(
+    path: &Path,
+    find: &str,
+    replace: &str,
+    occurrence: Occurrence,
+    options: &NoteOptions,
+) -> Result<usize> {
+    if find.is_empty() {
+        anyhow::bail!("Text to find must not be empty");
+    }
+    let content = read_note_text(path)?;
+
+    let (edited, count) = match occurrence {
+        Occurrence::All => (
+            content.replace(find, replace),
+            content.matches(find).count(),
+        ),
+        Occurrence::First => match content.find(find) {
+            Some(start) => (splice(&content, start, find.len(), replace), 1),
+            None => (content, 0),
+        },
+        Occurrence::Last => match content.rfind(find) {
+            Some(start) => (splice(&content, start, find.len(), replace), 1),
+            None => (content, 0),
+        },
+    };
+    if count == 0 {
+        anyhow::bail!(NoteError::NotFound(format!(
+            "Text not found in {}: {}",
+            path.display(),
+            find
+        )));
+    }
+
+    write_note(path, &edited, options)?;
+    Ok(count)
+}
+
+fn splice(content: &str, start: usize, len: usize, replace: &str) -> String {
+    format!(
+        "{}{}{}",
+        &content[..start],
+        replace,
+        &content[start + len..]
+    )
+}
+
+/// Regex find-and-replace across all notes, optionally restricted by a glob on the relative path.
+/// The replacement supports capture groups like `$1`.
+/// In dry-run mode, reports the substitution counts without writing anything.
+pub fn replace_in_notes(
+    root: &Path,
+    pattern: &str,
+    replacement: &str,
+    path_glob: Option<&str>,
+    dry_run: bool,
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Result<Vec<ReplaceResult>> {
+    let regex = compile_regex(pattern, "regex pattern")?;
+
+    let glob = path_glob
+        .map(|g| {
+            globset::Glob::new(g)
+                .map(|g| g.compile_matcher())
+                .with_context(|| format!("Invalid glob pattern: {}", g))
+        })
+        .transpose()?;
+
+    let files: Vec<_> = collect_markdown_files(root, trash_dir)
+        .into_iter()
+        .filter(|path| {
+            glob.as_ref()
+                .map(|g| g.is_match(relative_path(root, path)))
+                .unwrap_or(true)
+        })
+        .collect();
+
+    // Replace in parallel; each modified file goes through the atomic write path
+    let mut results = files
+        .par_iter()
+        .map(|path| -> Result<Option<ReplaceResult>> {
+            let content = read_note_text(path)?;
+
+            let replacements = regex.find_iter(&content).count();
+            if replacements == 0 {
+                return Ok(None);
+            }
+
+            if !dry_run {
+                let replaced = regex.replace_all(&content, replacement);
+                write_note(path, &replaced, options)?;
+            }
+
+            Ok(Some(ReplaceResult {
+                path: relative_path(root, path),
+                replacements,
+            }))
+        })
+        .filter_map(|r| r.transpose())
+        .collect::<Result<Vec<_>>>()?;
+
+    results.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(results)
+}
+
+/// Outcome of `rename_note`: which notes had links rewritten, and how many in total.
+#[derive(Debug, Serialize)]
+pub struct RenameReport {
+    pub from: String,
+    pub to: String,
+    pub updated_links: usize,
+    pub files: Vec<ReplaceResult>,
+}
+
+/// Rename or move a note, rewriting the wikilinks and relative markdown links that
+/// pointed at it. Aliases and headings in links are kept, and links inside code are left
+/// alone. The moved note's own relative links are updated for its new directory.
+/// In dry-run mode, reports the links that would change without writing or moving anything.
+pub fn rename_note(
+    root: &Path,
+    from: &Path,
+    to: &Path,
+    dry_run: bool,
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Result<RenameReport> {
+    if !from.is_file() {
+        anyhow::bail!(NoteError::NotFound(format!(
+            "File does not exist: {}",
+            from.display()
+        )));
+    }
+    if to.exists() {
+        anyhow::bail!(NoteError::Conflict(format!(
+            "Note already exists: {}",
+            to.display()
+        )));
+    }
+
+    let old = relative_path(root, from);
+    let new = relative_path(root, to);
+    let old_stem = from
+        .file_stem()
+        .context("Note has no file name")?
+        .to_string_lossy()
+        .to_lowercase();
+    // Resolve links against the vault as it is before the move
+    let stems = note_stems(root, trash_dir);
+
+    let mut rewrites = collect_markdown_files(root, trash_dir)
+        .par_iter()
+        .map(|path| -> Result<Option<(PathBuf, String, usize)>> {
+            let content = read_note_text(path)?;
+
+            let relative = relative_path(root, path);
+            let moved = relative == old;
+            // Every link to the note mentions its name
+            if !moved && !content.to_lowercase().contains(&old_stem) {
+                return Ok(None);
+            }
+
+            let links = LinkRename {
+                root,
+                old: &old,
+                new: &new,
+                stems: &stems,
+            };
+            let new_dir = Path::new(if moved { &new } else { &relative })
+                .parent()
+                .unwrap_or(Path::new(""));
+            let note_dir = path.parent().unwrap_or(root);
+            let (rewritten, count) = links.rewrite(&content, note_dir, new_dir, moved);
+            if count == 0 {
+                return Ok(None);
+            }
+            let path = if moved {
+                to.to_path_buf()
+            } else {
+                path.clone()
+            };
+            Ok(Some((path, rewritten, count)))
+        })
+        .filter_map(|r| r.transpose())
+        .collect::<Result<Vec<_>>>()?;
+    rewrites.sort_by(|a, b| a.0.cmp(&b.0));
+
+    if !dry_run {
+        // Move first, so a failed move leaves every link as it was
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        rename(from, to, options)
+            .with_context(|| format!("Failed to move note: {}", from.display()))?;
+        for (path, content, _) in &rewrites {
+            write_note(path, content, options)?;
+        }
+    }
+
+    Ok(RenameReport {
+        from: old,
+        to: new,
+        updated_links: rewrites.iter().map(|(_, _, count)| count).sum(),
+        files: rewrites
+            .into_iter()
+            .map(|(path, _, replacements)| ReplaceResult {
+                path: relative_path(root, &path),
+                replacements,
+            })
+            .collect(),
+    })
+}
+
+/// A note moved by `move_notes`, as paths relative to the vault root.
+#[derive(Debug, Serialize)]
+pub struct MovedNote {
+    pub from: String,
+    pub to: String,
+}
+
+/// Move many notes at once. Every pair is checked before anything moves: both paths must
+/// be inside root, each source must exist, and no destination may already exist or be used
+/// twice. If a move then fails, the ones already done are moved back.
+/// Links are not rewritten; use `rename_note` for that.
+pub fn move_notes(
+    root: &Path,
+    moves: &[(String, String)],
+    options: &NoteOptions,
+) -> Result<Vec<MovedNote>> {
+    let mut resolved = Vec::with_capacity(moves.len());
+    let mut sources = BTreeSet::new();
+    let mut destinations = BTreeSet::new();
+    for (i, (from, to)) in moves.iter().enumerate() {
+        let pair = || format!("Move {} ({} -> {})", i + 1, from, to);
+        let from_path = resolve_note_path(root, from).with_context(pair)?;
+        let to_path = resolve_note_path(root, to).with_context(pair)?;
+        if !from_path.is_file() {
+            anyhow::bail!(NoteError::NotFound(format!(
+                "{}: note does not exist",
+                pair()
+            )));
+        }
+        if to_path.exists() {
+            anyhow::bail!(NoteError::Conflict(format!(
+                "{}: destination already exists",
+                pair()
+            )));
+        }
+        if !sources.insert(from_path.clone()) {
+            anyhow::bail!("{}: source is moved more than once", pair());
+        }
+        if !destinations.insert(to_path.clone()) {
+            anyhow::bail!(NoteError::Conflict(format!(
+                "{}: destination already exists in this batch",
+                pair()
+            )));
+        }
+        resolved.push((from_path, to_path));
+    }
+
+    for (i, (from, to)) in resolved.iter().enumerate() {
+        let moved = to
+            .parent()
+            .map_or(Ok(()), |parent| {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory: {}", parent.display()))
+            })
+            .and_then(|_| {
+                rename(from, to, options)
+                    .with_context(|| format!("Failed to move note: {}", from.display()))
+            });
+        if let Err(e) = moved {
+            // Best effort: put back what was already moved, newest first
+            for (from, to) in resolved[..i].iter().rev() {
+                let _ = rename(to, from, options);
+            }
+            let (from, to) = &moves[i];
+            return Err(e.context(format!(
+                "Move {} ({} -> {}) failed; no notes were moved",
+                i + 1,
+                from,
+                to
+            )));
+        }
+    }
+
+    Ok(resolved
+        .iter()
+        .map(|(from, to)| MovedNote {
+            from: relative_path(root, from),
+            to: relative_path(root, to),
+        })
+        .collect())
+}
+
+/// The note being renamed by `rename_note`, for rewriting links to it.
+struct LinkRename<'a> {
+    root: &'a Path,
+    old: &'a str,
+    new: &'a str,
+    stems: &'a BTreeMap<String, Vec<String>>,
+}
+
+impl LinkRename<'_> {
+    /// Rewrite the links in one note, returning the new content and how many links changed.
+    /// `note_dir` is where the note is now and `new_dir` where it will be, relative to root.
+    fn rewrite(
+        &self,
+        content: &str,
+        note_dir: &Path,
+        new_dir: &Path,
+        moved: bool,
+    ) -> (String, usize) {
+        let code = code_ranges(content);
+        let in_code = |offset: usize| code.iter().any(|range| range.contains(&offset));
+
+        let mut edits = Vec::new();
+        for caps in wikilink_regex().captures_iter(content) {
+            let target = caps.get(1).expect("wikilink has a target");
+            if in_code(target.start()) {
+                continue;
+            }
+            let resolved = resolve_wikilink(self.root, target.as_str().trim(), self.stems);
+            if resolved.as_deref() == Some(self.old) {
+                edits.push((target.range(), self.wikilink_target(target.as_str().trim())));
+            }
+        }
+
+        for caps in markdown_link_regex().captures_iter(content) {
+            let url = caps.get(1).expect("markdown link has a url");
+            if in_code(url.start()) || is_external_url(url.as_str()) {
+                continue;
+            }
+            let split = url.as_str().find(['#', '?']).unwrap_or(url.len());
+            let (link, suffix) = url.as_str().split_at(split);
+            let Some(resolved) = resolve_relative_link(self.root, note_dir, link) else {
+                continue;
+            };
+            // Links from the moved note to other notes change only because it moved
+            let target = if resolved == self.old {
+                self.new
+            } else if moved {
+                resolved.as_str()
+            } else {
+                continue;
+            };
+            let rewritten = format!("{}{}", relative_link(new_dir, target), suffix);
+            if rewritten != url.as_str() {
+                edits.push((url.range(), rewritten));
+            }
+        }
+
+        edits.sort_by_key(|(range, _)| range.start);
+        let mut rewritten = String::with_capacity(content.len());
+        let mut last = 0;
+        for (range, replacement) in &edits {
+            rewritten.push_str(&content[last..range.start]);
+            rewritten.push_str(replacement);
+            last = range.end;
+        }
+        rewritten.push_str(&content[last..]);
+        (rewritten, edits.len())
+    }
+
+    /// The new target for a wikilink, in the same style: a bare name stays a bare name
+    /// unless another note shares the new name, and an explicit `.md` is kept.
+    fn wikilink_target(&self, target: &str) -> String {
+        let ext = if target.ends_with(".md") { ".md" } else { "" };
+        let new = self.new.strip_suffix(".md").unwrap_or(self.new);
+        let name = Path::new(new)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let unique = self
+            .stems
+            .get(&name.to_lowercase())
+            .is_none_or(|paths| paths.iter().all(|path| path == self.old));
+
+        if target.contains('/') || !unique {
+            format!("{}{}", new, ext)
+        } else {
+            format!("{}{}", name, ext)
+        }
+    }
+}
+
+/// Matches the url of an inline markdown link or image, `[text](url)`.
+fn markdown_link_regex() -> &'static regex::Regex {
+    static MARKDOWN_LINK: OnceLock<regex::Regex> = OnceLock::new();
+    MARKDOWN_LINK.get_or_init(|| {
+        regex::Regex::new(r"\]\(\s*<?([^()\s<>]+)").expect("markdown link regex is valid")
+    })
+}
+
+/// Byte ranges of code blocks and inline code spans.
+fn code_ranges(content: &str) -> Vec<std::ops::Range<usize>> {
+    fn collect(node: &Node, ranges: &mut Vec<std::ops::Range<usize>>) {
+        if let Node::Code(_) | Node::InlineCode(_) = node {
+            if let Some(position) = node.position() {
+                ranges.push(position.start.offset..position.end.offset);
+            }
+            return;
+        }
+        for child in node.children().into_iter().flatten() {
+            collect(child, ranges);
+        }
+    }
+
+    let mut ranges = Vec::new();
+    if let Ok(ast) = markdown::to_mdast(content, &link_parse_options()) {
+        collect(&ast, &mut ranges);
+    }
+    ranges
+}
+
+/// Path from a directory to a note, both relative to the vault root, as a markdown link.
+fn relative_link(from_dir: &Path, to: &str) -> String {
+    let from: Vec<_> = from_dir.components().collect();
+    let to: Vec<_> = Path::new(to).components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut parts: Vec<String> = vec!["..".to_string(); from.len() - common];
+    parts.extend(
+        to[common..]
+            .iter()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned()),
+    );
+    parts.join("/")
+}
+
+/// Rename a tag in the `tags` frontmatter field of every note.
+/// Only frontmatter is touched; `#tag` mentions in the body are left alone.
+/// Handles both array (`tags: [a, b]`) and scalar (`tags: a`) forms.
+/// Returns the relative paths of the notes that were changed.
+pub fn rename_tag(
+    root: &Path,
+    old: &str,
+    new: &str,
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Result<Vec<String>> {
+    let files = collect_markdown_files(root, trash_dir);
+
+    let mut changed = files
+        .par_iter()
+        .map(|path| -> Result<Option<String>> {
+            let content = read_note_text(path)?;
+
+            let Some(frontmatter) = parse_frontmatter(&content, options) else {
+                return Ok(None);
+            };
+            let Ok(mut meta) = frontmatter.metadata() else {
+                return Ok(None);
+            };
+            let Some(tags) = meta.get_mut("tags") else {
+                return Ok(None);
+            };
+
+            if !rename_in_tags(tags, old, new) {
+                return Ok(None);
+            }
+
+            let content = format!(
+                "{}{}",
+                frontmatter_block_like(&frontmatter, &meta),
+                frontmatter.body
+            );
+            write_note(path, &content, options)?;
+            Ok(Some(relative_path(root, path)))
+        })
+        .filter_map(|r| r.transpose())
+        .collect::<Result<Vec<_>>>()?;
+
+    changed.sort();
+    Ok(changed)
+}
+
+/// Replace `old` with `new` in a tags value, deduplicating arrays.
+/// Returns true if the value was changed.
+fn rename_in_tags(tags: &mut serde_json::Value, old: &str, new: &str) -> bool {
+    match tags {
+        serde_json::Value::String(s) if s == old => {
+            *s = new.to_string();
+            true
+        }
+        serde_json::Value::Array(arr) => {
+            if !arr.iter().any(|t| t == old) {
+                return false;
+            }
+            let mut renamed: Vec<serde_json::Value> = Vec::with_capacity(arr.len());
+            for tag in arr.drain(..) {
+                let tag = if tag == old { new.into() } else { tag };
+                if !renamed.contains(&tag) {
+                    renamed.push(tag);
+                }
+            }
+            *arr = renamed;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Resolve and create the trash directory. Walks skip it anyway; the `.ignore` marker
+/// keeps it out of other tools' searches, such as ripgrep, too.
+fn ensure_trash_dir(root: &Path, trash_dir: &str) -> Result<PathBuf> {
+    let trash_dir = resolve_note_path(root, trash_dir)?;
+    fs::create_dir_all(&trash_dir)
+        .with_context(|| format!("Failed to create trash directory: {}", trash_dir.display()))?;
+
+    let ignore_file = trash_dir.join(".ignore");
+    if !ignore_file.exists() {
+        fs::write(&ignore_file, "*\n")
+            .with_context(|| format!("Failed to write ignore file: {}", ignore_file.display()))?;
+    }
+    Ok(trash_dir)
+}
+
+/// Seconds since the Unix epoch, used to keep trashed names unique.
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Delete a directory of notes. By default it is moved to `<trash_dir>/<timestamp>/`,
+/// keeping its path relative to root; `permanent` removes it instead.
+/// The vault root and the trash directory itself can't be deleted.
+pub fn delete_dir(
+    root: &Path,
+    path: &Path,
+    permanent: bool,
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Result<String> {
+    check_deletable_dir(root, path, trash_dir)?;
+    let relative = path.strip_prefix(root).unwrap_or(path);
+
+    if permanent {
+        fs::remove_dir_all(path)
+            .with_context(|| format!("Failed to delete directory: {}", path.display()))?;
+        return Ok(format!("Permanently deleted {}", relative.display()));
+    }
+
+    let trash_path = move_dir_to_trash(root, path, trash_dir, options)?;
+    Ok(format!(
+        "Moved to trash: {}",
+        relative_path(root, &trash_path)
+    ))
+}
+
+/// The directory must exist, and the vault root and the trash directory itself
+/// can't be deleted.
+fn check_deletable_dir(root: &Path, path: &Path, trash_dir: &str) -> Result<()> {
+    if !path.is_dir() {
+        anyhow::bail!(NoteError::NotFound(format!(
+            "Directory does not exist: {}",
+            path.display()
+        )));
+    }
+    if path
+        .strip_prefix(root)
+        .unwrap_or(path)
+        .as_os_str()
+        .is_empty()
+    {
+        anyhow::bail!("Refusing to delete the vault root");
+    }
+    if path.starts_with(resolve_note_path(root, trash_dir)?) {
+        anyhow::bail!("Refusing to delete the trash directory: {}", trash_dir);
+    }
+    Ok(())
+}
+
+/// The trashing half of `delete_dir`. Returns where the directory ended up.
+pub fn move_dir_to_trash(
+    root: &Path,
+    path: &Path,
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Result<PathBuf> {
+    check_deletable_dir(root, path, trash_dir)?;
+    let relative = path.strip_prefix(root).unwrap_or(path);
+
+    let trash_path = ensure_trash_dir(root, trash_dir)?
+        .join(unix_timestamp().to_string())
+        .join(relative);
+    if trash_path.exists() {
+        anyhow::bail!(NoteError::Conflict(format!(
+            "Trash entry already exists: {}",
+            trash_path.display()
+        )));
+    }
+    if let Some(parent) = trash_path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    rename(path, &trash_path, options)
+        .with_context(|| format!("Failed to move directory to trash: {}", path.display()))?;
+    Ok(trash_path)
+}
+
+/// Notes to edit in bulk: those matching a glob on the relative path, plus explicit paths.
+/// Explicit paths must exist.
+pub fn select_notes(
+    root: &Path,
+    path_glob: Option<&str>,
+    paths: &[String],
+    trash_dir: &str,
+) -> Result<Vec<PathBuf>> {
+    let mut selected = Vec::new();
+    if let Some(glob) = path_glob {
+        let glob = globset::Glob::new(glob)
+            .map(|g| g.compile_matcher())
+            .with_context(|| format!("Invalid glob pattern: {}", glob))?;
+        selected.extend(
+            collect_markdown_files(root, trash_dir)
+                .into_iter()
+                .filter(|path| glob.is_match(relative_path(root, path))),
+        );
+    }
+    for relative in paths {
+        let path = resolve_note_path(root, relative)?;
+        if !path.is_file() {
+            anyhow::bail!(NoteError::NotFound(format!(
+                "File does not exist: {}",
+                path.display()
+            )));
+        }
+        selected.push(path);
+    }
+
+    selected.sort();
+    selected.dedup();
+    Ok(selected)
+}
+
+/// Add a tag to the `tags` frontmatter of each note, creating the list (and the
+/// frontmatter) if needed. Notes that already have the tag are left alone.
+/// Returns the relative paths of the notes that were changed.
+pub fn add_tag(
+    root: &Path,
+    paths: &[PathBuf],
+    tag: &str,
+    options: &NoteOptions,
+) -> Result<Vec<String>> {
+    edit_tags(
+        root,
+        paths,
+        |meta| {
+            let tags = meta.entry("tags").or_insert(serde_json::Value::Null);
+            match tags {
+                serde_json::Value::Array(items) if items.iter().any(|t| t == tag) => false,
+                serde_json::Value::Array(items) => {
+                    items.push(tag.into());
+                    true
+                }
+                serde_json::Value::Null => {
+                    *tags = serde_json::json!([tag]);
+                    true
+                }
+                existing if *existing == tag => false,
+                existing => {
+                    *existing = serde_json::json!([existing.take(), tag]);
+                    true
+                }
+            }
+        },
+        options,
+    )
+}
+
+/// Remove a tag from the `tags` frontmatter of each note. A scalar `tags: tag` is
+/// removed entirely. Returns the relative paths of the notes that were changed.
+pub fn remove_tag(
+    root: &Path,
+    paths: &[PathBuf],
+    tag: &str,
+    options: &NoteOptions,
+) -> Result<Vec<String>> {
+    edit_tags(
+        root,
+        paths,
+        |meta| match meta.get_mut("tags") {
+            Some(serde_json::Value::Array(items)) => {
+                let before = items.len();
+                items.retain(|t| t != tag);
+                items.len() != before
+            }
+            Some(existing) if *existing == tag => {
+                meta.shift_remove("tags");
+                true
+            }
+            _ => false,
+        },
+        options,
+    )
+}
+
+/// Apply `edit` to the frontmatter of each note in parallel, returning the changed notes.
+fn edit_tags(
+    root: &Path,
+    paths: &[PathBuf],
+    edit: impl Fn(&mut serde_json::Map<String, serde_json::Value>) -> bool + Sync,
+    options: &NoteOptions,
+) -> Result<Vec<String>> {
+    let mut changed = paths
+        .par_iter()
+        .map(|path| -> Result<Option<String>> {
+            let edited = edit_metadata(path, &edit, options)?;
+            Ok(edited.then(|| relative_path(root, path)))
+        })
+        .filter_map(|r| r.transpose())
+        .collect::<Result<Vec<_>>>()?;
+
+    changed.sort();
+    Ok(changed)
+}
+
+/// Something in the trash, as put there by `delete_note` or `delete_dir`.
+#[derive(Debug, Serialize)]
+pub struct TrashEntry {
+    /// Path of the entry, relative to the vault root
+    pub trash_path: String,
+    /// Where it was before being deleted, relative to the vault root. Notes trashed before
+    /// their path was recorded in the manifest only have their file name.
+    pub original: String,
+    pub is_dir: bool,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// The timestamp as RFC 3339, in UTC
+    pub deleted_at: String,
+}
+
+/// List what's in the trash, newest first. Entries not named by a timestamp are skipped.
+pub fn list_trash(root: &Path, trash_dir: &str) -> Result<Vec<TrashEntry>> {
+    let manifest = read_trash_manifest(&resolve_note_path(root, trash_dir)?);
+    let mut listed = Vec::new();
+    for entry in read_trash(root, trash_dir)? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = entry.path();
+        let is_dir = path.is_dir();
+
+        let Some((timestamp, file_name)) = parse_trash_name(&name, is_dir) else {
+            continue;
+        };
+        let original = if is_dir {
+            trashed_dir_path(&path)
+        } else {
+            manifest
+                .get(&name)
+                .cloned()
+                .unwrap_or_else(|| file_name.to_string())
+        };
+
+        let deleted_at = i64::try_from(timestamp)
+            .ok()
+            .and_then(|secs| DateTime::from_timestamp(secs, 0))
+            .map(|time| time.to_rfc3339())
+            .unwrap_or_default();
+        listed.push(TrashEntry {
+            trash_path: relative_path(root, &path),
+            original,
+            is_dir,
+            timestamp,
+            deleted_at,
+        });
+    }
+
+    listed.sort_by(|a, b| {
+        b.timestamp
+            .cmp(&a.timestamp)
+            .then_with(|| a.trash_path.cmp(&b.trash_path))
+    });
+    Ok(listed)
+}
+
+/// Top-level entries of the trash directory, or none if it doesn't exist yet.
+/// The `.ignore` file that keeps the trash out of searches and the manifest are left out.
+fn read_trash(root: &Path, trash_dir: &str) -> Result<Vec<fs::DirEntry>> {
+    let trash = resolve_note_path(root, trash_dir)?;
+    match fs::read_dir(&trash) {
+        Ok(entries) => Ok(entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name() != ".ignore" && e.file_name() != TRASH_MANIFEST)
+            .collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read directory: {}", trash.display())),
+    }
+}
+
+/// The trash manifest: trashed note names to their original paths relative to the root.
+/// Missing or unreadable manifests are empty.
+fn read_trash_manifest(trash: &Path) -> BTreeMap<String, String> {
+    fs::read_to_string(trash.join(TRASH_MANIFEST))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+/// Held while the trash manifest is read and rewritten, so concurrent deletes and
+/// restores don't lose each other's entries.
+static TRASH_MANIFEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// Change the trash manifest with `edit` and save it, returning what `edit` returns.
+fn edit_trash_manifest<T>(
+    trash: &Path,
+    options: &NoteOptions,
+    edit: impl FnOnce(&mut BTreeMap<String, String>) -> T,
+) -> Result<T> {
+    let _lock = TRASH_MANIFEST_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut manifest = read_trash_manifest(trash);
+    let result = edit(&mut manifest);
+    write_file(
+        &trash.join(TRASH_MANIFEST),
+        &serde_json::to_string_pretty(&manifest)?,
+        options,
+    )?;
+    Ok(result)
+}
+
+/// A trash name for a note deleted now: `<timestamp>_<name>`, or `<timestamp>-<n>_<name>`
+/// if that is already taken by a note of the same name deleted in the same second.
+fn unique_trash_name(trash: &Path, manifest: &BTreeMap<String, String>, file_name: &str) -> String {
+    let timestamp = unix_timestamp();
+    let mut trash_name = format!("{}_{}", timestamp, file_name);
+    let mut n = 1;
+    while manifest.contains_key(&trash_name) || trash.join(&trash_name).exists() {
+        trash_name = format!("{}-{}_{}", timestamp, n, file_name);
+        n += 1;
+    }
+    trash_name
+}
+
+/// Split a trash entry's name into its deletion timestamp and the note's file name.
+/// Notes are `<timestamp>_<name>`, or `<timestamp>-<n>_<name>` when several of the same
+/// name were deleted in one second; directories are `<timestamp>/<original path>`, so
+/// their file name is empty.
+fn parse_trash_name(name: &str, is_dir: bool) -> Option<(u64, &str)> {
+    let (timestamp, file_name) = if is_dir {
+        (name, "")
+    } else {
+        let (prefix, file_name) = name.split_once('_')?;
+        let timestamp = prefix
+            .split_once('-')
+            .map_or(prefix, |(timestamp, _)| timestamp);
+        (timestamp, file_name)
+    };
+    Some((timestamp.parse().ok()?, file_name))
+}
+
+/// Permanently remove trash entries deleted more than `max_age` ago, returning how many
+/// were removed. Age comes from the entry's timestamp prefix, or its modification time
+/// if it has none.
+pub fn prune_trash(
+    root: &Path,
+    trash_dir: &str,
+    max_age: Duration,
+    options: &NoteOptions,
+) -> Result<usize> {
+    let cutoff = SystemTime::now().checked_sub(max_age).unwrap_or(UNIX_EPOCH);
+
+    let mut pruned = 0;
+    let mut pruned_names = Vec::new();
+    for entry in read_trash(root, trash_dir)? {
+        let path = entry.path();
+        let is_dir = path.is_dir();
+        let name = entry.file_name();
+        let deleted = match parse_trash_name(&name.to_string_lossy(), is_dir) {
+            Some((timestamp, _)) => UNIX_EPOCH + Duration::from_secs(timestamp),
+            None => match entry.metadata().and_then(|meta| meta.modified()) {
+                Ok(modified) => modified,
+                Err(_) => continue,
+            },
+        };
+        if deleted >= cutoff {
+            continue;
+        }
+
+        let removed = if is_dir {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+        removed.with_context(|| format!("Failed to prune trash entry: {}", path.display()))?;
+        pruned += 1;
+        pruned_names.push(name.to_string_lossy().into_owned());
+    }
+
+    let trash = resolve_note_path(root, trash_dir)?;
+    if trash.join(TRASH_MANIFEST).exists() {
+        edit_trash_manifest(&trash, options, |manifest| {
+            for name in &pruned_names {
+                manifest.remove(name);
+            }
+        })?;
+    }
+    Ok(pruned)
+}
+
+/// The original path of a directory trashed under `<timestamp>/`, found by following the
+/// chain of single subdirectories. A best guess: a trashed directory holding only one
+/// subdirectory is reported as that subdirectory.
+fn trashed_dir_path(timestamp_dir: &Path) -> String {
+    let mut path = timestamp_dir.to_path_buf();
+    loop {
+        let children: Vec<_> = fs::read_dir(&path)
+            .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+            .unwrap_or_default();
+        match children.as_slice() {
+            [only] if only.is_dir() => path = only.clone(),
+            _ => break,
+        }
+    }
+    relative_path(timestamp_dir, &path)
+}
+
+/// Delete a note file.
+/// If permanent is false, moves to the trash directory (relative to root) with timestamp.
+/// If permanent is true, permanently deletes the file.
+pub fn delete_note(
+    root: &Path,
+    path: &Path,
+    permanent: bool,
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Result<String> {
+    if !path.exists() {
+        anyhow::bail!(NoteError::NotFound(format!(
+            "File does not exist: {}",
+            path.display()
+        )));
+    }
+
+    if permanent {
+        fs::remove_file(path)
+            .with_context(|| format!("Failed to delete file: {}", path.display()))?;
+        Ok(format!("Permanently deleted {}", path.display()))
+    } else {
+        let trash_path = move_note_to_trash(root, path, trash_dir, options)?;
+        Ok(format!(
+            "Moved to trash: {}",
+            relative_path(root, &trash_path)
+        ))
+    }
+}
+
+/// The trashing half of `delete_note`: move the note into the trash directory under a
+/// timestamped name, recording its original path in the trash manifest for
+/// `restore_note`. Returns where it ended up.
+pub fn move_note_to_trash(
+    root: &Path,
+    path: &Path,
+    trash_dir: &str,
+    options: &NoteOptions,
+) -> Result<PathBuf> {
+    if !path.exists() {
+        anyhow::bail!(NoteError::NotFound(format!(
+            "File does not exist: {}",
+            path.display()
+        )));
+    }
+    let trash_dir = ensure_trash_dir(root, trash_dir)?;
+
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    // Recorded first: a stale entry is harmless, but a trashed note without one is
+    // restored to the vault root. The entry also reserves the name until the move.
+    let trash_path = edit_trash_manifest(&trash_dir, options, |manifest| {
+        let trash_name = unique_trash_name(&trash_dir, manifest, &file_name);
+        manifest.insert(trash_name.clone(), relative_path(root, path));
+        trash_dir.join(trash_name)
+    })?;
+    rename(path, &trash_path, options)
+        .with_context(|| format!("Failed to move file to trash: {}", path.display()))?;
+    Ok(trash_path)
+}
+
+/// Move a note out of the trash. `trash_path` is the entry's path relative to the root,
+/// as listed by `list_trash`. It goes back to its original path from the trash manifest
+/// (or the vault root, for notes trashed without one) unless `to` is given. Missing
+/// folders are recreated; an existing note is never overwritten. Returns the restored
+/// path relative to root.
+pub fn restore_note(
+    root: &Path,
+    trash_dir: &str,
+    trash_path: &str,
+    to: Option<&Path>,
+    options: &NoteOptions,
+) -> Result<String> {
+    let trash = resolve_note_path(root, trash_dir)?;
+    let entry = resolve_note_path(root, trash_path)?;
+    let name = entry
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let file_name = match parse_trash_name(&name, false) {
+        Some((_, file_name)) if entry.parent() == Some(trash.as_path()) => file_name,
+        _ => anyhow::bail!("Not a trashed note: {}", trash_path),
+    };
+    if !entry.is_file() {
+        anyhow::bail!("Not a trashed note: {}", trash_path);
+    }
+
+    let destination = match to {
+        Some(to) => to.to_path_buf(),
+        None => {
+            let original = read_trash_manifest(&trash).remove(&name);
+            resolve_note_path(root, original.as_deref().unwrap_or(file_name))?
+        }
+    };
+    if destination.exists() {
+        anyhow::bail!(NoteError::Conflict(format!(
+            "Note already exists: {}",
+            destination.display()
+        )));
+    }
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    rename(&entry, &destination, options)
+        .with_context(|| format!("Failed to restore from trash: {}", trash_path))?;
+
+    if trash.join(TRASH_MANIFEST).exists() {
+        edit_trash_manifest(&trash, options, |manifest| {
+            manifest.remove(&name);
+        })?;
+    }
+    Ok(relative_path(root, &destination))
+}
+
+/// Set top-level frontmatter fields of a note, adding a frontmatter block if it has none.
+pub fn update_metadata(
+    path: &Path,
+    fields: serde_json::Map<String, serde_json::Value>,
+    options: &NoteOptions,
+) -> Result<()> {
+    edit_metadata(
+        path,
+        |meta| {
+            meta.extend(fields);
+            true
+        },
+        options,
+    )
+    .map(|_| ())
+}
+
+/// Change a note's frontmatter in place with `edit`, adding a frontmatter block if it has
+/// none. The note is rewritten, keeping its frontmatter format, only if `edit` returns true.
+fn edit_metadata(
+    path: &Path,
+    edit: impl FnOnce(&mut serde_json::Map<String, serde_json::Value>) -> bool,
+    options: &NoteOptions,
+) -> Result<bool> {
+    let content = read_note_text(path)?;
+
+    let frontmatter = parse_frontmatter(&content, options);
+    let mut meta = match &frontmatter {
+        Some(frontmatter) => match frontmatter.metadata()? {
+            serde_json::Value::Object(meta) => meta,
+            _ => anyhow::bail!("Frontmatter is not a mapping: {}", path.display()),
+        },
+        None => serde_json::Map::new(),
+    };
+    if !edit(&mut meta) {
+        return Ok(false);
+    }
+
+    let meta = serde_json::Value::Object(meta);
+    let content = match &frontmatter {
+        Some(frontmatter) => {
+            format!(
+                "{}{}",
+                frontmatter_block_like(frontmatter, &meta),
+                frontmatter.body
+            )
+        }
+        None => format_with_frontmatter(&meta, &content, options),
+    };
+    write_note(path, &content, options)?;
+    Ok(true)
+}
+
+/// Rewrite a note's frontmatter in canonical form, keeping its format and key order:
+/// a scalar `tags` becomes a one-item list, lists are written as block lists, dates like
+/// `2024/1/5` become `2024-01-05`, and quoting is whatever the serializer picks. The body
+/// is left byte-identical. Returns whether the note changed (or would, with `dry_run`).
+/// Notes without frontmatter are left alone.
+pub fn normalize_frontmatter(path: &Path, dry_run: bool, options: &NoteOptions) -> Result<bool> {
+    let content = read_note_text(path)?;
+    let Some(frontmatter) = parse_frontmatter(&content, options) else {
+        return Ok(false);
+    };
+
+    let mut meta = frontmatter.metadata()?;
+    if let Some(tags) = meta.get_mut("tags") {
+        if let serde_json::Value::String(tag) = tags {
+            *tags = serde_json::Value::Array(vec![tag.clone().into()]);
+        }
+    }
+    normalize_dates(&mut meta);
+
+    // The block is spliced in front of the original text so the body keeps its exact bytes
+    let block = frontmatter_block_like(&frontmatter, &meta);
+    let normalized = format!(
+        "{}{}",
+        block.trim_end_matches('\n'),
+        &content[frontmatter.end..]
+    );
+    if normalized == content {
+        return Ok(false);
+    }
+    if !dry_run {
+        write_note(path, &normalized, options)?;
+    }
+    Ok(true)
+}
+
+/// Rewrite date-only strings, wherever they are nested, as `YYYY-MM-DD`.
+fn normalize_dates(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::String(s) => {
+            let date = ["%Y-%m-%d", "%Y/%m/%d", "%Y.%m.%d"]
+                .iter()
+                .find_map(|format| NaiveDate::parse_from_str(s.trim(), format).ok());
+            if let Some(date) = date {
+                *s = date.format("%Y-%m-%d").to_string();
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(normalize_dates),
+        serde_json::Value::Object(map) => map.values_mut().for_each(normalize_dates),
+        _ => {}
+    }
+}
+
+/// Hex SHA-256 of a note's file bytes.
+pub fn note_hash(path: &Path) -> Result<String> {
+    let bytes =
+        fs::read(path).with_context(|| format!("Failed to read file: {}", path.display()))?;
+    Ok(format!("{:x}", Sha256::digest(&bytes)))
+}
+
+/// Hex SHA-256 of a note's body, ignoring frontmatter, and store it as the `checksum`
+/// frontmatter field. Since the field isn't part of the body, storing it again is a no-op.
+pub fn store_checksum(path: &Path, options: &NoteOptions) -> Result<String> {
+    let checksum = format!("{:x}", Sha256::digest(read_body(path, options)?.as_bytes()));
+
+    let current = read_metadata(path, options);
+    if current.as_ref().and_then(|m| m.get("checksum")) != Some(&checksum.clone().into()) {
+        let mut fields = serde_json::Map::new();
+        fields.insert("checksum".to_string(), checksum.clone().into());
+        update_metadata(path, fields, options)?;
+    }
+
+    Ok(checksum)
+}
+
+/// Move a note into `<archive_dir>/<YYYY>/`, keeping its filename.
+/// The year comes from the note's `created` frontmatter field, or today if it has none.
+/// If the name is taken in the archive, a numeric suffix is added (`note-2.md`).
+/// With `stamp`, an `archived: <YYYY-MM-DD>` field is set on the archived note.
+/// Returns the new path relative to root.
+pub fn archive_note(
+    root: &Path,
+    path: &Path,
+    archive_dir: &str,
+    stamp: bool,
+    options: &NoteOptions,
+) -> Result<String> {
+    if !path.is_file() {
+        anyhow::bail!(NoteError::NotFound(format!(
+            "File does not exist: {}",
+            path.display()
+        )));
+    }
+
+    let today = chrono::Local::now().date_naive();
+    let date = read_metadata(path, options)
+        .as_ref()
+        .and_then(|meta| meta.get("created"))
+        .and_then(|created| created.as_str())
+        .and_then(parse_date)
+        .unwrap_or(today);
+
+    let year_dir = resolve_note_path(root, archive_dir)?.join(date.format("%Y").to_string());
+    let stem = path
+        .file_stem()
+        .context("Note has no file name")?
+        .to_string_lossy();
+    let mut archived = year_dir.join(format!("{}.md", stem));
+    let mut n = 1;
+    while archived.exists() {
+        n += 1;
+        archived = year_dir.join(format!("{}-{}.md", stem, n));
+    }
+
+    fs::create_dir_all(&year_dir)
+        .with_context(|| format!("Failed to create directory: {}", year_dir.display()))?;
+    rename(path, &archived, options)
+        .with_context(|| format!("Failed to move file to archive: {}", path.display()))?;
+
+    if stamp {
+        let mut fields = serde_json::Map::new();
+        fields.insert(
+            "archived".to_string(),
+            today.format("%Y-%m-%d").to_string().into(),
+        );
+        update_metadata(&archived, fields, &NoteOptions::default())?;
+    }
+
+    Ok(relative_path(root, &archived))
+}
+
+/// Check a STUMBLING_DAILY_FORMAT value: a valid chrono format that yields a relative
+/// `.md` path.
+pub fn validate_daily_format(format: &str) -> Result<()> {
+    use chrono::format::{Item, StrftimeItems};
+
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        anyhow::bail!("Invalid STUMBLING_DAILY_FORMAT: {}", format);
+    }
+    let sample = chrono::Local::now().date_naive().format(format).to_string();
+    if !sample.ends_with(".md") {
+        anyhow::bail!(
+            "STUMBLING_DAILY_FORMAT must end with .md: {} (e.g. daily/%Y-%m-%d.md)",
+            format
+        );
+    }
+    resolve_note_path(Path::new(""), &sample)
+        .with_context(|| format!("Invalid STUMBLING_DAILY_FORMAT: {}", format))?;
+    Ok(())
+}
+
+/// Append `- HH:MM text` to the daily note for `now`, whose path is `daily_format` (a
+/// chrono format like `daily/%Y-%m-%d.md`) under root. A missing note is first created
+/// from `template`, with `{{date}}` replaced by the date. Returns the relative path.
+pub fn add_to_daily(
+    root: &Path,
+    daily_format: &str,
+    template: &str,
+    text: &str,
+    now: NaiveDateTime,
+    options: &NoteOptions,
+) -> Result<String> {
+    let relative = now.format(daily_format).to_string();
+    let path = resolve_note_path(root, &relative)?;
+
+    if !path.exists() {
+        let date = now.format("%Y-%m-%d").to_string();
+        // Another call may create it first, and then this one appends to that
+        if let Err(e) = create_note(&path, &template.replace("{{date}}", &date), options) {
+            if !path.exists() {
+                return Err(e);
+            }
+        }
+    }
+    append_note(
+        &path,
+        &format!("- {} {}\n", now.format("%H:%M"), text.trim()),
+        options,
+    )?;
+
+    Ok(relative_path(root, &path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn setup_test_vault() -> TempDir {
+        let dir = TempDir::new().unwrap();
+
+        // Create a note with frontmatter
+        let note1 = r#"---
+title: Test Note
+tags: [rust, mcp]
+---
+
+# Hello World
+
+This is a test note about Gagagigo."#;
+        fs::write(dir.path().join("test.md"), note1).unwrap();
+
+        // Create a simple note
+        let note2 = "# Simple Note\n\nNo frontmatter here.";
+        fs::write(dir.path().join("simple.md"), note2).unwrap();
+
+        // Create a subdirectory with a note
+        fs::create_dir_all(dir.path().join("daily")).unwrap();
+        let note3 = "# Daily Note\n\nGagagigo awakens!";
+        fs::write(dir.path().join("daily/2024-01-01.md"), note3).unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn test_read_note_without_frontmatter() {
+        let vault = setup_test_vault();
+        let result = read_note(
+            &vault.path().join("simple.md"),
+            false,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+        assert!(result.contains("# Simple Note"));
+    }
+
+    #[test]
+    fn test_read_note_with_frontmatter_parsing() {
+        let vault = setup_test_vault();
+        let result =
+            read_note(&vault.path().join("test.md"), true, &NoteOptions::default()).unwrap();
+
+        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["metadata"]["title"], "Test Note");
+        assert!(parsed["body"].as_str().unwrap().contains("Hello World"));
+    }
+
+    #[test]
+    fn test_read_body_strips_frontmatter() {
+        let vault = setup_test_vault();
+        let body = read_body(&vault.path().join("test.md"), &NoteOptions::default()).unwrap();
+
+        assert!(body.starts_with("\n# Hello World"));
+        assert!(!body.contains("title:"));
+    }
+
+    #[test]
+    fn test_read_body_without_frontmatter() {
+        let vault = setup_test_vault();
+        let body = read_body(&vault.path().join("simple.md"), &NoteOptions::default()).unwrap();
+
+        assert_eq!(body, "# Simple Note\n\nNo frontmatter here.");
+    }
+
+    #[test]
+    fn test_read_body_keeps_leading_indentation() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("code.md");
+        fs::write(&path, "---\ntitle: Code\n---\n\n    indented code\n").unwrap();
+
+        assert_eq!(
+            read_body(&path, &NoteOptions::default()).unwrap(),
+            "\n    indented code\n"
+        );
+    }
+
+    #[test]
+    fn test_read_body_keeps_blank_lines_after_frontmatter() {
+        let vault = setup_test_vault();
+        let spaced = vault.path().join("spaced.md");
+        fs::write(&spaced, "---\ntitle: Spaced\n---\n\n\nAfter a blank line").unwrap();
+        let tight = vault.path().join("tight.md");
+        fs::write(&tight, "---\r\ntitle: Tight\r\n---\r\nNo separator").unwrap();
+        let json = vault.path().join("json.md");
+        fs::write(&json, "{\"title\": \"Json\"}\n\n\nBody").unwrap();
+
+        assert_eq!(
+            read_body(&spaced, &NoteOptions::default()).unwrap(),
+            "\n\nAfter a blank line"
+        );
+        assert_eq!(
+            read_body(&tight, &NoteOptions::default()).unwrap(),
+            "No separator"
+        );
+        assert_eq!(
+            read_body(&json, &NoteOptions::default()).unwrap(),
+            "\n\nBody"
+        );
+    }
+
+    #[test]
+    fn test_read_body_not_found() {
+        let vault = setup_test_vault();
+
+        assert!(read_body(
+            &vault.path().join("nonexistent.md"),
+            &NoteOptions::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_resolve_note_path() {
+        let root = Path::new("/vault");
+
+        assert_eq!(
+            resolve_note_path(root, "daily/2024-01-01.md").unwrap(),
+            Path::new("/vault/daily/2024-01-01.md")
+        );
+        assert!(resolve_note_path(root, "../secret.md").is_err());
+        assert!(resolve_note_path(root, "daily/../../secret.md").is_err());
+        assert!(resolve_note_path(root, "/etc/passwd").is_err());
+    }
+
+    #[test]
+    fn test_read_many_rejects_escaping_paths() {
+        let vault = setup_test_vault();
+        let entries = read_many(
+            vault.path(),
+            &["../outside.md".to_string()],
+            false,
+            &NoteOptions::default(),
+        );
+
+        assert!(entries[0]
+            .error
+            .as_ref()
+            .unwrap()
+            .contains("relative to the vault root"));
+    }
+
+    #[test]
+    fn test_read_many_mixed() {
+        let vault = setup_test_vault();
+        let paths = vec![
+            "simple.md".to_string(),
+            "nonexistent.md".to_string(),
+            "daily/2024-01-01.md".to_string(),
+        ];
+
+        let entries = read_many(vault.path(), &paths, false, &NoteOptions::default());
+
+        // Order matches the requested paths
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].path, "simple.md");
+        assert!(entries[0]
+            .content
+            .as_ref()
+            .unwrap()
+            .contains("# Simple Note"));
+        assert!(entries[0].error.is_none());
+
+        assert_eq!(entries[1].path, "nonexistent.md");
+        assert!(entries[1].content.is_none());
+        assert!(entries[1].error.is_some());
+
+        assert!(entries[2]
+            .content
+            .as_ref()
+            .unwrap()
+            .contains("Gagagigo awakens!"));
+    }
+
+    #[test]
+    fn test_read_many_with_frontmatter_parsing() {
+        let vault = setup_test_vault();
+        let entries = read_many(
+            vault.path(),
+            &["test.md".to_string()],
+            true,
+            &NoteOptions::default(),
+        );
+
+        let parsed: serde_json::Value =
+            serde_json::from_str(entries[0].content.as_ref().unwrap()).unwrap();
+        assert_eq!(parsed["metadata"]["title"], "Test Note");
+    }
+
+    #[test]
+    fn test_read_many_empty() {
+        let vault = setup_test_vault();
+        let entries = read_many(vault.path(), &[], false, &NoteOptions::default());
+
+        assert!(entries.is_empty());
+    }
+
+    #[test]
+    fn test_outline_nested() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("outline.md");
+        fs::write(
+            &path,
+            "---\ntitle: Outline\n---\n\n# Top\n\n## Child A\n\n### Grandchild\n\n## Child B\n\n# Second",
+        )
+        .unwrap();
+
+        let result = outline(&path).unwrap();
+
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].text, "Top");
+        assert_eq!(result[0].level, 1);
+        assert_eq!(result[0].line_number, 5);
+        assert_eq!(result[0].children.len(), 2);
+        assert_eq!(result[0].children[0].text, "Child A");
+        assert_eq!(result[0].children[0].children[0].text, "Grandchild");
+        assert_eq!(result[0].children[0].children[0].line_number, 9);
+        assert_eq!(result[0].children[1].text, "Child B");
+        assert_eq!(result[1].text, "Second");
+        assert!(result[1].children.is_empty());
+    }
+
+    #[test]
+    fn test_outline_excludes_frontmatter() {
+        let vault = setup_test_vault();
+        let result = outline(&vault.path().join("test.md")).unwrap();
+
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].text, "Hello World");
+    }
+
+    #[test]
+    fn test_outline_skipped_level() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("skipped.md");
+        fs::write(&path, "### Deep first\n\n# Top\n\n### Deep child").unwrap();
+
+        let result = outline(&path).unwrap();
+
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].text, "Deep first");
+        assert_eq!(result[1].children[0].text, "Deep child");
+    }
+
+    #[test]
+    fn test_outline_no_headings() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("plain.md");
+        fs::write(&path, "Just text.").unwrap();
+
+        assert!(outline(&path).unwrap().is_empty());
+    }
+
+    // --- random_note ---
+
+    #[test]
+    fn test_random_note() {
+        let vault = setup_test_vault();
+        fs::create_dir_all(vault.path().join(".trash")).unwrap();
+        fs::write(vault.path().join(".trash/1_gone.md"), "gone").unwrap();
+        let notes: BTreeSet<String> = collect_markdown_files(vault.path(), DEFAULT_TRASH_DIR)
+            .iter()
+            .map(|path| relative_path(vault.path(), path))
+            .collect();
+
+        assert_eq!(
+            random_note(&[(None, vault.path())], Some(7), DEFAULT_TRASH_DIR),
+            random_note(&[(None, vault.path())], Some(7), DEFAULT_TRASH_DIR)
+        );
+        let picked: BTreeSet<String> = (0..200)
+            .filter_map(|seed| random_note(&[(None, vault.path())], Some(seed), DEFAULT_TRASH_DIR))
+            .collect();
+        // Every note can come up, and nothing else does
+        assert_eq!(picked, notes);
+
+        let empty = TempDir::new().unwrap();
+        assert_eq!(
+            random_note(&[(None, empty.path())], None, DEFAULT_TRASH_DIR),
+            None
+        );
+    }
+
+    // --- get_tree ---
+
+    #[test]
+    fn test_get_tree() {
+        let vault = setup_test_vault();
+        fs::create_dir_all(vault.path().join(".obsidian")).unwrap();
+        fs::write(vault.path().join(".obsidian/hidden.md"), "# Hidden").unwrap();
+        fs::write(vault.path().join("daily/notes.txt"), "not markdown").unwrap();
+        delete_note(
+            vault.path(),
+            &vault.path().join("simple.md"),
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+        fs::write(vault.path().join("a.md"), "# A").unwrap();
+
+        let tree = get_tree(vault.path(), DEFAULT_TRASH_DIR);
+
+        assert!(tree.is_dir);
+        assert_eq!(tree.path, "");
+        let names: Vec<_> = tree.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["daily", "a.md", "test.md"]);
+        let daily = &tree.children[0];
+        assert!(daily.is_dir);
+        assert_eq!(daily.children.len(), 1);
+        assert_eq!(daily.children[0].name, "2024-01-01.md");
+        assert_eq!(daily.children[0].path, "daily/2024-01-01.md");
+        assert!(!daily.children[0].is_dir);
+    }
+
+    // --- render_html ---
+
+    #[test]
+    fn test_render_html() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("render.md");
+        fs::write(
+            &path,
+            "---\ntitle: A <b>\ntags: [x, y]\n---\n\n# Heading\n\n| a | b |\n|---|---|\n| 1 | ~~2~~ |\n",
+        )
+        .unwrap();
+
+        let html = render_html(&path, false, &NoteOptions::default()).unwrap();
+        assert!(html.starts_with("<h1>Heading</h1>"));
+        assert!(html.contains("<table>"));
+        assert!(html.contains("<del>2</del>"));
+        assert!(!html.contains("frontmatter"));
+
+        let html = render_html(&path, true, &NoteOptions::default()).unwrap();
+        assert!(html.starts_with("<table class=\"frontmatter\">"));
+        assert!(html.contains("<tr><th>title</th><td>A &lt;b&gt;</td></tr>"));
+        assert!(html.contains("<tr><th>tags</th><td>x, y</td></tr>"));
+    }
+
+    // --- read_section ---
+
+    fn write_sectioned_note(vault: &TempDir) -> std::path::PathBuf {
+        let path = vault.path().join("sections.md");
+        fs::write(
+            &path,
+            "# Top\n\nIntro\n\n## Setup\n\nInstall it.\n\n### Details\n\nMore.\n\n## Usage\n\nRun it.\n\n# Setup\n\nAgain.\n",
+        )
+        .unwrap();
+        path
+    }
+
+    #[test]
+    fn test_read_section_until_same_level() {
+        let vault = setup_test_vault();
+        let path = write_sectioned_note(&vault);
+
+        let section = read_section(&path, "setup").unwrap();
+
+        // Nested headings are included; the next level-2 heading ends the section
+        assert_eq!(section.heading, "Setup");
+        assert_eq!(section.line_number, 5);
+        assert_eq!(
+            section.content,
+            "## Setup\n\nInstall it.\n\n### Details\n\nMore."
+        );
+        assert_eq!(section.other_matches, vec![17]);
+    }
+
+    #[test]
+    fn test_read_section_last_section() {
+        let vault = setup_test_vault();
+        let path = write_sectioned_note(&vault);
+
+        let section = read_section(&path, "USAGE").unwrap();
+
+        assert_eq!(section.content, "## Usage\n\nRun it.");
+        assert!(section.other_matches.is_empty());
+    }
+
+    #[test]
+    fn test_read_section_not_found() {
+        let vault = setup_test_vault();
+        let path = write_sectioned_note(&vault);
+
+        assert!(read_section(&path, "Missing").is_err());
+    }
+
+    #[test]
+    fn test_read_section_top_level() {
+        let vault = setup_test_vault();
+        let path = write_sectioned_note(&vault);
+
+        let section = read_section(&path, "Top").unwrap();
+
+        assert_eq!(section.line_number, 1);
+        assert!(section.content.ends_with("## Usage\n\nRun it."));
+    }
+
+    #[test]
+    fn test_read_section_nested_path() {
+        let vault = setup_test_vault();
+        let path = write_sectioned_note(&vault);
+
+        // "Top > Setup" rules out the level-1 "# Setup"
+        let setup = read_section(&path, "Top > Setup").unwrap();
+        assert_eq!(setup.line_number, 5);
+        assert!(setup.other_matches.is_empty());
+
+        let details = read_section(&path, "top > setup > DETAILS").unwrap();
+        assert_eq!(details.content, "### Details\n\nMore.");
+
+        // Ancestors may skip levels
+        let details = read_section(&path, "Top > Details").unwrap();
+        assert_eq!(details.line_number, 9);
+    }
+
+    #[test]
+    fn test_read_section_nested_path_not_found() {
+        let vault = setup_test_vault();
+        let path = write_sectioned_note(&vault);
+
+        let result = read_section(&path, "Usage > Details");
+
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Heading not found"));
+    }
+
+    // --- split_at_headings ---
+
+    #[test]
+    fn test_split_at_headings() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("long.md");
+        fs::write(
+            &path,
+            "---\ntitle: Long\n---\n\n# Long\n\nIntro.\n\n## First Part\n\nOne.\n\n### Detail\n\nDeep.\n\n## Second Part\n\nTwo.\n",
+        )
+        .unwrap();
+
+        let created = split_at_headings(&path, 2, false, &NoteOptions::default()).unwrap();
+
+        assert_eq!(
+            created,
+            vec![
+                vault.path().join("first-part.md"),
+                vault.path().join("second-part.md")
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(&created[0]).unwrap(),
+            "## First Part\n\nOne.\n\n### Detail\n\nDeep.\n\nSplit from [[long]]\n"
+        );
+        // The original is untouched without replace_with_toc
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .contains("## Second Part"));
+    }
+
+    #[test]
+    fn test_split_at_headings_replace_with_toc() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("long.md");
+        fs::write(
+            &path,
+            "# Top\n\nIntro.\n\n## A\n\nOne.\n\n# Other\n\n## B\n\nTwo.\n",
+        )
+        .unwrap();
+
+        split_at_headings(&path, 2, true, &NoteOptions::default()).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# Top\n\nIntro.\n\n- [[a]]\n# Other\n\n- [[b]]\n"
+        );
+    }
+
+    #[test]
+    fn test_split_at_headings_duplicate_slugs() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("dupes.md");
+        fs::write(
+            &path,
+            "## Simple\n\nA\n\n## Notes?\n\nB\n\n## Notes!\n\nC\n",
+        )
+        .unwrap();
+
+        let created = split_at_headings(&path, 2, false, &NoteOptions::default()).unwrap();
+        let names: Vec<_> = created
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+
+        // simple.md already exists in the vault
+        assert_eq!(names, ["simple-2.md", "notes.md", "notes-2.md"]);
+        assert_eq!(
+            fs::read_to_string(vault.path().join("simple.md")).unwrap(),
+            "# Simple Note\n\nNo frontmatter here."
+        );
+    }
+
+    #[test]
+    fn test_split_at_headings_no_matching_level() {
+        let vault = setup_test_vault();
+
+        let result = split_at_headings(
+            &vault.path().join("test.md"),
+            2,
+            false,
+            &NoteOptions::default(),
+        );
+
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("No level 2 headings"));
+    }
+
+    #[test]
+    fn test_merge_notes_body_order() {
+        let vault = setup_test_vault();
+        let sources = vec![
+            vault.path().join("daily/2024-01-01.md"),
+            vault.path().join("simple.md"),
+            vault.path().join("test.md"),
+        ];
+        let dest = vault.path().join("merged.md");
+
+        merge_notes(vault.path(), &sources, &dest, None, &NoteOptions::default()).unwrap();
+
+        let merged = read_body(&dest, &NoteOptions::default()).unwrap();
+        assert_eq!(
+            merged,
+            "\n# 2024-01-01\n\n# Daily Note\n\nGagagigo awakens!\n\n\
+             # simple\n\n# Simple Note\n\nNo frontmatter here.\n\n\
+             # Test Note\n\n# Hello World\n\nThis is a test note about Gagagigo.\n"
+        );
+        // Sources are kept unless trashing was requested
+        assert!(sources.iter().all(|s| s.exists()));
+    }
+
+    #[test]
+    fn test_merge_notes_unions_arrays() {
+        let vault = setup_test_vault();
+        let other = vault.path().join("other.md");
+        fs::write(
+            &other,
+            "---\ntitle: Other\ntags: [mcp, gagagigo]\nstatus: done\n---\n\nOther body",
+        )
+        .unwrap();
+        let sources = vec![vault.path().join("test.md"), other.clone()];
+        let dest = vault.path().join("merged.md");
+
+        merge_notes(
+            vault.path(),
+            &sources,
+            &dest,
+            Some(DEFAULT_TRASH_DIR),
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        let meta = read_metadata(&dest, &NoteOptions::default()).unwrap();
+        assert_eq!(meta["title"], "Other");
+        assert_eq!(meta["tags"], serde_json::json!(["rust", "mcp", "gagagigo"]));
+        assert_eq!(meta["status"], "done");
+        assert!(sources.iter().all(|s| !s.exists()));
+    }
+
+    #[test]
+    fn test_merge_notes_into_first_source() {
+        let vault = setup_test_vault();
+        let dest = vault.path().join("test.md");
+        let sources = vec![dest.clone(), vault.path().join("simple.md")];
+
+        merge_notes(
+            vault.path(),
+            &sources,
+            &dest,
+            Some(DEFAULT_TRASH_DIR),
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        assert!(read_body(&dest, &NoteOptions::default())
+            .unwrap()
+            .contains("No frontmatter here."));
+        assert!(!vault.path().join("simple.md").exists());
+    }
+
+    #[test]
+    fn test_merge_notes_refuses_existing_destination() {
+        let vault = setup_test_vault();
+        let sources = vec![vault.path().join("test.md")];
+
+        let result = merge_notes(
+            vault.path(),
+            &sources,
+            &vault.path().join("simple.md"),
+            None,
+            &NoteOptions::default(),
+        );
+
+        assert!(result.unwrap_err().to_string().contains("already exists"));
+    }
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  C++ & Rust: 2024  "), "c-rust-2024");
+        assert_eq!(slugify("Café Ünïcode"), "café-ünïcode");
+        assert_eq!(slugify("???"), "section");
+    }
+
+    // --- export_vault ---
+
+    #[test]
+    fn test_export_vault() {
+        let vault = setup_test_vault();
+        delete_note(
+            vault.path(),
+            &vault.path().join("simple.md"),
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+        let mut out = Vec::new();
+
+        let count = export_vault(
+            &[(None, vault.path())],
+            true,
+            &mut out,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        let bundle: Vec<BundleNote> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(count, 2);
+        let paths: Vec<_> = bundle.iter().map(|n| n.path.as_str()).collect();
+        assert_eq!(paths, ["daily/2024-01-01.md", "test.md"]);
+        assert!(bundle[0].metadata.is_none());
+        assert_eq!(bundle[1].metadata.as_ref().unwrap()["title"], "Test Note");
+        assert!(bundle[1]
+            .body
+            .as_deref()
+            .unwrap()
+            .starts_with("\n# Hello World"));
+    }
+
+    #[test]
+    fn test_export_vault_to_file_never_clobbers() {
+        let vault = setup_test_vault();
+        let note = vault.path().join("daily/2024-01-01.md");
+        let original = fs::read_to_string(&note).unwrap();
+
+        let err = export_vault_to_file(
+            &[(None, vault.path())],
+            true,
+            &note,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Refusing to export to a note"));
+        let gz = vault.path().join("backup.md.gz");
+        assert!(export_vault_to_file(
+            &[(None, vault.path())],
+            true,
+            &gz,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default()
+        )
+        .is_err());
+        assert!(!gz.exists());
+        assert_eq!(fs::read_to_string(&note).unwrap(), original);
+
+        let output = vault.path().join("exports/bundle.json");
+        assert_eq!(
+            export_vault_to_file(
+                &[(None, vault.path())],
+                false,
+                &output,
+                DEFAULT_TRASH_DIR,
+                &NoteOptions::default()
+            )
+            .unwrap(),
+            3
+        );
+        let bundle: Vec<BundleNote> = serde_json::from_slice(&fs::read(&output).unwrap()).unwrap();
+        assert_eq!(bundle.len(), 3);
+        assert!(!vault.path().join("exports/bundle.tmp").exists());
+
+        let err = export_vault_to_file(
+            &[(None, vault.path())],
+            false,
+            &output,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+    }
+
+    #[test]
+    fn test_walk_skips_trash_dir_without_marker() {
+        let vault = setup_test_vault();
+        for trash_dir in ["bin", ".bin"] {
+            let trash = vault.path().join(trash_dir);
+            fs::create_dir_all(&trash).unwrap();
+            fs::write(trash.join("1000_old.md"), "trashed").unwrap();
+
+            for include_hidden in [false, true] {
+                let files: Vec<_> =
+                    markdown_files(markdown_walk(vault.path(), include_hidden, trash_dir))
+                        .collect();
+                assert!(!files.is_empty());
+                assert!(files.iter().all(|f| !f.starts_with(&trash)), "{:?}", files);
+            }
+        }
+    }
+
+    #[test]
+    fn test_walk_skips_state_dir_with_hidden_files() {
+        let vault = setup_test_vault();
+        let state = vault.path().join(STATE_DIR);
+        fs::create_dir_all(&state).unwrap();
+        fs::write(state.join("stray.md"), "server state").unwrap();
+
+        let files = collect_markdown_files_with(vault.path(), true, DEFAULT_TRASH_DIR);
+        assert!(!files.is_empty());
+        assert!(files.iter().all(|f| !f.starts_with(&state)), "{:?}", files);
+    }
+
+    #[test]
+    fn test_export_vault_without_body() {
+        let vault = setup_test_vault();
+        let mut out = Vec::new();
+
+        export_vault(
+            &[(None, vault.path())],
+            false,
+            &mut out,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        let bundle: Vec<serde_json::Value> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(bundle.len(), 3);
+        assert!(bundle.iter().all(|n| n.get("body").is_none()));
+    }
+
+    // --- export_bundle ---
+
+    #[test]
+    fn test_export_bundle_follows_links_to_depth() {
+        let vault = TempDir::new().unwrap();
+        fs::create_dir(vault.path().join("img")).unwrap();
+        fs::write(vault.path().join("img/diagram.png"), b"png").unwrap();
+        fs::write(
+            vault.path().join("a.md"),
+            "Links to [[b]] and ![diagram](img/diagram.png)",
+        )
+        .unwrap();
+        // b links back to a, so the walk has to stop at notes it has seen
+        fs::write(vault.path().join("b.md"), "Links to [c](c.md) and [[a]]").unwrap();
+        fs::write(vault.path().join("c.md"), "The end").unwrap();
+
+        let bundle = export_bundle(
+            vault.path(),
+            &vault.path().join("a.md"),
+            1,
+            false,
+            DEFAULT_TRASH_DIR,
+        )
+        .unwrap();
+        let paths: Vec<_> = bundle.notes.keys().map(String::as_str).collect();
+        assert_eq!(paths, ["a.md", "b.md"]);
+        assert_eq!(bundle.notes["b.md"], "Links to [c](c.md) and [[a]]");
+        assert!(bundle.attachments.is_none());
+
+        let bundle = export_bundle(
+            vault.path(),
+            &vault.path().join("a.md"),
+            5,
+            true,
+            DEFAULT_TRASH_DIR,
+        )
+        .unwrap();
+        assert_eq!(bundle.notes.len(), 3);
+        assert_eq!(bundle.attachments.unwrap(), ["img/diagram.png"]);
+
+        let bundle = export_bundle(
+            vault.path(),
+            &vault.path().join("c.md"),
+            0,
+            false,
+            DEFAULT_TRASH_DIR,
+        )
+        .unwrap();
+        assert_eq!(bundle.notes.keys().collect::<Vec<_>>(), ["c.md"]);
+    }
+
+    // --- import_vault ---
+
+    #[test]
+    fn test_import_vault_round_trip() {
+        let source = setup_test_vault();
+        let mut out = Vec::new();
+        export_vault(
+            &[(None, source.path())],
+            true,
+            &mut out,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+        let bundle: Vec<BundleNote> = serde_json::from_slice(&out).unwrap();
+        let dest = TempDir::new().unwrap();
+
+        let report = import_vault(dest.path(), &bundle, false, &NoteOptions::default()).unwrap();
+
+        assert_eq!(report.created, 3);
+        // Frontmatter is reformatted, but the metadata and body survive
+        for note in ["test.md", "simple.md", "daily/2024-01-01.md"] {
+            let (from, to) = (source.path().join(note), dest.path().join(note));
+            assert_eq!(
+                read_metadata(&to, &NoteOptions::default()),
+                read_metadata(&from, &NoteOptions::default())
+            );
+            assert_eq!(
+                read_body(&to, &NoteOptions::default()).unwrap(),
+                read_body(&from, &NoteOptions::default()).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_import_vault_overwrite() {
+        let vault = setup_test_vault();
+        let bundle = vec![
+            BundleNote {
+                path: "simple.md".to_string(),
+                metadata: None,
+                body: Some("Replaced".to_string()),
+            },
+            BundleNote {
+                path: "new.md".to_string(),
+                metadata: Some(serde_json::json!({"title": "New"})),
+                body: None,
+            },
+        ];
+
+        let report = import_vault(vault.path(), &bundle, false, &NoteOptions::default()).unwrap();
+        assert_eq!((report.created, report.skipped), (1, 1));
+        assert!(fs::read_to_string(vault.path().join("simple.md"))
+            .unwrap()
+            .starts_with("# Simple Note"));
+        assert_eq!(
+            fs::read_to_string(vault.path().join("new.md")).unwrap(),
+            "---\ntitle: New\n---\n"
+        );
+
+        let report = import_vault(vault.path(), &bundle, true, &NoteOptions::default()).unwrap();
+        assert_eq!(report.overwritten, 2);
+        assert_eq!(
+            fs::read_to_string(vault.path().join("simple.md")).unwrap(),
+            "Replaced"
+        );
+    }
+
+    #[test]
+    fn test_import_vault_rejects_escaping_paths_before_writing() {
+        let vault = setup_test_vault();
+        let bundle = vec![
+            BundleNote {
+                path: "fine.md".to_string(),
+                metadata: None,
+                body: Some("ok".to_string()),
+            },
+            BundleNote {
+                path: "../escape.md".to_string(),
+                metadata: None,
+                body: Some("bad".to_string()),
+            },
+        ];
+
+        assert!(import_vault(vault.path(), &bundle, false, &NoteOptions::default()).is_err());
+        assert!(!vault.path().join("fine.md").exists());
+    }
+
+    // --- find_duplicates ---
+
+    #[test]
+    fn test_find_duplicates() {
+        let vault = setup_test_vault();
+        fs::write(
+            vault.path().join("copy.md"),
+            "---\ntitle: Copy\n---\n# Simple Note\n\nNo frontmatter here.",
+        )
+        .unwrap();
+        fs::write(
+            vault.path().join("spaced.md"),
+            "  # Simple Note\n\n\nNo   frontmatter here.\n",
+        )
+        .unwrap();
+
+        let exact = find_duplicates(
+            vault.path(),
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        );
+        assert_eq!(
+            exact,
+            [vec!["copy.md".to_string(), "simple.md".to_string()]]
+        );
+
+        let fuzzy = find_duplicates(
+            vault.path(),
+            true,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        );
+        assert_eq!(
+            fuzzy,
+            [vec![
+                "copy.md".to_string(),
+                "simple.md".to_string(),
+                "spaced.md".to_string()
+            ]]
+        );
+    }
+
+    // --- suggest_tags ---
+
+    #[test]
+    fn test_suggest_tags() {
+        let vault = setup_test_vault();
+        fs::write(
+            vault.path().join("other.md"),
+            "---\ntags: [Rustacean, rust, mcp]\n---\n",
+        )
+        .unwrap();
+
+        let tags = suggest_tags(
+            vault.path(),
+            "RU",
+            10,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        );
+
+        let tags: Vec<_> = tags
+            .iter()
+            .map(|t| (t.value.as_str().unwrap(), t.count))
+            .collect();
+        assert_eq!(tags, [("rust", 2), ("Rustacean", 1)]);
+        assert_eq!(
+            suggest_tags(
+                vault.path(),
+                "ru",
+                1,
+                DEFAULT_TRASH_DIR,
+                &NoteOptions::default()
+            )
+            .len(),
+            1
+        );
+        assert!(suggest_tags(
+            vault.path(),
+            "zzz",
+            10,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default()
+        )
+        .is_empty());
+    }
+
+    // --- tag_cooccurrence ---
+
+    #[test]
+    fn test_tag_cooccurrence() {
+        let vault = TempDir::new().unwrap();
+        for (name, tags) in [
+            ("a.md", "[rust, mcp, cli]"),
+            ("b.md", "[rust, mcp]"),
+            ("c.md", "[mcp, cli, mcp]"),
+            ("d.md", "rust"),
+        ] {
+            fs::write(
+                vault.path().join(name),
+                format!("---\ntags: {}\n---\n", tags),
+            )
+            .unwrap();
+        }
+
+        let pairs = tag_cooccurrence(
+            &[vault.path()],
+            10,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        );
+        let pairs: Vec<_> = pairs
+            .iter()
+            .map(|p| (p.tags[0].as_str(), p.tags[1].as_str(), p.count))
+            .collect();
+        assert_eq!(
+            pairs,
+            [("cli", "mcp", 2), ("mcp", "rust", 2), ("cli", "rust", 1)]
+        );
+        assert_eq!(
+            tag_cooccurrence(
+                &[vault.path()],
+                1,
+                DEFAULT_TRASH_DIR,
+                &NoteOptions::default()
+            )
+            .len(),
+            1
+        );
+    }
+
+    // --- notes_by_tags ---
+
+    #[test]
+    fn test_notes_by_tags() {
+        let vault = TempDir::new().unwrap();
+        for (name, tags) in [
+            ("a.md", "[Rust, mcp]"),
+            ("b.md", "['#rust']"),
+            ("c.md", "[mcp, draft]"),
+            ("d.md", "[other]"),
+        ] {
+            fs::write(
+                vault.path().join(name),
+                format!("---\ntags: {}\n---\n", tags),
+            )
+            .unwrap();
+        }
+        let tags = |tags: &[&str]| -> Vec<String> { tags.iter().map(|t| t.to_string()).collect() };
+
+        assert_eq!(
+            notes_by_tags(
+                vault.path(),
+                &tags(&["rust", "MCP"]),
+                TagMatch::All,
+                &[],
+                DEFAULT_TRASH_DIR,
+                &NoteOptions::default()
+            ),
+            ["a.md"]
+        );
+        assert_eq!(
+            notes_by_tags(
+                vault.path(),
+                &tags(&["#rust", "mcp"]),
+                TagMatch::Any,
+                &[],
+                DEFAULT_TRASH_DIR,
+                &NoteOptions::default()
+            ),
+            ["a.md", "b.md", "c.md"]
+        );
+        assert_eq!(
+            notes_by_tags(
+                vault.path(),
+                &tags(&["rust", "mcp"]),
+                TagMatch::Any,
+                &tags(&["Draft"]),
+                DEFAULT_TRASH_DIR,
+                &NoteOptions::default()
+            ),
+            ["a.md", "b.md"]
+        );
+    }
+
+    // --- keywords ---
+
+    #[test]
+    fn test_keywords() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("keywords.md");
+        fs::write(
+            &path,
+            "---\ntags: [ignored, ignored, ignored]\n---\n\n\
+             # Rust notes\n\n\
+             The borrow checker is the heart of Rust. Rust makes the borrow rules explicit.\n\n\
+             ```rust\nlet rust = rust_code();\n```\n\n\
+             See [the book](https://doc.rust-lang.org) from 2024.\n",
+        )
+        .unwrap();
+
+        let terms = keywords(&path, 3, None, &NoteOptions::default()).unwrap();
+        let terms: Vec<_> = terms.iter().map(|t| (t.term.as_str(), t.count)).collect();
+        assert_eq!(terms, [("rust", 3), ("borrow", 2), ("book", 1)]);
+
+        let all = keywords(&path, 100, None, &NoteOptions::default()).unwrap();
+        for excluded in ["the", "is", "of", "ignored", "2024", "https", "rust_code"] {
+            assert!(all.iter().all(|t| t.term != excluded), "{}", excluded);
+        }
+
+        let stopwords = BTreeSet::from(["rust".to_string()]);
+        let terms = keywords(&path, 1, Some(&stopwords), &NoteOptions::default()).unwrap();
+        assert_eq!(terms[0].term, "the");
+    }
+
+    // --- find_missing_metadata ---
+
+    #[test]
+    fn test_find_missing_metadata() {
+        let vault = setup_test_vault();
+        fs::remove_dir_all(vault.path().join("daily")).unwrap();
+        let required = ["title".to_string(), "created".to_string()];
+
+        let missing = find_missing_metadata(
+            vault.path(),
+            &required,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        );
+
+        let missing: Vec<_> = missing
+            .iter()
+            .map(|m| (m.path.as_str(), m.missing.clone()))
+            .collect();
+        assert_eq!(
+            missing,
+            [
+                (
+                    "simple.md",
+                    vec!["title".to_string(), "created".to_string()]
+                ),
+                ("test.md", vec!["created".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_missing_metadata_nested() {
+        let vault = setup_test_vault();
+        fs::write(
+            vault.path().join("test.md"),
+            "---
+title: Test
+author:
+  name: Alice
+---
+",
+        )
+        .unwrap();
+
+        let missing = find_missing_metadata(
+            vault.path(),
+            &["author.name".to_string()],
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        );
+
+        assert!(missing.iter().all(|m| m.path != "test.md"));
+        assert_eq!(missing.len(), 2);
+    }
+
+    // --- distinct_values ---
+
+    #[test]
+    fn test_distinct_values_counts_array_elements() {
+        let vault = setup_test_vault();
+        fs::write(
+            vault.path().join("other.md"),
+            "---\ntags: [mcp, notes]\n---\n\nOther",
+        )
+        .unwrap();
+
+        let values = distinct_values(
+            vault.path(),
+            "tags",
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        );
+
+        let counts: Vec<_> = values
+            .iter()
+            .map(|v| (v.value.as_str().unwrap(), v.count))
+            .collect();
+        assert_eq!(counts, [("mcp", 2), ("notes", 1), ("rust", 1)]);
+    }
+
+    #[test]
+    fn test_distinct_values_nested_and_scalar() {
+        let vault = setup_test_vault();
+        fs::write(
+            vault.path().join("a.md"),
+            "---\nauthor:\n  name: Alice\npriority: 1\n---\n",
+        )
+        .unwrap();
+        fs::write(
+            vault.path().join("b.md"),
+            "---\nauthor:\n  name: Alice\npriority: 2\n---\n",
+        )
+        .unwrap();
+
+        let authors = distinct_values(
+            vault.path(),
+            "author.name",
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        );
+        assert_eq!(authors.len(), 1);
+        assert_eq!(authors[0].value, "Alice");
+        assert_eq!(authors[0].count, 2);
+
+        let priorities = distinct_values(
+            vault.path(),
+            "priority",
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        );
+        assert_eq!(priorities.len(), 2);
+        assert!(distinct_values(
+            vault.path(),
+            "missing",
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default()
+        )
+        .is_empty());
+    }
+
+    // --- find_by_title ---
+
+    #[test]
+    fn test_find_by_title_frontmatter() {
+        let vault = setup_test_vault();
+
+        assert_eq!(
+            find_by_title(
+                vault.path(),
+                "test note",
+                DEFAULT_TRASH_DIR,
+                &NoteOptions::default()
+            ),
+            vec!["test.md"]
+        );
+    }
+
+    #[test]
+    fn test_find_by_title_falls_back_to_stem() {
+        let vault = setup_test_vault();
+
+        assert_eq!(
+            find_by_title(
+                vault.path(),
+                "2024-01-01",
+                DEFAULT_TRASH_DIR,
+                &NoteOptions::default()
+            ),
+            vec!["daily/2024-01-01.md"]
+        );
+        assert!(find_by_title(
+            vault.path(),
+            "Nonexistent",
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default()
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_find_by_title_multiple_matches() {
+        let vault = setup_test_vault();
+        fs::write(
+            vault.path().join("daily/copy.md"),
+            "---\ntitle: Test Note\n---\n\nAgain",
+        )
+        .unwrap();
+
+        assert_eq!(
+            find_by_title(
+                vault.path(),
+                "Test Note",
+                DEFAULT_TRASH_DIR,
+                &NoteOptions::default()
+            ),
+            vec!["daily/copy.md", "test.md"]
+        );
+    }
+
+    // --- normalize_frontmatter ---
+
+    #[test]
+    fn test_normalize_frontmatter_coerces_scalar_tags() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("messy.md");
+        let body = "No blank line before this body\n\n  indented  \n";
+        let original = format!(
+            "---\ntitle: 'Messy'\ntags: rust\ncreated: 2024/1/5\naliases: [a, b]\n---\n{}",
+            body
+        );
+        fs::write(&path, &original).unwrap();
+
+        assert!(normalize_frontmatter(&path, true, &NoteOptions::default()).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), original);
+
+        assert!(normalize_frontmatter(&path, false, &NoteOptions::default()).unwrap());
+        let normalized = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            normalized,
+            format!(
+                "---\ntitle: Messy\ntags:\n- rust\ncreated: 2024-01-05\naliases:\n- a\n- b\n---\n{}",
+                body
+            )
+        );
+        assert_eq!(
+            read_metadata(&path, &NoteOptions::default()).unwrap()["tags"],
+            serde_json::json!(["rust"])
+        );
+    }
+
+    #[test]
+    fn test_normalize_frontmatter_canonical_is_noop() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("canonical.md");
+        let meta = serde_json::json!({"title": "Tidy", "tags": ["rust"], "created": "2024-01-05"});
+        let content = format_with_frontmatter(&meta, "# Body\n", &NoteOptions::default());
+        fs::write(&path, &content).unwrap();
+
+        assert!(!normalize_frontmatter(&path, false, &NoteOptions::default()).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+
+        // Notes without frontmatter are left alone
+        assert!(!normalize_frontmatter(
+            &vault.path().join("simple.md"),
+            false,
+            &NoteOptions::default()
+        )
+        .unwrap());
+    }
+
+    // --- max note size ---
+
+    #[test]
+    fn test_check_note_size() {
+        let path = Path::new("big.md");
+
+        assert!(check_note_size(path, "12345", None).is_ok());
+        assert!(check_note_size(path, "12345", Some(5)).is_ok());
+        let err = check_note_size(path, "123456", Some(5)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Note is 6 bytes, over the 5 byte limit (STUMBLING_MAX_NOTE_BYTES): big.md"
+        );
+        // Bytes, not characters
+        assert!(check_note_size(path, "ééé", Some(5)).is_err());
+    }
+
+    // --- changed_since ---
+
+    #[test]
+    fn test_changed_since() {
+        let vault = setup_test_vault();
+        let hour_ago = SystemTime::now() - Duration::from_secs(60 * 60);
+        for note in ["test.md", "simple.md", "daily/2024-01-01.md"] {
+            fs::File::options()
+                .write(true)
+                .open(vault.path().join(note))
+                .unwrap()
+                .set_modified(hour_ago)
+                .unwrap();
+        }
+        let since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64()
+            - 1.0;
+
+        fs::write(vault.path().join("new.md"), "# New").unwrap();
+
+        let changed = changed_since(vault.path(), since, DEFAULT_TRASH_DIR);
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].path, "new.md");
+        assert!(changed[0].mtime > since);
+
+        // The newest mtime seen excludes everything up to it
+        assert!(changed_since(vault.path(), changed[0].mtime, DEFAULT_TRASH_DIR).is_empty());
+    }
+
+    // --- note_neighbors ---
+
+    #[test]
+    fn test_note_neighbors() {
+        let vault = setup_test_vault();
+        let daily = vault.path().join("daily");
+        for name in [
+            "2024-01-03.md",
+            "2024-01-10.md",
+            "notes.md",
+            "2023-12-31.md",
+        ] {
+            fs::write(daily.join(name), "# Day").unwrap();
+        }
+
+        let neighbors = note_neighbors(
+            vault.path(),
+            &daily.join("2024-01-03.md"),
+            DEFAULT_NEIGHBOR_DATE_FORMAT,
+        )
+        .unwrap();
+        assert_eq!(neighbors.previous.as_deref(), Some("daily/2024-01-01.md"));
+        assert_eq!(neighbors.next.as_deref(), Some("daily/2024-01-10.md"));
+
+        // A day without a note still has neighbors
+        let neighbors = note_neighbors(
+            vault.path(),
+            &daily.join("2024-01-05.md"),
+            DEFAULT_NEIGHBOR_DATE_FORMAT,
+        )
+        .unwrap();
+        assert_eq!(neighbors.previous.as_deref(), Some("daily/2024-01-03.md"));
+        assert_eq!(neighbors.next.as_deref(), Some("daily/2024-01-10.md"));
+
+        let last = note_neighbors(
+            vault.path(),
+            &daily.join("2024-01-10.md"),
+            DEFAULT_NEIGHBOR_DATE_FORMAT,
+        )
+        .unwrap();
+        assert_eq!(last.next, None);
+        assert_eq!(
+            serde_json::to_value(&last).unwrap()["next"],
+            serde_json::Value::Null
+        );
+
+        assert!(note_neighbors(
+            vault.path(),
+            &daily.join("notes.md"),
+            DEFAULT_NEIGHBOR_DATE_FORMAT
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_note_neighbors_custom_format() {
+        let vault = setup_test_vault();
+        let journal = vault.path().join("journal");
+        fs::create_dir_all(&journal).unwrap();
+        for name in ["05.01.2024.md", "20.12.2023.md", "02.02.2024.md"] {
+            fs::write(journal.join(name), "# Day").unwrap();
+        }
+
+        let neighbors =
+            note_neighbors(vault.path(), &journal.join("05.01.2024.md"), "%d.%m.%Y").unwrap();
+        assert_eq!(neighbors.previous.as_deref(), Some("journal/20.12.2023.md"));
+        assert_eq!(neighbors.next.as_deref(), Some("journal/02.02.2024.md"));
+
+        assert!(note_neighbors(vault.path(), &journal.join("05.01.2024.md"), "%Q").is_err());
+    }
+
+    // --- add_to_daily ---
+
+    #[test]
+    fn test_add_to_daily_creates_from_template_and_appends() {
+        let vault = setup_test_vault();
+        let morning = NaiveDate::from_ymd_opt(2024, 3, 5)
+            .unwrap()
+            .and_hms_opt(9, 7, 0)
+            .unwrap();
+        let evening = morning + chrono::Duration::hours(10);
+
+        let path = add_to_daily(
+            vault.path(),
+            DEFAULT_DAILY_FORMAT,
+            DEFAULT_DAILY_TEMPLATE,
+            "Started the draft",
+            morning,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(path, "daily/2024-03-05.md");
+        add_to_daily(
+            vault.path(),
+            DEFAULT_DAILY_FORMAT,
+            DEFAULT_DAILY_TEMPLATE,
+            "Finished it",
+            evening,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(vault.path().join(&path)).unwrap(),
+            "# 2024-03-05\n- 09:07 Started the draft\n- 19:07 Finished it\n"
+        );
+
+        // An existing note is appended to without the template
+        let path = add_to_daily(
+            vault.path(),
+            "daily/%Y-%m-%d.md",
+            "unused",
+            "Later",
+            NaiveDate::from_ymd_opt(2024, 1, 1)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+                .unwrap(),
+            &NoteOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(vault.path().join(path)).unwrap(),
+            "# Daily Note\n\nGagagigo awakens!\n- 12:00 Later\n"
+        );
+    }
+
+    #[test]
+    fn test_validate_daily_format() {
+        assert!(validate_daily_format(DEFAULT_DAILY_FORMAT).is_ok());
+        assert!(validate_daily_format("journal/%Y/%m/%d.md").is_ok());
+        assert!(validate_daily_format("daily/%Q.md").is_err());
+        assert!(validate_daily_format("daily/%Y-%m-%d.txt").is_err());
+        assert!(validate_daily_format("../%Y-%m-%d.md").is_err());
+    }
+
+    // --- link_notes ---
+
+    #[test]
+    fn test_link_notes_adds_related_section() {
+        let vault = setup_test_vault();
+        let from = vault.path().join("simple.md");
+        let to = vault.path().join("daily/2024-01-01.md");
+
+        let changed = link_notes(
+            vault.path(),
+            &from,
+            &to,
+            "Related",
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(changed, ["simple.md"]);
+        assert_eq!(
+            fs::read_to_string(&from).unwrap(),
+            "# Simple Note\n\nNo frontmatter here.\n\n## Related\n\n- [[daily/2024-01-01]]\n"
+        );
+        assert_eq!(
+            fs::read_to_string(&to).unwrap(),
+            "# Daily Note\n\nGagagigo awakens!"
+        );
+    }
+
+    #[test]
+    fn test_link_notes_appends_to_existing_section_without_duplicates() {
+        let vault = setup_test_vault();
+        let from = vault.path().join("hub.md");
+        fs::write(
+            &from,
+            "# Hub\n\n## Related\n\n- [[simple]]\n\n## Log\n\nEntry\n",
+        )
+        .unwrap();
+
+        // Already linked by name, so nothing changes
+        let changed = link_notes(
+            vault.path(),
+            &from,
+            &vault.path().join("simple.md"),
+            "related",
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+        assert!(changed.is_empty());
+
+        link_notes(
+            vault.path(),
+            &from,
+            &vault.path().join("test.md"),
+            "Related",
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&from).unwrap(),
+            "# Hub\n\n## Related\n\n- [[simple]]\n- [[test]]\n\n## Log\n\nEntry\n"
+        );
+    }
+
+    #[test]
+    fn test_link_notes_bidirectional() {
+        let vault = setup_test_vault();
+        let from = vault.path().join("test.md");
+        let to = vault.path().join("daily/2024-01-01.md");
+
+        let changed = link_notes(
+            vault.path(),
+            &from,
+            &to,
+            "See also",
+            true,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(changed, ["test.md", "daily/2024-01-01.md"]);
+        assert!(fs::read_to_string(&from)
+            .unwrap()
+            .ends_with("## See also\n\n- [[daily/2024-01-01]]\n"));
+        assert!(fs::read_to_string(&to)
+            .unwrap()
+            .ends_with("## See also\n\n- [[test]]\n"));
+        assert_eq!(
+            read_metadata(&from, &NoteOptions::default()).unwrap()["title"],
+            "Test Note"
+        );
+
+        // Linking again changes neither note
+        let changed = link_notes(
+            vault.path(),
+            &from,
+            &to,
+            "See also",
+            true,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+        assert!(changed.is_empty());
+
+        assert!(link_notes(
+            vault.path(),
+            &from,
+            &from,
+            "Related",
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default()
+        )
+        .is_err());
+    }
+
+    // --- note_links ---
+
+    #[test]
+    fn test_note_links_kinds() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("links.md");
+        fs::write(
+            &path,
+            "---\ntitle: Links\n---\n\nSee [[simple]] and [[daily/2024-01-01|today]].\n\nA [markdown link](test.md) and [site](https://example.com).\n\nBare https://rust-lang.org here.\n\n`[[not a link]]`\n",
+        )
+        .unwrap();
+
+        let links = note_links(vault.path(), &path, DEFAULT_TRASH_DIR).unwrap();
+
+        assert_eq!(links.len(), 5);
+
+        assert_eq!(links[0].kind, LinkKind::Wikilink);
+        assert_eq!(links[0].target, "simple");
+        assert_eq!(links[0].line_number, 5);
+        assert_eq!(links[0].resolved.as_deref(), Some("simple.md"));
+
+        assert_eq!(links[1].target, "daily/2024-01-01");
+        assert_eq!(links[1].text.as_deref(), Some("today"));
+        assert_eq!(links[1].resolved.as_deref(), Some("daily/2024-01-01.md"));
+
+        assert_eq!(links[2].kind, LinkKind::Markdown);
+        assert!(links[2].internal);
+        assert_eq!(links[2].resolved.as_deref(), Some("test.md"));
+        assert_eq!(links[2].line_number, 7);
+
+        assert_eq!(links[3].kind, LinkKind::Markdown);
+        assert!(!links[3].internal);
+        assert!(links[3].resolved.is_none());
+
+        assert_eq!(links[4].kind, LinkKind::Url);
+        assert_eq!(links[4].target, "https://rust-lang.org");
+        assert!(!links[4].internal);
+    }
+
+    #[test]
+    fn test_note_links_resolves_by_name() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("by_name.md");
+        fs::write(&path, "[[2024-01-01]] and [[Missing Note]]").unwrap();
+
+        let links = note_links(vault.path(), &path, DEFAULT_TRASH_DIR).unwrap();
+
+        assert_eq!(links[0].resolved.as_deref(), Some("daily/2024-01-01.md"));
+        // Dead links are internal but unresolved
+        assert!(links[1].internal);
+        assert!(links[1].resolved.is_none());
+    }
+
+    #[test]
+    fn test_note_links_relative_to_note() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("daily/links.md");
+        fs::write(
+            &path,
+            "[up](../simple.md#section) and [out](../../outside.md)",
+        )
+        .unwrap();
+
+        let links = note_links(vault.path(), &path, DEFAULT_TRASH_DIR).unwrap();
+
+        assert_eq!(links[0].resolved.as_deref(), Some("simple.md"));
+        assert!(links[1].resolved.is_none());
+    }
+
+    #[test]
+    fn test_note_links_none() {
+        let vault = setup_test_vault();
+        let links = note_links(
+            vault.path(),
+            &vault.path().join("simple.md"),
+            DEFAULT_TRASH_DIR,
+        )
+        .unwrap();
+
+        assert!(links.is_empty());
+    }
+
+    // --- find_orphans ---
+
+    #[test]
+    fn test_find_orphans() {
+        let vault = TempDir::new().unwrap();
+        fs::create_dir(vault.path().join("daily")).unwrap();
+        fs::write(vault.path().join("hub.md"), "[[linked]] and [[hub]]").unwrap();
+        fs::write(vault.path().join("linked.md"), "[back](hub.md)").unwrap();
+        fs::write(vault.path().join("lonely.md"), "# Nobody links here").unwrap();
+        fs::write(vault.path().join("daily/2024-01-01.md"), "[[lonely]]? no").unwrap();
+        fs::write(vault.path().join("daily/2024-01-02.md"), "`[[lonely]]`").unwrap();
+
+        assert_eq!(
+            find_orphans(vault.path(), Some("daily/**"), DEFAULT_TRASH_DIR).unwrap(),
+            Vec::<String>::new()
+        );
+        fs::write(vault.path().join("daily/2024-01-01.md"), "Just a day").unwrap();
+        assert_eq!(
+            find_orphans(vault.path(), Some("daily/**"), DEFAULT_TRASH_DIR).unwrap(),
+            ["lonely.md"]
+        );
+        assert_eq!(
+            find_orphans(vault.path(), None, DEFAULT_TRASH_DIR).unwrap(),
+            ["daily/2024-01-01.md", "daily/2024-01-02.md", "lonely.md"]
+        );
+    }
+
+    #[test]
+    fn test_stumblingignore_excludes_notes() {
+        let vault = setup_test_vault();
+        fs::create_dir_all(vault.path().join("templates/daily")).unwrap();
+        fs::write(
+            vault.path().join("templates/daily/template.md"),
+            "---\ntitle: Template\n---\n\nGagagigo template",
+        )
+        .unwrap();
+        fs::write(vault.path().join(IGNORE_FILE), "templates/**\n").unwrap();
+
+        let results = search_notes(
+            &[(None, vault.path())],
+            "Gagagigo",
+            10,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .results;
+        assert!(results.iter().all(|r| !r.path.starts_with("templates")));
+        assert_eq!(results.len(), 2);
+
+        let metadata = search_metadata(
+            vault.path(),
+            "title",
+            "Template",
+            10,
+            None,
+            false,
+            &MetadataSearchOptions::default(),
+        )
+        .unwrap();
+        assert!(metadata.is_empty());
+    }
+
+    #[test]
+    fn test_stumblingignore_changes_apply_immediately() {
+        let vault = setup_test_vault();
+        fs::write(vault.path().join(IGNORE_FILE), "daily/\n").unwrap();
+        assert!(!collect_markdown_files(vault.path(), DEFAULT_TRASH_DIR)
+            .iter()
+            .any(|p| p.starts_with(vault.path().join("daily"))));
+
+        fs::write(vault.path().join(IGNORE_FILE), "").unwrap();
+        assert!(collect_markdown_files(vault.path(), DEFAULT_TRASH_DIR)
+            .iter()
+            .any(|p| p.starts_with(vault.path().join("daily"))));
+    }
+
+    #[test]
+    fn test_search_notes_exclude() {
+        let vault = setup_test_vault();
+        fs::write(vault.path().join("todo.md"), "TODO: write\nTODONE: read\n").unwrap();
+        let options = SearchOptions {
+            exclude: Some("TODONE".to_string()),
+            ..Default::default()
+        };
+
+        let results = search_notes(&[(None, vault.path())], "TODO", 10, &options)
+            .unwrap()
+            .results;
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].line.as_deref(), Some("TODO: write"));
+    }
+
+    #[test]
+    fn test_search_notes_invalid_exclude() {
+        let vault = setup_test_vault();
+        let options = SearchOptions {
+            exclude: Some("[".to_string()),
+            ..Default::default()
+        };
+
+        let err = search_notes(&[(None, vault.path())], "TODO", 10, &options).unwrap_err();
+
+        assert!(err.to_string().contains("Invalid exclude pattern"));
+    }
+
+    #[test]
+    fn test_search_notes_snippet() {
+        let vault = setup_test_vault();
+        let long_line = format!(
+            "{} the Gagagigo line {}",
+            "lorem ipsum ".repeat(20).trim_end(),
+            "dolor sit ".repeat(20).trim_end()
+        );
+        fs::write(vault.path().join("long.md"), &long_line).unwrap();
+        let options = SearchOptions {
+            snippet_len: 40,
+            ..Default::default()
+        };
+
+        let results = search_notes(&[(None, vault.path())], "Gagagigo line", 10, &options)
+            .unwrap()
+            .results;
+
+        assert_eq!(results.len(), 1);
+        assert!(results[0].line.is_none());
+        let snippet = results[0].snippet.as_deref().unwrap();
+        assert!(snippet.starts_with('…') && snippet.ends_with('…'));
+        assert!(snippet.contains("Gagagigo line"));
+        assert!(snippet.chars().count() <= 42);
+        // Cut on word boundaries only
+        let words = snippet.trim_matches('…');
+        assert!(words
+            .split(' ')
+            .all(|w| ["lorem", "ipsum", "the", "Gagagigo", "line", "dolor", "sit"].contains(&w)));
+    }
+
+    #[test]
+    fn test_search_notes_snippet_short_line_and_disabled() {
+        let vault = setup_test_vault();
+        let options = SearchOptions {
+            snippet_len: 200,
+            ..Default::default()
+        };
+
+        let results = search_notes(&[(None, vault.path())], "awakens", 10, &options)
+            .unwrap()
+            .results;
+        assert_eq!(results[0].snippet.as_deref(), Some("Gagagigo awakens!"));
+
+        let results = search_notes(
+            &[(None, vault.path())],
+            "awakens",
+            10,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .results;
+        assert_eq!(results[0].line.as_deref(), Some("Gagagigo awakens!"));
+        assert!(results[0].snippet.is_none());
+    }
+
+    #[test]
+    fn test_snippet_at_line_edges() {
+        let line = "Gagagigo stumbles through a long and winding road before awakening";
+
+        assert_eq!(snippet(line, 0, 8, 20), "Gagagigo stumbles…");
+        assert_eq!(snippet(line, 57, 66, 20), "…before awakening");
+    }
+
+    #[test]
+    fn test_search_notes_reports_oversized() {
+        let vault = setup_test_vault();
+        let options = SearchOptions {
+            max_file_bytes: Some(40),
+            ..Default::default()
+        };
+
+        let report = search_notes(&[(None, vault.path())], "Gagagigo", 10, &options).unwrap();
+
+        assert_eq!(report.results.len(), 1);
+        assert_eq!(report.results[0].path, "daily/2024-01-01.md");
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].path, "test.md");
+        assert!(report.skipped[0].reason.contains("larger than 40 bytes"));
+    }
+
+    #[test]
+    fn test_search_notes_reports_binary_and_invalid_utf8() {
+        let vault = setup_test_vault();
+        fs::write(vault.path().join("binary.md"), b"Gagagigo\0\x01\x02").unwrap();
+        fs::write(
+            vault.path().join("latin1.md"),
+            b"Gagagigo first\ncaf\xe9\nGagagigo",
+        )
+        .unwrap();
+
+        let report = search_notes(
+            &[(None, vault.path())],
+            "Gagagigo",
+            10,
+            &SearchOptions::default(),
+        )
+        .unwrap();
+
+        let skipped: Vec<_> = report
+            .skipped
+            .iter()
+            .map(|s| (s.path.as_str(), s.reason.as_str()))
+            .collect();
+        assert_eq!(skipped[0], ("binary.md", "binary content"));
+        assert_eq!(skipped[1].0, "latin1.md");
+        assert!(skipped[1].1.starts_with("stopped at line 2"));
+        // Matches before the bad line are kept
+        assert!(report.results.iter().any(|r| r.path == "latin1.md"));
+        assert!(report.results.iter().all(|r| r.path != "binary.md"));
+    }
+
+    #[test]
+    fn test_search_notes_lossy_utf8() {
+        let vault = setup_test_vault();
+        fs::write(
+            vault.path().join("latin1.md"),
+            b"Gagagigo first\r\ncaf\xe9 Gagagigo\nGagagigo",
+        )
+        .unwrap();
+        let options = SearchOptions {
+            lossy_utf8: true,
+            ..Default::default()
+        };
+
+        let report = search_notes(&[(None, vault.path())], "Gagagigo", 10, &options).unwrap();
+
+        let lines: Vec<_> = report
+            .results
+            .iter()
+            .filter(|r| r.path == "latin1.md")
+            .map(|r| r.line.as_deref().unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            ["Gagagigo first", "caf\u{FFFD} Gagagigo", "Gagagigo"]
+        );
+        assert!(report.skipped.is_empty());
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].path, "latin1.md");
+        assert!(report.warnings[0].message.contains("line 2"));
+    }
+
+    #[test]
+    fn test_search_notes_match_offsets() {
+        let vault = setup_test_vault();
+        fs::write(
+            vault.path().join("unicode.md"),
+            "café ☕ Gagagigo and Gagagigo",
+        )
+        .unwrap();
+
+        let results = search_notes(
+            &[(None, vault.path())],
+            "Gaga\\w+",
+            10,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .results;
+
+        let result = results.iter().find(|r| r.path == "unicode.md").unwrap();
+        let line = result.line.as_deref().unwrap();
+        // The first match is reported, on valid UTF-8 boundaries
+        assert_eq!(result.match_start, "café ☕ ".len());
+        assert_eq!(&line[result.match_start..result.match_end], "Gagagigo");
+    }
+
+    #[test]
+    fn test_search_notes() {
+        let vault = setup_test_vault();
+        let results = search_notes(
+            &[(None, vault.path())],
+            "Gagagigo",
+            10,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .results;
+
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn test_search_notes_with_limit() {
+        let vault = setup_test_vault();
+        let results = search_notes(
+            &[(None, vault.path())],
+            "Gagagigo",
+            1,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .results;
+
+        assert_eq!(results.len(), 1);
+    }
+
+    #[test]
+    fn test_search_notes_regex() {
+        let vault = setup_test_vault();
+        let results = search_notes(
+            &[(None, vault.path())],
+            r"#\s+\w+",
+            10,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .results;
+
+        // Should match headings
+        assert!(!results.is_empty());
+    }
+
+    #[test]
+    fn test_write_note_new() {
+        let vault = setup_test_vault();
+        let new_path = vault.path().join("new_note.md");
+
+        write_note(
+            &new_path,
+            "# New Note\n\nContent here.",
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        assert!(new_path.exists());
+        let content = fs::read_to_string(&new_path).unwrap();
+        assert!(content.contains("# New Note"));
+    }
+
+    #[test]
+    fn test_write_note_creates_directories() {
+        let vault = setup_test_vault();
+        let nested_path = vault.path().join("nested/dir/note.md");
+
+        write_note(&nested_path, "# Nested Note", &NoteOptions::default()).unwrap();
+
+        assert!(nested_path.exists());
+    }
+
+    #[test]
+    fn test_delete_note_to_trash() {
+        let vault = setup_test_vault();
+        let note_path = vault.path().join("simple.md");
+
+        let result = delete_note(
+            vault.path(),
+            &note_path,
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        assert!(!note_path.exists());
+        assert!(result.contains("Moved to trash"));
+        assert!(vault.path().join(".trash").exists());
+    }
+
+    #[test]
+    fn test_delete_note_custom_trash_dir() {
+        let vault = setup_test_vault();
+        let note_path = vault.path().join("test.md");
+
+        let result = delete_note(
+            vault.path(),
+            &note_path,
+            false,
+            "stumbling-trash",
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        assert!(result.starts_with("Moved to trash: stumbling-trash/"));
+        assert!(!vault.path().join(".trash").exists());
+
+        // Not hidden, but still excluded from search
+        let results = search_notes(
+            &[(None, vault.path())],
+            "Gagagigo",
+            10,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .results;
+        assert!(results
+            .iter()
+            .all(|r| !r.path.starts_with("stumbling-trash")));
+        assert_eq!(results.len(), 1);
+    }
+
+    #[test]
+    fn test_delete_note_trash_dir_outside_root() {
+        let vault = setup_test_vault();
+        let note_path = vault.path().join("test.md");
+
+        let result = delete_note(
+            vault.path(),
+            &note_path,
+            false,
+            "../trash",
+            &NoteOptions::default(),
+        );
+
+        assert!(result.is_err());
+        assert!(note_path.exists());
+    }
+
+    #[test]
+    fn test_delete_note_permanent() {
+        let vault = setup_test_vault();
+        let note_path = vault.path().join("simple.md");
+
+        let result = delete_note(
+            vault.path(),
+            &note_path,
+            true,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        assert!(!note_path.exists());
+        assert!(result.contains("Permanently deleted"));
+    }
+
+    // ========================================
+    // Boundary & Error Tests (0-1-N)
+    // ========================================
+
+    // --- read_note boundaries ---
+
+    #[test]
+    fn test_get_frontmatter() {
+        let vault = setup_test_vault();
+
+        let meta = get_frontmatter(&vault.path().join("test.md"), &NoteOptions::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(meta["title"], "Test Note");
+        assert_eq!(
+            get_frontmatter(&vault.path().join("simple.md"), &NoteOptions::default()).unwrap(),
+            None
+        );
+
+        let invalid = vault.path().join("invalid.md");
+        fs::write(&invalid, "---\ntitle: [unclosed\n---\n\nBody").unwrap();
+        let err = get_frontmatter(&invalid, &NoteOptions::default())
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("Invalid YAML frontmatter"));
+        assert!(err.contains("invalid.md"));
+    }
+
+    #[test]
+    fn test_read_frontmatter_only_skips_body() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("huge.md");
+        // Reading the body as text would fail on the invalid UTF-8 at its end
+        let mut content = b"---\ntitle: Huge\n---\n\n".to_vec();
+        content.extend(b"Lorem ipsum\n".repeat(100_000));
+        content.extend([0xff, 0xfe]);
+        fs::write(&path, &content).unwrap();
+        assert!(fs::read_to_string(&path).is_err());
+
+        let meta = read_frontmatter_only(&path, &NoteOptions::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(meta["title"], "Huge");
+
+        fs::write(&path, "+++\ntitle = \"Toml\"\n+++\n\nBody").unwrap();
+        assert_eq!(
+            read_frontmatter_only(&path, &NoteOptions::default())
+                .unwrap()
+                .unwrap()["title"],
+            "Toml"
+        );
+        fs::write(&path, "{\"title\": \"Json\"}\n\nBody").unwrap();
+        assert_eq!(
+            read_frontmatter_only(&path, &NoteOptions::default())
+                .unwrap()
+                .unwrap()["title"],
+            "Json"
+        );
+        fs::write(&path, "---\ntitle: Unclosed\n").unwrap();
+        assert_eq!(
+            read_frontmatter_only(&path, &NoteOptions::default()).unwrap(),
+            None
+        );
+        fs::write(&path, "# Heading\n---\ntitle: Late\n---\n").unwrap();
+        assert_eq!(
+            read_frontmatter_only(&path, &NoteOptions::default()).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_read_raw_frontmatter_is_verbatim() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("raw.md");
+        let yaml = "# Reviewed by hand\ntitle: 'Quoted'\nbase: &base {a: 1}\ncopy: *base";
+        fs::write(&path, format!("---\n{}\n---\n\nBody", yaml)).unwrap();
+
+        let raw = read_raw_frontmatter(&path, &NoteOptions::default()).unwrap();
+        assert_eq!(raw.frontmatter.as_deref(), Some(yaml));
+        assert_eq!(raw.format, Some(FrontmatterFormat::Yaml));
+        assert_eq!(raw.body, "\nBody");
+
+        let plain =
+            read_raw_frontmatter(&vault.path().join("simple.md"), &NoteOptions::default()).unwrap();
+        assert_eq!(plain.frontmatter, None);
+        assert_eq!(
+            plain.body,
+            fs::read_to_string(vault.path().join("simple.md")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_read_note_spans() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("spans.md");
+        for (content, body) in [
+            ("---\ntitle: Spans\n---\n\n# Body\n", "\n# Body\n"),
+            ("+++\ntitle = \"Spans\"\n+++\n# Body\n", "# Body\n"),
+            ("{\"title\": \"Spans\"}\n\n# Body\n", "\n# Body\n"),
+        ] {
+            fs::write(&path, content).unwrap();
+            let spans = read_note_spans(&path, &NoteOptions::default()).unwrap();
+            assert_eq!(&content[spans.body_byte_offset..], body);
+            let body_line = content.lines().nth(spans.body_line_offset).unwrap();
+            assert_eq!(Some(body_line), body.lines().next());
+            let range = spans.frontmatter_byte_range.unwrap();
+            assert!(content[range.clone()].ends_with(['-', '+', '}']));
+            assert_eq!(range.start, 0);
+        }
+
+        fs::write(&path, "# Body\n").unwrap();
+        let spans = read_note_spans(&path, &NoteOptions::default()).unwrap();
+        assert_eq!(spans.frontmatter_byte_range, None);
+        assert_eq!((spans.body_byte_offset, spans.body_line_offset), (0, 0));
+    }
+
+    #[test]
+    fn test_read_note_empty_file() {
+        let vault = setup_test_vault();
+        let empty_path = vault.path().join("empty.md");
+        fs::write(&empty_path, "").unwrap();
+
+        let result = read_note(&empty_path, false, &NoteOptions::default()).unwrap();
+        assert_eq!(result, "");
+    }
+
+    #[test]
+    fn test_read_note_not_found() {
+        let vault = setup_test_vault();
+        let result = read_note(
+            &vault.path().join("nonexistent.md"),
+            false,
+            &NoteOptions::default(),
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_read_note_frontmatter_only() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("frontmatter_only.md");
+        fs::write(&path, "---\ntitle: Only FM\n---\n").unwrap();
+
+        let result = read_note(&path, true, &NoteOptions::default()).unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
+
+        assert_eq!(parsed["metadata"]["title"], "Only FM");
+        assert_eq!(parsed["body"], "");
+    }
+
+    #[test]
+    fn test_read_note_invalid_yaml() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("invalid_yaml.md");
+        fs::write(&path, "---\n: invalid yaml [[\n---\n\nBody here").unwrap();
+
+        // Should return raw content when YAML is invalid
+        let result = read_note(&path, true, &NoteOptions::default()).unwrap();
+        assert!(result.contains(": invalid yaml"));
+    }
+
+    #[test]
+    fn test_read_note_unclosed_frontmatter() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("unclosed.md");
+        fs::write(&path, "---\ntitle: Unclosed\n\nNo closing delimiter").unwrap();
+
+        // Should return raw content when frontmatter is unclosed
+        let result = read_note(&path, true, &NoteOptions::default()).unwrap();
+        assert!(result.contains("No closing delimiter"));
+    }
+
+    #[test]
+    fn test_read_note_json_frontmatter() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("json.md");
+        fs::write(
+            &path,
+            "{\n  \"title\": \"JSON Note\",\n  \"tags\": [\"json\"]\n}\n\n# Body",
+        )
+        .unwrap();
+
+        let result = read_note(&path, true, &NoteOptions::default()).unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
+
+        assert_eq!(parsed["metadata"]["title"], "JSON Note");
+        assert_eq!(parsed["body"], "\n# Body");
+    }
+
+    #[test]
+    fn test_read_note_brace_text_is_not_frontmatter() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("brace.md");
+        fs::write(&path, "{placeholder} text\n\nBody").unwrap();
+
+        let result = read_note(&path, true, &NoteOptions::default()).unwrap();
+
+        assert_eq!(result, "{placeholder} text\n\nBody");
+    }
+
+    #[test]
+    fn test_read_note_first_frontmatter_style_wins() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("both.md");
+        fs::write(
+            &path,
+            "{\"title\": \"JSON\"}\n---\ntitle: YAML\n---\n\nBody",
+        )
+        .unwrap();
+
+        let result = read_note(&path, true, &NoteOptions::default()).unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
+
+        // The JSON block comes first, so the YAML block is part of the body
+        assert_eq!(parsed["metadata"]["title"], "JSON");
+        assert!(parsed["body"].as_str().unwrap().contains("title: YAML"));
+    }
+
+    #[test]
+    fn test_read_note_no_frontmatter_with_parse_flag() {
+        let vault = setup_test_vault();
+        let result = read_note(
+            &vault.path().join("simple.md"),
+            true,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        // Should return raw content when no frontmatter exists
+        assert!(result.contains("# Simple Note"));
+    }
+
+    // --- search_notes boundaries ---
+
+    #[test]
+    fn test_search_notes_empty_vault() {
+        let dir = TempDir::new().unwrap();
+        let results = search_notes(
+            &[(None, dir.path())],
+            "anything",
+            10,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .results;
+
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn test_search_notes_no_matches() {
+        let vault = setup_test_vault();
+        let results = search_notes(
+            &[(None, vault.path())],
+            "zzz_no_match_zzz",
+            10,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .results;
+
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn test_search_notes_invalid_regex() {
+        let vault = setup_test_vault();
+        let result = search_notes(
+            &[(None, vault.path())],
+            "[invalid(regex",
+            10,
+            &SearchOptions::default(),
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_invalid_regex_messages() {
+        let vault = setup_test_vault();
+
+        let err = search_notes(
+            &[(None, vault.path())],
+            "a[bc",
+            10,
+            &SearchOptions::default(),
+        )
+        .unwrap_err()
+        .to_string();
+        assert_eq!(
+            err.lines().take(3).collect::<Vec<_>>(),
+            [
+                "Invalid regex pattern: unclosed character class at position 2",
+                "  a[bc",
+                "   ^",
+            ]
+        );
+        assert!(err.contains("escape them with a backslash"));
+
+        let err = search_metadata(
+            vault.path(),
+            "title",
+            r"(?:\w{1000}){1000}",
+            10,
+            None,
+            false,
+            &MetadataSearchOptions::default(),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("is too large"), "{}", err);
+        assert!(!err.contains("position"));
+    }
+
+    #[test]
+    fn test_search_notes_limit_zero() {
+        let vault = setup_test_vault();
+        let results = search_notes(
+            &[(None, vault.path())],
+            "Gagagigo",
+            0,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .results;
+
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn test_search_notes_skips_hidden_dirs() {
+        let vault = setup_test_vault();
+
+        // Create a hidden directory with a note
+        fs::create_dir_all(vault.path().join(".obsidian")).unwrap();
+        fs::write(
+            vault.path().join(".obsidian/config.md"),
+            "# Hidden Gagagigo",
+        )
+        .unwrap();
+
+        let results = search_notes(
+            &[(None, vault.path())],
+            "Hidden Gagagigo",
+            10,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .results;
+
+        // Should not find the hidden file
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn test_search_paths() {
+        let vault = setup_test_vault();
+        fs::create_dir(vault.path().join("meetings")).unwrap();
+        fs::write(vault.path().join("meetings/standup.md"), "# Standup").unwrap();
+        fs::write(vault.path().join("notes.md"), "Agenda for the meeting").unwrap();
+
+        let results = search_notes(
+            &[(None, vault.path())],
+            "meeting",
+            10,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .results;
+        assert_eq!(results.len(), 1);
+
+        let options = SearchOptions {
+            search_paths: true,
+            ..Default::default()
+        };
+        let results = search_notes(&[(None, vault.path())], "meeting", 10, &options)
+            .unwrap()
+            .results;
+        let found: Vec<_> = results
+            .iter()
+            .map(|r| (r.path.as_str(), r.line_number))
+            .collect();
+        assert_eq!(found, [("meetings/standup.md", 0), ("notes.md", 1)]);
+        assert_eq!(results[0].line.as_deref(), Some("meetings/standup.md"));
+        assert_eq!((results[0].match_start, results[0].match_end), (0, 7));
+    }
+
+    #[test]
+    fn test_search_max_per_file() {
+        let vault = setup_test_vault();
+        fs::write(vault.path().join("many.md"), "Gagagigo\n".repeat(50)).unwrap();
+
+        let options = SearchOptions {
+            max_per_file: Some(2),
+            ..Default::default()
+        };
+        let results = search_notes(&[(None, vault.path())], "Gagagigo", 100, &options)
+            .unwrap()
+            .results;
+        let many: Vec<_> = results
+            .iter()
+            .filter(|r| r.path == "many.md")
+            .map(|r| r.line_number)
+            .collect();
+        assert_eq!(many, [1, 2]);
+        // Other notes still contribute
+        assert!(results.iter().any(|r| r.path == "test.md"));
+
+        let unlimited = search_notes(
+            &[(None, vault.path())],
+            "Gagagigo",
+            100,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .results;
+        assert_eq!(unlimited.iter().filter(|r| r.path == "many.md").count(), 50);
+    }
+
+    #[test]
+    fn test_search_include_hidden() {
+        let vault = setup_test_vault();
+        for dir in [".config", ".trash", ".git"] {
+            fs::create_dir_all(vault.path().join(dir)).unwrap();
+            fs::write(
+                vault.path().join(dir).join("note.md"),
+                "---\ntitle: Hidden Gagagigo\n---\nHidden Gagagigo",
+            )
+            .unwrap();
+        }
+
+        let options = SearchOptions {
+            include_hidden: true,
+            ..Default::default()
+        };
+        let results = search_notes(&[(None, vault.path())], "Hidden Gagagigo", 10, &options)
+            .unwrap()
+            .results;
+        let paths: BTreeSet<_> = results.iter().map(|r| r.path.as_str()).collect();
+        assert_eq!(paths, BTreeSet::from([".config/note.md"]));
+
+        let title = "Hidden Gagagigo";
+        assert!(search_metadata(
+            vault.path(),
+            "title",
+            title,
+            10,
+            None,
+            false,
+            &MetadataSearchOptions::default()
+        )
+        .unwrap()
+        .is_empty());
+        let results = search_metadata(
+            vault.path(),
+            "title",
+            title,
+            10,
+            None,
+            false,
+            &MetadataSearchOptions {
+                include_hidden: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, ".config/note.md");
+    }
+
+    #[test]
+    fn test_search_notes_sorted_by_path() {
+        let vault = setup_test_vault();
+        let results = search_notes(
+            &[(None, vault.path())],
+            "Gagagigo",
+            10,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .results;
+
+        assert_eq!(results[0].path, "daily/2024-01-01.md");
+        assert_eq!(results[1].path, "test.md");
+    }
+
+    #[test]
+    fn test_search_notes_cancelled() {
+        let dir = TempDir::new().unwrap();
+        let body = "lorem ipsum\n".repeat(100);
+        for i in 0..20 {
+            fs::write(dir.path().join(format!("note_{:03}.md", i)), &body).unwrap();
+        }
+
+        // Cancelled up front, so the result doesn't depend on how fast the scan runs
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let options = SearchOptions {
+            cancel: Some(cancel),
+            ..Default::default()
+        };
+        // Nothing matches, so only cancelling can stop the scan early
+        let (report, lines_scanned) =
+            search_files(&[(None, dir.path())], "Gagagigo", 5, &options).unwrap();
+        assert!(report.results.is_empty());
+        assert_eq!(lines_scanned, 0);
+
+        let err = search_notes(&[(None, dir.path())], "lorem", 5, &options).unwrap_err();
+        assert_eq!(err.to_string(), "Search cancelled");
+        let options = MetadataSearchOptions {
+            cancel: options.cancel,
+            ..Default::default()
+        };
+        let err = search_metadata(dir.path(), "title", ".*", 5, None, false, &options).unwrap_err();
+        assert_eq!(err.to_string(), "Search cancelled");
+    }
+
+    #[test]
+    fn test_search_notes_stops_scanning_at_limit() {
+        let dir = TempDir::new().unwrap();
+        let body = "Gagagigo line\n".repeat(50);
+        for i in 0..200 {
+            fs::write(dir.path().join(format!("note_{:03}.md", i)), &body).unwrap();
+        }
+
+        let (report, lines_scanned) = search_files(
+            &[(None, dir.path())],
+            "Gagagigo",
+            5,
+            &SearchOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(report.results.len(), 5);
+        // Searching stops after the first batch of notes meets the limit, and no note
+        // is read past the limit, so only a tiny fraction of the 10,000 lines is scanned
+        assert!(
+            lines_scanned < 1000,
+            "scanned {} lines after the limit was met",
+            lines_scanned
+        );
+    }
+
+    #[test]
+    fn test_search_notes_limit_is_deterministic() {
+        let dir = TempDir::new().unwrap();
+        // Later notes are short, so they would meet the limit first if workers raced
+        for i in 0..300 {
+            let lines = if i < 10 { 200 } else { 1 };
+            let body = "filler\n".repeat(lines) + "Gagagigo\n";
+            fs::write(dir.path().join(format!("note_{:03}.md", i)), body).unwrap();
+        }
+
+        let expected: Vec<String> = (0..25).map(|i| format!("note_{:03}.md", i)).collect();
+        for _ in 0..20 {
+            let report = search_notes(
+                &[(None, dir.path())],
+                "Gagagigo",
+                25,
+                &SearchOptions::default(),
+            )
+            .unwrap();
+            let paths: Vec<_> = report.results.into_iter().map(|r| r.path).collect();
+            assert_eq!(paths, expected);
+            assert!(report.skipped.is_empty());
+        }
+    }
+
+    fn write_gzipped(path: &Path, content: &str) {
+        let mut encoder =
+            flate2::write::GzEncoder::new(fs::File::create(path).unwrap(), Default::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+    }
+
+    #[test]
+    fn test_search_notes_compressed() {
+        let vault = setup_test_vault();
+        fs::create_dir_all(vault.path().join("archive")).unwrap();
+        write_gzipped(
+            &vault.path().join("archive/old.md.gz"),
+            "---\ntitle: Old\n---\n\n# Old\n\nGagagigo, archived.\n",
+        );
+
+        let options = SearchOptions {
+            body_relative_lines: true,
+            ..Default::default()
+        };
+        let report = search_notes(&[(None, vault.path())], "archived", 10, &options).unwrap();
+
+        assert_eq!(report.results.len(), 1);
+        assert_eq!(report.results[0].path, "archive/old.md.gz");
+        assert_eq!(report.results[0].line_number, 4);
+        assert!(report.skipped.is_empty());
+    }
+
+    #[test]
+    fn test_read_note_compressed_and_write_rejected() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("old.md.gz");
+        write_gzipped(&path, "---\ntitle: Old\n---\n\n# Old\n");
+
+        assert_eq!(
+            read_note(&path, false, &NoteOptions::default()).unwrap(),
+            "---\ntitle: Old\n---\n\n# Old\n"
+        );
+        let parsed: serde_json::Value =
+            serde_json::from_str(&read_note(&path, true, &NoteOptions::default()).unwrap())
+                .unwrap();
+        assert_eq!(parsed["metadata"]["title"], "Old");
+        assert_eq!(
+            read_body(&path, &NoteOptions::default()).unwrap(),
+            "\n# Old\n"
+        );
+
+        // Frontmatter-only and structural reads decompress too
+        assert_eq!(
+            get_frontmatter(&path, &NoteOptions::default()).unwrap(),
+            Some(serde_json::json!({"title": "Old"}))
+        );
+        let raw = read_raw_frontmatter(&path, &NoteOptions::default()).unwrap();
+        assert_eq!(raw.frontmatter.as_deref(), Some("title: Old"));
+        assert_eq!(outline(&path).unwrap()[0].text, "Old");
+
+        // Metadata walks leave compressed notes out
+        assert!(!collect_markdown_files(vault.path(), DEFAULT_TRASH_DIR).contains(&path));
+
+        let err = write_note(&path, "# New", &NoteOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("Compressed notes are read-only"));
+        let err = append_note(&path, "more", &NoteOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("Compressed notes are read-only"));
+    }
+
+    #[test]
+    fn test_search_notes_terms_mode() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("terms.md");
+        fs::write(
+            &path,
+            "The borrow checker (again)\nchecker borrow\nBorrow Checker rules\nborrow only\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            parse_terms(r#"  rust "borrow checker"  mcp "unclosed phrase"#),
+            ["rust", "borrow checker", "mcp", "unclosed phrase"]
+        );
+
+        let options = SearchOptions {
+            mode: SearchMode::Terms,
+            ..Default::default()
+        };
+        let lines = |query: &str| -> Vec<usize> {
+            search_notes(&[(None, vault.path())], query, 10, &options)
+                .unwrap()
+                .results
+                .iter()
+                .map(|r| r.line_number)
+                .collect()
+        };
+        // Every term must appear, in any order and case
+        assert_eq!(lines("checker borrow"), [1, 2, 3]);
+        // A phrase must appear as written
+        assert_eq!(lines(r#""borrow checker""#), [1, 3]);
+        // Regex syntax is matched literally
+        assert_eq!(lines("(again)"), [1]);
+
+        let report = search_notes(&[(None, vault.path())], "checker borrow", 10, &options).unwrap();
+        assert_eq!(report.results[0].match_start, 4);
+        assert_eq!(report.results[0].match_end, 10);
+
+        assert!(search_notes(&[(None, vault.path())], r#"  "" "#, 10, &options).is_err());
+    }
+
+    #[test]
+    fn test_search_notes_include_stats() {
+        let vault = setup_test_vault();
+
+        let report = search_notes(
+            &[(None, vault.path())],
+            "Gagagigo",
+            10,
+            &SearchOptions::default(),
+        )
+        .unwrap();
+        assert!(report.stats.is_none());
+
+        let options = SearchOptions {
+            include_stats: true,
+            ..Default::default()
+        };
+        let report = search_notes(&[(None, vault.path())], "Gagagigo", 10, &options).unwrap();
+        let stats = report.stats.unwrap();
+        assert_eq!(stats.files_scanned, 3);
+        let total: usize = ["test.md", "simple.md", "daily/2024-01-01.md"]
+            .iter()
+            .map(|p| fs::read_to_string(vault.path().join(p)).unwrap().len())
+            .sum();
+        // Each note's last line has no line break but is counted with one
+        assert_eq!(stats.bytes_read, total + 3);
+    }
+
+    #[test]
+    fn test_search_notes_sort() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("a.md"), "match\n").unwrap();
+        fs::write(dir.path().join("b.md"), "match\nmatch\nmatch\n").unwrap();
+        fs::write(dir.path().join("c.md"), "match\nmatch\n").unwrap();
+        let now = SystemTime::now();
+        for (name, age) in [("a.md", 0), ("b.md", 200), ("c.md", 100)] {
+            fs::File::options()
+                .write(true)
+                .open(dir.path().join(name))
+                .unwrap()
+                .set_modified(now - Duration::from_secs(age))
+                .unwrap();
+        }
+
+        let paths = |sort: SearchSort, limit: usize| {
+            let options = SearchOptions {
+                sort,
+                ..Default::default()
+            };
+            search_notes(&[(None, dir.path())], "match", limit, &options)
+                .unwrap()
+                .results
+                .into_iter()
+                .map(|r| format!("{}:{}", r.path, r.line_number))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(paths(SearchSort::Path, 2), ["a.md:1", "b.md:1"]);
+        assert_eq!(
+            paths(SearchSort::Modified, 3),
+            ["a.md:1", "c.md:1", "c.md:2"]
+        );
+        assert_eq!(
+            paths(SearchSort::Relevance, 4),
+            ["b.md:1", "b.md:2", "b.md:3", "c.md:1"]
+        );
+    }
+
+    #[test]
+    fn test_search_notes_body_relative_lines() {
+        let vault = setup_test_vault();
+        let options = SearchOptions {
+            body_relative_lines: true,
+            ..Default::default()
+        };
+
+        let results = search_notes(&[(None, vault.path())], "Gagagigo", 10, &options)
+            .unwrap()
+            .results;
+
+        // test.md has 4 frontmatter lines; the blank line after them is the body's first
+        let test = results.iter().find(|r| r.path == "test.md").unwrap();
+        assert_eq!(test.line_number, 4);
+        // Notes without frontmatter keep whole-file numbering
+        let daily = results
+            .iter()
+            .find(|r| r.path == "daily/2024-01-01.md")
+            .unwrap();
+        assert_eq!(daily.line_number, 3);
+    }
+
+    #[test]
+    fn test_search_notes_body_relative_skips_frontmatter() {
+        let vault = setup_test_vault();
+        let options = SearchOptions {
+            body_relative_lines: true,
+            ..Default::default()
+        };
+
+        let whole = search_notes(
+            &[(None, vault.path())],
+            "Test Note",
+            10,
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .results;
+        let body = search_notes(&[(None, vault.path())], "Test Note", 10, &options)
+            .unwrap()
+            .results;
+
+        assert_eq!(whole.len(), 1);
+        assert_eq!(whole[0].line_number, 2);
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn test_body_line_offset_json_frontmatter() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("json.md");
+        fs::write(&path, "{\n  \"title\": \"JSON\"\n}\n\nBody").unwrap();
+
+        assert_eq!(body_line_offset(&path), 3);
+        assert_eq!(body_line_offset(&vault.path().join("simple.md")), 0);
+    }
+
+    #[test]
+    fn test_body_line_offset_keeps_blank_lines_in_body() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("spaced.md");
+        fs::write(&path, "---\ntitle: Spaced\n---\n\n\nBody").unwrap();
+
+        // Matches read_body, which starts with the first blank line
+        assert_eq!(body_line_offset(&path), 3);
+    }
+
+    #[test]
+    fn test_search_notes_skips_oversized_files() {
+        let vault = setup_test_vault();
+
+        let large = format!("Gagagigo oversized\n{}", "x".repeat(1024));
+        fs::write(vault.path().join("large.md"), large).unwrap();
+
+        let results = search_notes(
+            &[(None, vault.path())],
+            "Gagagigo",
+            10,
+            &SearchOptions {
+                max_file_bytes: Some(512),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .results;
+
+        // The oversized note is skipped, the normal notes are still searched
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.path != "large.md"));
+    }
+
+    // --- write_note boundaries ---
+
+    #[test]
+    fn test_write_note_empty_content() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("empty_write.md");
+
+        write_note(&path, "", &NoteOptions::default()).unwrap();
+
+        assert!(path.exists());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+    }
+
+    #[test]
+    fn test_write_note_overwrite_existing() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("simple.md");
+
+        write_note(&path, "# Overwritten Content", &NoteOptions::default()).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains("Overwritten"));
+        assert!(!content.contains("No frontmatter"));
+    }
+
+    #[test]
+    fn test_write_note_unicode_content() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("unicode.md");
+
+        let content = "# ガガギゴ 🐉\n\n日本語テスト";
+        write_note(&path, content, &NoteOptions::default()).unwrap();
+
+        let read_back = fs::read_to_string(&path).unwrap();
+        assert_eq!(read_back, content);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_note_preserves_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let vault = setup_test_vault();
+        let path = vault.path().join("test.md");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        write_note(&path, "overwritten", &NoteOptions::default()).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    // --- with_retries ---
+
+    #[test]
+    fn test_with_retries_recovers_from_transient_rename_failure() {
+        let vault = setup_test_vault();
+        let from = vault.path().join("simple.md");
+        let to = vault.path().join("renamed.md");
+
+        // Fails the first rename the way a locked file on SMB does, then renames for real
+        let mut attempts = 0;
+        with_retries(2, || {
+            attempts += 1;
+            if attempts == 1 {
+                return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+            }
+            fs::rename(&from, &to)
+        })
+        .unwrap();
+
+        assert_eq!(attempts, 2);
+        assert!(to.exists());
+        assert!(!from.exists());
+    }
+
+    #[test]
+    fn test_with_retries_gives_up() {
+        let vault = setup_test_vault();
+        let missing = vault.path().join("missing.md");
+
+        // Permanent errors aren't retried
+        let mut attempts = 0;
+        let err = with_retries(2, || {
+            attempts += 1;
+            fs::rename(&missing, vault.path().join("renamed.md"))
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        assert_eq!(attempts, 1);
+
+        // Transient ones are, but only so many times
+        let mut attempts = 0;
+        let err = with_retries(2, || -> std::io::Result<()> {
+            attempts += 1;
+            Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied))
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        assert_eq!(attempts, 3);
+    }
+
+    // --- replace_first ---
+
+    #[test]
+    fn test_replace_first_occurrences() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("edit.md");
+        let edit = |occurrence| {
+            fs::write(&path, "one two one two one").unwrap();
+            let count =
+                replace_first(&path, "one", "1", occurrence, &NoteOptions::default()).unwrap();
+            (count, fs::read_to_string(&path).unwrap())
+        };
+
+        assert_eq!(
+            edit(Occurrence::First),
+            (1, "1 two one two one".to_string())
+        );
+        assert_eq!(edit(Occurrence::Last), (1, "one two one two 1".to_string()));
+        assert_eq!(edit(Occurrence::All), (3, "1 two 1 two 1".to_string()));
+    }
+
+    #[test]
+    fn test_replace_first_missing_text() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("simple.md");
+        let before = fs::read_to_string(&path).unwrap();
+
+        let err = replace_first(
+            &path,
+            "absent",
+            "x",
+            Occurrence::First,
+            &NoteOptions::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("not found"));
+        assert!(replace_first(&path, "", "x", Occurrence::All, &NoteOptions::default()).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), before);
+    }
+
+    // --- patch_note ---
+
+    #[test]
+    fn test_patch_note_replaces_range() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("patch.md");
+        fs::write(&path, "one\ntwo\nthree\nfour\n").unwrap();
+
+        patch_note(&path, 2, 3, "TWO\nTHREE\nEXTRA", &NoteOptions::default()).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(content, "one\nTWO\nTHREE\nEXTRA\nfour\n");
+    }
+
+    #[test]
+    fn test_patch_note_single_line() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("patch.md");
+        fs::write(&path, "one\ntwo\nthree").unwrap();
+
+        patch_note(&path, 1, 1, "ONE", &NoteOptions::default()).unwrap();
+
+        // No trailing newline in the original, so none is added
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(content, "ONE\ntwo\nthree");
+    }
+
+    #[test]
+    fn test_patch_note_delete_lines() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("patch.md");
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+
+        patch_note(&path, 2, 2, "", &NoteOptions::default()).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(content, "one\nthree\n");
+    }
+
+    #[test]
+    fn test_create_note_new() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("new/created.md");
+
+        create_note(&path, "# Created", &NoteOptions::default()).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "# Created");
+    }
+
+    #[test]
+    fn test_create_note_existing_fails() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("simple.md");
+        let before = fs::read_to_string(&path).unwrap();
+
+        let result = create_note(&path, "clobbered", &NoteOptions::default());
+
+        assert!(result.unwrap_err().to_string().contains("already exists"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), before);
+    }
+
+    #[test]
+    fn test_create_note_concurrent() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("race.md");
+
+        let created: Vec<_> = (0..8)
+            .into_par_iter()
+            .filter_map(|i| {
+                let content = format!("writer {}", i);
+                create_note(&path, &content, &NoteOptions::default())
+                    .ok()
+                    .map(|_| content)
+            })
+            .collect();
+
+        // Exactly one writer wins, and its content is what the note holds
+        assert_eq!(created.len(), 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), created[0]);
+        let leftovers: Vec<_> = fs::read_dir(vault.path())
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().ends_with(".tmp"))
+            .collect();
+        assert!(leftovers.is_empty(), "{:?}", leftovers);
+    }
+
+    #[test]
+    fn test_append_note_existing() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("daily/2024-01-01.md");
+
+        append_note(&path, "- another entry\n", &NoteOptions::default()).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# Daily Note\n\nGagagigo awakens!\n- another entry\n"
+        );
+    }
+
+    #[test]
+    fn test_append_note_missing_creates() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("log.md");
+
+        append_note(&path, "first", &NoteOptions::default()).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first");
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let diff = unified_diff("note.md", "keep\nold line\n", "keep\nnew line\n");
+
+        assert!(diff.starts_with("--- a/note.md\n+++ b/note.md\n"));
+        assert!(diff.contains("-old line\n"));
+        assert!(diff.contains("+new line\n"));
+        assert!(diff.contains(" keep\n"));
+    }
+
+    #[test]
+    fn test_unified_diff_identical() {
+        assert_eq!(unified_diff("note.md", "same\n", "same\n"), "");
+    }
+
+    #[test]
+    fn test_patch_note_append() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("patch.md");
+        fs::write(&path, "one\ntwo\n").unwrap();
+
+        patch_note(&path, 3, 3, "three", &NoteOptions::default()).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(content, "one\ntwo\nthree\n");
+    }
+
+    #[test]
+    fn test_patch_note_keeps_crlf() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("patch.md");
+        fs::write(&path, "one\r\ntwo\r\nthree\r\n").unwrap();
+
+        patch_note(&path, 2, 2, "TWO\nmore", &NoteOptions::default()).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(content, "one\r\nTWO\r\nmore\r\nthree\r\n");
+    }
+
+    #[test]
+    fn test_patch_note_start_after_end() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("patch.md");
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+
+        let result = patch_note(&path, 3, 2, "x", &NoteOptions::default());
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo\nthree\n");
+    }
+
+    #[test]
+    fn test_patch_note_out_of_range() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("patch.md");
+        fs::write(&path, "one\ntwo\n").unwrap();
+
+        assert!(patch_note(&path, 1, 4, "x", &NoteOptions::default()).is_err());
+        assert!(patch_note(&path, 0, 1, "x", &NoteOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_patch_note_not_found() {
+        let vault = setup_test_vault();
+        let result = patch_note(
+            &vault.path().join("nonexistent.md"),
+            1,
+            1,
+            "x",
+            &NoteOptions::default(),
+        );
+
+        assert!(result.is_err());
+    }
+
+    // --- replace_in_notes ---
+
+    #[test]
+    fn test_replace_in_notes() {
+        let vault = setup_test_vault();
+        let results = replace_in_notes(
+            vault.path(),
+            "Gagagigo",
+            "Giga Gagagigo",
+            None,
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.replacements == 1));
+
+        let content = fs::read_to_string(vault.path().join("daily/2024-01-01.md")).unwrap();
+        assert!(content.contains("Giga Gagagigo awakens!"));
+    }
+
+    #[test]
+    fn test_replace_in_notes_dry_run() {
+        let vault = setup_test_vault();
+        let results = replace_in_notes(
+            vault.path(),
+            "Gagagigo",
+            "X",
+            None,
+            true,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(results.len(), 2);
+
+        // Nothing is written in dry-run mode
+        let content = fs::read_to_string(vault.path().join("test.md")).unwrap();
+        assert!(content.contains("Gagagigo"));
+    }
+
+    #[test]
+    fn test_replace_in_notes_capture_groups() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("tags.md");
+        fs::write(&path, "#old-rust and #old-mcp").unwrap();
+
+        let results = replace_in_notes(
+            vault.path(),
+            r"#old-(\w+)",
+            "#new-$1",
+            None,
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].replacements, 2);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "#new-rust and #new-mcp");
+    }
+
+    #[test]
+    fn test_replace_in_notes_path_glob() {
+        let vault = setup_test_vault();
+        let results = replace_in_notes(
+            vault.path(),
+            "Gagagigo",
+            "X",
+            Some("daily/**"),
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, "daily/2024-01-01.md");
+
+        let content = fs::read_to_string(vault.path().join("test.md")).unwrap();
+        assert!(content.contains("Gagagigo"));
+    }
+
+    #[test]
+    fn test_replace_in_notes_invalid_regex() {
+        let vault = setup_test_vault();
+        let result = replace_in_notes(
+            vault.path(),
+            "[invalid(regex",
+            "x",
+            None,
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        );
+
+        assert!(result.is_err());
+    }
+
+    // --- move_notes ---
+
+    #[test]
+    fn test_move_notes() {
+        let vault = setup_test_vault();
+        fs::write(vault.path().join("a.md"), "a").unwrap();
+        fs::write(vault.path().join("b.md"), "b").unwrap();
+        let moves = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs
+                .iter()
+                .map(|(from, to)| (from.to_string(), to.to_string()))
+                .collect()
+        };
+
+        let moved = move_notes(
+            vault.path(),
+            &moves(&[("a.md", "archive/a.md"), ("b.md", "archive/b.md")]),
+            &NoteOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(moved[1].to, "archive/b.md");
+        assert!(vault.path().join("archive/a.md").is_file());
+        assert!(!vault.path().join("b.md").exists());
+
+        // One bad pair stops the whole batch before anything moves
+        let err = move_notes(
+            vault.path(),
+            &moves(&[("archive/a.md", "a.md"), ("missing.md", "c.md")]),
+            &NoteOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Move 2 (missing.md -> c.md): note does not exist"
+        );
+        assert!(vault.path().join("archive/a.md").is_file());
+
+        let err = move_notes(
+            vault.path(),
+            &moves(&[("archive/a.md", "c.md"), ("archive/b.md", "c.md")]),
+            &NoteOptions::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("already exists in this batch"));
+        assert!(move_notes(
+            vault.path(),
+            &moves(&[("archive/a.md", "../a.md")]),
+            &NoteOptions::default()
+        )
+        .is_err());
+    }
+
+    // --- rename_note ---
+
+    fn setup_link_vault() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("daily")).unwrap();
+        fs::write(
+            dir.path().join("old.md"),
+            "# Old\n\nSee [other](daily/other.md).\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("index.md"),
+            "[[old]], [[Old|the old one]], [[old#Intro|intro]] and [link](old.md#intro).\n\n\
+             `[[old]]` stays.\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("daily/other.md"),
+            "Back to [old](../old.md) and [[unrelated]].\n",
+        )
+        .unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_rename_note_rewrites_links() {
+        let vault = setup_link_vault();
+        let from = vault.path().join("old.md");
+        let to = vault.path().join("archive/new.md");
+
+        let report = rename_note(
+            vault.path(),
+            &from,
+            &to,
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(report.from, "old.md");
+        assert_eq!(report.to, "archive/new.md");
+        assert_eq!(report.updated_links, 6);
+        assert!(!from.exists());
+        assert_eq!(
+            fs::read_to_string(vault.path().join("index.md")).unwrap(),
+            "[[new]], [[new|the old one]], [[new#Intro|intro]] and [link](archive/new.md#intro).\n\n\
+             `[[old]]` stays.\n"
+        );
+        assert_eq!(
+            fs::read_to_string(vault.path().join("daily/other.md")).unwrap(),
+            "Back to [old](../archive/new.md) and [[unrelated]].\n"
+        );
+        // The moved note's own links follow it to its new directory
+        assert_eq!(
+            fs::read_to_string(&to).unwrap(),
+            "# Old\n\nSee [other](../daily/other.md).\n"
+        );
+    }
+
+    #[test]
+    fn test_rename_note_keeps_path_style_and_avoids_ambiguity() {
+        let vault = setup_link_vault();
+        fs::write(vault.path().join("daily/new.md"), "# Another new").unwrap();
+        fs::write(vault.path().join("paths.md"), "[[old.md]]").unwrap();
+
+        rename_note(
+            vault.path(),
+            &vault.path().join("old.md"),
+            &vault.path().join("notes/new.md"),
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        // A bare [[new]] would be ambiguous with daily/new.md
+        assert!(fs::read_to_string(vault.path().join("index.md"))
+            .unwrap()
+            .starts_with("[[notes/new]], "));
+        assert_eq!(
+            fs::read_to_string(vault.path().join("paths.md")).unwrap(),
+            "[[notes/new.md]]"
+        );
     }
 
-    Ok(content)
-}
+    #[test]
+    fn test_rename_note_dry_run() {
+        let vault = setup_link_vault();
+        let index = fs::read_to_string(vault.path().join("index.md")).unwrap();
+
+        let report = rename_note(
+            vault.path(),
+            &vault.path().join("old.md"),
+            &vault.path().join("new.md"),
+            true,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-/// Search for notes matching the query using parallel processing.
-pub fn search_notes(root: &Path, query: &str, limit: usize) -> Result<Vec<SearchResult>> {
-    let regex = grep::regex::RegexMatcher::new(query)
-        .with_context(|| format!("Invalid regex pattern: {}", query))?;
+        assert_eq!(report.updated_links, 5);
+        let files: Vec<_> = report.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(files, vec!["daily/other.md", "index.md"]);
+        assert!(vault.path().join("old.md").exists());
+        assert!(!vault.path().join("new.md").exists());
+        assert_eq!(
+            fs::read_to_string(vault.path().join("index.md")).unwrap(),
+            index
+        );
+    }
 
-    let results: Mutex<Vec<SearchResult>> = Mutex::new(Vec::new());
+    #[test]
+    fn test_rename_note_refuses_to_overwrite() {
+        let vault = setup_link_vault();
+        let result = rename_note(
+            vault.path(),
+            &vault.path().join("old.md"),
+            &vault.path().join("index.md"),
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        );
+        assert!(result.is_err());
+        assert!(vault.path().join("old.md").exists());
+    }
 
-    // Collect all markdown files first
-    let files: Vec<_> = WalkBuilder::new(root)
-        .hidden(true) // Skip hidden files/dirs
-        .filter_entry(|e| {
-            // Skip .obsidian and other common ignored directories
-            let name = e.file_name().to_string_lossy();
-            !name.starts_with('.')
-        })
-        .build()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.path().extension().map(|ext| ext == "md").unwrap_or(false))
-        .map(|e| e.into_path())
-        .collect();
+    // --- search_by_date ---
 
-    // Search files in parallel using rayon
-    files.par_iter().for_each(|path| {
-        if let Ok(content) = fs::read_to_string(path) {
-            let relative_path = path
-                .strip_prefix(root)
-                .unwrap_or(path)
-                .to_string_lossy()
-                .to_string();
-
-            for (line_num, line) in content.lines().enumerate() {
-                if grep::matcher::Matcher::is_match(&regex, line.as_bytes()).unwrap_or(false) {
-                    let mut results = results
-                        .lock()
-                        .unwrap_or_else(|poisoned| poisoned.into_inner());
-                    if results.len() < limit {
-                        results.push(SearchResult {
-                            path: relative_path.clone(),
-                            line_number: line_num + 1,
-                            line: line.to_string(),
-                        });
-                    }
-                }
-            }
+    fn setup_dated_vault() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let notes = [
+            ("jan.md", "2024-01-15"),
+            ("feb.md", "2024-02-10T09:30:00+09:00"),
+            ("mar.md", "2024-03-01"),
+            ("bad.md", "sometime"),
+        ];
+        for (name, created) in notes {
+            let content = format!("---\ncreated: {}\n---\n\nBody", created);
+            fs::write(dir.path().join(name), content).unwrap();
         }
-    });
+        fs::write(dir.path().join("none.md"), "No frontmatter").unwrap();
+        dir
+    }
 
-    Ok(results
-        .into_inner()
-        .unwrap_or_else(|poisoned| poisoned.into_inner()))
-}
+    #[test]
+    fn test_search_by_date_bounded() {
+        let vault = setup_dated_vault();
+        let results = search_by_date(
+            vault.path(),
+            "created",
+            Some("2024-02-01"),
+            Some("2024-03-01"),
+            10,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-/// Get a nested field value from JSON using dot notation (e.g., "author.name").
-fn get_nested_field<'a>(
-    value: &'a serde_json::Value,
-    field: &str,
-) -> Option<&'a serde_json::Value> {
-    let mut current = value;
-    for part in field.split('.') {
-        current = current.get(part)?;
+        // The range is inclusive on both ends
+        let paths: Vec<_> = results.iter().map(|r| r.path.as_str()).collect();
+        assert_eq!(paths, vec!["feb.md", "mar.md"]);
     }
-    Some(current)
-}
 
-/// Check if a JSON value matches a regex pattern.
-fn value_matches_pattern(value: &serde_json::Value, regex: &regex::Regex) -> bool {
-    match value {
-        serde_json::Value::String(s) => regex.is_match(s),
-        serde_json::Value::Number(n) => regex.is_match(&n.to_string()),
-        serde_json::Value::Bool(b) => regex.is_match(&b.to_string()),
-        serde_json::Value::Array(arr) => arr.iter().any(|v| value_matches_pattern(v, regex)),
-        _ => false,
+    #[test]
+    fn test_search_by_date_only_from() {
+        let vault = setup_dated_vault();
+        let results = search_by_date(
+            vault.path(),
+            "created",
+            Some("2024-02-10"),
+            None,
+            10,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        let paths: Vec<_> = results.iter().map(|r| r.path.as_str()).collect();
+        assert_eq!(paths, vec!["feb.md", "mar.md"]);
     }
-}
 
-/// Search notes by frontmatter metadata field.
-pub fn search_metadata(
-    root: &Path,
-    field: &str,
-    pattern: &str,
-    limit: usize,
-) -> Result<Vec<MetadataSearchResult>> {
-    let regex = regex::Regex::new(pattern)
-        .with_context(|| format!("Invalid regex pattern: {}", pattern))?;
+    #[test]
+    fn test_search_by_date_only_to() {
+        let vault = setup_dated_vault();
+        let results = search_by_date(
+            vault.path(),
+            "created",
+            None,
+            Some("2024-01-31"),
+            10,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-    let results: Mutex<Vec<MetadataSearchResult>> = Mutex::new(Vec::new());
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, "jan.md");
+        assert_eq!(results[0].value, "2024-01-15");
+    }
 
-    // Collect all markdown files
-    let files: Vec<_> = WalkBuilder::new(root)
-        .hidden(true)
-        .filter_entry(|e| {
-            let name = e.file_name().to_string_lossy();
-            !name.starts_with('.')
-        })
-        .build()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.path().extension().map(|ext| ext == "md").unwrap_or(false))
-        .map(|e| e.into_path())
-        .collect();
+    #[test]
+    fn test_search_by_date_skips_unparseable() {
+        let vault = setup_dated_vault();
+        let results = search_by_date(
+            vault.path(),
+            "created",
+            None,
+            None,
+            10,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-    // Search files in parallel
-    files.par_iter().for_each(|path| {
-        if let Ok(content) = fs::read_to_string(path) {
-            // Parse frontmatter using markdown-rs AST
-            if let Some((yaml_str, _)) = parse_frontmatter(&content) {
-                if let Ok(meta) = serde_yaml_ng::from_str::<serde_json::Value>(&yaml_str) {
-                    if let Some(value) = get_nested_field(&meta, field) {
-                        if value_matches_pattern(value, &regex) {
-                            let relative_path = path
-                                .strip_prefix(root)
-                                .unwrap_or(path)
-                                .to_string_lossy()
-                                .to_string();
-
-                            let mut results = results
-                                .lock()
-                                .unwrap_or_else(|poisoned| poisoned.into_inner());
-                            if results.len() < limit {
-                                results.push(MetadataSearchResult {
-                                    path: relative_path,
-                                    value: value.clone(),
-                                });
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    });
+        // bad.md and none.md are skipped
+        assert_eq!(results.len(), 3);
+    }
 
-    Ok(results
-        .into_inner()
-        .unwrap_or_else(|poisoned| poisoned.into_inner()))
-}
+    #[test]
+    fn test_search_by_date_returns_parsed_date() {
+        let vault = setup_dated_vault();
+        fs::write(
+            vault.path().join("local.md"),
+            "---\ncreated: 2024-02-20 18:45:00\n---\n\nBody",
+        )
+        .unwrap();
 
-/// Format content with YAML frontmatter.
-///
-/// Note: AI tools (e.g., Claude) sometimes serialize metadata as a JSON string
-/// `"{\"title\": ...}"` instead of passing a JSON object `{"title": ...}`.
-/// This function handles both cases by parsing string values as JSON.
-pub fn format_with_frontmatter(metadata: &serde_json::Value, body: &str) -> String {
-    let meta = if let serde_json::Value::String(s) = metadata {
-        serde_json::from_str(s).unwrap_or_else(|_| metadata.clone())
-    } else {
-        metadata.clone()
-    };
+        let results = search_by_date(
+            vault.path(),
+            "created",
+            Some("2024-02-01"),
+            Some("2024-02-29"),
+            10,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-    let yaml = serde_yaml_ng::to_string(&meta).unwrap_or_default();
-    // serde_yaml_ng adds a trailing newline, so we trim it
-    let yaml = yaml.trim_end();
-    format!("---\n{}\n---\n\n{}", yaml, body)
-}
+        let dates: Vec<_> = results
+            .iter()
+            .map(|r| (r.path.as_str(), r.date.as_str()))
+            .collect();
+        assert_eq!(
+            dates,
+            [("feb.md", "2024-02-10"), ("local.md", "2024-02-20")]
+        );
+        assert_eq!(results[0].value, "2024-02-10T09:30:00+09:00");
+    }
 
-/// Write content to a note file.
-/// Creates parent directories if they don't exist.
-/// Uses atomic write (write to temp, then rename) to prevent data corruption.
-pub fn write_note(path: &Path, content: &str) -> Result<()> {
-    // Ensure parent directory exists
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent)
-            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    #[test]
+    fn test_search_by_date_invalid_bound() {
+        let vault = setup_dated_vault();
+        let result = search_by_date(
+            vault.path(),
+            "created",
+            Some("not-a-date"),
+            None,
+            10,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        );
+
+        assert!(result.is_err());
     }
 
-    // Atomic write: write to temp file, then rename
-    let temp_path = path.with_extension("tmp");
-    fs::write(&temp_path, content)
-        .with_context(|| format!("Failed to write temp file: {}", temp_path.display()))?;
+    // --- validate_frontmatter ---
 
-    fs::rename(&temp_path, path)
-        .with_context(|| format!("Failed to rename temp file to: {}", path.display()))?;
+    #[test]
+    fn test_validate_frontmatter_missing_required() {
+        let vault = setup_test_vault();
+        let required = vec!["title".to_string(), "created".to_string()];
+
+        let results = validate_frontmatter(
+            vault.path(),
+            &required,
+            &BTreeMap::new(),
+            None,
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-    Ok(())
-}
+        // All three notes lack `created`; the two without frontmatter are flagged as such
+        assert_eq!(results.len(), 3);
+        let test = results.iter().find(|r| r.path == "test.md").unwrap();
+        assert_eq!(test.problems, vec!["missing required field: created"]);
+        let simple = results.iter().find(|r| r.path == "simple.md").unwrap();
+        assert_eq!(simple.problems, vec!["missing frontmatter"]);
+    }
 
-/// Delete a note file.
-/// If permanent is false, moves to .trash directory with timestamp.
-/// If permanent is true, permanently deletes the file.
-pub fn delete_note(root: &Path, path: &Path, permanent: bool) -> Result<String> {
-    if !path.exists() {
-        anyhow::bail!("File does not exist: {}", path.display());
+    #[test]
+    fn test_validate_frontmatter_wrong_type() {
+        let vault = setup_test_vault();
+        fs::write(
+            vault.path().join("scalar_tags.md"),
+            "---\ntitle: Scalar\ntags: rust\n---\n\nBody",
+        )
+        .unwrap();
+        let types = BTreeMap::from([
+            ("tags".to_string(), FieldType::Array),
+            ("title".to_string(), FieldType::String),
+        ]);
+
+        let results = validate_frontmatter(
+            vault.path(),
+            &[],
+            &types,
+            None,
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, "scalar_tags.md");
+        assert!(results[0].problems[0].contains("tags"));
     }
 
-    if permanent {
-        fs::remove_file(path)
-            .with_context(|| format!("Failed to delete file: {}", path.display()))?;
-        Ok(format!("Permanently deleted {}", path.display()))
-    } else {
-        // Move to .trash directory
-        let trash_dir = root.join(".trash");
-        fs::create_dir_all(&trash_dir).with_context(|| {
-            format!("Failed to create trash directory: {}", trash_dir.display())
-        })?;
+    #[test]
+    fn test_validate_frontmatter_no_requirements() {
+        let vault = setup_test_vault();
 
-        // Generate unique name with timestamp
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
+        // Notes without frontmatter are fine when nothing is required
+        let results = validate_frontmatter(
+            vault.path(),
+            &[],
+            &BTreeMap::new(),
+            None,
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
-        let trash_path = trash_dir.join(format!("{}_{}", timestamp, file_name));
+        assert!(results.is_empty());
+    }
 
-        fs::rename(path, &trash_path)
-            .with_context(|| format!("Failed to move file to trash: {}", path.display()))?;
+    #[test]
+    fn test_validate_frontmatter_nested_field() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("nested_meta.md");
+        let content = format_with_frontmatter(
+            &serde_json::json!({"author": {"name": "Gagagigo", "level": "four"}}),
+            "Body",
+            &NoteOptions::default(),
+        );
+        write_note(&path, &content, &NoteOptions::default()).unwrap();
+        let types = BTreeMap::from([("author.level".to_string(), FieldType::Number)]);
+
+        let results = validate_frontmatter(
+            vault.path(),
+            &[],
+            &types,
+            None,
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-        Ok(format!(
-            "Moved to trash: {}",
-            trash_path
-                .strip_prefix(root)
-                .unwrap_or(&trash_path)
-                .display()
-        ))
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, "nested_meta.md");
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
-    use tempfile::TempDir;
+    #[test]
+    fn test_validate_frontmatter_schema() {
+        let vault = setup_test_vault();
+        fs::write(
+            vault.path().join("scalar_tags.md"),
+            "---\ntitle: Scalar\ntags: rust\n---\n\nBody",
+        )
+        .unwrap();
+        let schema_path = vault.path().join("schema.json");
+        fs::write(
+            &schema_path,
+            r#"{
+                "type": "object",
+                "required": ["title", "tags"],
+                "properties": {
+                    "title": {"type": "string"},
+                    "tags": {"type": "array"}
+                }
+            }"#,
+        )
+        .unwrap();
+        let schema = load_schema(&schema_path).unwrap();
+
+        let results = validate_frontmatter(
+            vault.path(),
+            &[],
+            &BTreeMap::new(),
+            Some(&schema),
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-    fn setup_test_vault() -> TempDir {
-        let dir = TempDir::new().unwrap();
+        // test.md is valid; notes without frontmatter aren't checked
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, "scalar_tags.md");
+        assert_eq!(results[0].problems.len(), 1);
+        assert!(results[0].problems[0].starts_with("schema: /tags: "));
+
+        let results = validate_frontmatter(
+            vault.path(),
+            &[],
+            &BTreeMap::new(),
+            Some(&schema),
+            true,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+        let missing: Vec<_> = results
+            .iter()
+            .filter(|r| r.problems == ["missing frontmatter"])
+            .map(|r| r.path.as_str())
+            .collect();
+        assert_eq!(missing.len(), 2);
+    }
 
-        // Create a note with frontmatter
-        let note1 = r#"---
-title: Test Note
-tags: [rust, mcp]
----
+    #[test]
+    fn test_load_schema_invalid() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("schema.json");
 
-# Hello World
+        assert!(load_schema(&path).is_err());
+        fs::write(&path, "not json").unwrap();
+        assert!(load_schema(&path).is_err());
+        fs::write(&path, r#"{"type": 12}"#).unwrap();
+        assert!(load_schema(&path).is_err());
+    }
 
-This is a test note about Gagagigo."#;
-        fs::write(dir.path().join("test.md"), note1).unwrap();
+    // --- add_tag / remove_tag ---
 
-        // Create a simple note
-        let note2 = "# Simple Note\n\nNo frontmatter here.";
-        fs::write(dir.path().join("simple.md"), note2).unwrap();
+    #[test]
+    fn test_add_tag() {
+        let vault = setup_test_vault();
+        let tagged = vault.path().join("tagged.md");
+        let scalar = vault.path().join("scalar.md");
+        let plain = vault.path().join("simple.md");
+        fs::write(&tagged, "---\ntitle: Tagged\ntags: [a, b]\n---\n\nBody").unwrap();
+        fs::write(&scalar, "---\ntags: a\n---\n\nBody").unwrap();
+        let plain_body = read_body(&plain, &NoteOptions::default()).unwrap();
+        let paths = [tagged.clone(), scalar.clone(), plain.clone()];
+
+        let changed = add_tag(vault.path(), &paths, "c", &NoteOptions::default()).unwrap();
+        assert_eq!(changed, ["scalar.md", "simple.md", "tagged.md"]);
+        let tags =
+            |path: &Path| read_metadata(path, &NoteOptions::default()).unwrap()["tags"].clone();
+        assert_eq!(tags(&tagged), serde_json::json!(["a", "b", "c"]));
+        assert_eq!(tags(&scalar), serde_json::json!(["a", "c"]));
+        assert_eq!(tags(&plain), serde_json::json!(["c"]));
+        assert_eq!(
+            read_metadata(&tagged, &NoteOptions::default()).unwrap()["title"],
+            "Tagged"
+        );
+        // The new frontmatter is followed by a blank line, which the body starts with
+        assert_eq!(
+            read_body(&plain, &NoteOptions::default()).unwrap(),
+            format!("\n{}", plain_body)
+        );
 
-        // Create a subdirectory with a note
-        fs::create_dir_all(dir.path().join("daily")).unwrap();
-        let note3 = "# Daily Note\n\nGagagigo awakens!";
-        fs::write(dir.path().join("daily/2024-01-01.md"), note3).unwrap();
+        // Adding it again changes nothing
+        assert!(add_tag(vault.path(), &paths, "c", &NoteOptions::default())
+            .unwrap()
+            .is_empty());
+        assert_eq!(tags(&tagged), serde_json::json!(["a", "b", "c"]));
+    }
 
-        dir
+    #[test]
+    fn test_remove_tag() {
+        let vault = setup_test_vault();
+        let tagged = vault.path().join("tagged.md");
+        let scalar = vault.path().join("scalar.md");
+        fs::write(&tagged, "---\ntags: [a, b]\n---\n\nBody").unwrap();
+        fs::write(&scalar, "---\ntitle: Scalar\ntags: a\n---\n\nBody").unwrap();
+        let paths = [
+            tagged.clone(),
+            scalar.clone(),
+            vault.path().join("simple.md"),
+        ];
+
+        let changed = remove_tag(vault.path(), &paths, "a", &NoteOptions::default()).unwrap();
+        assert_eq!(changed, ["scalar.md", "tagged.md"]);
+        assert_eq!(
+            read_metadata(&tagged, &NoteOptions::default()).unwrap()["tags"],
+            serde_json::json!(["b"])
+        );
+        assert_eq!(
+            read_metadata(&scalar, &NoteOptions::default()).unwrap(),
+            serde_json::json!({"title": "Scalar"})
+        );
     }
 
     #[test]
-    fn test_read_note_without_frontmatter() {
+    fn test_select_notes() {
         let vault = setup_test_vault();
-        let result = read_note(&vault.path().join("simple.md"), false).unwrap();
-        assert!(result.contains("# Simple Note"));
+        let selected = select_notes(
+            vault.path(),
+            Some("daily/**"),
+            &["test.md".to_string()],
+            DEFAULT_TRASH_DIR,
+        )
+        .unwrap();
+        let selected: Vec<_> = selected
+            .iter()
+            .map(|p| relative_path(vault.path(), p))
+            .collect();
+        assert_eq!(selected, ["daily/2024-01-01.md", "test.md"]);
+
+        assert!(select_notes(
+            vault.path(),
+            None,
+            &["missing.md".to_string()],
+            DEFAULT_TRASH_DIR
+        )
+        .is_err());
     }
 
+    // --- rename_tag ---
+
     #[test]
-    fn test_read_note_with_frontmatter_parsing() {
+    fn test_rename_tag_array() {
         let vault = setup_test_vault();
-        let result = read_note(&vault.path().join("test.md"), true).unwrap();
+        let changed = rename_tag(
+            vault.path(),
+            "rust",
+            "rustlang",
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
-        assert_eq!(parsed["metadata"]["title"], "Test Note");
-        assert!(parsed["body"].as_str().unwrap().contains("Hello World"));
+        assert_eq!(changed, vec!["test.md"]);
+
+        let results = search_metadata(
+            vault.path(),
+            "tags",
+            "^rustlang$",
+            10,
+            None,
+            false,
+            &MetadataSearchOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].value, serde_json::json!(["rustlang", "mcp"]));
     }
 
     #[test]
-    fn test_search_notes() {
+    fn test_rename_tag_scalar() {
         let vault = setup_test_vault();
-        let results = search_notes(vault.path(), "Gagagigo", 10).unwrap();
+        let path = vault.path().join("scalar.md");
+        fs::write(&path, "---\ntags: rust\n---\n\nBody").unwrap();
+
+        let changed = rename_tag(
+            vault.path(),
+            "rust",
+            "rustlang",
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-        assert_eq!(results.len(), 2);
+        assert_eq!(changed.len(), 2);
+        let result = read_note(&path, true, &NoteOptions::default()).unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["metadata"]["tags"], "rustlang");
     }
 
     #[test]
-    fn test_search_notes_with_limit() {
+    fn test_rename_tag_leaves_body_untouched() {
         let vault = setup_test_vault();
-        let results = search_notes(vault.path(), "Gagagigo", 1).unwrap();
+        let path = vault.path().join("body_tag.md");
+        fs::write(&path, "---\ntags: [rust]\n---\n\nBody mentions #rust\n").unwrap();
+
+        rename_tag(
+            vault.path(),
+            "rust",
+            "rustlang",
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-        assert_eq!(results.len(), 1);
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains("- rustlang"));
+        assert!(content.contains("Body mentions #rust\n"));
     }
 
     #[test]
-    fn test_search_notes_regex() {
+    fn test_rename_tag_dedupes() {
         let vault = setup_test_vault();
-        let results = search_notes(vault.path(), r"#\s+\w+", 10).unwrap();
+        let path = vault.path().join("dupe.md");
+        fs::write(&path, "---\ntags: [old, new]\n---\n\nBody").unwrap();
+
+        rename_tag(
+            vault.path(),
+            "old",
+            "new",
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-        // Should match headings
-        assert!(!results.is_empty());
+        let result = read_note(&path, true, &NoteOptions::default()).unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["metadata"]["tags"], serde_json::json!(["new"]));
     }
 
     #[test]
-    fn test_write_note_new() {
+    fn test_rename_tag_no_match() {
         let vault = setup_test_vault();
-        let new_path = vault.path().join("new_note.md");
-
-        write_note(&new_path, "# New Note\n\nContent here.").unwrap();
+        let changed = rename_tag(
+            vault.path(),
+            "nonexistent",
+            "x",
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-        assert!(new_path.exists());
-        let content = fs::read_to_string(&new_path).unwrap();
-        assert!(content.contains("# New Note"));
+        assert!(changed.is_empty());
     }
 
+    // --- note_hash ---
+
     #[test]
-    fn test_write_note_creates_directories() {
+    fn test_note_hash_is_stable() {
         let vault = setup_test_vault();
-        let nested_path = vault.path().join("nested/dir/note.md");
+        let path = vault.path().join("simple.md");
 
-        write_note(&nested_path, "# Nested Note").unwrap();
+        let first = note_hash(&path).unwrap();
 
-        assert!(nested_path.exists());
+        assert_eq!(first.len(), 64);
+        assert_eq!(first, note_hash(&path).unwrap());
+        fs::write(&path, "changed").unwrap();
+        assert_ne!(first, note_hash(&path).unwrap());
     }
 
     #[test]
-    fn test_delete_note_to_trash() {
+    fn test_store_checksum_is_idempotent() {
         let vault = setup_test_vault();
-        let note_path = vault.path().join("simple.md");
+        let path = vault.path().join("test.md");
 
-        let result = delete_note(vault.path(), &note_path, false).unwrap();
+        let checksum = store_checksum(&path, &NoteOptions::default()).unwrap();
+        let stored = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            read_metadata(&path, &NoteOptions::default()).unwrap()["checksum"],
+            checksum
+        );
 
-        assert!(!note_path.exists());
-        assert!(result.contains("Moved to trash"));
-        assert!(vault.path().join(".trash").exists());
+        // Storing changes only the frontmatter, so the body checksum is unchanged
+        assert_eq!(
+            store_checksum(&path, &NoteOptions::default()).unwrap(),
+            checksum
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), stored);
     }
 
+    // --- archive_note ---
+
     #[test]
-    fn test_delete_note_permanent() {
+    fn test_archive_note_by_current_date() {
+        use chrono::Datelike;
+
         let vault = setup_test_vault();
         let note_path = vault.path().join("simple.md");
 
-        let result = delete_note(vault.path(), &note_path, true).unwrap();
+        let archived = archive_note(
+            vault.path(),
+            &note_path,
+            DEFAULT_ARCHIVE_DIR,
+            false,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
+        let year = chrono::Local::now().year();
+        assert_eq!(archived, format!("archive/{}/simple.md", year));
         assert!(!note_path.exists());
-        assert!(result.contains("Permanently deleted"));
+        assert!(vault.path().join(&archived).exists());
     }
 
-    // ========================================
-    // Boundary & Error Tests (0-1-N)
-    // ========================================
-
-    // --- read_note boundaries ---
-
     #[test]
-    fn test_read_note_empty_file() {
+    fn test_archive_note_by_created_field() {
         let vault = setup_test_vault();
-        let empty_path = vault.path().join("empty.md");
-        fs::write(&empty_path, "").unwrap();
+        let note_path = vault.path().join("daily/old.md");
+        fs::write(&note_path, "---\ncreated: 2021-06-30\n---\n\nOld note").unwrap();
+
+        let archived = archive_note(
+            vault.path(),
+            &note_path,
+            "vault-archive",
+            false,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-        let result = read_note(&empty_path, false).unwrap();
-        assert_eq!(result, "");
+        assert_eq!(archived, "vault-archive/2021/old.md");
+        assert_eq!(
+            fs::read_to_string(vault.path().join(&archived)).unwrap(),
+            "---\ncreated: 2021-06-30\n---\n\nOld note"
+        );
     }
 
     #[test]
-    fn test_read_note_not_found() {
+    fn test_archive_note_adds_suffix_on_conflict() {
         let vault = setup_test_vault();
-        let result = read_note(&vault.path().join("nonexistent.md"), false);
+        fs::create_dir_all(vault.path().join("archive/2021")).unwrap();
+        fs::write(vault.path().join("archive/2021/old.md"), "already here").unwrap();
+        let note_path = vault.path().join("old.md");
+        fs::write(&note_path, "---\ncreated: 2021-01-01\n---\n\nNew").unwrap();
+
+        let archived = archive_note(
+            vault.path(),
+            &note_path,
+            DEFAULT_ARCHIVE_DIR,
+            false,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-        assert!(result.is_err());
+        assert_eq!(archived, "archive/2021/old-2.md");
+        assert!(!note_path.exists());
+        assert_eq!(
+            fs::read_to_string(vault.path().join("archive/2021/old.md")).unwrap(),
+            "already here"
+        );
     }
 
     #[test]
-    fn test_read_note_frontmatter_only() {
+    fn test_archive_note_stamps_archived_date() {
         let vault = setup_test_vault();
-        let path = vault.path().join("frontmatter_only.md");
-        fs::write(&path, "---\ntitle: Only FM\n---\n").unwrap();
-
-        let result = read_note(&path, true).unwrap();
-        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
+        let note_path = vault.path().join("test.md");
+
+        let archived = archive_note(
+            vault.path(),
+            &note_path,
+            DEFAULT_ARCHIVE_DIR,
+            true,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-        assert_eq!(parsed["metadata"]["title"], "Only FM");
-        assert_eq!(parsed["body"], "");
+        let meta = read_metadata(&vault.path().join(&archived), &NoteOptions::default()).unwrap();
+        let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+        assert_eq!(meta["archived"], today);
+        assert_eq!(meta["title"], "Test Note");
+        assert_eq!(
+            read_body(&vault.path().join(&archived), &NoteOptions::default()).unwrap(),
+            "\n# Hello World\n\nThis is a test note about Gagagigo."
+        );
     }
 
     #[test]
-    fn test_read_note_invalid_yaml() {
+    fn test_update_metadata_adds_frontmatter() {
         let vault = setup_test_vault();
-        let path = vault.path().join("invalid_yaml.md");
-        fs::write(&path, "---\n: invalid yaml [[\n---\n\nBody here").unwrap();
+        let path = vault.path().join("simple.md");
+        let mut fields = serde_json::Map::new();
+        fields.insert("status".to_string(), "draft".into());
 
-        // Should return raw content when YAML is invalid
-        let result = read_note(&path, true).unwrap();
-        assert!(result.contains(": invalid yaml"));
+        update_metadata(&path, fields, &NoteOptions::default()).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "---\nstatus: draft\n---\n\n# Simple Note\n\nNo frontmatter here."
+        );
     }
 
+    // --- delete_note boundaries ---
+
     #[test]
-    fn test_read_note_unclosed_frontmatter() {
+    fn test_delete_note_not_found() {
         let vault = setup_test_vault();
-        let path = vault.path().join("unclosed.md");
-        fs::write(&path, "---\ntitle: Unclosed\n\nNo closing delimiter").unwrap();
+        let result = delete_note(
+            vault.path(),
+            &vault.path().join("nonexistent.md"),
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        );
 
-        // Should return raw content when frontmatter is unclosed
-        let result = read_note(&path, true).unwrap();
-        assert!(result.contains("No closing delimiter"));
+        assert!(result.is_err());
     }
 
     #[test]
-    fn test_read_note_no_frontmatter_with_parse_flag() {
+    fn test_delete_note_in_subdirectory() {
         let vault = setup_test_vault();
-        let result = read_note(&vault.path().join("simple.md"), true).unwrap();
-
-        // Should return raw content when no frontmatter exists
-        assert!(result.contains("# Simple Note"));
-    }
-
-    // --- search_notes boundaries ---
+        let note_path = vault.path().join("daily/2024-01-01.md");
 
-    #[test]
-    fn test_search_notes_empty_vault() {
-        let dir = TempDir::new().unwrap();
-        let results = search_notes(dir.path(), "anything", 10).unwrap();
+        let result = delete_note(
+            vault.path(),
+            &note_path,
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-        assert!(results.is_empty());
+        assert!(!note_path.exists());
+        assert!(result.contains("Moved to trash"));
     }
 
+    // --- restore_note ---
+
     #[test]
-    fn test_search_notes_no_matches() {
+    fn test_restore_note_to_original_folder() {
         let vault = setup_test_vault();
-        let results = search_notes(vault.path(), "zzz_no_match_zzz", 10).unwrap();
+        let note = vault.path().join("daily/2024-01-01.md");
 
-        assert!(results.is_empty());
+        let trashed = move_note_to_trash(
+            vault.path(),
+            &note,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+        // The folder is gone too, and comes back with the note
+        fs::remove_dir(vault.path().join("daily")).unwrap();
+
+        let trash_path = relative_path(vault.path(), &trashed);
+        let entries = list_trash(vault.path(), DEFAULT_TRASH_DIR).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].trash_path, trash_path);
+        assert_eq!(entries[0].original, "daily/2024-01-01.md");
+
+        let restored = restore_note(
+            vault.path(),
+            DEFAULT_TRASH_DIR,
+            &trash_path,
+            None,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(restored, "daily/2024-01-01.md");
+        assert_eq!(
+            fs::read_to_string(&note).unwrap(),
+            "# Daily Note\n\nGagagigo awakens!"
+        );
+        assert!(list_trash(vault.path(), DEFAULT_TRASH_DIR)
+            .unwrap()
+            .is_empty());
+        let manifest = read_trash_manifest(&vault.path().join(DEFAULT_TRASH_DIR));
+        assert!(manifest.is_empty());
     }
 
     #[test]
-    fn test_search_notes_invalid_regex() {
+    fn test_trash_same_named_notes_from_different_folders() {
         let vault = setup_test_vault();
-        let result = search_notes(vault.path(), "[invalid(regex", 10);
+        for dir in ["a", "b"] {
+            fs::create_dir_all(vault.path().join(dir)).unwrap();
+            fs::write(vault.path().join(dir).join("index.md"), dir).unwrap();
+        }
 
-        assert!(result.is_err());
+        // Within the same second, so both get the same timestamp prefix
+        let first = move_note_to_trash(
+            vault.path(),
+            &vault.path().join("a/index.md"),
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+        let second = move_note_to_trash(
+            vault.path(),
+            &vault.path().join("b/index.md"),
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+        assert_ne!(first, second);
+        assert_eq!(fs::read_to_string(&first).unwrap(), "a");
+        assert_eq!(fs::read_to_string(&second).unwrap(), "b");
+
+        for (trashed, original) in [(&first, "a/index.md"), (&second, "b/index.md")] {
+            let trash_path = relative_path(vault.path(), trashed);
+            let restored = restore_note(
+                vault.path(),
+                DEFAULT_TRASH_DIR,
+                &trash_path,
+                None,
+                &NoteOptions::default(),
+            )
+            .unwrap();
+            assert_eq!(restored, original);
+        }
+        assert_eq!(
+            fs::read_to_string(vault.path().join("a/index.md")).unwrap(),
+            "a"
+        );
+        assert_eq!(
+            fs::read_to_string(vault.path().join("b/index.md")).unwrap(),
+            "b"
+        );
     }
 
     #[test]
-    fn test_search_notes_limit_zero() {
+    fn test_trash_concurrent_deletes_keep_manifest_entries() {
         let vault = setup_test_vault();
-        let results = search_notes(vault.path(), "Gagagigo", 0).unwrap();
+        for i in 0..16 {
+            fs::create_dir_all(vault.path().join(format!("d{}", i))).unwrap();
+            fs::write(vault.path().join(format!("d{}/note.md", i)), "x").unwrap();
+        }
 
-        assert!(results.is_empty());
+        (0..16).into_par_iter().for_each(|i| {
+            let note = vault.path().join(format!("d{}/note.md", i));
+            move_note_to_trash(
+                vault.path(),
+                &note,
+                DEFAULT_TRASH_DIR,
+                &NoteOptions::default(),
+            )
+            .unwrap();
+        });
+
+        let manifest = read_trash_manifest(&vault.path().join(DEFAULT_TRASH_DIR));
+        assert_eq!(manifest.len(), 16);
+        assert_eq!(
+            list_trash(vault.path(), DEFAULT_TRASH_DIR).unwrap().len(),
+            16
+        );
     }
 
     #[test]
-    fn test_search_notes_skips_hidden_dirs() {
+    fn test_restore_note_elsewhere_and_conflicts() {
         let vault = setup_test_vault();
+        let trash = vault.path().join(DEFAULT_TRASH_DIR);
 
-        // Create a hidden directory with a note
-        fs::create_dir_all(vault.path().join(".obsidian")).unwrap();
-        fs::write(
-            vault.path().join(".obsidian/config.md"),
-            "# Hidden Gagagigo",
+        let trashed = move_note_to_trash(
+            vault.path(),
+            &vault.path().join("simple.md"),
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
         )
         .unwrap();
-
-        let results = search_notes(vault.path(), "Hidden Gagagigo", 10).unwrap();
-
-        // Should not find the hidden file
-        assert!(results.is_empty());
+        let trash_path = relative_path(vault.path(), &trashed);
+        fs::write(vault.path().join("simple.md"), "# Replacement").unwrap();
+
+        // The original path is taken again
+        let err = restore_note(
+            vault.path(),
+            DEFAULT_TRASH_DIR,
+            &trash_path,
+            None,
+            &NoteOptions::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+
+        let to = vault.path().join("restored/simple.md");
+        let restored = restore_note(
+            vault.path(),
+            DEFAULT_TRASH_DIR,
+            &trash_path,
+            Some(&to),
+            &NoteOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(restored, "restored/simple.md");
+        assert!(fs::read_to_string(&to)
+            .unwrap()
+            .starts_with("# Simple Note"));
+
+        // Notes trashed without a manifest entry go back to the vault root
+        fs::write(trash.join("1700000100_legacy.md"), "# Legacy").unwrap();
+        let restored = restore_note(
+            vault.path(),
+            DEFAULT_TRASH_DIR,
+            ".trash/1700000100_legacy.md",
+            None,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(restored, "legacy.md");
+
+        assert!(restore_note(
+            vault.path(),
+            DEFAULT_TRASH_DIR,
+            "test.md",
+            None,
+            &NoteOptions::default()
+        )
+        .is_err());
     }
 
-    // --- write_note boundaries ---
+    // --- list_trash ---
 
     #[test]
-    fn test_write_note_empty_content() {
+    fn test_list_trash() {
         let vault = setup_test_vault();
-        let path = vault.path().join("empty_write.md");
-
-        write_note(&path, "").unwrap();
-
-        assert!(path.exists());
-        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        assert!(list_trash(vault.path(), DEFAULT_TRASH_DIR)
+            .unwrap()
+            .is_empty());
+
+        let trash = vault.path().join(DEFAULT_TRASH_DIR);
+        fs::create_dir_all(trash.join("1700000300/projects/old")).unwrap();
+        fs::write(trash.join("1700000300/projects/old/a.md"), "a").unwrap();
+        fs::write(trash.join("1700000100_note.md"), "older").unwrap();
+        fs::write(trash.join("1700000200_snake_case.md"), "newer").unwrap();
+        fs::write(trash.join(".ignore"), "*\n").unwrap();
+
+        let entries = list_trash(vault.path(), DEFAULT_TRASH_DIR).unwrap();
+        let listed: Vec<_> = entries
+            .iter()
+            .map(|e| (e.trash_path.as_str(), e.original.as_str(), e.is_dir))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                (".trash/1700000300", "projects/old", true),
+                (".trash/1700000200_snake_case.md", "snake_case.md", false),
+                (".trash/1700000100_note.md", "note.md", false),
+            ]
+        );
+        assert_eq!(entries[2].deleted_at, "2023-11-14T22:15:00+00:00");
     }
 
     #[test]
-    fn test_write_note_overwrite_existing() {
+    fn test_prune_trash() {
         let vault = setup_test_vault();
-        let path = vault.path().join("simple.md");
-
-        write_note(&path, "# Overwritten Content").unwrap();
+        let trash = vault.path().join(DEFAULT_TRASH_DIR);
+        fs::create_dir_all(trash.join("1000/old-dir")).unwrap();
+        fs::write(trash.join("1000_old.md"), "old").unwrap();
+        fs::write(trash.join(format!("{}_new.md", unix_timestamp())), "new").unwrap();
+        fs::write(trash.join(".ignore"), "*\n").unwrap();
+        // No timestamp prefix, so mtime decides
+        let day = Duration::from_secs(24 * 60 * 60);
+        fs::File::create(trash.join("stale.md"))
+            .unwrap()
+            .set_modified(SystemTime::now() - 10 * day)
+            .unwrap();
+        fs::write(trash.join("fresh.md"), "fresh").unwrap();
+
+        assert_eq!(
+            prune_trash(
+                vault.path(),
+                DEFAULT_TRASH_DIR,
+                7 * day,
+                &NoteOptions::default()
+            )
+            .unwrap(),
+            3
+        );
 
-        let content = fs::read_to_string(&path).unwrap();
-        assert!(content.contains("Overwritten"));
-        assert!(!content.contains("No frontmatter"));
+        let mut left: Vec<_> = fs::read_dir(&trash)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| !name.ends_with("_new.md"))
+            .collect();
+        left.sort();
+        assert_eq!(left, [".ignore", "fresh.md"]);
+        assert_eq!(fs::read_dir(&trash).unwrap().count(), 3);
     }
 
+    // --- delete_dir ---
+
     #[test]
-    fn test_write_note_unicode_content() {
+    fn test_delete_dir_to_trash_and_restore() {
         let vault = setup_test_vault();
-        let path = vault.path().join("unicode.md");
-
-        let content = "# ガガギゴ 🐉\n\n日本語テスト";
-        write_note(&path, content).unwrap();
+        fs::create_dir_all(vault.path().join("projects/old/deep")).unwrap();
+        fs::write(vault.path().join("projects/old/a.md"), "# A").unwrap();
+        fs::write(vault.path().join("projects/old/deep/b.md"), "# B").unwrap();
+        let dir = vault.path().join("projects/old");
+
+        let msg = delete_dir(
+            vault.path(),
+            &dir,
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-        let read_back = fs::read_to_string(&path).unwrap();
-        assert_eq!(read_back, content);
+        assert!(!dir.exists());
+        let trashed = vault
+            .path()
+            .join(msg.strip_prefix("Moved to trash: ").unwrap());
+        assert!(trashed.ends_with("projects/old"));
+        assert_eq!(
+            fs::read_to_string(trashed.join("deep/b.md")).unwrap(),
+            "# B"
+        );
+        // Trashed notes are no longer walked
+        assert!(collect_markdown_files(vault.path(), DEFAULT_TRASH_DIR)
+            .iter()
+            .all(|p| !p.ends_with("a.md")));
+
+        // Restoring is moving the folder back to its original place
+        fs::rename(&trashed, &dir).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("a.md")).unwrap(), "# A");
+        assert_eq!(fs::read_to_string(dir.join("deep/b.md")).unwrap(), "# B");
     }
 
-    // --- delete_note boundaries ---
-
     #[test]
-    fn test_delete_note_not_found() {
+    fn test_delete_dir_permanent() {
         let vault = setup_test_vault();
-        let result = delete_note(vault.path(), &vault.path().join("nonexistent.md"), false);
+        let dir = vault.path().join("daily");
+
+        delete_dir(
+            vault.path(),
+            &dir,
+            true,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
 
-        assert!(result.is_err());
+        assert!(!dir.exists());
+        assert!(!vault.path().join(DEFAULT_TRASH_DIR).exists());
     }
 
     #[test]
-    fn test_delete_note_in_subdirectory() {
+    fn test_delete_dir_refuses_root_and_trash() {
         let vault = setup_test_vault();
-        let note_path = vault.path().join("daily/2024-01-01.md");
-
-        let result = delete_note(vault.path(), &note_path, false).unwrap();
-
-        assert!(!note_path.exists());
-        assert!(result.contains("Moved to trash"));
+        delete_note(
+            vault.path(),
+            &vault.path().join("simple.md"),
+            false,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default(),
+        )
+        .unwrap();
+        let trash = vault.path().join(DEFAULT_TRASH_DIR);
+
+        assert!(delete_dir(
+            vault.path(),
+            vault.path(),
+            true,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default()
+        )
+        .is_err());
+        assert!(delete_dir(
+            vault.path(),
+            &trash,
+            true,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default()
+        )
+        .is_err());
+        assert!(trash.exists());
+        assert!(delete_dir(
+            vault.path(),
+            &vault.path().join("test.md"),
+            true,
+            DEFAULT_TRASH_DIR,
+            &NoteOptions::default()
+        )
+        .is_err());
     }
 
     // --- format_with_frontmatter ---
@@ -599,7 +9547,7 @@ This is a test note about Gagagigo."#;
         });
         let body = "# Hello\n\nThis is content.";
 
-        let result = format_with_frontmatter(&metadata, body);
+        let result = format_with_frontmatter(&metadata, body, &NoteOptions::default());
 
         assert!(result.starts_with("---\n"));
         assert!(result.contains("title: Test Note"));
@@ -607,12 +9555,178 @@ This is a test note about Gagagigo."#;
         assert!(result.contains("---\n\n# Hello"));
     }
 
+    #[test]
+    fn test_frontmatter_round_trip_keeps_key_order() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("ordered.md");
+        let original =
+            "---\ntitle: Ordered\ntags:\n- b\n- a\ncreated: 2024-01-01\nauthor: Zed\n---\n\nBody";
+        fs::write(&path, original).unwrap();
+
+        let metadata = read_metadata(&path, &NoteOptions::default()).unwrap();
+        let rewritten = format_with_frontmatter(&metadata, "Body", &NoteOptions::default());
+        assert_eq!(rewritten, original);
+
+        let mut fields = serde_json::Map::new();
+        fields.insert("archived".to_string(), "2024-02-01".into());
+        update_metadata(&path, fields, &NoteOptions::default()).unwrap();
+        let keys: Vec<String> = read_metadata(&path, &NoteOptions::default())
+            .unwrap()
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        assert_eq!(keys, ["title", "tags", "created", "author", "archived"]);
+    }
+
+    #[test]
+    fn test_toml_frontmatter_round_trip() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("hugo.md");
+        let original =
+            "+++\ntitle = \"Hugo\"\ntags = [\"rust\", \"mcp\"]\ndate = 2024-01-01\n+++\n\n# Body";
+        fs::write(&path, original).unwrap();
+
+        let meta = read_metadata(&path, &NoteOptions::default()).unwrap();
+        assert_eq!(meta["title"], "Hugo");
+        assert_eq!(meta["tags"], serde_json::json!(["rust", "mcp"]));
+        assert_eq!(meta["date"], "2024-01-01");
+        assert_eq!(
+            read_body(&path, &NoteOptions::default()).unwrap(),
+            "\n# Body"
+        );
+
+        let rewritten = format_with_frontmatter_as(FrontmatterFormat::Toml, &meta, "# Body");
+        assert!(rewritten.starts_with("+++\ntitle = \"Hugo\"\n"));
+        assert!(rewritten.ends_with("+++\n\n# Body"));
+        fs::write(&path, &rewritten).unwrap();
+        assert_eq!(read_metadata(&path, &NoteOptions::default()).unwrap(), meta);
+
+        // Rewrites keep the note's own format
+        let mut fields = serde_json::Map::new();
+        fields.insert("draft".to_string(), true.into());
+        update_metadata(&path, fields, &NoteOptions::default()).unwrap();
+        let updated = fs::read_to_string(&path).unwrap();
+        assert!(updated.starts_with("+++\n"));
+        assert!(updated.contains("draft = true"));
+        assert!(updated.ends_with("+++\n\n# Body"));
+    }
+
+    #[test]
+    fn test_json_frontmatter_round_trip() {
+        let vault = setup_test_vault();
+        let meta = serde_json::json!({"title": "Json", "tags": ["a"], "empty": null});
+
+        let formatted = format_with_frontmatter_as(FrontmatterFormat::Json, &meta, "# Body");
+        let path = vault.path().join("json.md");
+        fs::write(&path, &formatted).unwrap();
+        assert_eq!(read_metadata(&path, &NoteOptions::default()).unwrap(), meta);
+        assert_eq!(
+            read_body(&path, &NoteOptions::default()).unwrap(),
+            "\n# Body"
+        );
+
+        for fenced in [
+            ";;;\n{\"title\": \"Json\"}\n;;;\n\n# Body",
+            "```json\n{\"title\": \"Json\"}\n```\n\n# Body",
+        ] {
+            fs::write(&path, fenced).unwrap();
+            assert_eq!(
+                read_metadata(&path, &NoteOptions::default()).unwrap()["title"],
+                "Json"
+            );
+            assert_eq!(
+                read_body(&path, &NoteOptions::default()).unwrap(),
+                "\n# Body"
+            );
+            assert_eq!(body_line_offset(&path), 3);
+        }
+    }
+
+    #[test]
+    fn test_format_with_frontmatter_for_keeps_existing_format() {
+        let vault = setup_test_vault();
+        let path = vault.path().join("note.md");
+        let meta = serde_json::json!({"title": "New"});
+        for (original, opener) in [
+            ("---\ntitle: Old\n---\n\nBody", "---\n"),
+            ("+++\ntitle = \"Old\"\n+++\n\nBody", "+++\n"),
+            ("{\"title\": \"Old\"}\n\nBody", "{\n"),
+            (";;;\n{\"title\": \"Old\"}\n;;;\n\nBody", ";;;\n"),
+            ("```json\n{\"title\": \"Old\"}\n```\n\nBody", "```json\n"),
+        ] {
+            fs::write(&path, original).unwrap();
+
+            let rewritten =
+                format_with_frontmatter_for(&path, &meta, "Body", &NoteOptions::default());
+            assert!(rewritten.starts_with(opener), "{}", rewritten);
+            fs::write(&path, &rewritten).unwrap();
+            assert_eq!(read_metadata(&path, &NoteOptions::default()).unwrap(), meta);
+            assert_eq!(read_body(&path, &NoteOptions::default()).unwrap(), "\nBody");
+        }
+
+        // Without frontmatter, or a note at all, the configured format is used
+        fs::write(&path, "Body").unwrap();
+        assert!(
+            format_with_frontmatter_for(&path, &meta, "Body", &NoteOptions::default())
+                .starts_with("---\n")
+        );
+        let missing = vault.path().join("missing.md");
+        assert!(
+            format_with_frontmatter_for(&missing, &meta, "Body", &NoteOptions::default())
+                .starts_with("---\n")
+        );
+    }
+
+    #[test]
+    fn test_toml_frontmatter_drops_nulls_and_unclosed_blocks_are_body() {
+        let meta = serde_json::json!({"title": "T", "gone": null, "list": [1, null]});
+
+        let formatted = format_with_frontmatter_as(FrontmatterFormat::Toml, &meta, "");
+        assert_eq!(formatted, "+++\ntitle = \"T\"\nlist = [1]\n+++\n\n");
+
+        assert!(parse_frontmatter("+++\ntitle = \"T\"\nbody", &NoteOptions::default()).is_none());
+        assert!(FrontmatterFormat::parse("xml").is_err());
+        assert_eq!(
+            FrontmatterFormat::parse("toml").unwrap(),
+            FrontmatterFormat::Toml
+        );
+    }
+
+    #[test]
+    fn test_frontmatter_detect_limits_formats() {
+        let formats = FrontmatterFormat::parse_list("yaml, toml").unwrap();
+        assert_eq!(formats, [FrontmatterFormat::Yaml, FrontmatterFormat::Toml]);
+        assert!(FrontmatterFormat::parse_list("yaml,xml").is_err());
+
+        let yaml = "---\ntitle: Y\n---\n\n# Body";
+        let toml = "+++\ntitle = \"T\"\n+++\n\n# Body";
+        let json = "{\"title\": \"J\"}\n\n# Body";
+        for (content, format) in [
+            (yaml, FrontmatterFormat::Yaml),
+            (toml, FrontmatterFormat::Toml),
+            (json, FrontmatterFormat::Json),
+        ] {
+            let frontmatter = parse_frontmatter_detecting(content, &[format]).unwrap();
+            assert_eq!(frontmatter.format, format);
+            assert_eq!(frontmatter.body, "\n# Body");
+
+            let others: Vec<_> = ALL_FRONTMATTER_FORMATS
+                .iter()
+                .copied()
+                .filter(|f| *f != format)
+                .collect();
+            assert!(parse_frontmatter_detecting(content, &others).is_none());
+        }
+    }
+
     #[test]
     fn test_format_with_frontmatter_empty_metadata() {
         let metadata = serde_json::json!({});
         let body = "Just body content";
 
-        let result = format_with_frontmatter(&metadata, body);
+        let result = format_with_frontmatter(&metadata, body, &NoteOptions::default());
 
         assert!(result.starts_with("---\n"));
         assert!(result.contains("---\n\nJust body content"));
@@ -624,7 +9738,7 @@ This is a test note about Gagagigo."#;
         let metadata = serde_json::json!(r#"{"title": "Test", "tags": ["a", "b"]}"#);
         let body = "Body";
 
-        let result = format_with_frontmatter(&metadata, body);
+        let result = format_with_frontmatter(&metadata, body, &NoteOptions::default());
 
         // Should parse the string and convert to YAML properly
         assert!(result.contains("title: Test"));
@@ -638,12 +9752,12 @@ This is a test note about Gagagigo."#;
 
         let metadata = serde_json::json!({"title": "Roundtrip Test"});
         let body = "Body content here";
-        let content = format_with_frontmatter(&metadata, body);
+        let content = format_with_frontmatter(&metadata, body, &NoteOptions::default());
 
-        write_note(&path, &content).unwrap();
+        write_note(&path, &content, &NoteOptions::default()).unwrap();
 
         // Read back and parse
-        let result = read_note(&path, true).unwrap();
+        let result = read_note(&path, true, &NoteOptions::default()).unwrap();
         let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
 
         assert_eq!(parsed["metadata"]["title"], "Roundtrip Test");
@@ -662,7 +9776,7 @@ This is a test note about Gagagigo."#;
         });
         let body = "Content";
 
-        let result = format_with_frontmatter(&metadata, body);
+        let result = format_with_frontmatter(&metadata, body, &NoteOptions::default());
 
         // Should be valid YAML that can be parsed back
         assert!(result.contains("title:"));
@@ -671,9 +9785,9 @@ This is a test note about Gagagigo."#;
         // Verify roundtrip
         let vault = TempDir::new().unwrap();
         let path = vault.path().join("special.md");
-        write_note(&path, &result).unwrap();
+        write_note(&path, &result, &NoteOptions::default()).unwrap();
 
-        let read_back = read_note(&path, true).unwrap();
+        let read_back = read_note(&path, true, &NoteOptions::default()).unwrap();
         let parsed: serde_json::Value = serde_json::from_str(&read_back).unwrap();
 
         assert_eq!(parsed["metadata"]["title"], "Note: Important!");
@@ -687,20 +9801,20 @@ This is a test note about Gagagigo."#;
 
         let metadata = serde_json::json!({
             "count": 42,
-            "ratio": 3.14,
+            "ratio": 2.5,
             "active": true,
             "tags": ["a", "b"]
         });
-        let content = format_with_frontmatter(&metadata, "Body");
+        let content = format_with_frontmatter(&metadata, "Body", &NoteOptions::default());
 
-        write_note(&path, &content).unwrap();
+        write_note(&path, &content, &NoteOptions::default()).unwrap();
 
-        let result = read_note(&path, true).unwrap();
+        let result = read_note(&path, true, &NoteOptions::default()).unwrap();
         let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
 
         // Verify types are preserved
         assert_eq!(parsed["metadata"]["count"], 42);
-        assert_eq!(parsed["metadata"]["ratio"], 3.14);
+        assert_eq!(parsed["metadata"]["ratio"], 2.5);
         assert_eq!(parsed["metadata"]["active"], true);
         assert!(parsed["metadata"]["tags"].is_array());
     }
@@ -716,11 +9830,11 @@ This is a test note about Gagagigo."#;
                 "level": 4
             }
         });
-        let content = format_with_frontmatter(&metadata, "Body");
+        let content = format_with_frontmatter(&metadata, "Body", &NoteOptions::default());
 
-        write_note(&path, &content).unwrap();
+        write_note(&path, &content, &NoteOptions::default()).unwrap();
 
-        let result = read_note(&path, true).unwrap();
+        let result = read_note(&path, true, &NoteOptions::default()).unwrap();
         let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
 
         assert_eq!(parsed["metadata"]["author"]["name"], "Gagagigo");
@@ -732,7 +9846,16 @@ This is a test note about Gagagigo."#;
     #[test]
     fn test_search_metadata_by_title() {
         let vault = setup_test_vault();
-        let results = search_metadata(vault.path(), "title", "Test", 10).unwrap();
+        let results = search_metadata(
+            vault.path(),
+            "title",
+            "Test",
+            10,
+            None,
+            false,
+            &MetadataSearchOptions::default(),
+        )
+        .unwrap();
 
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].value, "Test Note");
@@ -741,7 +9864,16 @@ This is a test note about Gagagigo."#;
     #[test]
     fn test_search_metadata_by_tags() {
         let vault = setup_test_vault();
-        let results = search_metadata(vault.path(), "tags", "rust", 10).unwrap();
+        let results = search_metadata(
+            vault.path(),
+            "tags",
+            "rust",
+            10,
+            None,
+            false,
+            &MetadataSearchOptions::default(),
+        )
+        .unwrap();
 
         assert_eq!(results.len(), 1);
         assert!(results[0].value.is_array());
@@ -750,7 +9882,16 @@ This is a test note about Gagagigo."#;
     #[test]
     fn test_search_metadata_no_match() {
         let vault = setup_test_vault();
-        let results = search_metadata(vault.path(), "title", "NonExistent", 10).unwrap();
+        let results = search_metadata(
+            vault.path(),
+            "title",
+            "NonExistent",
+            10,
+            None,
+            false,
+            &MetadataSearchOptions::default(),
+        )
+        .unwrap();
 
         assert!(results.is_empty());
     }
@@ -762,10 +9903,20 @@ This is a test note about Gagagigo."#;
         let content = format_with_frontmatter(
             &serde_json::json!({"author": {"name": "Gagagigo", "level": 8}}),
             "Body",
+            &NoteOptions::default(),
         );
-        write_note(&path, &content).unwrap();
-
-        let results = search_metadata(vault.path(), "author.name", "Gagagigo", 10).unwrap();
+        write_note(&path, &content, &NoteOptions::default()).unwrap();
+
+        let results = search_metadata(
+            vault.path(),
+            "author.name",
+            "Gagagigo",
+            10,
+            None,
+            false,
+            &MetadataSearchOptions::default(),
+        )
+        .unwrap();
 
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].value, "Gagagigo");
@@ -774,7 +9925,16 @@ This is a test note about Gagagigo."#;
     #[test]
     fn test_search_metadata_regex() {
         let vault = setup_test_vault();
-        let results = search_metadata(vault.path(), "title", "^Test.*", 10).unwrap();
+        let results = search_metadata(
+            vault.path(),
+            "title",
+            "^Test.*",
+            10,
+            None,
+            false,
+            &MetadataSearchOptions::default(),
+        )
+        .unwrap();
 
         assert_eq!(results.len(), 1);
     }
@@ -782,11 +9942,125 @@ This is a test note about Gagagigo."#;
     #[test]
     fn test_search_metadata_missing_field() {
         let vault = setup_test_vault();
-        let results = search_metadata(vault.path(), "nonexistent_field", ".*", 10).unwrap();
+        let results = search_metadata(
+            vault.path(),
+            "nonexistent_field",
+            ".*",
+            10,
+            None,
+            false,
+            &MetadataSearchOptions::default(),
+        )
+        .unwrap();
 
         assert!(results.is_empty());
     }
 
+    #[test]
+    fn test_search_metadata_json_frontmatter() {
+        let vault = setup_test_vault();
+        fs::write(
+            vault.path().join("json.md"),
+            "{\"title\": \"JSON Note\", \"author\": {\"name\": \"Gagagigo\"}}\n\nBody",
+        )
+        .unwrap();
+
+        let results = search_metadata(
+            vault.path(),
+            "author.name",
+            "Gagagigo",
+            10,
+            None,
+            false,
+            &MetadataSearchOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, "json.md");
+    }
+
+    #[test]
+    fn test_search_metadata_sorted_by_field() {
+        let dir = TempDir::new().unwrap();
+        let notes = [
+            ("b.md", Some(2)),
+            ("a.md", Some(3)),
+            ("c.md", None),
+            ("d.md", Some(1)),
+            ("e.md", Some(2)),
+        ];
+        for (name, order) in notes {
+            let mut meta = serde_json::json!({"tags": ["sorted"]});
+            if let Some(order) = order {
+                meta["order"] = order.into();
+            }
+            write_note(
+                &dir.path().join(name),
+                &format_with_frontmatter(&meta, "Body", &NoteOptions::default()),
+                &NoteOptions::default(),
+            )
+            .unwrap();
+        }
+
+        let asc = search_metadata(
+            dir.path(),
+            "tags",
+            "sorted",
+            10,
+            Some("order"),
+            false,
+            &MetadataSearchOptions::default(),
+        )
+        .unwrap();
+        let paths: Vec<_> = asc.iter().map(|r| r.path.as_str()).collect();
+        // Ties broken by path, missing sort value last
+        assert_eq!(paths, vec!["d.md", "b.md", "e.md", "a.md", "c.md"]);
+
+        let desc = search_metadata(
+            dir.path(),
+            "tags",
+            "sorted",
+            10,
+            Some("order"),
+            true,
+            &MetadataSearchOptions::default(),
+        )
+        .unwrap();
+        let paths: Vec<_> = desc.iter().map(|r| r.path.as_str()).collect();
+        assert_eq!(paths, vec!["a.md", "b.md", "e.md", "d.md", "c.md"]);
+    }
+
+    #[test]
+    fn test_search_metadata_sorted_then_limited() {
+        let dir = TempDir::new().unwrap();
+        for i in 0..5 {
+            let meta = serde_json::json!({"tags": ["sorted"], "order": i});
+            let path = dir.path().join(format!("note_{}.md", i));
+            write_note(
+                &path,
+                &format_with_frontmatter(&meta, "Body", &NoteOptions::default()),
+                &NoteOptions::default(),
+            )
+            .unwrap();
+        }
+
+        let results = search_metadata(
+            dir.path(),
+            "tags",
+            "sorted",
+            2,
+            Some("order"),
+            true,
+            &MetadataSearchOptions::default(),
+        )
+        .unwrap();
+
+        // The limit applies after sorting
+        let paths: Vec<_> = results.iter().map(|r| r.path.as_str()).collect();
+        assert_eq!(paths, vec!["note_4.md", "note_3.md"]);
+    }
+
     #[test]
     fn test_search_metadata_limit() {
         let vault = setup_test_vault();
@@ -797,11 +10071,21 @@ This is a test note about Gagagigo."#;
             let content = format_with_frontmatter(
                 &serde_json::json!({"tags": ["common"]}),
                 &format!("Note {}", i),
+                &NoteOptions::default(),
             );
-            write_note(&path, &content).unwrap();
+            write_note(&path, &content, &NoteOptions::default()).unwrap();
         }
 
-        let results = search_metadata(vault.path(), "tags", "common", 3).unwrap();
+        let results = search_metadata(
+            vault.path(),
+            "tags",
+            "common",
+            3,
+            None,
+            false,
+            &MetadataSearchOptions::default(),
+        )
+        .unwrap();
 
         assert_eq!(results.len(), 3);
     }
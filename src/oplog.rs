@@ -0,0 +1,350 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{index::STATE_DIR, notes};
+
+const OPLOG_FILE: &str = "oplog.jsonl";
+
+/// Snapshots are named by the hash of their content, so identical content is stored once.
+const SNAPSHOT_DIR: &str = "snapshots";
+
+/// Oldest operations are dropped beyond this many, along with snapshots only they used.
+pub const MAX_ENTRIES: usize = 100;
+
+/// A change to the vault that `undo_last` knows how to reverse.
+/// Paths are relative to the vault root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    /// A note or directory was moved, e.g. into the trash or the archive
+    Move { from: String, to: String },
+    /// A note was written. `snapshot` holds its previous content, or None if it was created.
+    Write {
+        path: String,
+        snapshot: Option<String>,
+    },
+    /// A note was permanently deleted; `snapshot` holds its content
+    Delete { path: String, snapshot: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    /// Seconds since the Unix epoch
+    timestamp: u64,
+    #[serde(flatten)]
+    operation: Operation,
+}
+
+/// Append-only log of destructive operations under `.stumbling/`, for undo.
+pub struct OperationLog {
+    root: PathBuf,
+    options: notes::NoteOptions,
+}
+
+impl OperationLog {
+    pub fn new(root: &Path, options: &notes::NoteOptions) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            options: options.clone(),
+        }
+    }
+
+    fn log_path(&self) -> PathBuf {
+        self.root.join(STATE_DIR).join(OPLOG_FILE)
+    }
+
+    fn snapshot_path(&self, hash: &str) -> PathBuf {
+        self.root.join(STATE_DIR).join(SNAPSHOT_DIR).join(hash)
+    }
+
+    /// Save the current content of a note so a later write or delete can be undone.
+    /// Returns None if the note doesn't exist yet.
+    pub fn snapshot(&self, path: &Path) -> Result<Option<String>> {
+        let content = match fs::read(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read file: {}", path.display()))
+            }
+        };
+
+        let hash = format!("{:x}", Sha256::digest(&content));
+        let snapshot = self.snapshot_path(&hash);
+        if !snapshot.exists() {
+            if let Some(parent) = snapshot.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+            }
+            fs::write(&snapshot, &content)
+                .with_context(|| format!("Failed to write snapshot: {}", snapshot.display()))?;
+        }
+        Ok(Some(hash))
+    }
+
+    /// Append an operation, dropping the oldest once there are more than MAX_ENTRIES.
+    pub fn record(&self, operation: Operation) -> Result<()> {
+        let entry = Entry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            operation,
+        };
+
+        let mut entries = self.entries()?;
+        entries.push(entry);
+        if entries.len() > MAX_ENTRIES {
+            entries.drain(..entries.len() - MAX_ENTRIES);
+            self.save(&entries)?;
+            self.remove_unused_snapshots(&entries)
+        } else {
+            let path = self.log_path();
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+            }
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("Failed to open operation log: {}", path.display()))?;
+            let line = serde_json::to_string(&entries[entries.len() - 1])?;
+            writeln!(file, "{}", line)
+                .with_context(|| format!("Failed to write operation log: {}", path.display()))
+        }
+    }
+
+    /// Logged operations, oldest first. Unreadable lines are skipped.
+    fn entries(&self) -> Result<Vec<Entry>> {
+        let content = match fs::read_to_string(self.log_path()) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context("Failed to read operation log"),
+        };
+        Ok(content
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    fn save(&self, entries: &[Entry]) -> Result<()> {
+        let mut content = String::new();
+        for entry in entries {
+            content.push_str(&serde_json::to_string(entry)?);
+            content.push('\n');
+        }
+        notes::write_file(&self.log_path(), &content, &self.options)
+    }
+
+    fn remove_unused_snapshots(&self, entries: &[Entry]) -> Result<()> {
+        let used: BTreeSet<&str> = entries
+            .iter()
+            .filter_map(|entry| match &entry.operation {
+                Operation::Write { snapshot, .. } => snapshot.as_deref(),
+                Operation::Delete { snapshot, .. } => Some(snapshot.as_str()),
+                Operation::Move { .. } => None,
+            })
+            .collect();
+
+        let Ok(snapshots) = fs::read_dir(self.root.join(STATE_DIR).join(SNAPSHOT_DIR)) else {
+            return Ok(());
+        };
+        for snapshot in snapshots.filter_map(|e| e.ok()) {
+            if !used.contains(snapshot.file_name().to_string_lossy().as_ref()) {
+                fs::remove_file(snapshot.path()).with_context(|| {
+                    format!("Failed to remove snapshot: {}", snapshot.path().display())
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn restore_snapshot(&self, path: &str, hash: &str) -> Result<()> {
+        let content = fs::read_to_string(self.snapshot_path(hash))
+            .with_context(|| format!("Snapshot for {} not found", path))?;
+        notes::write_note(
+            &notes::resolve_note_path(&self.root, path)?,
+            &content,
+            &self.options,
+        )
+    }
+
+    /// Reverse the most recent operation and drop it from the log.
+    /// Returns a description of what was undone, or None if the log is empty.
+    pub fn undo_last(&self) -> Result<Option<String>> {
+        let mut entries = self.entries()?;
+        let Some(last) = entries.pop() else {
+            return Ok(None);
+        };
+
+        let message = match &last.operation {
+            Operation::Move { from, to } => {
+                let original = notes::resolve_note_path(&self.root, from)?;
+                if original.exists() {
+                    anyhow::bail!(notes::NoteError::Conflict(format!(
+                        "Cannot move {} back: {} already exists",
+                        to, from
+                    )));
+                }
+                if let Some(parent) = original.parent() {
+                    fs::create_dir_all(parent).with_context(|| {
+                        format!("Failed to create directory: {}", parent.display())
+                    })?;
+                }
+                notes::rename(
+                    &notes::resolve_note_path(&self.root, to)?,
+                    &original,
+                    &self.options,
+                )
+                .with_context(|| format!("Failed to move {} back to {}", to, from))?;
+                format!("Moved {} back to {}", to, from)
+            }
+            Operation::Write {
+                path,
+                snapshot: Some(hash),
+            } => {
+                self.restore_snapshot(path, hash)?;
+                format!("Restored previous content of {}", path)
+            }
+            Operation::Write {
+                path,
+                snapshot: None,
+            } => {
+                let created = notes::resolve_note_path(&self.root, path)?;
+                fs::remove_file(&created).with_context(|| format!("Failed to remove {}", path))?;
+                format!("Removed {}, which had been created", path)
+            }
+            Operation::Delete { path, snapshot } => {
+                if notes::resolve_note_path(&self.root, path)?.exists() {
+                    anyhow::bail!(notes::NoteError::Conflict(format!(
+                        "Cannot restore {}: it already exists",
+                        path
+                    )));
+                }
+                self.restore_snapshot(path, snapshot)?;
+                format!("Restored deleted note {}", path)
+            }
+        };
+
+        self.save(&entries)?;
+        self.remove_unused_snapshots(&entries)?;
+        Ok(Some(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn setup_vault() -> (TempDir, OperationLog) {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("note.md"), "original").unwrap();
+        let log = OperationLog::new(dir.path(), &notes::NoteOptions::default());
+        (dir, log)
+    }
+
+    #[test]
+    fn test_undo_overwrite_and_create() {
+        let (vault, log) = setup_vault();
+        let note = vault.path().join("note.md");
+        let new = vault.path().join("new.md");
+
+        let snapshot = log.snapshot(&note).unwrap();
+        fs::write(&note, "changed").unwrap();
+        log.record(Operation::Write {
+            path: "note.md".to_string(),
+            snapshot,
+        })
+        .unwrap();
+        assert_eq!(log.snapshot(&new).unwrap(), None);
+        fs::write(&new, "created").unwrap();
+        log.record(Operation::Write {
+            path: "new.md".to_string(),
+            snapshot: None,
+        })
+        .unwrap();
+
+        // Most recent first
+        assert_eq!(
+            log.undo_last().unwrap().unwrap(),
+            "Removed new.md, which had been created"
+        );
+        assert!(!new.exists());
+        log.undo_last().unwrap().unwrap();
+        assert_eq!(fs::read_to_string(&note).unwrap(), "original");
+        assert_eq!(log.undo_last().unwrap(), None);
+    }
+
+    #[test]
+    fn test_undo_move_and_delete() {
+        let (vault, log) = setup_vault();
+        let note = vault.path().join("note.md");
+
+        fs::create_dir(vault.path().join(".trash")).unwrap();
+        fs::rename(&note, vault.path().join(".trash/1_note.md")).unwrap();
+        log.record(Operation::Move {
+            from: "note.md".to_string(),
+            to: ".trash/1_note.md".to_string(),
+        })
+        .unwrap();
+        log.undo_last().unwrap().unwrap();
+        assert_eq!(fs::read_to_string(&note).unwrap(), "original");
+
+        let snapshot = log.snapshot(&note).unwrap().unwrap();
+        fs::remove_file(&note).unwrap();
+        log.record(Operation::Delete {
+            path: "note.md".to_string(),
+            snapshot,
+        })
+        .unwrap();
+        log.undo_last().unwrap().unwrap();
+        assert_eq!(fs::read_to_string(&note).unwrap(), "original");
+    }
+
+    #[test]
+    fn test_undo_move_refuses_to_clobber() {
+        let (vault, log) = setup_vault();
+        fs::write(vault.path().join("moved.md"), "moved").unwrap();
+        log.record(Operation::Move {
+            from: "note.md".to_string(),
+            to: "moved.md".to_string(),
+        })
+        .unwrap();
+
+        assert!(log.undo_last().is_err());
+        // The failed undo is kept so it can be retried
+        assert_eq!(log.entries().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_log_is_capped_and_unused_snapshots_removed() {
+        let (vault, log) = setup_vault();
+        let note = vault.path().join("note.md");
+
+        for i in 0..=MAX_ENTRIES {
+            let snapshot = log.snapshot(&note).unwrap();
+            fs::write(&note, format!("version {}", i)).unwrap();
+            log.record(Operation::Write {
+                path: "note.md".to_string(),
+                snapshot,
+            })
+            .unwrap();
+        }
+
+        assert_eq!(log.entries().unwrap().len(), MAX_ENTRIES);
+        let snapshots = fs::read_dir(vault.path().join(STATE_DIR).join(SNAPSHOT_DIR))
+            .unwrap()
+            .count();
+        // The snapshot of "original" belonged to the dropped entry
+        assert_eq!(snapshots, MAX_ENTRIES);
+    }
+}
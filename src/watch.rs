@@ -0,0 +1,156 @@
+use anyhow::{Context, Result};
+use notify::{Event, RecursiveMode, Watcher};
+use rmcp::{model::*, service::Peer, RoleServer};
+use std::{
+    collections::BTreeSet,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+use crate::notes;
+
+/// How long to wait for further events before notifying the peer.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watch the vault for changes on disk and send a logging notification to the peer.
+/// Rapid events are debounced into one notification listing the changed notes.
+pub fn spawn_watcher(root: PathBuf, trash_dir: String, peer: Peer<RoleServer>) -> Result<()> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Event>();
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+        if let Ok(event) = res {
+            let _ = tx.send(event);
+        }
+    })
+    .context("Failed to create file watcher")?;
+    watcher
+        .watch(&root, RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch: {}", root.display()))?;
+
+    tokio::spawn(async move {
+        // Keep the watcher alive for as long as the task runs
+        let _watcher = watcher;
+
+        while let Some(event) = rx.recv().await {
+            let mut changed = BTreeSet::new();
+            collect_changed(&root, &trash_dir, &event, &mut changed);
+
+            // Debounce: keep collecting until events stop for DEBOUNCE
+            while let Ok(Some(event)) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {
+                collect_changed(&root, &trash_dir, &event, &mut changed);
+            }
+
+            if changed.is_empty() {
+                continue;
+            }
+
+            let result = peer
+                .notify_logging_message(LoggingMessageNotificationParam {
+                    level: LoggingLevel::Info,
+                    logger: Some("stumbling-rs".into()),
+                    data: serde_json::json!({
+                        "message": "Vault changed on disk",
+                        "changed": changed,
+                    }),
+                })
+                .await;
+
+            // The peer is gone, so there is nobody left to notify
+            if result.is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn collect_changed(root: &Path, trash_dir: &str, event: &Event, changed: &mut BTreeSet<String>) {
+    changed.extend(
+        event
+            .paths
+            .iter()
+            .filter_map(|p| relevant_path(root, trash_dir, p)),
+    );
+}
+
+/// Relative path of a changed markdown note, or None if the change should be ignored.
+/// Changes in hidden directories, the trash directory, and non-markdown files are ignored,
+/// except for the vault's ignore file.
+fn relevant_path(root: &Path, trash_dir: &str, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    if relative.starts_with(trash_dir) {
+        return None;
+    }
+    // Editing the ignore file changes which notes are visible
+    if relative == Path::new(notes::IGNORE_FILE) {
+        return Some(notes::IGNORE_FILE.to_string());
+    }
+
+    let hidden = relative.components().any(|c| match c {
+        Component::Normal(name) => name.to_string_lossy().starts_with('.'),
+        _ => false,
+    });
+    if hidden || relative.extension().map(|ext| ext != "md").unwrap_or(true) {
+        return None;
+    }
+
+    Some(relative.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relevant_path_note() {
+        let root = Path::new("/vault");
+        let result = relevant_path(root, ".trash", Path::new("/vault/daily/2024-01-01.md"));
+
+        assert_eq!(result.as_deref(), Some("daily/2024-01-01.md"));
+    }
+
+    #[test]
+    fn test_relevant_path_ignores_custom_trash() {
+        let root = Path::new("/vault");
+        let result = relevant_path(
+            root,
+            "stumbling-trash",
+            Path::new("/vault/stumbling-trash/1_note.md"),
+        );
+
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_relevant_path_reports_ignore_file() {
+        let root = Path::new("/vault");
+        let result = relevant_path(root, ".trash", Path::new("/vault/.stumblingignore"));
+
+        assert_eq!(result.as_deref(), Some(".stumblingignore"));
+    }
+
+    #[test]
+    fn test_relevant_path_ignores_trash() {
+        let root = Path::new("/vault");
+
+        assert!(relevant_path(root, ".trash", Path::new("/vault/.trash/1_note.md")).is_none());
+        assert!(relevant_path(root, ".trash", Path::new("/vault/.obsidian/config.md")).is_none());
+    }
+
+    #[test]
+    fn test_relevant_path_ignores_non_markdown() {
+        let root = Path::new("/vault");
+
+        // Temp files from atomic writes should not be reported
+        assert!(relevant_path(root, ".trash", Path::new("/vault/note.tmp")).is_none());
+        assert!(relevant_path(root, ".trash", Path::new("/vault/daily")).is_none());
+    }
+
+    #[test]
+    fn test_relevant_path_outside_root() {
+        let root = Path::new("/vault");
+
+        assert!(relevant_path(root, ".trash", Path::new("/elsewhere/note.md")).is_none());
+    }
+}